@@ -5,6 +5,38 @@ pub struct CompactionStats{
     pub sstables_merged:usize,
     pub space_reclaimed:u64,
     pub duration_ms:u64,
+    // Set when no level, tier or bucket met its trigger, so nothing was merged
+    #[serde(default)]
+    pub skipped:bool,
+}
+
+impl CompactionStats{
+    pub fn skipped()->Self{
+        Self{sstables_merged:0,space_reclaimed:0,duration_ms:0,skipped:true}
+    }
+}
+
+// Outcome of a time-budgeted compaction; `remaining_merges` is the work still
+// planned when the budget ran out, zero once the tables are fully compacted
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct BudgetedCompaction{
+    pub stats:CompactionStats,
+    pub remaining_merges:usize,
+}
+
+impl BudgetedCompaction{
+    pub fn is_complete(&self)->bool{
+        self.remaining_merges==0
+    }
+}
+
+// Snapshot of a running compaction: input tables merged so far out of the
+// run's total, and bytes written to output tables
+#[derive(Debug,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct CompactionProgress{
+    pub tables_processed:usize,
+    pub tables_total:usize,
+    pub bytes_written:u64,
 }
 
 #[derive(Debug,Clone,Serialize,Deserialize)]
@@ -29,7 +61,33 @@ pub struct CompactionConfig{
     pub strategy:CompactionStrategy,
     pub enabled:bool,
     pub background_interval_secs:u64,
+    // Bounds for the background interval as it adapts to the flush rate
+    pub min_background_interval_secs:u64,
+    pub max_background_interval_secs:u64,
     pub max_sstable_per_level:usize,
+    // Once a flush leaves more tables than this, the next write waits for a full compaction
+    pub max_total_sstables:usize,
+    // With this many L0 tables a write waits, up to `l0_stall_max_delay_ms`, for
+    // compaction to bring the count back down
+    pub l0_stall_threshold:usize,
+    pub l0_stall_max_delay_ms:u64,
+    // With this many L0 tables writes are rejected outright
+    pub l0_stop_threshold:usize,
+    // Record a CRC32 beside each compaction output, checked on the table's first read
+    pub checksum_outputs:bool,
+    // A tombstone merged into the bottom level is dropped only once it's at least
+    // this old, and only if no table left out of the merge has an older value it hides
+    pub tombstone_grace_secs:u64,
+    // Runs of two or more adjacent tables in a level, each smaller than this, are
+    // merged into one whatever the strategy. Zero turns this off.
+    pub min_sstable_size:u64,
+    // Caps the bytes a compaction reads and writes per second, leaving disk
+    // bandwidth for foreground reads and writes. Zero means unlimited.
+    pub compaction_io_bytes_per_sec:u64,
+    // Keys under these prefixes stay in level 1 or above: a merge into a deeper
+    // level writes them back out to level 1 instead. Reads of hot keys touch
+    // fewer tables, paid for by rewriting them on every such merge.
+    pub pinned_prefixes:Vec<Vec<u8>>,
 }
 
 impl Default for CompactionConfig{
@@ -41,7 +99,18 @@ impl Default for CompactionConfig{
             },
             enabled:true,
             background_interval_secs:300,
+            min_background_interval_secs:30,
+            max_background_interval_secs:1800,
             max_sstable_per_level:10,
+            max_total_sstables:64,
+            l0_stall_threshold:20,
+            l0_stall_max_delay_ms:1000,
+            l0_stop_threshold:36,
+            checksum_outputs:true,
+            tombstone_grace_secs:0,
+            min_sstable_size:0,
+            compaction_io_bytes_per_sec:0,
+            pinned_prefixes:Vec::new(),
         }
     }
 }
@@ -0,0 +1,347 @@
+// Primary key encoding
+//
+// Key components are concatenated in declaration order. Integers are written
+// big-endian at a fixed width (signed values with the sign bit flipped) so the
+// encoded bytes sort the same way as the numbers. Variable-length components
+// are escaped and terminated: each 0x00 byte is written as 0x00 0xFF and the
+// component ends with 0x00 0x00. A boundary can never be confused with data,
+// which keeps prefix scans on the leading components exact, and since the
+// terminator sorts below any escaped byte, a string sorts before the longer
+// ones it prefixes, so keys sort like the strings themselves.
+//
+// Floats are stored as their IEEE bits with every bit flipped for negatives and
+// only the sign bit flipped otherwise, so keys sort like `f64::total_cmp`:
+// -NaN, -inf, negatives, -0.0, 0.0, positives, inf, NaN.
+
+use crate::{DbError, Result, Value};
+
+pub trait KeyComponent {
+    fn encode_key(&self, out: &mut Vec<u8>);
+}
+
+macro_rules! impl_unsigned_key {
+    ($($ty:ty),*) => {
+        $(
+            impl KeyComponent for $ty {
+                fn encode_key(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed_key {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl KeyComponent for $ty {
+                fn encode_key(&self, out: &mut Vec<u8>) {
+                    let flipped = (*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+                    out.extend_from_slice(&flipped.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned_key!(u8, u16, u32, u64);
+impl_signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64);
+
+macro_rules! impl_float_key {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl KeyComponent for $ty {
+                fn encode_key(&self, out: &mut Vec<u8>) {
+                    let bits = self.to_bits();
+                    let sign = 1 << (<$unsigned>::BITS - 1);
+                    let ordered = if bits & sign != 0 { !bits } else { bits ^ sign };
+                    out.extend_from_slice(&ordered.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_float_key!(f32 => u32, f64 => u64);
+
+impl KeyComponent for bool {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x00;
+
+impl KeyComponent for [u8] {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.reserve(self.len() + 2);
+        for &byte in self {
+            out.push(byte);
+            if byte == ESCAPE {
+                out.push(ESCAPED_ZERO);
+            }
+        }
+        out.extend_from_slice(&[ESCAPE, TERMINATOR]);
+    }
+}
+
+impl KeyComponent for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_key(out);
+    }
+}
+
+impl KeyComponent for str {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyComponent for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_str().encode_key(out);
+    }
+}
+
+// The source of the `DbError::Serialization` returned when a key ends partway
+// through a component
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TruncatedKey {
+    #[error("key truncated: needed {needed} more bytes, {left} left")]
+    Bytes { needed: usize, left: usize },
+    #[error("key truncated: unterminated component")]
+    Unterminated,
+}
+
+// Reverses `KeyComponent::encode_key`, consuming the component from the front of `input`
+pub trait DecodeKeyComponent: Sized {
+    fn decode_key(input: &mut &[u8]) -> Result<Self>;
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(DbError::serialization(TruncatedKey::Bytes { needed: len, left: input.len() }));
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+macro_rules! impl_unsigned_decode {
+    ($($ty:ty),*) => {
+        $(
+            impl DecodeKeyComponent for $ty {
+                fn decode_key(input: &mut &[u8]) -> Result<Self> {
+                    let bytes = take(input, std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_be_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed_decode {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl DecodeKeyComponent for $ty {
+                fn decode_key(input: &mut &[u8]) -> Result<Self> {
+                    let flipped = <$unsigned>::decode_key(input)?;
+                    Ok((flipped ^ (1 << (<$unsigned>::BITS - 1))) as $ty)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_float_decode {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl DecodeKeyComponent for $ty {
+                fn decode_key(input: &mut &[u8]) -> Result<Self> {
+                    let ordered = <$unsigned>::decode_key(input)?;
+                    let sign = 1 << (<$unsigned>::BITS - 1);
+                    let bits = if ordered & sign != 0 { ordered ^ sign } else { !ordered };
+                    Ok(<$ty>::from_bits(bits))
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned_decode!(u8, u16, u32, u64);
+impl_signed_decode!(i8 => u8, i16 => u16, i32 => u32, i64 => u64);
+impl_float_decode!(f32 => u32, f64 => u64);
+
+impl DecodeKeyComponent for bool {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        match u8::decode_key(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(DbError::Serialization(format!("invalid bool key byte {}", other), None)),
+        }
+    }
+}
+
+impl DecodeKeyComponent for Vec<u8> {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        let mut bytes = Vec::new();
+        loop {
+            let Some(at) = input.iter().position(|&b| b == ESCAPE) else {
+                return Err(DbError::serialization(TruncatedKey::Unterminated));
+            };
+            bytes.extend_from_slice(take(input, at)?);
+            let marker = take(input, 2)?[1];
+            match marker {
+                ESCAPED_ZERO => bytes.push(ESCAPE),
+                TERMINATOR => return Ok(bytes),
+                other => return Err(DbError::Serialization(format!("invalid escape byte {} in key", other), None)),
+            }
+        }
+    }
+}
+
+impl DecodeKeyComponent for String {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        String::from_utf8(Vec::<u8>::decode_key(input)?)
+            .map_err(|e| DbError::Serialization(format!("invalid UTF-8 in key: {}", e), Some(Box::new(e))))
+    }
+}
+
+// Values start with a type byte ranked as `Value::sort_cmp` ranks them, so
+// their keys sort the same way. Ints and floats share a rank and are written
+// as their f64 value, then a variant byte and, for ints, the exact int, so ints
+// too large to convert exactly still sort by the int. List elements are each
+// written behind a 0x01 byte and the list ends with 0x00, so a list sorts
+// before the longer ones it prefixes.
+const VALUE_NULL: u8 = 0;
+const VALUE_BOOL: u8 = 1;
+const VALUE_NUMBER: u8 = 2;
+const VALUE_STRING: u8 = 3;
+const VALUE_POINT: u8 = 4;
+const VALUE_LIST: u8 = 5;
+
+const NUMBER_INT: u8 = 0;
+const NUMBER_FLOAT: u8 = 1;
+
+const LIST_ELEMENT: u8 = 1;
+const LIST_END: u8 = 0;
+
+impl KeyComponent for Value {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Null => out.push(VALUE_NULL),
+            Value::Bool(b) => {
+                out.push(VALUE_BOOL);
+                b.encode_key(out);
+            }
+            Value::Int(i) => {
+                out.push(VALUE_NUMBER);
+                (*i as f64).encode_key(out);
+                out.push(NUMBER_INT);
+                i.encode_key(out);
+            }
+            Value::Float(f) => {
+                out.push(VALUE_NUMBER);
+                f.encode_key(out);
+                out.push(NUMBER_FLOAT);
+            }
+            Value::String(s) => {
+                out.push(VALUE_STRING);
+                s.encode_key(out);
+            }
+            Value::Point { lat, lon } => {
+                out.push(VALUE_POINT);
+                lat.encode_key(out);
+                lon.encode_key(out);
+            }
+            Value::List(items) => {
+                out.push(VALUE_LIST);
+                for item in items {
+                    out.push(LIST_ELEMENT);
+                    item.encode_key(out);
+                }
+                out.push(LIST_END);
+            }
+        }
+    }
+}
+
+impl DecodeKeyComponent for Value {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        match u8::decode_key(input)? {
+            VALUE_NULL => Ok(Value::Null),
+            VALUE_BOOL => Ok(Value::Bool(bool::decode_key(input)?)),
+            VALUE_NUMBER => {
+                let float = f64::decode_key(input)?;
+                match u8::decode_key(input)? {
+                    NUMBER_INT => Ok(Value::Int(i64::decode_key(input)?)),
+                    NUMBER_FLOAT => Ok(Value::Float(float)),
+                    other => Err(DbError::Serialization(format!("invalid number key byte {}", other), None)),
+                }
+            }
+            VALUE_STRING => Ok(Value::String(String::decode_key(input)?)),
+            VALUE_POINT => Ok(Value::Point { lat: f64::decode_key(input)?, lon: f64::decode_key(input)? }),
+            VALUE_LIST => {
+                let mut items = Vec::new();
+                loop {
+                    match u8::decode_key(input)? {
+                        LIST_ELEMENT => items.push(Value::decode_key(input)?),
+                        LIST_END => return Ok(Value::List(items)),
+                        other => return Err(DbError::Serialization(format!("invalid list key byte {}", other), None)),
+                    }
+                }
+            }
+            other => Err(DbError::Serialization(format!("invalid value key byte {}", other), None)),
+        }
+    }
+}
+
+impl<T: KeyComponent + ?Sized> KeyComponent for &T {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (**self).encode_key(out);
+    }
+}
+
+// Builds a (possibly partial) primary key. Pushing only the leading components
+// of a composite key yields a prefix that matches every full key starting with them.
+#[derive(Debug, Clone, Default)]
+pub struct KeyBuilder {
+    buf: Vec<u8>,
+}
+
+impl KeyBuilder {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn push<C: KeyComponent + ?Sized>(mut self, component: &C) -> Self {
+        component.encode_key(&mut self.buf);
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+// Reads components back out of an encoded key, in the order they were pushed
+#[derive(Debug, Clone)]
+pub struct KeyReader<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> KeyReader<'a> {
+    pub fn new(key: &'a [u8]) -> Self {
+        Self { remaining: key }
+    }
+
+    pub fn read<C: DecodeKeyComponent>(&mut self) -> Result<C> {
+        C::decode_key(&mut self.remaining)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining.is_empty()
+    }
+}
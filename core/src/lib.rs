@@ -2,14 +2,17 @@ use async_trait::async_trait;
 use serde::{de::DeserializeOwned, Serialize, Deserialize};
 use thiserror::Error;
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64,Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub mod compaction;
+pub mod key;
 pub mod security;
 pub mod wasm;
 
-pub use compaction::{CompactionStats,CompactionConfig,CompactionStrategy,GcConfig,GcStats};
+pub use compaction::{BudgetedCompaction,CompactionStats,CompactionProgress,CompactionConfig,CompactionStrategy,GcConfig,GcStats};
+pub use key::{DecodeKeyComponent,KeyBuilder,KeyComponent,KeyReader,TruncatedKey};
 pub use security::{
     Principal, Permission, SecurityContext, OperationType, Resource,
     AccessDecision, AuditLogEntry, EncryptionConfig, EncryptionAlgorithm
@@ -19,10 +22,19 @@ pub use wasm::{
     WasmValue, WasmExecutionResult
 };
 
+// The error underneath a `DbError`, kept so `Error::source` can walk the chain
+pub type ErrorSource = Box<dyn std::error::Error+Send+Sync>;
+
+// `Storage` and `Serialization` carry the error that caused them, when there
+// was one; their messages already include its text. There's no separate I/O
+// variant: a failed read or write of the store is a `Storage` error whose
+// source is the `std::io::Error`, so callers that treat storage failures alike
+// (repair, supervised tasks) keep matching one variant and can still downcast
+// the source for its `kind()`.
 #[derive(Error,Debug)]
 pub enum DbError {
     #[error("Storage error: {0}")]
-    Storage(String),
+    Storage(String,#[source] Option<ErrorSource>),
     
     #[error("Query error: {0}")]
     Query(String),
@@ -31,7 +43,7 @@ pub enum DbError {
     Schema(String),
     
     #[error("Serialization error: {0}")]
-    Serialization(String),
+    Serialization(String,#[source] Option<ErrorSource>),
     
     #[error("Transaction conflict: {0}")]
     TransactionConflict(String),
@@ -61,6 +73,18 @@ pub enum DbError {
     Encryption(String),
 }
 
+impl DbError{
+    // A storage error wrapping `source`, for `map_err`
+    pub fn storage<E:std::error::Error+Send+Sync+'static>(source:E)->Self{
+        DbError::Storage(source.to_string(),Some(Box::new(source)))
+    }
+
+    // A serialization error wrapping `source`, for `map_err`
+    pub fn serialization<E:std::error::Error+Send+Sync+'static>(source:E)->Self{
+        DbError::Serialization(source.to_string(),Some(Box::new(source)))
+    }
+}
+
 //type alias 
 pub type Result<T> = std::result::Result<T,DbError>;
 
@@ -70,12 +94,83 @@ pub trait Database:Send+Sync{
     async fn get<T:DeserializeOwned>(&self,key:&[u8])->Result<Option<T>>;
     async fn delete(&self,key:&[u8]) -> Result<()>;
     async fn scan(&self,prefic:&[u8])-> Result<Vec<(Vec<u8>,Vec<u8>)>>;
+
+    // Records with start <= key < end in key order. The default filters a full
+    // scan; engines that keep keys sorted should override it.
+    async fn scan_range(&self,start:&[u8],end:&[u8])->Result<Vec<(Vec<u8>,Vec<u8>)>>{
+        let mut records=self.scan(&[]).await?;
+        records.retain(|(key,_)| key.as_slice()>=start && key.as_slice()<end);
+        records.sort_by(|a,b| a.0.cmp(&b.0));
+        Ok(records)
+    }
+
+    // The first `limit` records of `scan_range`. The default truncates the full
+    // range; engines should override it to read only what they return.
+    async fn scan_range_limited(&self,start:&[u8],end:&[u8],limit:usize)->Result<Vec<(Vec<u8>,Vec<u8>)>>{
+        let mut records=self.scan_range(start,end).await?;
+        records.truncate(limit);
+        Ok(records)
+    }
+
+    // `scan` with each key run through `decode`, e.g. a `KeyReader` pulling the
+    // id back out of a `Schema::key`. Fails on the first key `decode` rejects.
+    async fn scan_decoded<K,F>(&self,prefix:&[u8],decode:F)->Result<Vec<(K,Vec<u8>)>>
+    where K:Send,F:Fn(&[u8])->Result<K>+Send{
+        self.scan(prefix).await?.into_iter().map(|(key,value)| Ok((decode(&key)?,value))).collect()
+    }
+
+    // (value, record key) for every record of `table` in an index on `field`
+    // that keeps the indexed value, so a projection of that field can be
+    // answered without reading the records. None when no index covers the
+    // field; engines without secondary indexes keep the default.
+    async fn scan_covering_index(&self,_table:&str,_field:&str)->Result<Option<Vec<(Value,Vec<u8>)>>>{
+        Ok(None)
+    }
+
+    // Up to `limit` entries of `scan_covering_index`, in index order: by value
+    // as `Value::sort_cmp` orders them, then by record key. With `after`, an
+    // entry from an earlier page, it picks up just past that entry. None when
+    // no index covers the field or the engine can't read one in order.
+    async fn scan_covering_index_after(&self,_table:&str,_field:&str,_after:Option<(&Value,&[u8])>,_limit:usize)->Result<Option<Vec<(Value,Vec<u8>)>>>{
+        Ok(None)
+    }
+
+    // Keys of the `table` records an index on `field` lists under any of `values`,
+    // each once, or None when no index can look the field up. Callers recheck
+    // the records they fetch against their filters.
+    async fn lookup_index_keys(&self,_table:&str,_field:&str,_values:&[Value])->Result<Option<Vec<Vec<u8>>>>{
+        Ok(None)
+    }
+
+    // Live keys. The default counts a full scan.
+    async fn exact_key_count(&self)->Result<usize>{
+        Ok(self.scan(&[]).await?.len())
+    }
+
+    // A quick count that may include overwritten or deleted keys, for capacity
+    // planning. The default is exact; engines that can count without reading
+    // the data should override it.
+    async fn estimated_key_count(&self)->Result<usize>{
+        self.exact_key_count().await
+    }
 }
 
 pub trait Schema:Send+Sync {
     fn validate(&self) -> Result<()>;
     fn table_name()-> &'static str;
     fn indexes(&self)-> HashMap<String,Vec<u8>>;
+
+    // Encoded primary key built from the `#[key]` fields in declaration order.
+    // Types without key fields return an empty key.
+    fn key(&self)-> Vec<u8>{
+        Vec::new()
+    }
+
+    // Field names and types in declaration order, so rows of differently shaped
+    // types under one table name can be told apart. None opts out of the check.
+    fn fingerprint()-> Option<&'static str>{
+        None
+    }
 }
 
 #[derive(Debug,Clone)]
@@ -106,18 +201,84 @@ pub enum Operator{
     Contains,
     StartsWith,
     EndsWith,
+    // Matches a field equal to any value in a `Value::List`
+    In,
+    // Match a field that is, or isn't, `Value::Null` or missing. The filter's
+    // value is ignored.
+    IsNull,
+    IsNotNull,
+}
+
+impl Operator{
+    // Whether comparing against `value` can ever match: ranges need a number and
+    // the string operators a string. Anything else would quietly match nothing.
+    pub fn applies_to(&self,value:&Value)->bool{
+        match self{
+            Operator::Eq|Operator::Ne => true,
+            Operator::Gt|Operator::Lt|Operator::Gte|Operator::Lte => matches!(value,Value::Int(_)|Value::Float(_)),
+            Operator::Contains|Operator::StartsWith|Operator::EndsWith => matches!(value,Value::String(_)),
+            Operator::In => matches!(value,Value::List(_)),
+            Operator::IsNull|Operator::IsNotNull => true,
+        }
+    }
 }
 
 // Alias for backward compatibility
 pub type FilterOperator = Operator;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Value{
     Int(i64),
     Float(f64),
     String(String),
     Bool(bool),
     Null,
+    Point{lat:f64,lon:f64},
+    // The candidates of an `In` filter
+    List(Vec<Value>),
+}
+
+// Floats compare and hash by their bits, with every NaN taken as the same
+// one, so values can key a HashMap. Like `sort_cmp`, that tells -0.0 from 0.0.
+impl PartialEq for Value{
+    fn eq(&self,other:&Value)->bool{
+        match (self,other){
+            (Value::Int(a),Value::Int(b))=>a==b,
+            (Value::Float(a),Value::Float(b))=>float_bits(*a)==float_bits(*b),
+            (Value::String(a),Value::String(b))=>a==b,
+            (Value::Bool(a),Value::Bool(b))=>a==b,
+            (Value::Null,Value::Null)=>true,
+            (Value::Point{lat:a_lat,lon:a_lon},Value::Point{lat:b_lat,lon:b_lon})=>{
+                float_bits(*a_lat)==float_bits(*b_lat)&&float_bits(*a_lon)==float_bits(*b_lon)
+            }
+            (Value::List(a),Value::List(b))=>a==b,
+            _=>false,
+        }
+    }
+}
+
+impl Eq for Value{}
+
+impl std::hash::Hash for Value{
+    fn hash<H:std::hash::Hasher>(&self,state:&mut H){
+        std::mem::discriminant(self).hash(state);
+        match self{
+            Value::Int(i)=>i.hash(state),
+            Value::Float(f)=>float_bits(*f).hash(state),
+            Value::String(s)=>s.hash(state),
+            Value::Bool(b)=>b.hash(state),
+            Value::Null=>{}
+            Value::Point{lat,lon}=>{
+                float_bits(*lat).hash(state);
+                float_bits(*lon).hash(state);
+            }
+            Value::List(items)=>items.hash(state),
+        }
+    }
+}
+
+fn float_bits(f:f64)->u64{
+    if f.is_nan(){ f64::NAN.to_bits() }else{ f.to_bits() }
 }
 
 impl Value{
@@ -128,9 +289,112 @@ impl Value{
             (Value::Float(_),Value::Float(_))|
             (Value::String(_),Value::String(_))|
             (Value::Bool(_),Value::Bool(_))|
-            (Value::Null,Value::Null)
+            (Value::Null,Value::Null)|
+            (Value::Point{..},Value::Point{..})|
+            (Value::List(_),Value::List(_))
         )
     }
+
+    // Total order used for sorting: Null first, then by type (bool, number, string, point,
+    // list), then by value; lists compare element by element. Ints and floats compare numerically with each other.
+    pub fn sort_cmp(&self,other:&Value)->std::cmp::Ordering{
+        fn rank(v:&Value)->u8{
+            match v{
+                Value::Null=>0,
+                Value::Bool(_)=>1,
+                Value::Int(_)|Value::Float(_)=>2,
+                Value::String(_)=>3,
+                Value::Point{..}=>4,
+                Value::List(_)=>5,
+            }
+        }
+        match (self,other){
+            (Value::Bool(a),Value::Bool(b))=>a.cmp(b),
+            (Value::Int(a),Value::Int(b))=>a.cmp(b),
+            (Value::Float(a),Value::Float(b))=>a.total_cmp(b),
+            (Value::Int(a),Value::Float(b))=>(*a as f64).total_cmp(b),
+            (Value::Float(a),Value::Int(b))=>a.total_cmp(&(*b as f64)),
+            (Value::String(a),Value::String(b))=>a.cmp(b),
+            (Value::Point{lat:a_lat,lon:a_lon},Value::Point{lat:b_lat,lon:b_lon})=>{
+                a_lat.total_cmp(b_lat).then(a_lon.total_cmp(b_lon))
+            }
+            (Value::List(a),Value::List(b))=>{
+                a.iter().zip(b).map(|(x,y)| x.sort_cmp(y)).find(|o| o.is_ne()).unwrap_or_else(|| a.len().cmp(&b.len()))
+            }
+            _=>rank(self).cmp(&rank(other)),
+        }
+    }
+}
+
+impl Value{
+    // Ints widen to f64 so numeric results read the same whichever variant they came back as
+    pub fn as_f64(&self)->Option<f64>{
+        match self{
+            Value::Float(f)=>Some(*f),
+            Value::Int(i)=>Some(*i as f64),
+            _=>None,
+        }
+    }
+
+    pub fn as_i64(&self)->Option<i64>{
+        match self{
+            Value::Int(i)=>Some(*i),
+            _=>None,
+        }
+    }
+
+    pub fn as_str(&self)->Option<&str>{
+        match self{
+            Value::String(s)=>Some(s),
+            _=>None,
+        }
+    }
+
+    pub fn as_bool(&self)->Option<bool>{
+        match self{
+            Value::Bool(b)=>Some(*b),
+            _=>None,
+        }
+    }
+}
+
+fn conversion_error(value:&Value,target:&str)->DbError{
+    DbError::Query(format!("Cannot convert {:?} to {}",value,target))
+}
+
+impl TryFrom<Value> for f64{
+    type Error=DbError;
+
+    fn try_from(value:Value)->Result<Self>{
+        value.as_f64().ok_or_else(|| conversion_error(&value,"f64"))
+    }
+}
+
+impl TryFrom<Value> for i64{
+    type Error=DbError;
+
+    fn try_from(value:Value)->Result<Self>{
+        value.as_i64().ok_or_else(|| conversion_error(&value,"i64"))
+    }
+}
+
+impl TryFrom<Value> for bool{
+    type Error=DbError;
+
+    fn try_from(value:Value)->Result<Self>{
+        value.as_bool().ok_or_else(|| conversion_error(&value,"bool"))
+    }
+}
+
+impl TryFrom<Value> for String{
+    type Error=DbError;
+
+    fn try_from(value:Value)->Result<Self>{
+        match value{
+            Value::String(s)=>Ok(s),
+            other=>Err(conversion_error(&other,"String")),
+        }
+    }
 }
 
 // pub struct Transaction{
@@ -150,6 +414,27 @@ impl Transaction{
 
 pub trait FieldAccess{
     fn get_field(&self,field_name:&str) -> Option<Value>;
+
+    // A field that is itself a struct with fields, for `get_path`
+    fn nested(&self,_field_name:&str)->Option<&dyn FieldAccess>{
+        None
+    }
+
+    // Resolves a dotted path like `address.city` through `nested`; a plain
+    // field name is just `get_field`
+    fn get_path(&self,path:&str)->Option<Value>{
+        match path.split_once('.'){
+            Some((head,rest)) => self.nested(head)?.get_path(rest),
+            None => self.get_field(path),
+        }
+    }
+}
+
+// Enums whose fields are stored and queried as the name of their variant.
+// `#[derive(IntoValueEnum)]` implements this and `From<&T> for Value`, which
+// makes the enum usable as a `#[derive(Schema)]` field.
+pub trait IntoValueEnum{
+    fn variant_name(&self)->&'static str;
 }
 
 // From implementations for Value conversion
@@ -204,6 +489,10 @@ impl TransactionId{
         TransactionId(COUNTER.fetch_add(1,Ordering::SeqCst))
     }
 
+    pub fn from_u64(value:u64)->Self{
+        TransactionId(value)
+    }
+
     pub fn as_u64(&self)->u64{
         self.0
     }
@@ -307,25 +596,127 @@ impl VersionedRecord{
     }
 }
 
+// Profiling counters for one transaction. Reads only get `&Transaction`, and the
+// transaction manager shares them to report on open transactions, hence the atomics.
+#[derive(Debug)]
+pub struct TransactionStats{
+    keys_read:AtomicU64,
+    keys_written:AtomicU64,
+    rows_scanned:AtomicU64,
+    // Distinct keys in the write set
+    write_set_size:AtomicU64,
+    started:Instant,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct TransactionStatsSnapshot{
+    pub keys_read:u64,
+    pub keys_written:u64,
+    pub rows_scanned:u64,
+    // Time since the transaction began; final once it has committed
+    pub duration:Duration,
+}
+
+impl TransactionStats{
+    pub fn new()->Self{
+        Self { keys_read: AtomicU64::new(0), keys_written: AtomicU64::new(0), rows_scanned: AtomicU64::new(0), write_set_size: AtomicU64::new(0), started: Instant::now() }
+    }
+
+    pub fn write_set_size(&self)->usize{
+        self.write_set_size.load(Ordering::Relaxed) as usize
+    }
+
+    pub fn started(&self)->Instant{
+        self.started
+    }
+
+    pub fn record_read(&self){
+        self.keys_read.fetch_add(1,Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self){
+        self.keys_written.fetch_add(1,Ordering::Relaxed);
+    }
+
+    pub fn record_scan(&self,rows:usize){
+        self.rows_scanned.fetch_add(rows as u64,Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self)->TransactionStatsSnapshot{
+        TransactionStatsSnapshot{
+            keys_read:self.keys_read.load(Ordering::Relaxed),
+            keys_written:self.keys_written.load(Ordering::Relaxed),
+            rows_scanned:self.rows_scanned.load(Ordering::Relaxed),
+            duration:self.started.elapsed(),
+        }
+    }
+}
+
+impl Default for TransactionStats{
+    fn default()->Self{
+        Self::new()
+    }
+}
 
 pub struct Transaction{
     pub id:TransactionId,
     pub snapshot_ts:VersionTimestamp,
     pub state:TransactionState,
     pub writes:HashMap<Vec<u8>,Option<Vec<u8>>>,
+    // Key prefixes this transaction may write; empty means the whole key space
+    pub scopes:Vec<Vec<u8>>,
+    pub stats:Arc<TransactionStats>,
+    // Most distinct keys `put` and `delete` will accept; None means no limit
+    pub max_writes:Option<usize>,
 }
 
 impl Transaction{
     pub fn new()->Self{
-        Self { id: TransactionId::new(), snapshot_ts: VersionTimestamp::now(), state: TransactionState::Active, writes: HashMap::new() }
+        Self { id: TransactionId::new(), snapshot_ts: VersionTimestamp::now(), state: TransactionState::Active, writes: HashMap::new(), scopes: Vec::new(), stats: Arc::new(TransactionStats::new()), max_writes: None }
+    }
+
+    // Declares a prefix the transaction will touch. Once scoped, commit rejects
+    // writes outside every declared prefix, and conflict checks skip transactions
+    // whose scopes don't overlap
+    pub fn scope(&mut self,prefix:&[u8])->&mut Self{
+        self.scopes.push(prefix.to_vec());
+        self
     }
 
-    pub fn put(&mut self,key:Vec<u8>,value:Vec<u8>){
-        self.writes.insert(key, Some(value));
+    pub fn in_scope(&self,key:&[u8])->bool{
+        self.scopes.is_empty() || self.scopes.iter().any(|prefix| key.starts_with(prefix))
     }
 
-    pub fn delete(&mut self,key:Vec<u8>){
-        self.writes.insert(key, None);
+    // Two prefixes overlap when one extends the other; an empty scope list overlaps everything
+    pub fn scopes_overlap(&self,other:&[Vec<u8>])->bool{
+        if self.scopes.is_empty() || other.is_empty(){
+            return true;
+        }
+        self.scopes.iter().any(|a| other.iter().any(|b| a.starts_with(b) || b.starts_with(a)))
+    }
+
+    // Fails once the write set holds `max_writes` keys and `key` isn't one of
+    // them; rewriting a key already in the set always succeeds
+    pub fn put(&mut self,key:Vec<u8>,value:Vec<u8>)->Result<()>{
+        self.record(key,Some(value))
+    }
+
+    pub fn delete(&mut self,key:Vec<u8>)->Result<()>{
+        self.record(key,None)
+    }
+
+    fn record(&mut self,key:Vec<u8>,write:Option<Vec<u8>>)->Result<()>{
+        if let Some(max) = self.max_writes{
+            if self.writes.len()>=max && !self.writes.contains_key(&key){
+                return Err(DbError::Transaction(format!(
+                    "Write set is at its limit of {} keys; commit or split the transaction",max
+                )));
+            }
+        }
+        self.stats.record_write();
+        self.writes.insert(key, write);
+        self.stats.write_set_size.store(self.writes.len() as u64,Ordering::Relaxed);
+        Ok(())
     }
 }
 
@@ -347,6 +738,27 @@ pub trait MvccDatabase:Database {
         prefix:&[u8],
         transaction:&Transaction,
     )->Result<Vec<(Vec<u8>,Vec<u8>)>>;
+
+    // Keys in [start, end) as the transaction sees them, its own writes included
+    async fn scan_range_for_transaction(
+        &self,
+        start:&[u8],
+        end:&[u8],
+        transaction:&Transaction,
+    )->Result<Vec<(Vec<u8>,Vec<u8>)>>;
+
+    // Whether the key has a live value as the transaction sees it. The default
+    // scans the range holding only `key`, so it never needs the value's type
+    // and reads no keys that merely start with it.
+    async fn contains_for_transaction(&self,key:&[u8],transaction:&Transaction)->Result<bool>{
+        let mut end=key.to_vec();
+        end.push(0);
+        Ok(!self.scan_range_for_transaction(key,&end,transaction).await?.is_empty())
+    }
+
+    // Synchronous rollback for a transaction dropped without commit or rollback.
+    // Called from `Drop`, so implementations must not block on async work.
+    fn abandon_transaction(&self,_transaction:Transaction){}
 }
 
 pub struct TransactionContext<'a,D:MvccDatabase>{
@@ -368,6 +780,16 @@ impl<'a,D:MvccDatabase> TransactionContext<'a,D>{
         }
     }
 
+    // Commits and returns the transaction's final counters and total duration
+    pub async fn commit_with_stats(mut self) -> Result<TransactionStatsSnapshot>{
+        let transaction = self.transaction.take()
+            .ok_or_else(|| DbError::Transaction("Transaction already completed".to_string()))?;
+        let keys = transaction.stats.snapshot();
+        let started = transaction.stats.started;
+        self.db.commit_transaction(transaction).await?;
+        Ok(TransactionStatsSnapshot{ duration: started.elapsed(), ..keys })
+    }
+
     pub async fn rollback(mut self) -> Result<()>{
         if let Some(transaction) = self.transaction.take(){
             self.db.rollback_transaction(transaction).await
@@ -376,6 +798,10 @@ impl<'a,D:MvccDatabase> TransactionContext<'a,D>{
         }
     }
 
+    pub fn stats(&self) -> TransactionStatsSnapshot{
+        self.transaction().stats.snapshot()
+    }
+
     pub fn transaction(&self) -> &Transaction{
         self.transaction.as_ref().unwrap()
     }
@@ -383,12 +809,22 @@ impl<'a,D:MvccDatabase> TransactionContext<'a,D>{
     pub fn transaction_mut(&mut self) -> &mut Transaction{
         self.transaction.as_mut().unwrap()
     }
+
+    // Records a tombstone only if the key currently exists for this transaction,
+    // returning whether it did
+    pub async fn delete_existing(&mut self,key:&[u8])->Result<bool>{
+        if !self.db.contains_for_transaction(key,self.transaction()).await?{
+            return Ok(false);
+        }
+        self.transaction_mut().delete(key.to_vec())?;
+        Ok(true)
+    }
 }
 
 impl<'a ,D:MvccDatabase> Drop for TransactionContext<'a,D>{
     fn drop(&mut self){
-        if self.transaction.is_some(){
-
+        if let Some(transaction) = self.transaction.take(){
+            self.db.abandon_transaction(transaction);
         }
     }
 }
@@ -411,6 +847,34 @@ impl<T: Database> Database for std::sync::Arc<T> {
     async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         (**self).scan(prefix).await
     }
+
+    async fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        (**self).scan_range(start, end).await
+    }
+
+    async fn scan_range_limited(&self, start: &[u8], end: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        (**self).scan_range_limited(start, end, limit).await
+    }
+
+    async fn scan_covering_index(&self, table: &str, field: &str) -> Result<Option<Vec<(Value, Vec<u8>)>>> {
+        (**self).scan_covering_index(table, field).await
+    }
+
+    async fn scan_covering_index_after(&self, table: &str, field: &str, after: Option<(&Value, &[u8])>, limit: usize) -> Result<Option<Vec<(Value, Vec<u8>)>>> {
+        (**self).scan_covering_index_after(table, field, after, limit).await
+    }
+
+    async fn lookup_index_keys(&self, table: &str, field: &str, values: &[Value]) -> Result<Option<Vec<Vec<u8>>>> {
+        (**self).lookup_index_keys(table, field, values).await
+    }
+
+    async fn exact_key_count(&self) -> Result<usize> {
+        (**self).exact_key_count().await
+    }
+
+    async fn estimated_key_count(&self) -> Result<usize> {
+        (**self).estimated_key_count().await
+    }
 }
 
 // Implement MvccDatabase trait for Arc<T> where T: MvccDatabase
@@ -443,6 +907,23 @@ impl<T: MvccDatabase> MvccDatabase for std::sync::Arc<T> {
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         (**self).scan_for_transaction(prefix, transaction).await
     }
+
+    async fn scan_range_for_transaction(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        transaction: &Transaction,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        (**self).scan_range_for_transaction(start, end, transaction).await
+    }
+
+    async fn contains_for_transaction(&self, key: &[u8], transaction: &Transaction) -> Result<bool> {
+        (**self).contains_for_transaction(key, transaction).await
+    }
+
+    fn abandon_transaction(&self, transaction: Transaction) {
+        (**self).abandon_transaction(transaction)
+    }
 }
 // Security and WASM extension traits
 #[async_trait]
@@ -20,11 +20,7 @@ async fn main() -> anyhow::Result<()> {
     let storage = LsmStorage::new(std::path::Path::new("./data"))?;
     
     // Create email index
-    storage.create_index(IndexDescriptor {
-        name: "idx_user_email".to_string(),
-        field: "email".to_string(),
-        index_type: IndexType::Hash,
-    }).await?;
+    storage.create_index(IndexDescriptor::new("idx_user_email", "email", IndexType::Hash)).await?;
     
     // Insert users
     let users = vec![
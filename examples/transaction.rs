@@ -51,7 +51,7 @@ async fn main() -> anyhow::Result<()> {
     let alice_key = b"accounts:1";
     if let Some(mut alice) = storage.get_for_transaction::<Account>(alice_key, tx_ctx.transaction()).await? {
         alice.balance += 100.0;
-        tx_ctx.transaction_mut().put(alice_key.to_vec(), bincode::serialize(&alice)?);
+        tx_ctx.transaction_mut().put(alice_key.to_vec(), bincode::serialize(&alice)?)?;
         println!("Updated Alice's balance to: {}", alice.balance);
     }
     
@@ -99,7 +99,7 @@ async fn main() -> anyhow::Result<()> {
         let key = b"accounts:1";
         if let Some(mut account) = storage1.get_for_transaction::<Account>(key, tx_ctx.transaction()).await.unwrap() {
             account.balance -= 50.0;
-            tx_ctx.transaction_mut().put(key.to_vec(), bincode::serialize(&account).unwrap());
+            tx_ctx.transaction_mut().put(key.to_vec(), bincode::serialize(&account).unwrap()).unwrap();
             println!("Task 1: Deducted $50 from account 1");
         }
         
@@ -166,8 +166,8 @@ async fn transfer_money(
     updated_to.balance += amount;
     
     // Apply updates
-    tx_ctx.transaction_mut().put(from_key, bincode::serialize(&updated_from)?);
-    tx_ctx.transaction_mut().put(to_key, bincode::serialize(&updated_to)?);
+    tx_ctx.transaction_mut().put(from_key, bincode::serialize(&updated_from)?)?;
+    tx_ctx.transaction_mut().put(to_key, bincode::serialize(&updated_to)?)?;
     
     // Create transfer record
     let transfer = Transfer {
@@ -177,7 +177,7 @@ async fn transfer_money(
         status: "completed".to_string(),
     };
     let transfer_key = format!("transfers:{}", rust_db_core::VersionTimestamp::now().as_u64()).into_bytes();
-    tx_ctx.transaction_mut().put(transfer_key, bincode::serialize(&transfer)?);
+    tx_ctx.transaction_mut().put(transfer_key, bincode::serialize(&transfer)?)?;
     
     println!("Transfer: ${} from {} to {}", amount, from_account.name, to_account.name);
     
@@ -0,0 +1,86 @@
+use crate::QueryBuilder;
+use std::collections::HashMap;
+use rust_db_core::{Database, DbError, FieldAccess, Result, Schema, Value};
+
+// What `GroupedQuery::aggregate` computes from each group's values of a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFn {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+pub struct GroupedQuery<'a, T, D> {
+    pub(crate) query: QueryBuilder<'a, T, D>,
+    pub(crate) field: String,
+}
+
+impl<'a, T, D> GroupedQuery<'a, T, D>
+where
+    T: Schema + serde::de::DeserializeOwned + Send + Sync + FieldAccess,
+    D: Database,
+{
+    // The aggregate for each distinct value of the grouped field among the rows
+    // that pass the filters. Rows lacking the grouped field fall in the
+    // `Value::Null` group. Missing and null values of `agg_field` are skipped,
+    // so `Count` counts the rows that have one. `Sum` stays an Int while every
+    // value is one, `Avg` is always a Float, and a group with nothing to
+    // aggregate gets `Value::Null` (0 for `Count`). A limit caps the rows
+    // grouped, not the groups returned.
+    pub async fn aggregate(self, agg_field: &str, agg: AggFn) -> Result<HashMap<Value, Value>> {
+        let rows = self.query.fetch().await?;
+        let mut groups: HashMap<Value, Vec<Value>> = HashMap::new();
+        for row in &rows {
+            let group = self.query.field_value(row, &self.field).unwrap_or(Value::Null);
+            let values = groups.entry(group).or_default();
+            if let Some(value) = self.query.field_value(row, agg_field).filter(|v| *v != Value::Null) {
+                values.push(value);
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(group, values)| {
+                let values: Vec<&Value> = values.iter().collect();
+                Ok((group, apply(agg, agg_field, &values)?))
+            })
+            .collect()
+    }
+}
+
+fn apply(agg: AggFn, field: &str, values: &[&Value]) -> Result<Value> {
+    match agg {
+        AggFn::Count => Ok(Value::Int(values.len() as i64)),
+        AggFn::Min => Ok(values.iter().min_by(|a, b| a.sort_cmp(b)).map_or(Value::Null, |v| (*v).clone())),
+        AggFn::Max => Ok(values.iter().max_by(|a, b| a.sort_cmp(b)).map_or(Value::Null, |v| (*v).clone())),
+        AggFn::Sum if values.is_empty() => Ok(Value::Null),
+        AggFn::Sum => {
+            let ints: Option<Vec<i64>> = values.iter().map(|v| v.as_i64()).collect();
+            match ints {
+                Some(ints) => ints
+                    .into_iter()
+                    .try_fold(0i64, |sum, i| sum.checked_add(i))
+                    .map(Value::Int)
+                    .ok_or_else(|| DbError::Query(format!("Sum of {} overflows", field))),
+                None => Ok(Value::Float(numbers(field, values)?.iter().sum())),
+            }
+        }
+        AggFn::Avg if values.is_empty() => Ok(Value::Null),
+        AggFn::Avg => {
+            let numbers = numbers(field, values)?;
+            Ok(Value::Float(numbers.iter().sum::<f64>() / numbers.len() as f64))
+        }
+    }
+}
+
+fn numbers(field: &str, values: &[&Value]) -> Result<Vec<f64>> {
+    values
+        .iter()
+        .map(|v| {
+            v.as_f64()
+                .ok_or_else(|| DbError::Query(format!("Field {} has non-numeric value {:?}", field, v)))
+        })
+        .collect()
+}
@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use rust_db_core::{Database, Result, Value};
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Identifies a query by its table and everything that shapes its results:
+// filters (sorted, since they're ANDed), ordering, limit and key range
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct QuerySignature {
+    pub(crate) table: String,
+    pub(crate) shape: String,
+}
+
+struct CachedResult {
+    // Always a `Vec<T>` for the query's row type
+    results: Arc<dyn Any + Send + Sync>,
+    inserted: Instant,
+}
+
+// Opt-in cache of deserialized query results. Entries expire after `ttl` and are
+// dropped by any write under their table's prefix; past `capacity` entries the
+// oldest is evicted.
+pub struct QueryCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<HashMap<QuerySignature, CachedResult>>,
+    // Bumped by every invalidation, so a query that raced a write doesn't cache
+    // what it read before the write
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn get<T: Clone + 'static>(&self, signature: &QuerySignature) -> Option<Vec<T>> {
+        let mut entries = self.entries.lock().unwrap();
+        let cached = match entries.get(signature) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => {
+                entry.results.downcast_ref::<Vec<T>>().cloned()
+            }
+            Some(_) => {
+                entries.remove(signature);
+                None
+            }
+            None => None,
+        };
+        let counter = if cached.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    // Skipped if anything was invalidated since `generation` was read
+    pub(crate) fn insert<T: Clone + Send + Sync + 'static>(
+        &self,
+        signature: QuerySignature,
+        generation: u64,
+        results: &[T],
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if self.generation() != generation {
+            return;
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&signature) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(signature, _)| signature.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            signature,
+            CachedResult {
+                results: Arc::new(results.to_vec()),
+                inserted: Instant::now(),
+            },
+        );
+    }
+
+    // Drops every cached query over a table whose prefix `key` falls under
+    pub fn invalidate(&self, key: &[u8]) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.retain(|signature, _| !key.starts_with(signature.table.as_bytes()));
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+// Database wrapper that keeps a `QueryCache` coherent: writes go to `inner`
+// and then invalidate the cached queries over the written key's table
+pub struct CachingDatabase<D> {
+    inner: D,
+    cache: Arc<QueryCache>,
+}
+
+impl<D: Database> CachingDatabase<D> {
+    pub fn new(inner: D, cache: Arc<QueryCache>) -> Self {
+        Self { inner, cache }
+    }
+
+    pub fn cache(&self) -> &QueryCache {
+        &self.cache
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<D: Database> Database for CachingDatabase<D> {
+    async fn insert<T: Serialize + Send + Sync>(&self, key: &[u8], value: &T) -> Result<()> {
+        let result = self.inner.insert(key, value).await;
+        self.cache.invalidate(key);
+        result
+    }
+
+    async fn get<T: DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>> {
+        self.inner.get(key).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        let result = self.inner.delete(key).await;
+        self.cache.invalidate(key);
+        result
+    }
+
+    async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.scan(prefix).await
+    }
+
+    async fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.scan_range(start, end).await
+    }
+
+    async fn scan_range_limited(&self, start: &[u8], end: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.scan_range_limited(start, end, limit).await
+    }
+
+    async fn scan_covering_index(&self, table: &str, field: &str) -> Result<Option<Vec<(Value, Vec<u8>)>>> {
+        self.inner.scan_covering_index(table, field).await
+    }
+
+    async fn scan_covering_index_after(&self, table: &str, field: &str, after: Option<(&Value, &[u8])>, limit: usize) -> Result<Option<Vec<(Value, Vec<u8>)>>> {
+        self.inner.scan_covering_index_after(table, field, after, limit).await
+    }
+
+    async fn lookup_index_keys(&self, table: &str, field: &str, values: &[Value]) -> Result<Option<Vec<Vec<u8>>>> {
+        self.inner.lookup_index_keys(table, field, values).await
+    }
+
+    async fn exact_key_count(&self) -> Result<usize> {
+        self.inner.exact_key_count().await
+    }
+
+    async fn estimated_key_count(&self) -> Result<usize> {
+        self.inner.estimated_key_count().await
+    }
+}
@@ -0,0 +1,305 @@
+use rust_db_core::{DbError, Filter, Operator, Result, Value};
+use std::str::FromStr;
+
+// A boolean combination of filters, for conditions a plain list of ANDed
+// filters can't express. Usually parsed from a string such as
+// `age > 25 AND (email CONTAINS "example.com" OR NOT active = true)`; see
+// `FilterExpr::parse` for the syntax.
+#[derive(Debug, Clone)]
+pub enum FilterExpr {
+    Filter(Filter),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+}
+
+impl FilterExpr {
+    pub fn filter(field: &str, operator: Operator, value: Value) -> Self {
+        FilterExpr::Filter(Filter { field: field.to_string(), operator, value })
+    }
+
+    pub fn and(self, other: FilterExpr) -> Self {
+        FilterExpr::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: FilterExpr) -> Self {
+        FilterExpr::Or(Box::new(self), Box::new(other))
+    }
+
+    // Parses a filter expression. Comparisons are `field op literal`, where a
+    // field may be a dotted path and `op` is one of `=`, `!=`, `<>`, `>`, `<`,
+    // `>=`, `<=`, `CONTAINS`, `STARTSWITH` or `ENDSWITH`; `field IN (a, b)`,
+    // `field IS NULL` and `field IS NOT NULL` work too. Literals are integers,
+    // floats, quoted strings (single or double, with `\` escapes), `true`,
+    // `false` and `null`. `NOT` binds tighter than `AND`, which binds tighter
+    // than `OR`; parentheses group. Keywords are case-insensitive. Errors are
+    // `DbError::Query` naming the byte offset where parsing failed.
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0, end: input.len() };
+        let expr = parser.or_expr()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((token, at)) => Err(parse_error(&format!("unexpected {}", token.describe()), *at)),
+        }
+    }
+
+    // Whether a row passes, given whether it passes each leaf filter
+    pub fn matches(&self, leaf: &impl Fn(&Filter) -> bool) -> bool {
+        match self {
+            FilterExpr::Filter(filter) => leaf(filter),
+            FilterExpr::And(a, b) => a.matches(leaf) && b.matches(leaf),
+            FilterExpr::Or(a, b) => a.matches(leaf) || b.matches(leaf),
+            FilterExpr::Not(expr) => !expr.matches(leaf),
+        }
+    }
+
+    // Every leaf filter, left to right
+    pub fn filters(&self) -> Vec<&Filter> {
+        match self {
+            FilterExpr::Filter(filter) => vec![filter],
+            FilterExpr::And(a, b) | FilterExpr::Or(a, b) => {
+                let mut filters = a.filters();
+                filters.extend(b.filters());
+                filters
+            }
+            FilterExpr::Not(expr) => expr.filters(),
+        }
+    }
+}
+
+impl std::ops::Not for FilterExpr {
+    type Output = FilterExpr;
+
+    fn not(self) -> FilterExpr {
+        FilterExpr::Not(Box::new(self))
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = DbError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Self::parse(input)
+    }
+}
+
+fn parse_error(message: &str, at: usize) -> DbError {
+    DbError::Query(format!("{} at position {}", message, at))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // Field names and keywords; keywords are told apart by the parser
+    Word(String),
+    Literal(Value),
+    Symbol(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("'{}'", word),
+            Token::Literal(value) => format!("literal {:?}", value),
+            Token::Symbol(symbol) => format!("'{}'", symbol),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+// Longest first, so `>=` isn't read as `>` then `=`
+const SYMBOLS: [&str; 11] = ["==", "!=", "<>", ">=", "<=", "=", ">", "<", "(", ")", ","];
+
+// Tokens paired with the byte offset each starts at
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => text.push(escaped),
+                        None => return Err(parse_error("unterminated string", at)),
+                    },
+                    Some((_, quote)) if quote == c => break,
+                    Some((_, other)) => text.push(other),
+                    None => return Err(parse_error("unterminated string", at)),
+                }
+            }
+            tokens.push((Token::Literal(Value::String(text)), at));
+        } else if c.is_ascii_digit() || (c == '-' && input[at + 1..].starts_with(|next: char| next.is_ascii_digit())) {
+            let mut end = at + c.len_utf8();
+            chars.next();
+            while let Some(&(i, next)) = chars.peek() {
+                if !(next.is_ascii_digit() || next == '.') {
+                    break;
+                }
+                end = i + next.len_utf8();
+                chars.next();
+            }
+            let text = &input[at..end];
+            let value = match text.parse::<i64>() {
+                Ok(int) => Value::Int(int),
+                Err(_) => Value::Float(text.parse().map_err(|_| parse_error(&format!("invalid number {}", text), at))?),
+            };
+            tokens.push((Token::Literal(value), at));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = at;
+            while let Some(&(i, next)) = chars.peek() {
+                if !(next.is_alphanumeric() || next == '_' || next == '.') {
+                    break;
+                }
+                end = i + next.len_utf8();
+                chars.next();
+            }
+            let word = &input[at..end];
+            let token = match word.to_ascii_lowercase().as_str() {
+                "true" => Token::Literal(Value::Bool(true)),
+                "false" => Token::Literal(Value::Bool(false)),
+                "null" => Token::Literal(Value::Null),
+                _ => Token::Word(word.to_string()),
+            };
+            tokens.push((token, at));
+        } else {
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| input[at..].starts_with(**symbol)) else {
+                return Err(parse_error(&format!("unexpected character '{}'", c), at));
+            };
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((Token::Symbol(symbol), at));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    // Reported as the position of errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(Token, usize)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(Token, usize)> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| parse_error(&format!("expected {}, found the end of the input", expected), self.end))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().is_some_and(|(token, _)| token.is_keyword(keyword));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some((Token::Symbol(s), _)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<()> {
+        let (token, at) = self.next(&format!("'{}'", symbol))?;
+        if token != Token::Symbol(symbol) {
+            return Err(parse_error(&format!("expected '{}', found {}", symbol, token.describe()), at));
+        }
+        Ok(())
+    }
+
+    fn or_expr(&mut self) -> Result<FilterExpr> {
+        let mut expr = self.and_expr()?;
+        while self.eat_keyword("OR") {
+            expr = expr.or(self.and_expr()?);
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<FilterExpr> {
+        let mut expr = self.not_expr()?;
+        while self.eat_keyword("AND") {
+            expr = expr.and(self.not_expr()?);
+        }
+        Ok(expr)
+    }
+
+    fn not_expr(&mut self) -> Result<FilterExpr> {
+        if self.eat_keyword("NOT") {
+            return Ok(!self.not_expr()?);
+        }
+        if self.eat_symbol("(") {
+            let expr = self.or_expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<FilterExpr> {
+        let (token, at) = self.next("a field name")?;
+        let field = match token {
+            Token::Word(word) if !["AND", "OR", "NOT"].iter().any(|k| word.eq_ignore_ascii_case(k)) => word,
+            other => return Err(parse_error(&format!("expected a field name, found {}", other.describe()), at)),
+        };
+
+        if self.eat_keyword("IS") {
+            let operator = if self.eat_keyword("NOT") { Operator::IsNotNull } else { Operator::IsNull };
+            let (token, at) = self.next("NULL")?;
+            if token != Token::Literal(Value::Null) {
+                return Err(parse_error(&format!("expected NULL, found {}", token.describe()), at));
+            }
+            return Ok(FilterExpr::filter(&field, operator, Value::Null));
+        }
+        if self.eat_keyword("IN") {
+            self.expect_symbol("(")?;
+            let mut values = vec![self.literal()?];
+            while self.eat_symbol(",") {
+                values.push(self.literal()?);
+            }
+            self.expect_symbol(")")?;
+            return Ok(FilterExpr::filter(&field, Operator::In, Value::List(values)));
+        }
+
+        let (token, at) = self.next("an operator")?;
+        let operator = match &token {
+            Token::Symbol("=") | Token::Symbol("==") => Operator::Eq,
+            Token::Symbol("!=") | Token::Symbol("<>") => Operator::Ne,
+            Token::Symbol(">") => Operator::Gt,
+            Token::Symbol("<") => Operator::Lt,
+            Token::Symbol(">=") => Operator::Gte,
+            Token::Symbol("<=") => Operator::Lte,
+            word if word.is_keyword("CONTAINS") => Operator::Contains,
+            word if word.is_keyword("STARTSWITH") => Operator::StartsWith,
+            word if word.is_keyword("ENDSWITH") => Operator::EndsWith,
+            other => return Err(parse_error(&format!("expected an operator, found {}", other.describe()), at)),
+        };
+        Ok(FilterExpr::filter(&field, operator, self.literal()?))
+    }
+
+    fn literal(&mut self) -> Result<Value> {
+        match self.next("a value")? {
+            (Token::Literal(value), _) => Ok(value),
+            (other, at) => Err(parse_error(&format!("expected a value, found {}", other.describe()), at)),
+        }
+    }
+}
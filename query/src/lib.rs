@@ -1,9 +1,26 @@
-use rust_db_core::{DbError, Database, Result, Schema, Filter, Operator, Value, FieldAccess};
+use rust_db_core::{DbError, Database, Result, Schema, Filter, Operator, Value, FieldAccess, KeyBuilder, KeyComponent};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::sync::Arc;
+pub use tokio_util::sync::CancellationToken;
 
 mod transaction;
 pub use transaction::{TransactionalQueryBuilder, TransactionalQueryExt};
 
+mod cache;
+pub use cache::{CachingDatabase, QueryCache};
+use cache::QuerySignature;
+
+mod aggregate;
+pub use aggregate::{AggFn, GroupedQuery};
+
+mod expr;
+pub use expr::FilterExpr;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 pub struct QueryEngine<D> {
     db: D,
 }
@@ -18,10 +35,51 @@ impl<D: Database> QueryEngine<D> {
     }
 }
 
+// Records fetched per batch by a cancellable query that didn't set `batch_size`
+const CANCELLABLE_BATCH_SIZE: usize = 1024;
+
+// Custom ordering for a field's values, e.g. case-folded or version-aware strings
+pub type CollationFn = Arc<dyn Fn(&Value, &Value) -> Ordering + Send + Sync>;
+
+// Computes a virtual field's value from a row
+pub type VirtualFieldFn<T> = Arc<dyn Fn(&T) -> Value + Send + Sync>;
+
+struct OrderBy {
+    field: String,
+    descending: bool,
+}
+
+// How `execute_with_plan` answered a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryPlan {
+    // Records were fetched in the order of a BTree index on the `order_by` field
+    pub ordered_by_index: bool,
+    // The matching records were sorted after they were read
+    pub sorted_in_memory: bool,
+    // Only the records an index lists for an `In` filter's values were read
+    pub index_lookup: bool,
+}
+
 pub struct QueryBuilder<'a, T, D> {
     db: &'a D,
     filters: Vec<Filter>,
+    // Indexes into `filters`, cheapest and most selective first unless
+    // `preserve_filter_order` was called
+    filter_order: Vec<usize>,
+    reorder_filters: bool,
+    // Boolean expressions that must also hold, checked after `filters`
+    exprs: Vec<FilterExpr>,
     limit: Option<usize>,
+    order_by: Option<OrderBy>,
+    // Per-field overrides of `Value::sort_cmp`, used for ordering and range filters
+    collations: HashMap<String, CollationFn>,
+    // Read-only fields computed from each row, shadowing stored fields of the same name
+    virtual_fields: HashMap<String, VirtualFieldFn<T>>,
+    // Encoded `[start, end)` record keys, scanned instead of the whole table
+    key_range: Option<(Vec<u8>, Vec<u8>)>,
+    // Records fetched per `scan_range_limited` call, instead of one big scan
+    batch_size: Option<usize>,
+    cancellation: Option<CancellationToken>,
     _phantom: PhantomData<T>,
 }
 
@@ -34,7 +92,16 @@ where
         Self {
             db,
             filters: Vec::new(),
+            filter_order: Vec::new(),
+            reorder_filters: true,
+            exprs: Vec::new(),
             limit: None,
+            order_by: None,
+            collations: HashMap::new(),
+            virtual_fields: HashMap::new(),
+            key_range: None,
+            batch_size: None,
+            cancellation: None,
             _phantom: PhantomData,
         }
     }
@@ -45,6 +112,31 @@ where
             operator,
             value,
         });
+        self.plan_filter_order();
+        self
+    }
+    
+    // Keeps rows matching `expr`, ANDed with the other filters. Parse one from
+    // a string with `FilterExpr::parse`.
+    pub fn filter_expr(mut self, expr: FilterExpr) -> Self {
+        self.exprs.push(expr);
+        self
+    }
+    
+    // Keeps rows where `field` is `Value::Null` or missing
+    pub fn filter_null(self, field: &str) -> Self {
+        self.filter(field, Operator::IsNull, Value::Null)
+    }
+    
+    // Keeps rows where `field` is present and not `Value::Null`
+    pub fn filter_not_null(self, field: &str) -> Self {
+        self.filter(field, Operator::IsNotNull, Value::Null)
+    }
+    
+    // Evaluates filters in the order they were added, rather than cheapest first
+    pub fn preserve_filter_order(mut self) -> Self {
+        self.reorder_filters = false;
+        self.plan_filter_order();
         self
     }
     
@@ -53,95 +145,593 @@ where
         self
     }
     
+    pub fn order_by(mut self, field: &str) -> Self {
+        self.order_by = Some(OrderBy { field: field.to_string(), descending: false });
+        self
+    }
+    
+    pub fn order_by_desc(mut self, field: &str) -> Self {
+        self.order_by = Some(OrderBy { field: field.to_string(), descending: true });
+        self
+    }
+    
+    // Compares `field` with `collation` instead of the default `Value` ordering,
+    // for both `order_by` and the Gt/Lt/Gte/Lte filters. Applies to this query only.
+    pub fn collate<F>(mut self, field: &str, collation: F) -> Self
+    where
+        F: Fn(&Value, &Value) -> Ordering + Send + Sync + 'static,
+    {
+        self.collations.insert(field.to_string(), Arc::new(collation));
+        self.plan_filter_order();
+        self
+    }
+    
+    // Makes `name` queryable as if it were a stored field, with `compute`
+    // working out its value for each row as the query reads it. Filters,
+    // `order_by`, `select` and `group_by` all see it; indexes never cover it.
+    // Applies to this query only.
+    pub fn virtual_field<F>(mut self, name: &str, compute: F) -> Self
+    where
+        F: Fn(&T) -> Value + Send + Sync + 'static,
+    {
+        self.virtual_fields.insert(name.to_string(), Arc::new(compute));
+        self
+    }
+    
+    // A row's value for `field`, computed if it's a virtual field
+    fn field_value(&self, item: &T, field: &str) -> Option<Value> {
+        match self.virtual_fields.get(field) {
+            Some(compute) => Some(compute(item)),
+            None => item.get_path(field),
+        }
+    }
+    
+    // Restricts the scan to rows whose primary key is in [start, end), using the
+    // `Schema::key` encoding under the `{table}:` prefix. Assumes a single-component
+    // key, or bounds on its leading component.
+    pub fn key_range<K: KeyComponent + ?Sized>(mut self, start: &K, end: &K) -> Self {
+        let bound = |component: &K| {
+            let mut key = format!("{}:", T::table_name()).into_bytes();
+            key.extend(KeyBuilder::new().push(component).finish());
+            key
+        };
+        self.key_range = Some((bound(start), bound(end)));
+        self
+    }
+    
+    // Reads the table `batch_size` records at a time rather than in one scan, so
+    // a filtered scan of a large table doesn't hold all of it in memory at once
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size.max(1));
+        self
+    }
+    
+    // Stops the query with `DbError::Query("cancelled")` once `token` is
+    // cancelled. The table is then read in batches, checked before each one, so
+    // a scan stops within a batch of the cancellation; no locks are held between
+    // batches. Dropping the `execute` future also stops it at the next batch.
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+    
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(DbError::Query("cancelled".to_string()));
+        }
+        Ok(())
+    }
+    
+    // Rejects filters whose operator can't apply to their value, e.g. `Contains`
+    // with an Int. Range filters on a collated field take any value.
+    pub fn validate(&self) -> Result<()> {
+        for filter in self.filters.iter().chain(self.exprs.iter().flat_map(FilterExpr::filters)) {
+            let collated = self.collations.contains_key(&filter.field)
+                && matches!(filter.operator, Operator::Gt | Operator::Lt | Operator::Gte | Operator::Lte);
+            if !collated && !filter.operator.applies_to(&filter.value) {
+                return Err(DbError::Query(format!(
+                    "{:?} can't be applied to {:?} in the filter on {}",
+                    filter.operator, filter.value, filter.field
+                )));
+            }
+        }
+        Ok(())
+    }
+    
     pub async fn execute(self) -> Result<Vec<T>> {
-        let table_name = T::table_name();
-        let prefix = table_name.as_bytes();
+        self.fetch().await
+    }
+
+    // The only matching row; an error if there are none or more than one
+    pub async fn one(self) -> Result<T> {
+        self.optional()
+            .await?
+            .ok_or_else(|| DbError::Query(format!("expected one {} row, found none", T::table_name())))
+    }
+
+    // The matching row if there's one; an error if more than one matches
+    pub async fn optional(mut self) -> Result<Option<T>> {
+        // A second match is all it takes to know there's more than one
+        self.limit = Some(self.limit.map_or(2, |limit| limit.min(2)));
+        let mut results = self.fetch().await?;
+        if results.len() > 1 {
+            return Err(DbError::Query(format!("expected at most one {} row, found several", T::table_name())));
+        }
+        Ok(results.pop())
+    }
+
+    async fn fetch(&self) -> Result<Vec<T>> {
+        Ok(self.fetch_with_plan().await?.0)
+    }
+    
+    // Every way of running the query goes through here, so `execute`, `one`,
+    // `select` and `group_by` all read through an index when one applies. A
+    // limited query ordered on a field with a BTree index reads records in
+    // index order and stops at the limit, instead of reading and sorting the
+    // whole table. An `Eq` or `In` filter on an indexed field reads just the
+    // records the index lists. Anything else scans the table.
+    async fn fetch_with_plan(&self) -> Result<(Vec<T>, QueryPlan)> {
+        self.validate()?;
+        let sorted_in_memory = self.order_by.is_some() && self.limit != Some(0);
+        let plan = QueryPlan { ordered_by_index: false, sorted_in_memory, index_lookup: false };
+        // Nothing can be returned, so skip the scan entirely
+        if self.limit == Some(0) {
+            return Ok((Vec::new(), plan));
+        }
+        self.check_cancelled()?;
+        if let Some(results) = self.execute_in_index_order().await? {
+            let plan = QueryPlan { ordered_by_index: true, sorted_in_memory: false, index_lookup: false };
+            return Ok((results, plan));
+        }
+        if let Some(results) = self.execute_from_index_lookup().await? {
+            return Ok((results, QueryPlan { index_lookup: true, ..plan }));
+        }
+        Ok((self.scan_table().await?, plan))
+    }
+    
+    async fn scan_table(&self) -> Result<Vec<T>> {
+        let batch_size = self.batch_size.or(self.cancellation.as_ref().map(|_| CANCELLABLE_BATCH_SIZE));
+        if let Some(batch_size) = batch_size {
+            return self.execute_batched(batch_size).await;
+        }
         
-        // Scan all records for this table
-        let records = self.db.scan(prefix).await?;
+        let records = match &self.key_range {
+            Some((start, end)) => self.db.scan_range(start, end).await?,
+            // Scan all records for this table
+            None => self.db.scan(T::table_name().as_bytes()).await?,
+        };
         
-        let mut results = Vec::new();
+        // Without filters every live record is a result, so size for that up front
+        let mut results = if self.filters.is_empty() && self.exprs.is_empty() {
+            Vec::with_capacity(self.limit.map_or(records.len(), |limit| limit.min(records.len())))
+        } else {
+            Vec::new()
+        };
+        self.collect_matching(records, &mut results)?;
+        Ok(self.finish(results))
+    }
+    
+    async fn execute_batched(&self, batch_size: usize) -> Result<Vec<T>> {
+        let (mut start, end) = match &self.key_range {
+            Some((start, end)) => (start.clone(), end.clone()),
+            None => {
+                // Everything under the table-name prefix. Names are UTF-8, which
+                // never has a 0xff byte, so the last byte can always be bumped.
+                let start = T::table_name().as_bytes().to_vec();
+                let mut end = start.clone();
+                *end.last_mut().unwrap() += 1;
+                (start, end)
+            }
+        };
         
-        for (_key, value) in records {
-            // Skip tombstones (empty values used for deletion)
-            if value.is_empty() {
-                continue;
+        let mut results = Vec::new();
+        loop {
+            self.check_cancelled()?;
+            let batch = self.db.scan_range_limited(&start, &end, batch_size).await?;
+            let exhausted = batch.len() < batch_size;
+            // The next batch starts just past this one's last key
+            if let Some((last, _)) = batch.last() {
+                start = last.clone();
+                start.push(0);
+            }
+            if self.collect_matching(batch, &mut results)? || exhausted {
+                break;
             }
-            
+            // Lets whoever cancels us run, even on a single-threaded runtime
+            tokio::task::yield_now().await;
+        }
+        Ok(self.finish(results))
+    }
+    
+    // Adds the records that pass the filters to `results`, returning true once
+    // the limit is reached and no more records are needed
+    fn collect_matching(&self, records: Vec<(Vec<u8>, Vec<u8>)>, results: &mut Vec<T>) -> Result<bool> {
+        for (_key, value) in records {
             let item: T = bincode::deserialize(&value)
-                .map_err(|e| DbError::Serialization(e.to_string()))?;
+                .map_err(DbError::serialization)?;
                 
             // Apply filters
             if self.apply_filters(&item) {
                 results.push(item);
                 
-                // Apply limit
-                if let Some(limit) = self.limit {
+                // Apply limit; with an ordering it has to wait until after the sort
+                if let (Some(limit), None) = (self.limit, &self.order_by) {
                     if results.len() >= limit {
-                        break;
+                        return Ok(true);
                     }
                 }
             }
         }
+        Ok(false)
+    }
+    
+    fn finish(&self, mut results: Vec<T>) -> Vec<T> {
+        if let Some(order) = &self.order_by {
+            self.sort(&mut results, order);
+            if let Some(limit) = self.limit {
+                results.truncate(limit);
+            }
+        }
+        results
+    }
+    
+    // Projects `fields` out of the matching rows, in the order given; a field a
+    // row lacks comes back as `Value::Null`
+    pub fn select(self, fields: &[&str]) -> SelectQuery<'a, T, D> {
+        SelectQuery {
+            query: self,
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+    
+    // Groups the matching rows by `field`; see `GroupedQuery::aggregate`
+    pub fn group_by(self, field: &str) -> GroupedQuery<'a, T, D> {
+        GroupedQuery {
+            query: self,
+            field: field.to_string(),
+        }
+    }
+    
+    // Like `execute`, but answers from `cache` when the same query ran recently.
+    // Queries with collations or virtual fields always run, since closures
+    // can't be compared.
+    pub async fn execute_cached(self, cache: &QueryCache) -> Result<Vec<T>>
+    where
+        T: Clone + 'static,
+    {
+        if !self.collations.is_empty() || !self.virtual_fields.is_empty() {
+            return self.execute().await;
+        }
+        let signature = self.signature();
+        if let Some(results) = cache.get::<T>(&signature) {
+            return Ok(results);
+        }
         
+        let generation = cache.generation();
+        let results = self.execute().await?;
+        cache.insert(signature, generation, &results);
         Ok(results)
     }
     
+    fn signature(&self) -> QuerySignature {
+        let mut filters: Vec<String> = self
+            .filters
+            .iter()
+            .map(|f| format!("{}:{:?}:{:?}", f.field, f.operator, f.value))
+            .collect();
+        filters.sort();
+        let order = self.order_by.as_ref().map(|o| (&o.field, o.descending));
+        QuerySignature {
+            table: T::table_name().to_string(),
+            shape: format!("{:?}|{:?}|{:?}|{:?}|{:?}", filters, self.exprs, self.limit, order, self.key_range),
+        }
+    }
+    
+    fn compare(&self, field: &str, a: &Value, b: &Value) -> Ordering {
+        match self.collations.get(field) {
+            Some(collation) => collation(a, b),
+            None => a.sort_cmp(b),
+        }
+    }
+    
+    // Stable sort on the ordering field; rows missing the field sort last
+    fn sort(&self, results: &mut [T], order: &OrderBy) {
+        results.sort_by(|a, b| {
+            match (self.field_value(a, &order.field), self.field_value(b, &order.field)) {
+                (Some(a), Some(b)) => {
+                    let ordering = self.compare(&order.field, &a, &b);
+                    if order.descending { ordering.reverse() } else { ordering }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        });
+    }
+    
+    // Every filter must pass, so the order only changes how soon a row is
+    // rejected: equality first, then ranges, then `Ne`, then string matching.
+    // Ties keep the order the filters were added in.
+    fn plan_filter_order(&mut self) {
+        self.filter_order = (0..self.filters.len()).collect();
+        if self.reorder_filters {
+            let costs: Vec<u8> = self.filters.iter().map(|filter| self.filter_cost(filter)).collect();
+            self.filter_order.sort_by_key(|&i| costs[i]);
+        }
+    }
+    
+    fn filter_cost(&self, filter: &Filter) -> u8 {
+        match filter.operator {
+            Operator::Eq | Operator::In | Operator::IsNull | Operator::IsNotNull => 0,
+            // A collation is arbitrary user code, so it ranks with string matching
+            Operator::Gt | Operator::Lt | Operator::Gte | Operator::Lte => {
+                if self.collations.contains_key(&filter.field) { 3 } else { 1 }
+            }
+            Operator::Ne => 2,
+            Operator::StartsWith | Operator::EndsWith => 3,
+            Operator::Contains => 4,
+        }
+    }
+    
     fn apply_filters(&self, item: &T) -> bool {
         // Check all filters - item must pass ALL filters (AND logic)
-        for filter in &self.filters {
-            // Get the field value from the item
-            let field_value = match item.get_field(&filter.field) {
-                Some(val) => val,
-                None => return false, // Field doesn't exist
+        self.filter_order.iter().all(|&i| self.leaf_matches(item, &self.filters[i]))
+            && self.exprs.iter().all(|expr| expr.matches(&|filter| self.leaf_matches(item, filter)))
+    }
+    
+    fn leaf_matches(&self, item: &T, filter: &Filter) -> bool {
+        // Get the field value from the item
+        match self.field_value(item, &filter.field) {
+            Some(field_value) => self.filter_matches(filter, &field_value),
+            // Field doesn't exist, which only a null check lets through
+            None => matches!(filter.operator, Operator::IsNull),
+        }
+    }
+    
+    fn filter_matches(&self, filter: &Filter, field_value: &Value) -> bool {
+        // Range filters on a collated field use the collation instead
+        if let Some(collation) = self.collations.get(&filter.field) {
+            let ordering = collation(field_value, &filter.value);
+            let in_range = match &filter.operator {
+                Operator::Gt => Some(ordering == Ordering::Greater),
+                Operator::Lt => Some(ordering == Ordering::Less),
+                Operator::Gte => Some(ordering != Ordering::Less),
+                Operator::Lte => Some(ordering != Ordering::Greater),
+                _ => None,
             };
-            
-            // Apply the operator
-            let matches = match &filter.operator {
-                Operator::Eq => field_value == filter.value,
-                Operator::Ne => field_value != filter.value,
-                Operator::Gt => match (&field_value, &filter.value) {
-                    (Value::Int(a), Value::Int(b)) => a > b,
-                    (Value::Float(a), Value::Float(b)) => a > b,
-                    _ => false,
-                },
-                Operator::Lt => match (&field_value, &filter.value) {
-                    (Value::Int(a), Value::Int(b)) => a < b,
-                    (Value::Float(a), Value::Float(b)) => a < b,
-                    _ => false,
-                },
-                Operator::Gte => match (&field_value, &filter.value) {
-                    (Value::Int(a), Value::Int(b)) => a >= b,
-                    (Value::Float(a), Value::Float(b)) => a >= b,
-                    _ => false,
-                },
-                Operator::Lte => match (&field_value, &filter.value) {
-                    (Value::Int(a), Value::Int(b)) => a <= b,
-                    (Value::Float(a), Value::Float(b)) => a <= b,
-                    _ => false,
-                },
-                Operator::Contains => match (&field_value, &filter.value) {
-                    (Value::String(a), Value::String(b)) => a.contains(b),
-                    _ => false,
-                },
-                Operator::StartsWith => match (&field_value, &filter.value) {
-                    (Value::String(a), Value::String(b)) => a.starts_with(b),
-                    _ => false,
-                },
-                Operator::EndsWith => match (&field_value, &filter.value) {
-                    (Value::String(a), Value::String(b)) => a.ends_with(b),
-                    _ => false,
-                },
+            if let Some(in_range) = in_range {
+                return in_range;
+            }
+        }
+        
+        // Apply the operator
+        match &filter.operator {
+            Operator::Eq => *field_value == filter.value,
+            Operator::Ne => *field_value != filter.value,
+            Operator::Gt => match (field_value, &filter.value) {
+                (Value::Int(a), Value::Int(b)) => a > b,
+                (Value::Float(a), Value::Float(b)) => a > b,
+                _ => false,
+            },
+            Operator::Lt => match (field_value, &filter.value) {
+                (Value::Int(a), Value::Int(b)) => a < b,
+                (Value::Float(a), Value::Float(b)) => a < b,
+                _ => false,
+            },
+            Operator::Gte => match (field_value, &filter.value) {
+                (Value::Int(a), Value::Int(b)) => a >= b,
+                (Value::Float(a), Value::Float(b)) => a >= b,
+                _ => false,
+            },
+            Operator::Lte => match (field_value, &filter.value) {
+                (Value::Int(a), Value::Int(b)) => a <= b,
+                (Value::Float(a), Value::Float(b)) => a <= b,
+                _ => false,
+            },
+            Operator::Contains => match (field_value, &filter.value) {
+                (Value::String(a), Value::String(b)) => a.contains(b),
+                _ => false,
+            },
+            Operator::StartsWith => match (field_value, &filter.value) {
+                (Value::String(a), Value::String(b)) => a.starts_with(b),
+                _ => false,
+            },
+            Operator::EndsWith => match (field_value, &filter.value) {
+                (Value::String(a), Value::String(b)) => a.ends_with(b),
+                _ => false,
+            },
+            Operator::In => match &filter.value {
+                Value::List(values) => values.contains(field_value),
+                _ => false,
+            },
+            Operator::IsNull => *field_value == Value::Null,
+            Operator::IsNotNull => *field_value != Value::Null,
+        }
+    }
+}
+
+impl<'a, T, D> QueryBuilder<'a, T, D>
+where
+    T: Schema + serde::de::DeserializeOwned + Send + Sync + FieldAccess,
+    D: Database,
+{
+    // Like `execute`, but also reports how the query ran; see `fetch_with_plan`
+    pub async fn execute_with_plan(self) -> Result<(Vec<T>, QueryPlan)> {
+        self.fetch_with_plan().await
+    }
+    
+    // None unless an `In` filter's field has an index to look its values up in
+    async fn execute_from_index_lookup(&self) -> Result<Option<Vec<T>>> {
+        if self.key_range.is_some() || self.limit == Some(0) {
+            return Ok(None);
+        }
+        let mut record_keys = None;
+        for filter in self.filters.iter().filter(|f| !self.virtual_fields.contains_key(&f.field)) {
+            let values = match (&filter.operator, &filter.value) {
+                (Operator::In, Value::List(values)) => values.as_slice(),
+                (Operator::Eq, value) => std::slice::from_ref(value),
+                _ => continue,
+            };
+            record_keys = self.db.lookup_index_keys(T::table_name(), &filter.field, values).await?;
+            if record_keys.is_some() {
+                break;
+            }
+        }
+        let Some(mut record_keys) = record_keys else {
+            return Ok(None);
+        };
+        
+        // Key order, as a scan would return them; the filters are rechecked
+        // against each record since a hash index entry may be a collision
+        record_keys.sort();
+        let mut results = Vec::new();
+        for record_key in record_keys {
+            let Some(item) = self.db.get::<T>(&record_key).await? else {
+                continue;
+            };
+            if self.apply_filters(&item) {
+                results.push(item);
+                if self.order_by.is_none() && self.limit == Some(results.len()) {
+                    break;
+                }
+            }
+        }
+        Ok(Some(self.finish(results)))
+    }
+    
+    // None when the index can't produce the whole result. Without a limit every
+    // record is read anyway, so scanning and sorting is no worse. Index entries
+    // sort by `Value::sort_cmp`, so a collation on the field leaves the query
+    // to the scan too. Records lacking the field have no index entry and sort
+    // last, so an index that runs out before the limit also does.
+    async fn execute_in_index_order(&self) -> Result<Option<Vec<T>>> {
+        let (Some(order), Some(limit)) = (&self.order_by, self.limit) else {
+            return Ok(None);
+        };
+        if self.key_range.is_some()
+            || limit == 0
+            || self.virtual_fields.contains_key(&order.field)
+            || self.collations.contains_key(&order.field)
+        {
+            return Ok(None);
+        }
+        
+        let mut results = Vec::with_capacity(limit);
+        if order.descending {
+            // Indexes are only read forwards, so the whole index is walked from the end
+            let Some(mut entries) = self.db.scan_covering_index(T::table_name(), &order.field).await? else {
+                return Ok(None);
             };
-            
-            // If any filter fails, reject the item
-            if !matches {
-                return false;
+            entries.reverse();
+            // Equal values keep record-key order, as in `sort`
+            for run in entries.chunk_by_mut(|(a, _), (b, _)| a.sort_cmp(b).is_eq()) {
+                run.reverse();
+            }
+            for (value, record_key) in entries {
+                if self.collect_indexed(&order.field, &value, &record_key, limit, &mut results).await? {
+                    return Ok(Some(results));
+                }
             }
+            return Ok(None);
         }
         
-        // All filters passed
-        true
+        // Pages of `limit` entries, so unless records are filtered out or
+        // entries are stale, the first page is the only one read
+        let mut after: Option<(Value, Vec<u8>)> = None;
+        loop {
+            self.check_cancelled()?;
+            let after_entry = after.as_ref().map(|(value, record_key)| (value, record_key.as_slice()));
+            let Some(page) = self.db.scan_covering_index_after(T::table_name(), &order.field, after_entry, limit).await? else {
+                return Ok(None);
+            };
+            let exhausted = page.len() < limit;
+            after = page.last().cloned();
+            for (value, record_key) in page {
+                if self.collect_indexed(&order.field, &value, &record_key, limit, &mut results).await? {
+                    return Ok(Some(results));
+                }
+            }
+            if exhausted {
+                return Ok(None);
+            }
+        }
+    }
+    
+    // Adds the record an index entry points at to `results` if it passes the
+    // filters, returning whether `results` now holds `limit` records. Entries
+    // written around `insert` and commits may be stale, so the record is read
+    // back and skipped unless it still holds the entry's value.
+    async fn collect_indexed(&self, field: &str, value: &Value, record_key: &[u8], limit: usize, results: &mut Vec<T>) -> Result<bool> {
+        let Some(item) = self.db.get::<T>(record_key).await? else {
+            return Ok(false);
+        };
+        if item.get_path(field).as_ref() != Some(value) || !self.apply_filters(&item) {
+            return Ok(false);
+        }
+        results.push(item);
+        Ok(results.len() == limit)
+    }
+}
+
+pub struct SelectQuery<'a, T, D> {
+    query: QueryBuilder<'a, T, D>,
+    fields: Vec<String>,
+}
+
+impl<'a, T, D> SelectQuery<'a, T, D>
+where
+    T: Schema + serde::de::DeserializeOwned + Send + Sync + FieldAccess,
+    D: Database,
+{
+    pub async fn execute(self) -> Result<Vec<Vec<Value>>> {
+        self.query.validate()?;
+        if let Some(rows) = self.execute_from_index().await? {
+            return Ok(rows);
+        }
+        let rows = self.query.fetch().await?;
+        Ok(rows
+            .iter()
+            .map(|row| self.fields.iter().map(|f| self.query.field_value(row, f).unwrap_or(Value::Null)).collect())
+            .collect())
+    }
+    
+    // When the one selected field is also the only one filtered and ordered on,
+    // and an index covers it, the values come from the index without reading
+    // any records. Writes replace a record's entries in the same batch as the
+    // record, so the index holds exactly the current values. None means the
+    // query has to run against the records.
+    async fn execute_from_index(&self) -> Result<Option<Vec<Vec<Value>>>> {
+        let query = &self.query;
+        let [field] = self.fields.as_slice() else {
+            return Ok(None);
+        };
+        let covered = query.key_range.is_none()
+            && !query.virtual_fields.contains_key(field)
+            && query.filters.iter().all(|f| &f.field == field)
+            && query.exprs.is_empty()
+            && query.order_by.as_ref().is_none_or(|o| &o.field == field);
+        if !covered {
+            return Ok(None);
+        }
+        let Some(entries) = query.db.scan_covering_index(T::table_name(), field).await? else {
+            return Ok(None);
+        };
+        
+        let mut values: Vec<Value> = entries
+            .into_iter()
+            .map(|(value, _)| value)
+            .filter(|value| query.filters.iter().all(|f| query.filter_matches(f, value)))
+            .collect();
+        if let Some(order) = &query.order_by {
+            values.sort_by(|a, b| {
+                let ordering = query.compare(field, a, b);
+                if order.descending { ordering.reverse() } else { ordering }
+            });
+        }
+        if let Some(limit) = query.limit {
+            values.truncate(limit);
+        }
+        Ok(Some(values.into_iter().map(|value| vec![value]).collect()))
     }
 }
 
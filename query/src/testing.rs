@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use rust_db_core::{
+    Database, DbError, MvccDatabase, Result, Transaction, TransactionState, VersionTimestamp,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+// Every version of a key, oldest first, stamped with the tick that wrote it.
+// None is a delete; an empty value is a value like any other.
+type Versions = Vec<(Option<Vec<u8>>, VersionTimestamp)>;
+
+// A `Database` and `MvccDatabase` over a `BTreeMap`, for testing query and
+// transaction logic without the LSM engine or a disk. Scans are in key order,
+// a transaction reads the snapshot it began with plus its own writes, and a
+// commit fails with `TransactionConflict` if any key it wrote changed after its
+// snapshot. Old versions are kept forever, and it has no secondary indexes.
+// Clones share the same data.
+#[derive(Clone, Default)]
+pub struct InMemoryDb {
+    data: Arc<RwLock<BTreeMap<Vec<u8>, Versions>>>,
+    // A logical clock, so two writes in the same microsecond still order
+    clock: Arc<AtomicU64>,
+}
+
+impl InMemoryDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tick(&self) -> VersionTimestamp {
+        VersionTimestamp::from_u64(self.clock.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn now(&self) -> VersionTimestamp {
+        VersionTimestamp::from_u64(self.clock.load(Ordering::SeqCst))
+    }
+
+    fn write(&self, key: &[u8], value: Option<Vec<u8>>) {
+        let mut data = self.data.write().unwrap_or_else(PoisonError::into_inner);
+        let ts = self.tick();
+        data.entry(key.to_vec()).or_default().push((value, ts));
+    }
+
+    // Live values in `[start, end)` (no upper bound if `end` is None) as of `as_of`
+    fn visible_range(&self, start: &[u8], end: Option<&[u8]>, as_of: VersionTimestamp) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let data = self.data.read().unwrap_or_else(PoisonError::into_inner);
+        data.range(start.to_vec()..)
+            .take_while(|(key, _)| end.is_none_or(|end| key.as_slice() < end))
+            .filter_map(|(key, versions)| Self::visible(versions, as_of).map(|value| (key.clone(), value.to_vec())))
+            .collect()
+    }
+
+    fn visible(versions: &Versions, as_of: VersionTimestamp) -> Option<&[u8]> {
+        versions
+            .iter()
+            .rev()
+            .find(|(_, ts)| *ts <= as_of)
+            .and_then(|(value, _)| value.as_deref())
+    }
+
+    fn scan_visible(&self, start: &[u8], end: Option<&[u8]>, transaction: &Transaction) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut merged = self.visible_range(start, end, transaction.snapshot_ts);
+        transaction.stats.record_scan(merged.len());
+        let in_bounds = |key: &[u8]| key >= start && end.is_none_or(|end| key < end);
+        for (key, write) in transaction.writes.iter().filter(|(key, _)| in_bounds(key)) {
+            match write {
+                Some(value) => {
+                    merged.insert(key.clone(), value.clone());
+                }
+                None => {
+                    merged.remove(key);
+                }
+            }
+        }
+        merged.into_iter().collect()
+    }
+}
+
+// The end of the key range starting with `prefix`, or None if every key past it does
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[async_trait]
+impl Database for InMemoryDb {
+    async fn insert<T: Serialize + Send + Sync>(&self, key: &[u8], value: &T) -> Result<()> {
+        let serialized = bincode::serialize(value).map_err(DbError::serialization)?;
+        self.write(key, Some(serialized));
+        Ok(())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>> {
+        let data = self.data.read().unwrap_or_else(PoisonError::into_inner);
+        match data.get(key).and_then(|versions| Self::visible(versions, self.now())) {
+            Some(value) => bincode::deserialize(value)
+                .map(Some)
+                .map_err(DbError::serialization),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write(key, None);
+        Ok(())
+    }
+
+    async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let end = prefix_end(prefix);
+        Ok(self.visible_range(prefix, end.as_deref(), self.now()).into_iter().collect())
+    }
+
+    async fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.visible_range(start, Some(end), self.now()).into_iter().collect())
+    }
+
+    async fn scan_range_limited(&self, start: &[u8], end: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let records = self.visible_range(start, Some(end), self.now());
+        Ok(records.into_iter().take(limit).collect())
+    }
+}
+
+#[async_trait]
+impl MvccDatabase for InMemoryDb {
+    async fn begin_transaction(&self) -> Result<Transaction> {
+        let mut transaction = Transaction::new();
+        transaction.snapshot_ts = self.now();
+        Ok(transaction)
+    }
+
+    async fn commit_transaction(&self, mut transaction: Transaction) -> Result<()> {
+        if !matches!(transaction.state, TransactionState::Active) {
+            return Err(DbError::Transaction("Transaction not active".to_string()));
+        }
+        if let Some(key) = transaction.writes.keys().find(|key| !transaction.in_scope(key)) {
+            return Err(DbError::Transaction(format!(
+                "Write to key {} is outside the transaction scope",
+                String::from_utf8_lossy(key)
+            )));
+        }
+
+        // Checked and applied under one lock, so commits can't interleave
+        let mut data = self.data.write().unwrap_or_else(PoisonError::into_inner);
+        for key in transaction.writes.keys() {
+            let latest = data.get(key).and_then(|versions| versions.last());
+            if latest.is_some_and(|(_, ts)| *ts > transaction.snapshot_ts) {
+                transaction.state = TransactionState::Aborted;
+                return Err(DbError::TransactionConflict(format!(
+                    "Key {} was modified by a transaction that committed after this snapshot",
+                    String::from_utf8_lossy(key)
+                )));
+            }
+        }
+        let commit_ts = self.tick();
+        for (key, write) in transaction.writes.drain() {
+            data.entry(key).or_default().push((write, commit_ts));
+        }
+        transaction.state = TransactionState::Committed;
+        Ok(())
+    }
+
+    async fn rollback_transaction(&self, mut transaction: Transaction) -> Result<()> {
+        transaction.state = TransactionState::Aborted;
+        Ok(())
+    }
+
+    async fn get_for_transaction<T: DeserializeOwned>(&self, key: &[u8], transaction: &Transaction) -> Result<Option<T>> {
+        transaction.stats.record_read();
+        let data = self.data.read().unwrap_or_else(PoisonError::into_inner);
+        let value = match transaction.writes.get(key) {
+            Some(write) => write.as_deref(),
+            None => data.get(key).and_then(|versions| Self::visible(versions, transaction.snapshot_ts)),
+        };
+        match value {
+            Some(value) => bincode::deserialize(value)
+                .map(Some)
+                .map_err(DbError::serialization),
+            None => Ok(None),
+        }
+    }
+
+    async fn scan_for_transaction(&self, prefix: &[u8], transaction: &Transaction) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let end = prefix_end(prefix);
+        Ok(self.scan_visible(prefix, end.as_deref(), transaction))
+    }
+
+    async fn scan_range_for_transaction(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        transaction: &Transaction,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.scan_visible(start, Some(end), transaction))
+    }
+}
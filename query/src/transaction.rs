@@ -37,12 +37,38 @@ where
         self
     }
     
+    // As `QueryBuilder::filter_null`
+    pub fn filter_null(self, field: &str) -> Self {
+        self.filter(field, FilterOperator::IsNull, Value::Null)
+    }
+    
+    // As `QueryBuilder::filter_not_null`
+    pub fn filter_not_null(self, field: &str) -> Self {
+        self.filter(field, FilterOperator::IsNotNull, Value::Null)
+    }
+    
     pub fn limit(mut self, limit: usize) -> Self {
         self.limit = Some(limit);
         self
     }
     
+    // As `QueryBuilder::validate`
+    pub fn validate(&self) -> Result<()> {
+        match self.filters.iter().find(|filter| !filter.operator.applies_to(&filter.value)) {
+            Some(filter) => Err(DbError::Query(format!(
+                "{:?} can't be applied to {:?} in the filter on {}",
+                filter.operator, filter.value, filter.field
+            ))),
+            None => Ok(()),
+        }
+    }
+    
     pub async fn execute(self) -> Result<Vec<T>> {
+        self.validate()?;
+        if self.limit == Some(0) {
+            return Ok(Vec::new());
+        }
+        
         let table_name = T::table_name();
         let prefix = table_name.as_bytes();
         
@@ -52,13 +78,8 @@ where
         let mut results = Vec::new();
         
         for (key, value) in records {
-            // Skip tombstones
-            if value.is_empty() {
-                continue;
-            }
-            
             let item: T = bincode::deserialize(&value)
-                .map_err(|e| DbError::Serialization(e.to_string()))?;
+                .map_err(DbError::serialization)?;
                 
             // Apply filters
             if self.apply_filters(&item) {
@@ -81,8 +102,10 @@ where
         // Check all filters - item must pass ALL filters (AND logic)
         for filter in &self.filters {
             // Get the field value from the item
-            let field_value = match item.get_field(&filter.field) {
+            let field_value = match item.get_path(&filter.field) {
                 Some(val) => val,
+                // A missing field counts as null
+                None if matches!(filter.operator, FilterOperator::IsNull) => continue,
                 None => return false, // Field doesn't exist
             };
 
@@ -122,6 +145,12 @@ where
                     (Value::String(a), Value::String(b)) => a.ends_with(b),
                     _ => false,
                 },
+                FilterOperator::In => match &filter.value {
+                    Value::List(values) => values.contains(&field_value),
+                    _ => false,
+                },
+                FilterOperator::IsNull => field_value == Value::Null,
+                FilterOperator::IsNotNull => field_value != Value::Null,
             };
 
             // If any filter fails, reject the item
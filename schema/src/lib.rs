@@ -1,13 +1,29 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
 use syn::{parse_macro_input, DeriveInput, Data, Fields};
 
-#[proc_macro_derive(Schema, attributes(index))]
+#[proc_macro_derive(Schema, attributes(index, key, skip, nested))]
 pub fn derive_schema(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let name = &input.ident;
 
-    let (field_checks, index_fields, field_accessors) = extract_fields(&input);
+    let (field_checks, index_fields, field_accessors, nested_accessors, key_fields) = extract_fields(&input);
+    let fingerprint = fingerprint(&input);
+
+    // Only emit `key()` when the struct declares key fields; otherwise the
+    // trait's default (an empty key) applies
+    let key_impl = if key_fields.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            fn key(&self) -> Vec<u8> {
+                rust_db_core::KeyBuilder::new()
+                    #(.push(&self.#key_fields))*
+                    .finish()
+            }
+        }
+    };
     
     // Only generate the impl blocks for Schema and FieldAccess
     // Don't generate the From impls here as they should be in the core crate
@@ -28,6 +44,12 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
                 #(#index_fields)*
                 indexes
             }
+
+            #key_impl
+
+            fn fingerprint() -> Option<&'static str> {
+                Some(#fingerprint)
+            }
         }
 
         // --- IMPL BLOCK 2: FieldAccess ---
@@ -38,21 +60,77 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
                     _ => None,
                 }
             }
+
+            fn nested(&self, field_name: &str) -> Option<&dyn rust_db_core::FieldAccess> {
+                match field_name {
+                    #(#nested_accessors)*
+                    _ => None,
+                }
+            }
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+// Maps each variant of an enum to a `Value::String` of its name, so a
+// `#[derive(Schema)]` struct can have a field of the enum's type
+#[proc_macro_derive(IntoValueEnum)]
+pub fn derive_into_value_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let Data::Enum(data) = &input.data else {
+        return syn::Error::new(input.span(), "IntoValueEnum can only be derived for enums")
+            .to_compile_error()
+            .into();
+    };
+    let arms = data.variants.iter().map(|variant| {
+        let variant_name = &variant.ident;
+        let variant_str = variant_name.to_string();
+        quote! { Self::#variant_name { .. } => #variant_str, }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics rust_db_core::IntoValueEnum for #name #ty_generics #where_clause {
+            fn variant_name(&self) -> &'static str {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        }
+
+        impl #impl_generics From<&#name #ty_generics> for rust_db_core::Value #where_clause {
+            fn from(value: &#name #ty_generics) -> Self {
+                rust_db_core::Value::String(rust_db_core::IntoValueEnum::variant_name(value).to_string())
+            }
         }
     };
     TokenStream::from(expanded)
 }
 
-fn extract_fields(
-    input: &DeriveInput,
-) -> (
-    Vec<proc_macro2::TokenStream>,
-    Vec<proc_macro2::TokenStream>,
-    Vec<proc_macro2::TokenStream>,
-) {
+// `name:Type` for every field, `#[skip]` ones included since they're still
+// serialized, e.g. `id:u64,tags:Vec<String>`
+fn fingerprint(input: &DeriveInput) -> String {
+    let mut parts = Vec::new();
+    if let Data::Struct(data) = &input.data {
+        for (i, field) in data.fields.iter().enumerate() {
+            let name = field.ident.as_ref().map_or_else(|| i.to_string(), |ident| ident.to_string());
+            let ty = &field.ty;
+            parts.push(format!("{}:{}", name, quote!(#ty).to_string().replace(' ', "")));
+        }
+    }
+    parts.join(",")
+}
+
+type Snippets = Vec<proc_macro2::TokenStream>;
+
+fn extract_fields(input: &DeriveInput) -> (Snippets, Snippets, Snippets, Snippets, Vec<syn::Ident>) {
     let mut field_checks = Vec::new();
     let mut index_fields = Vec::new();
     let mut field_accessors = Vec::new();
+    let mut nested_accessors = Vec::new();
+    let mut key_fields = Vec::new();
 
     if let Data::Struct(data) = &input.data {
         if let Fields::Named(fields) = &data.fields {
@@ -60,21 +138,42 @@ fn extract_fields(
                 let field_name = field.ident.as_ref().unwrap();
                 let field_name_str = field_name.to_string();
 
-                // Snippet for FieldAccess
-                field_accessors.push(quote! {
-                    #field_name_str => Some(rust_db_core::Value::from(&self.#field_name)),
-                });
+                // Snippet for FieldAccess; `#[skip]` fields aren't queryable, so
+                // their type needn't convert into a `Value`
+                let skipped = field.attrs.iter().any(|attr| attr.path().is_ident("skip"));
+                // A `#[nested]` struct is reached through `FieldAccess::nested`,
+                // so `address.city` resolves via the inner type's own fields
+                let nested = field.attrs.iter().any(|attr| attr.path().is_ident("nested"));
+                if nested {
+                    nested_accessors.push(quote! {
+                        #field_name_str => Some(&self.#field_name as &dyn rust_db_core::FieldAccess),
+                    });
+                } else if !skipped {
+                    field_accessors.push(quote! {
+                        #field_name_str => Some(rust_db_core::Value::from(&self.#field_name)),
+                    });
+                }
 
                 // Snippet for Schema::indexes
                 for attr in &field.attrs {
                     if attr.path().is_ident("index") {
-                        index_fields.push(quote! {
-                            indexes.insert(
-                                #field_name_str.to_string(),
-                                bincode::serialize(&self.#field_name).unwrap()
-                            );
+                        // Serializing through a helper named after the field makes a
+                        // non-`Serialize` type fail at the field with that name in the
+                        // error, instead of somewhere inside bincode
+                        let field_ty = &field.ty;
+                        let encode_fn = format_ident!("index_field_{}_must_implement_serialize", field_name);
+                        index_fields.push(quote_spanned! {field_ty.span()=>
+                            fn #encode_fn<T: ::serde::Serialize + ?Sized>(value: &T) -> Vec<u8> {
+                                bincode::serialize(value).unwrap()
+                            }
+                            indexes.insert(#field_name_str.to_string(), #encode_fn::<#field_ty>(&self.#field_name));
                         });
                     }
+
+                    // Key components, concatenated in declaration order
+                    if attr.path().is_ident("key") {
+                        key_fields.push(field_name.clone());
+                    }
                 }
                 
                 // Snippet for Schema::validate (placeholder)
@@ -85,5 +184,5 @@ fn extract_fields(
         }
     }
     
-    (field_checks, index_fields, field_accessors)
+    (field_checks, index_fields, field_accessors, nested_accessors, key_fields)
 }
\ No newline at end of file
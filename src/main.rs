@@ -579,6 +579,8 @@ fn parse_operator(s: &str) -> anyhow::Result<Operator> {
         "CONTAINS" => Ok(Operator::Contains),
         "STARTSWITH" => Ok(Operator::StartsWith),
         "ENDSWITH" => Ok(Operator::EndsWith),
+        "ISNULL" => Ok(Operator::IsNull),
+        "ISNOTNULL" => Ok(Operator::IsNotNull),
         _ => Err(anyhow::anyhow!("Unknown operator '{s}'")),
     }
 }
@@ -608,6 +610,16 @@ fn parse_where_clause(tokens: &[String]) -> anyhow::Result<(Vec<SimpleFilter>, O
             }
             let field = tokens[i + 1].to_lowercase();
             let op = parse_operator(&tokens[i + 2])?;
+            // The null checks take no value
+            if matches!(op, Operator::IsNull | Operator::IsNotNull) {
+                filters.push(SimpleFilter {
+                    field,
+                    operator: op,
+                    value: Value::Null,
+                });
+                i += 3;
+                continue;
+            }
             let val = parse_value(&tokens[i + 3]);
             filters.push(SimpleFilter {
                 field,
@@ -640,6 +652,7 @@ fn apply_filters(row: &Row, filters: &[SimpleFilter]) -> bool {
     for f in filters {
         let field_value = match row.get(&f.field) {
             Some(v) => v,
+            None if matches!(f.operator, Operator::IsNull) => continue,
             None => return false,
         };
         let ok = match &f.operator {
@@ -661,6 +674,12 @@ fn apply_filters(row: &Row, filters: &[SimpleFilter]) -> bool {
                 (Value::String(a), Value::String(b)) => a.ends_with(b.as_str()),
                 _ => false,
             },
+            Operator::In => match &f.value {
+                Value::List(values) => values.contains(field_value),
+                _ => false,
+            },
+            Operator::IsNull => *field_value == Value::Null,
+            Operator::IsNotNull => *field_value != Value::Null,
         };
         if !ok {
             return false;
@@ -768,5 +787,7 @@ fn value_display(v: &Value) -> String {
         Value::String(s) => s.clone(),
         Value::Bool(b) => b.to_string(),
         Value::Null => "NULL".to_string(),
+        Value::Point { lat, lon } => format!("({lat}, {lon})"),
+        Value::List(values) => format!("[{}]", values.iter().map(value_display).collect::<Vec<_>>().join(", ")),
     }
 }
@@ -0,0 +1,154 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::ptr::NonNull;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize};
+use std::sync::{Mutex, PoisonError};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Bump allocator for memtable keys and values. Bytes are copied into large
+// chunks and never freed one at a time: the whole arena goes at once when its
+// memtable is dropped after a flush, so a busy memtable costs a chunk
+// allocation every few thousand small entries rather than two per entry.
+// Writers claim space in the chunk being filled with one atomic add, so they
+// don't exclude each other; the lock is only taken to add a chunk.
+pub(crate) struct Arena {
+    // The chunk being filled, one of `chunks`; null until the first alloc
+    current: AtomicPtr<Chunk>,
+    // Every chunk handed out so far, leaked so `current` stays valid as it grows
+    chunks: Mutex<Vec<NonNull<Chunk>>>,
+}
+
+struct Chunk {
+    data: NonNull<[u8]>,
+    // Bytes claimed so far. Claims that don't fit still add to it, so it can
+    // pass the chunk's size once it's full.
+    used: AtomicUsize,
+}
+
+// Bytes written once and only read afterwards, through `ArenaSlice`s
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    pub(crate) fn new() -> Self {
+        Self {
+            current: AtomicPtr::new(std::ptr::null_mut()),
+            chunks: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Copies `bytes` into the arena. The slice is only valid while the arena
+    // is alive, so it mustn't outlive the memtable that owns both.
+    pub(crate) fn alloc(&self, bytes: &[u8]) -> ArenaSlice {
+        if bytes.is_empty() {
+            return ArenaSlice { ptr: NonNull::dangling(), len: 0 };
+        }
+        let dest = if bytes.len() > CHUNK_SIZE / 4 {
+            // Too big to share a chunk without wasting much of it. Gets one of
+            // its own, and the chunk being filled carries on as before.
+            let chunk = Chunk::new(bytes.len());
+            let dest = unsafe { chunk.as_ref() }.data.cast::<u8>();
+            self.chunks.lock().unwrap_or_else(PoisonError::into_inner).push(chunk);
+            dest
+        } else {
+            self.claim(bytes.len())
+        };
+        // The range was never handed out before, so nothing else reads it yet
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), dest.as_ptr(), bytes.len()) };
+        ArenaSlice { ptr: dest, len: bytes.len() }
+    }
+
+    // `len` bytes of the chunk being filled, starting a new one if it's full
+    fn claim(&self, len: usize) -> NonNull<u8> {
+        loop {
+            let current = self.current.load(atomic::Ordering::Acquire);
+            // Chunks live as long as the arena, so `current` is valid once set
+            if let Some(chunk) = unsafe { current.as_ref() } {
+                let offset = chunk.used.fetch_add(len, atomic::Ordering::Relaxed);
+                if offset + len <= CHUNK_SIZE {
+                    // In bounds: the chunk has room for `len` bytes past `offset`
+                    return unsafe { chunk.data.cast::<u8>().add(offset) };
+                }
+            }
+            let mut chunks = self.chunks.lock().unwrap_or_else(PoisonError::into_inner);
+            // Another writer may have added one while we waited for the lock
+            if self.current.load(atomic::Ordering::Acquire) == current {
+                let chunk = Chunk::new(CHUNK_SIZE);
+                self.current.store(chunk.as_ptr(), atomic::Ordering::Release);
+                chunks.push(chunk);
+            }
+        }
+    }
+}
+
+impl Chunk {
+    fn new(size: usize) -> NonNull<Self> {
+        let data = vec![0u8; size].into_boxed_slice();
+        let chunk = Box::new(Chunk { data: NonNull::from(Box::leak(data)), used: AtomicUsize::new(0) });
+        NonNull::from(Box::leak(chunk))
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        let chunks = self.chunks.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for chunk in chunks.drain(..) {
+            // Each came from `Box::leak` in `Chunk::new`
+            drop(unsafe { Box::from_raw(chunk.as_ptr()) });
+        }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // Came from `Box::leak` in `Chunk::new`
+        drop(unsafe { Box::from_raw(self.data.as_ptr()) });
+    }
+}
+
+// Bytes in an `Arena`, compared and borrowed as a plain `[u8]` so a skiplist
+// keyed on them can be searched with a `&[u8]`
+#[derive(Clone, Copy)]
+pub(crate) struct ArenaSlice {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// Points at immutable bytes, so sharing it across threads is as safe as a `&[u8]`
+unsafe impl Send for ArenaSlice {}
+unsafe impl Sync for ArenaSlice {}
+
+impl ArenaSlice {
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        // The memtable drops its skiplists, and the slices in them, no later
+        // than its arena
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Borrow<[u8]> for ArenaSlice {
+    fn borrow(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl PartialEq for ArenaSlice {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for ArenaSlice {}
+
+impl PartialOrd for ArenaSlice {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ArenaSlice {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
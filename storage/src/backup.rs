@@ -0,0 +1,188 @@
+use crate::schemas::SCHEMAS_FILE;
+use crate::{LocalFs, LsmStorage, SSTable, StorageConfig, WalEntryKind, WriteAheadLog};
+use rust_db_core::{DbError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MANIFEST_FILE: &str = "manifest.bin";
+
+// What a backup directory holds. Tables are named by their path under the
+// storage directory, e.g. `L1/sst_42.bin`; flush and compaction never reuse a
+// name, so it identifies a table across backups.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    // Microseconds since the epoch when the backup was taken
+    pub id: u64,
+    // The backup this one builds on; None for a full backup
+    pub base: Option<u64>,
+    // Every table live at backup time. A restore brings back exactly these.
+    pub tables: Vec<String>,
+    // The tables copied into this backup; the rest are in earlier ones of its chain
+    pub included: Vec<String>,
+}
+
+impl BackupManifest {
+    pub fn read(dir: &Path) -> Result<Self> {
+        let bytes = std::fs::read(dir.join(MANIFEST_FILE))
+            .map_err(|e| DbError::Storage(format!("No backup manifest in {:?}: {}", dir, e), Some(Box::new(e))))?;
+        bincode::deserialize(&bytes).map_err(DbError::serialization)
+    }
+
+    fn write(&self, dir: &Path) -> Result<()> {
+        let bytes = bincode::serialize(self).map_err(DbError::serialization)?;
+        std::fs::write(dir.join(MANIFEST_FILE), bytes).map_err(DbError::storage)
+    }
+}
+
+fn table_name(table: &SSTable) -> String {
+    let file_name = table.path.file_name().unwrap_or_default().to_string_lossy();
+    format!("L{}/{}", table.level, file_name)
+}
+
+// Copies a table file and its checksum, if it has one, from `from` to `to`
+fn copy_table(from: &Path, to: &Path, name: &str) -> Result<()> {
+    let target = to.join(name);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent).map_err(DbError::storage)?;
+    }
+    let source = from.join(name);
+    std::fs::copy(&source, &target).map_err(|e| DbError::Storage(format!("Failed to copy {:?}: {}", source, e), Some(Box::new(e))))?;
+    let checksum = SSTable::checksum_path_for(&source);
+    if checksum.exists() {
+        std::fs::copy(&checksum, SSTable::checksum_path_for(&target)).map_err(DbError::storage)?;
+    }
+    Ok(())
+}
+
+impl LsmStorage {
+    // Flushes, then copies every SSTable into `dest` along with a manifest.
+    // Writes made after the flush aren't included.
+    pub async fn backup(&self, dest: &Path) -> Result<BackupManifest> {
+        self.backup_tables(dest, None).await
+    }
+
+    // Like `backup`, but copies only the tables `base` doesn't list. `base` is
+    // the manifest of the previous backup in the chain, full or incremental.
+    pub async fn backup_incremental(&self, dest: &Path, base: &BackupManifest) -> Result<BackupManifest> {
+        self.backup_tables(dest, Some(base)).await
+    }
+
+    async fn backup_tables(&self, dest: &Path, base: Option<&BackupManifest>) -> Result<BackupManifest> {
+        self.flush().await?;
+        std::fs::create_dir_all(dest).map_err(DbError::storage)?;
+
+        // Each table keeps its file mapped, so one that compaction deletes while
+        // we copy is still written out whole from the mapping
+        let tables = self.get_all_sstables();
+        let already: HashSet<&String> = base.map(|base| base.tables.iter().collect()).unwrap_or_default();
+        let mut included = Vec::new();
+        for table in &tables {
+            let name = table_name(table);
+            if already.contains(&name) {
+                continue;
+            }
+            let target = dest.join(&name);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).map_err(DbError::storage)?;
+            }
+            std::fs::write(&target, table.data.file()).map_err(DbError::storage)?;
+            if let Some(checksum) = table.checksum {
+                std::fs::write(SSTable::checksum_path_for(&target), checksum.to_le_bytes())
+                    .map_err(DbError::storage)?;
+            }
+            included.push(name);
+        }
+        // Recorded table schemas go along whole; they're small
+        match std::fs::copy(self.base_path.join(SCHEMAS_FILE), dest.join(SCHEMAS_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(DbError::storage(e)),
+            _ => {}
+        }
+
+        // Written last, so a backup that didn't finish has no manifest
+        let id = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+        let manifest = BackupManifest {
+            id: base.map_or(id, |base| id.max(base.id + 1)),
+            base: base.map(|base| base.id),
+            tables: tables.iter().map(table_name).collect(),
+            included,
+        };
+        manifest.write(dest)?;
+        Ok(manifest)
+    }
+
+    // Rebuilds a storage directory at `target` from a full backup followed by
+    // its incrementals, in the order they were taken. The result holds the
+    // tables live at the last backup; open it with `LsmStorage::new`.
+    pub fn restore(backups: &[&Path], target: &Path) -> Result<()> {
+        let manifests = backups
+            .iter()
+            .map(|dir| BackupManifest::read(dir))
+            .collect::<Result<Vec<_>>>()?;
+        let Some(last) = manifests.last() else {
+            return Err(DbError::Storage("No backups to restore".to_string(), None));
+        };
+        if manifests[0].base.is_some() {
+            return Err(DbError::Storage("The first backup restored must be a full backup".to_string(), None));
+        }
+        for pair in manifests.windows(2) {
+            if pair[1].base != Some(pair[0].id) {
+                return Err(DbError::Storage(format!(
+                    "Backup {} doesn't build on backup {}",
+                    pair[1].id, pair[0].id
+                ), None));
+            }
+        }
+        if std::fs::read_dir(target).is_ok_and(|mut entries| entries.next().is_some()) {
+            return Err(DbError::Storage(format!("Restore target {:?} is not empty", target), None));
+        }
+
+        std::fs::create_dir_all(target).map_err(DbError::storage)?;
+        for name in &last.tables {
+            // The newest backup holding a copy, though any of them would do
+            let source = manifests
+                .iter()
+                .zip(backups)
+                .rev()
+                .find(|(manifest, _)| manifest.included.contains(name))
+                .map(|(_, dir)| *dir)
+                .ok_or_else(|| DbError::Storage(format!("No backup in the chain holds table {}", name), None))?;
+            copy_table(source, target, name)?;
+        }
+        match std::fs::copy(backups[backups.len() - 1].join(SCHEMAS_FILE), target.join(SCHEMAS_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(DbError::storage(e)),
+            _ => Ok(()),
+        }
+    }
+    
+    // Rebuilds a database at `dest` from the WAL at `wal_path` alone, for when
+    // the SSTables are lost. Replays the segments kept by
+    // `StorageConfig::with_wal_archive`, then any a flush didn't finish, then
+    // the live log, one SSTable per log. Without the archive only the writes
+    // not yet flushed survive in the WAL. Merge operands fail the rebuild, as
+    // there's no merge operator to fold them with, and so does an encrypted WAL.
+    pub fn rebuild_from_wal(wal_path: &Path, dest: &Path) -> Result<()> {
+        if std::fs::read_dir(dest).is_ok_and(|mut entries| entries.next().is_some()) {
+            return Err(DbError::Storage(format!("Rebuild target {:?} is not empty", dest), None));
+        }
+        let logs = Self::wal_history(&LocalFs, wal_path)?;
+        
+        let storage = LsmStorage::open(StorageConfig::new(dest).without_wal())?;
+        for log in &logs {
+            let entries = WriteAheadLog::replay(log)?;
+            {
+                let memtable = storage.active_memtable();
+                for entry in entries {
+                    match entry.kind {
+                        WalEntryKind::Put => memtable.insert(&entry.key, &entry.value),
+                        WalEntryKind::Merge => memtable.push_operand(&entry.key, &entry.value),
+                    }
+                }
+            }
+            // Each log flushed on its own, so later logs' tables are newer
+            storage.flush_memtable()?;
+        }
+        Ok(())
+    }
+}
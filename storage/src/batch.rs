@@ -0,0 +1,37 @@
+use crate::{encode_cell, WalEntry};
+
+// A group of writes applied together: logged to the WAL as one batch with a
+// single flush, then inserted into the memtable under one lock acquisition
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    entries: Vec<WalEntry>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.entries.push(WalEntry::new(key, &encode_cell(value)));
+        self
+    }
+
+    // Deletes are tombstones, as with `LsmStorage::delete`
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.entries.push(WalEntry::new(key, &[]));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn entries(&self) -> &[WalEntry] {
+        &self.entries
+    }
+}
@@ -0,0 +1,97 @@
+use crate::hash::HashFn;
+
+const BITS_PER_KEY: usize = 10;
+// Close to optimal for 10 bits per key, about a 1% false positive rate
+const NUM_PROBES: u32 = 7;
+// Prefix blooms are sized for at least this many prefixes
+const MIN_PREFIX_CAPACITY: usize = 16;
+
+// Per-SSTable filter that lets point reads skip tables which can't hold the key.
+// Probe positions come from one hash split into two halves (double hashing).
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    hash_fn: HashFn,
+}
+
+impl BloomFilter {
+    pub fn with_capacity(expected_keys: usize, hash_fn: HashFn) -> Self {
+        let num_bits = (expected_keys.max(1) * BITS_PER_KEY) as u64;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            hash_fn,
+        }
+    }
+
+    pub fn from_keys<'a, I>(keys: I, hash_fn: HashFn) -> Self
+    where
+        I: ExactSizeIterator<Item = &'a [u8]>,
+    {
+        let mut filter = Self::with_capacity(keys.len(), hash_fn);
+        for key in keys {
+            filter.insert(key);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        for bit in self.probes(key) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    // False means the key was never inserted; true may be a false positive
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.probes(key)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn hash_fn(&self) -> HashFn {
+        self.hash_fn
+    }
+
+    fn probes(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let hash = self.hash_fn.hash(key);
+        let h1 = hash & 0xffff_ffff;
+        let h2 = (hash >> 32) | 1;
+        let num_bits = self.num_bits;
+        (0..NUM_PROBES as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+// Bloom over the first `len` bytes of each key, so a prefix scan can skip
+// tables where no key starts with the prefix
+pub(crate) struct PrefixBloom {
+    len: usize,
+    filter: BloomFilter,
+}
+
+impl PrefixBloom {
+    // `keys` must be sorted, so equal prefixes are adjacent; keys shorter than
+    // `len` are left out, as no scan the filter can answer matches them
+    pub(crate) fn from_sorted_keys<'a, I>(keys: I, len: usize, hash_fn: HashFn) -> Self
+    where
+        I: Iterator<Item = &'a [u8]>,
+    {
+        let mut prefixes: Vec<&[u8]> = keys.filter(|key| key.len() >= len).map(|key| &key[..len]).collect();
+        prefixes.dedup();
+        // A table often holds just one or two prefixes, where the usual sizing
+        // leaves too few bits for a low false positive rate
+        let mut filter = BloomFilter::with_capacity(prefixes.len().max(MIN_PREFIX_CAPACITY), hash_fn);
+        for prefix in prefixes {
+            filter.insert(prefix);
+        }
+        Self { len, filter }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    // False means no key starts with `prefix`. Prefixes shorter than the
+    // filter's length can't be checked, so they always pass.
+    pub(crate) fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        prefix.len() < self.len || self.filter.may_contain(&prefix[..self.len])
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::warn;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+// Events a subscriber can fall behind by before it starts missing them
+const CHANGE_FEED_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Put,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub key: Vec<u8>,
+    pub kind: ChangeKind,
+    // Microseconds since the epoch, strictly increasing across events
+    pub timestamp: u64,
+}
+
+// Broadcasts each write to whoever has subscribed
+pub(crate) struct ChangeFeed {
+    sender: broadcast::Sender<ChangeEvent>,
+    last_timestamp: AtomicU64,
+}
+
+impl ChangeFeed {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANGE_FEED_CAPACITY);
+        Self {
+            sender,
+            last_timestamp: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> ChangeReceiver {
+        ChangeReceiver { inner: self.sender.subscribe() }
+    }
+
+    pub(crate) fn publish(&self, key: &[u8], deleted: bool) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+        let prev = self.last_timestamp
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+            .unwrap();
+        let kind = if deleted { ChangeKind::Delete } else { ChangeKind::Put };
+        // Fails only if every subscriber has dropped since the check above
+        let _ = self.sender.send(ChangeEvent {
+            key: key.to_vec(),
+            kind,
+            timestamp: now.max(prev + 1),
+        });
+    }
+}
+
+// Writes made after `LsmStorage::subscribe`, in the order they were applied.
+// Best effort: a subscriber more than `CHANGE_FEED_CAPACITY` events behind skips
+// the ones it missed, with a warning.
+pub struct ChangeReceiver {
+    inner: broadcast::Receiver<ChangeEvent>,
+}
+
+impl ChangeReceiver {
+    // Waits for the next event; None once the storage is gone
+    pub async fn recv(&mut self) -> Option<ChangeEvent> {
+        loop {
+            match self.inner.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(missed)) => warn!("Change subscriber lagged, missed {} events", missed),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    // The next event if one is already waiting
+    pub fn try_recv(&mut self) -> Option<ChangeEvent> {
+        loop {
+            match self.inner.try_recv() {
+                Ok(event) => return Some(event),
+                Err(TryRecvError::Lagged(missed)) => warn!("Change subscriber lagged, missed {} events", missed),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => return None,
+            }
+        }
+    }
+}
@@ -1,63 +1,300 @@
-use rust_db_core::{DbError, Result, CompactionConfig, CompactionStats, CompactionStrategy};
+use rust_db_core::{DbError, Result, BudgetedCompaction, CompactionConfig, CompactionProgress, CompactionStats, CompactionStrategy};
 use super::{LsmStorage, SSTable, ValueWithTimestamp};
 use std::path::PathBuf;
 use std::collections::{BTreeMap, HashMap};
-use std::sync::Arc;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use log::{info, warn, debug};
+use crate::supervisor::TaskSupervisor;
+use crate::throttle::IoRateLimiter;
 
 pub struct CompactionManager {
     config: CompactionConfig,
+    // Starts as `config.strategy`; a run plans with whatever was current when it began
+    strategy: RwLock<CompactionStrategy>,
     storage: Arc<LsmStorage>,
-    is_compacting: Mutex<bool>,
+    is_compacting: AtomicBool,
+    // `LsmStorage::sstable_generation` when a plan last came up empty; runs
+    // skip planning until the tables change
+    idle_generation: AtomicU64,
+    // Set when `compaction_io_bytes_per_sec` is non-zero
+    io_limiter: Option<IoRateLimiter>,
+}
+
+// Clears the in-progress flag when a run ends, including when it errors out or
+// panics, so a restarted background loop isn't locked out forever
+struct RunningGuard<'a>(&'a AtomicBool);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// Receives progress updates during a compaction run. Never invoked while an
+// internal lock is held, so it may call back into the storage engine.
+pub type CompactionProgressFn<'a> = dyn Fn(CompactionProgress) + Send + Sync + 'a;
+
+// `idle_generation` before any plan has come up empty
+const NOT_IDLE: u64 = u64::MAX;
+
+// Deepest level that keys under `CompactionConfig::pinned_prefixes` are merged into
+const PINNED_LEVEL: u32 = 1;
+
+// One unit of compaction work: `inputs` merged into a single table at `target_level`
+struct MergeJob {
+    inputs: Vec<SSTable>,
+    target_level: u32,
 }
 
 impl CompactionManager {
     pub fn new(storage: Arc<LsmStorage>, config: CompactionConfig) -> Self {
+        let io_limiter = (config.compaction_io_bytes_per_sec > 0)
+            .then(|| IoRateLimiter::new(config.compaction_io_bytes_per_sec));
         Self {
+            strategy: RwLock::new(config.strategy.clone()),
             config,
             storage,
-            is_compacting: Mutex::new(false),
+            is_compacting: AtomicBool::new(false),
+            idle_generation: AtomicU64::new(NOT_IDLE),
+            io_limiter,
         }
     }
     
+    // `strategy` here is the one the manager was created with; see `current_strategy`
+    pub fn config(&self) -> &CompactionConfig {
+        &self.config
+    }
+    
+    pub fn current_strategy(&self) -> CompactionStrategy {
+        self.strategy.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+    
+    // Switches strategy without recreating the manager. A run already in
+    // progress finishes with the strategy it started with; the next one uses this.
+    pub fn set_strategy(&self, strategy: CompactionStrategy) {
+        info!("Compaction strategy set to {:?}", strategy);
+        *self.strategy.write().unwrap_or_else(PoisonError::into_inner) = strategy;
+        // The new strategy may have work the old one didn't
+        self.idle_generation.store(NOT_IDLE, Ordering::SeqCst);
+    }
+    
     pub async fn trigger_compaction(&self) -> Result<CompactionStats> {
-        let mut compacting = self.is_compacting.lock().await;
-        if *compacting {
+        self.run_compaction(None).await
+    }
+    
+    pub async fn trigger_compaction_with_progress(&self, on_progress: &CompactionProgressFn<'_>) -> Result<CompactionStats> {
+        self.run_compaction(Some(on_progress)).await
+    }
+    
+    async fn run_compaction(&self, on_progress: Option<&CompactionProgressFn<'_>>) -> Result<CompactionStats> {
+        if self.is_compacting.swap(true, Ordering::SeqCst) {
+            return Err(DbError::Compaction("Compaction already in progress".to_string()));
+        }
+        let _running = RunningGuard(&self.is_compacting);
+        let start_time = std::time::Instant::now();
+        
+        // Plan the whole run up front so progress can be reported against a fixed total
+        let Some(jobs) = self.plan_unless_idle(&self.current_strategy()).await else {
+            return Ok(CompactionStats::skipped());
+        };
+        
+        let mut progress = CompactionProgress {
+            tables_processed: 0,
+            tables_total: jobs.iter().map(|job| job.inputs.len()).sum(),
+            bytes_written: 0,
+        };
+        let mut stats = CompactionStats {
+            sstables_merged: 0,
+            space_reclaimed: 0,
+            duration_ms: 0,
+            skipped: false,
+        };
+        
+        for job in jobs {
+            let bottom = self.is_bottom_level(job.target_level);
+            stats.sstables_merged += self
+                .merge_sstables(&job.inputs, job.target_level, bottom, &mut progress, on_progress)
+                .await?;
+        }
+        
+        stats.duration_ms = start_time.elapsed().as_millis() as u64;
+        info!("Compaction completed: {:?}", stats);
+        Ok(stats)
+    }
+    
+    // Runs merges until `budget` is spent, replanning from the live table set
+    // before each one. A merge swaps its output in atomically and the level
+    // directories are the durable record of what's done, so the next call (even
+    // after a restart) resumes wherever this one stopped. At least one merge runs
+    // per call so a too-small budget still makes progress.
+    pub async fn trigger_compaction_budgeted(&self, budget: Duration) -> Result<BudgetedCompaction> {
+        if self.is_compacting.swap(true, Ordering::SeqCst) {
             return Err(DbError::Compaction("Compaction already in progress".to_string()));
         }
+        let _running = RunningGuard(&self.is_compacting);
+        let start_time = std::time::Instant::now();
         
-        *compacting = true;
-        drop(compacting); // Release lock
+        let mut stats = CompactionStats {
+            sstables_merged: 0,
+            space_reclaimed: 0,
+            duration_ms: 0,
+            skipped: false,
+        };
+        let mut progress = CompactionProgress {
+            tables_processed: 0,
+            tables_total: 0,
+            bytes_written: 0,
+        };
+        let mut merges_run = 0;
+        let strategy = self.current_strategy();
         
-        let stats = match &self.config.strategy {
+        let remaining_merges = loop {
+            let Some(jobs) = self.plan_unless_idle(&strategy).await else {
+                if merges_run == 0 {
+                    stats.skipped = true;
+                }
+                break 0;
+            };
+            let remaining = jobs.len();
+            let job = match jobs.into_iter().next() {
+                Some(job) => job,
+                None => break 0,
+            };
+            if merges_run > 0 && start_time.elapsed() >= budget {
+                break remaining;
+            }
+            
+            progress.tables_total += job.inputs.len();
+            let bottom = self.is_bottom_level(job.target_level);
+            stats.sstables_merged += self
+                .merge_sstables(&job.inputs, job.target_level, bottom, &mut progress, None)
+                .await?;
+            merges_run += 1;
+        };
+        
+        stats.duration_ms = start_time.elapsed().as_millis() as u64;
+        info!("Budgeted compaction ran {} merges, {} remaining: {:?}", merges_run, remaining_merges, stats);
+        Ok(BudgetedCompaction { stats, remaining_merges })
+    }
+    
+    // None, without looking at any table, if nothing has changed since a plan
+    // last came up empty; otherwise the plan, which is never empty
+    async fn plan_unless_idle(&self, strategy: &CompactionStrategy) -> Option<Vec<MergeJob>> {
+        let generation = self.storage.sstable_generation();
+        if self.idle_generation.load(Ordering::SeqCst) == generation {
+            debug!("Compaction skipped: no SSTables changed since the last check");
+            return None;
+        }
+        let jobs = self.plan(strategy).await;
+        if jobs.is_empty() {
+            debug!("Compaction skipped: no level, tier or bucket met its trigger");
+            self.idle_generation.store(generation, Ordering::SeqCst);
+            return None;
+        }
+        Some(jobs)
+    }
+    
+    async fn plan(&self, strategy: &CompactionStrategy) -> Vec<MergeJob> {
+        let jobs = match strategy {
             CompactionStrategy::Leveled { level_size_multiplier, level0_sstables_trigger } => {
-                self.leveled_compaction(*level_size_multiplier, *level0_sstables_trigger).await
+                self.plan_leveled(*level_size_multiplier, *level0_sstables_trigger).await
             }
             CompactionStrategy::Tiered { max_tier_size, tier_size_multiplier } => {
-                self.tiered_compaction(*max_tier_size, *tier_size_multiplier).await
+                self.plan_tiered(*max_tier_size, *tier_size_multiplier).await
             }
             CompactionStrategy::SizeTiered { min_sstable_size, max_sstable_size, bucket_count } => {
-                self.size_tiered_compaction(*min_sstable_size, *max_sstable_size, *bucket_count).await
+                self.plan_size_tiered(*min_sstable_size, *max_sstable_size, *bucket_count).await
             }
-        }?;
+        };
         
-        *self.is_compacting.lock().await = false;
-        Ok(stats)
+        // Rewriting a lone table into its own level changes nothing
+        let mut jobs: Vec<MergeJob> = jobs.into_iter()
+            .filter(|job| !(job.inputs.len() == 1 && job.inputs[0].level == job.target_level))
+            .collect();
+        if self.config.min_sstable_size > 0 {
+            let coalesce = self.plan_coalesce(self.config.min_sstable_size, &jobs).await;
+            jobs.extend(coalesce);
+        }
+        jobs
     }
     
-    async fn leveled_compaction(
-        &self, 
-        level_size_multiplier: u64, 
-        level0_sstables_trigger: usize
-    ) -> Result<CompactionStats> {
-        info!("Starting leveled compaction");
+    // Merges each run of adjacent small tables within a level into one table at
+    // that level. Tables some other job already takes end a run.
+    async fn plan_coalesce(&self, min_sstable_size: u64, planned: &[MergeJob]) -> Vec<MergeJob> {
+        let taken = |sst: &SSTable| planned.iter().any(|job| job.inputs.iter().any(|input| input.path == sst.path));
+        let sstables_by_level = self.group_sstables_by_level().await;
+        let mut levels: Vec<&u32> = sstables_by_level.keys().collect();
+        levels.sort();
+        
+        let mut runs: Vec<(u32, Vec<SSTable>)> = Vec::new();
+        for level in levels {
+            let mut run: Vec<SSTable> = Vec::new();
+            for sstable in &sstables_by_level[level] {
+                if sstable.file_size < min_sstable_size && !taken(sstable) {
+                    run.push(sstable.clone());
+                } else {
+                    runs.push((*level, std::mem::take(&mut run)));
+                }
+            }
+            runs.push((*level, run));
+        }
+        
+        runs.into_iter()
+            .filter(|(_, run)| run.len() > 1)
+            .map(|(level, run)| {
+                info!("Coalescing {} small SSTables in level {}", run.len(), level);
+                MergeJob { inputs: run, target_level: level }
+            })
+            .collect()
+    }
+    
+    // Back-pressure path for writers: waits out any compaction already running,
+    // then merges every table into one at the deepest level
+    pub async fn force_compaction(&self) -> Result<CompactionStats> {
+        self.compact_everything(false).await
+    }
+    
+    // Like `force_compaction`, but also drops tombstones past the grace period:
+    // with every table in the merge there's no older value left on disk for one
+    // to hide. For reclaiming space and read amplification before a read-heavy period.
+    pub async fn major_compaction(&self) -> Result<CompactionStats> {
+        self.compact_everything(true).await
+    }
+    
+    async fn compact_everything(&self, drop_tombstones: bool) -> Result<CompactionStats> {
+        while self.is_compacting.swap(true, Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let _running = RunningGuard(&self.is_compacting);
+        
         let start_time = std::time::Instant::now();
-        let mut stats = CompactionStats {
-            sstables_merged: 0,
-            space_reclaimed: 0,
-            duration_ms: 0,
+        let sstables = self.get_all_sstables().await;
+        let target_level = sstables.iter().map(|sst| sst.level).max().unwrap_or(0).max(1);
+        info!("Compacting all {} SSTables into level {}", sstables.len(), target_level);
+        
+        let mut progress = CompactionProgress {
+            tables_processed: 0,
+            tables_total: sstables.len(),
+            bytes_written: 0,
         };
+        let size_before: u64 = sstables.iter().map(|sst| sst.file_size).sum();
+        let sstables_merged = self
+            .merge_sstables(&sstables, target_level, drop_tombstones, &mut progress, None)
+            .await?;
+        Ok(CompactionStats {
+            sstables_merged,
+            space_reclaimed: size_before.saturating_sub(progress.bytes_written),
+            duration_ms: start_time.elapsed().as_millis() as u64,
+            skipped: false,
+        })
+    }
+    
+    async fn plan_leveled(&self, level_size_multiplier: u64, level0_sstables_trigger: usize) -> Vec<MergeJob> {
+        let mut jobs = Vec::new();
         
         // Get current SSTables grouped by level
         let sstables_by_level = self.group_sstables_by_level().await;
@@ -66,8 +303,7 @@ impl CompactionManager {
         if let Some(level0_sstables) = sstables_by_level.get(&0) {
             if level0_sstables.len() >= level0_sstables_trigger {
                 info!("Level 0 compaction triggered: {} SSTables", level0_sstables.len());
-                let merged = self.merge_sstables(level0_sstables, 1).await?;
-                stats.sstables_merged += merged;
+                jobs.push(MergeJob { inputs: level0_sstables.clone(), target_level: 1 });
             }
         }
         
@@ -75,83 +311,68 @@ impl CompactionManager {
         for level in 1..=self.get_max_level(&sstables_by_level) {
             if let Some(current_level_sstables) = sstables_by_level.get(&level) {
                 let next_level_size = self.calculate_level_size(level, level_size_multiplier);
-                let current_size: u64 = current_level_sstables.iter().map(|sst| sst.file_size).sum();
+                // Pinned keys come straight back to their level, so they don't count against it
+                let current_size: u64 = current_level_sstables
+                    .iter()
+                    .filter(|sst| !self.holds_only_pinned(sst))
+                    .map(|sst| sst.file_size)
+                    .sum();
                 
                 if current_size > next_level_size {
                     info!("Level {} compaction triggered: {} bytes > {} bytes", 
                           level, current_size, next_level_size);
-                    let merged = self.merge_sstables(current_level_sstables, level + 1).await?;
-                    stats.sstables_merged += merged;
+                    jobs.push(MergeJob { inputs: current_level_sstables.clone(), target_level: level + 1 });
                 }
             }
         }
         
-        stats.duration_ms = start_time.elapsed().as_millis() as u64;
-        info!("Leveled compaction completed: {:?}", stats);
-        Ok(stats)
+        jobs
     }
     
-    async fn tiered_compaction(&self, max_tier_size: u64, tier_size_multiplier: f64) -> Result<CompactionStats> {
-        info!("Starting tiered compaction");
-        let start_time = std::time::Instant::now();
-        
+    async fn plan_tiered(&self, max_tier_size: u64, tier_size_multiplier: f64) -> Vec<MergeJob> {
         let sstables = self.get_all_sstables().await;
-        let mut tiers = self.group_sstables_by_tier(&sstables, max_tier_size, tier_size_multiplier);
-        
-        let mut stats = CompactionStats {
-            sstables_merged: 0,
-            space_reclaimed: 0,
-            duration_ms: 0,
-        };
+        let tiers = self.group_sstables_by_tier(&sstables, max_tier_size, tier_size_multiplier);
         
         // Compact tiers that exceed the size limit
-        for (tier, tier_sstables) in &mut tiers {
+        let mut jobs = Vec::new();
+        for (tier, tier_sstables) in tiers {
             let tier_size: u64 = tier_sstables.iter().map(|sst| sst.file_size).sum();
             if tier_size > max_tier_size {
                 info!("Tier {} compaction triggered: {} bytes", tier, tier_size);
-                let merged = self.merge_sstables(tier_sstables, *tier as u32).await?;
-                stats.sstables_merged += merged;
+                jobs.push(MergeJob { inputs: tier_sstables, target_level: tier as u32 });
             }
         }
-        
-        stats.duration_ms = start_time.elapsed().as_millis() as u64;
-        info!("Tiered compaction completed: {:?}", stats);
-        Ok(stats)
+        jobs
     }
     
-    async fn size_tiered_compaction(
+    async fn plan_size_tiered(
         &self, 
         min_sstable_size: u64, 
         max_sstable_size: u64, 
         bucket_count: usize
-    ) -> Result<CompactionStats> {
-        info!("Starting size-tiered compaction");
-        let start_time = std::time::Instant::now();
-        
+    ) -> Vec<MergeJob> {
         let sstables = self.get_all_sstables().await;
         let buckets = self.group_sstables_by_size(&sstables, min_sstable_size, max_sstable_size, bucket_count);
         
-        let mut stats = CompactionStats {
-            sstables_merged: 0,
-            space_reclaimed: 0,
-            duration_ms: 0,
-        };
-        
         // Compact buckets that have multiple SSTables
+        let mut jobs = Vec::new();
         for (bucket, bucket_sstables) in buckets {
             if bucket_sstables.len() > 1 {
                 info!("Bucket {} compaction triggered: {} SSTables", bucket, bucket_sstables.len());
-                let merged = self.merge_sstables(&bucket_sstables, 0).await?;
-                stats.sstables_merged += merged;
+                jobs.push(MergeJob { inputs: bucket_sstables, target_level: 0 });
             }
         }
-        
-        stats.duration_ms = start_time.elapsed().as_millis() as u64;
-        info!("Size-tiered compaction completed: {:?}", stats);
-        Ok(stats)
+        jobs
     }
     
-    async fn merge_sstables(&self, sstables: &[SSTable], target_level: u32) -> Result<usize> {
+    async fn merge_sstables(
+        &self,
+        sstables: &[SSTable],
+        target_level: u32,
+        drop_tombstones: bool,
+        progress: &mut CompactionProgress,
+        on_progress: Option<&CompactionProgressFn<'_>>,
+    ) -> Result<usize> {
         if sstables.is_empty() {
             return Ok(0);
         }
@@ -163,6 +384,7 @@ impl CompactionManager {
         for sstable in sstables {
             _total_size_before += sstable.file_size;
             debug!("Merging SSTable: {:?}", &sstable.path);
+            self.throttle_io(sstable.file_size).await;
             
             // Read all entries from this SSTable
             let entries = sstable.iter().await?;
@@ -179,47 +401,135 @@ impl CompactionManager {
                     })
                     .or_insert(value);
             }
+            
+            progress.tables_processed += 1;
+            if let Some(report) = on_progress {
+                report(progress.clone());
+            }
         }
         
-        // Create new merged SSTable
-        let new_sstable_path = self.generate_sstable_path(target_level);
-        let _new_sstable = SSTable::create(&new_sstable_path, merged_data).await?;
+        if drop_tombstones {
+            let outside: Vec<SSTable> = self
+                .get_all_sstables()
+                .await
+                .into_iter()
+                .filter(|sst| !sstables.iter().any(|input| input.path == sst.path))
+                .collect();
+            let mut droppable = Vec::new();
+            for (key, value) in merged_data.iter().filter(|(_, value)| value.value.is_empty()) {
+                if self.tombstone_droppable(key, value.timestamp, &outside)? {
+                    droppable.push(key.clone());
+                }
+            }
+            for key in droppable {
+                merged_data.remove(&key);
+            }
+        }
+        
+        // Pinned keys headed below their level go back there in a table of their own
+        let mut outputs = Vec::new();
+        if target_level > PINNED_LEVEL {
+            let pinned = self.take_pinned(&mut merged_data);
+            outputs.push((pinned, PINNED_LEVEL));
+        }
+        outputs.push((merged_data, target_level));
+        
+        let mut new_sstables = Vec::new();
+        for (data, level) in outputs.into_iter().filter(|(data, _)| !data.is_empty()) {
+            let new_sstable_path = self.generate_sstable_path(level);
+            let hash_fn = self.storage.hash_fn();
+            let checksum = self.config.checksum_outputs;
+            let (fs, encryption) = (self.storage.file_system(), self.storage.encryption());
+            new_sstables.push(SSTable::create(&new_sstable_path, data, level, hash_fn, checksum, fs, encryption).await?);
+        }
+        let bytes_written = new_sstables.iter().map(|sst| sst.file_size).sum();
+        self.storage.record_compaction_write(bytes_written);
+        // Paid once the outputs are written, as their size isn't known before
+        self.throttle_io(bytes_written).await;
+        
+        // Swap the merged tables in before the inputs disappear from disk
+        self.storage.replace_sstables(sstables, new_sstables);
         
         // Remove old SSTables
         for sstable in sstables {
-            tokio::fs::remove_file(&sstable.path).await.map_err(|e| {
-                DbError::Storage(format!("Failed to remove old SSTable: {}", e))
-            })?;
+            self.storage.retire_sstable(sstable)?;
         }
         
-        // Add new SSTable to storage
-        // Note: This requires implementing add_sstable method in LsmStorage
-        // For now, we'll skip this step - you'll need to add this to LsmStorage
-        // self.storage.add_sstable(new_sstable, target_level).await?;
+        progress.bytes_written += bytes_written;
+        if let Some(report) = on_progress {
+            report(progress.clone());
+        }
         
         Ok(sstables.len())
     }
     
+    // Whether every key of a table at `PINNED_LEVEL` is pinned, as in the table
+    // a merge splits them into. A prefix's keys are contiguous, so the first and
+    // last key sharing one means they all do.
+    fn holds_only_pinned(&self, sstable: &SSTable) -> bool {
+        let meta = sstable.meta();
+        sstable.level == PINNED_LEVEL
+            && self.config.pinned_prefixes
+                .iter()
+                .any(|prefix| meta.min_key.starts_with(prefix) && meta.max_key.starts_with(prefix))
+    }
+    
+    // Moves the entries under `CompactionConfig::pinned_prefixes` out of `merged`
+    fn take_pinned(&self, merged: &mut BTreeMap<Vec<u8>, ValueWithTimestamp>) -> BTreeMap<Vec<u8>, ValueWithTimestamp> {
+        let prefixes = &self.config.pinned_prefixes;
+        if prefixes.is_empty() {
+            return BTreeMap::new();
+        }
+        let (pinned, rest) = std::mem::take(merged)
+            .into_iter()
+            .partition(|(key, _)| prefixes.iter().any(|prefix| key.starts_with(prefix)));
+        *merged = rest;
+        pinned
+    }
+    
+    async fn throttle_io(&self, bytes: u64) {
+        if let Some(limiter) = &self.io_limiter {
+            limiter.acquire(bytes).await;
+        }
+    }
+    
+    // Whether a merge into `target_level` reaches the deepest level in use
+    fn is_bottom_level(&self, target_level: u32) -> bool {
+        self.storage.get_all_sstables().iter().all(|sst| sst.level <= target_level)
+    }
+    
+    // A tombstone can go once it's past the grace period and none of the tables
+    // outside the merge holds an older value for its key, which would otherwise resurface
+    fn tombstone_droppable(&self, key: &[u8], timestamp: u64, outside: &[SSTable]) -> Result<bool> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+        let grace = self.config.tombstone_grace_secs.saturating_mul(1_000_000);
+        if now.saturating_sub(timestamp) < grace {
+            return Ok(false);
+        }
+        for sstable in outside {
+            if !sstable.may_contain(key) {
+                continue;
+            }
+            if sstable.get(key)?.is_some_and(|older| older.timestamp < timestamp) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    
     // Helper methods
     async fn group_sstables_by_level(&self) -> HashMap<u32, Vec<SSTable>> {
-        let sstables_by_level = HashMap::new();
-        
-        // Get all SSTables from storage and group by their level
-        // Note: This requires implementing get_all_sstables method in LsmStorage
-        // For now, return empty HashMap
-        // let all_sstables = self.storage.get_all_sstables().await;
+        let mut sstables_by_level: HashMap<u32, Vec<SSTable>> = HashMap::new();
         
-        // for sstable in all_sstables {
-        //     let level = sstable.level;
-        //     sstables_by_level.entry(level).or_insert_with(Vec::new).push(sstable);
-        // }
+        for sstable in self.storage.get_all_sstables() {
+            sstables_by_level.entry(sstable.level).or_default().push(sstable);
+        }
         
         sstables_by_level
     }
     
     async fn get_all_sstables(&self) -> Vec<SSTable> {
-        // Note: This requires implementing get_all_sstables method in LsmStorage
-        Vec::new()
+        self.storage.get_all_sstables()
     }
     
     fn group_sstables_by_tier(
@@ -285,9 +595,10 @@ impl CompactionManager {
         buckets
     }
     
-    fn calculate_level_size(&self, level: u32, multiplier: u64) -> u64 {
-        // Level size grows exponentially
-        multiplier.pow(level)
+    // Target size of a level, growing exponentially. Levels too deep to fit in
+    // a u64 saturate, so their target is never exceeded.
+    pub fn calculate_level_size(&self, level: u32, multiplier: u64) -> u64 {
+        multiplier.checked_pow(level).unwrap_or(u64::MAX)
     }
     
     fn get_max_level(&self, sstables_by_level: &HashMap<u32, Vec<SSTable>>) -> u32 {
@@ -295,36 +606,71 @@ impl CompactionManager {
     }
     
     fn generate_sstable_path(&self, level: u32) -> PathBuf {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-        
-        self.storage.base_path().join(format!("sst_l{}_{}.bin", level, timestamp))
+        self.storage.sstable_path(level)
     }
 }
 
 // Background compaction task
+//
+// The sleep between runs adapts to write load: it halves while flushes keep
+// arriving and doubles while the database is quiet, staying within the
+// configured min/max bounds.
 pub struct BackgroundCompactor {
     manager: Arc<CompactionManager>,
-    interval_secs: u64,
+    schedule: std::sync::Mutex<AdaptiveSchedule>,
     stopped: Mutex<bool>,
 }
 
+struct AdaptiveSchedule {
+    interval_secs: u64,
+    last_flush_count: u64,
+}
+
+// Flushes per interval at or above which compaction runs more often
+const BUSY_FLUSHES_PER_INTERVAL: u64 = 2;
+
 impl BackgroundCompactor {
     pub fn new(manager: Arc<CompactionManager>, interval_secs: u64) -> Self {
+        let config = manager.config();
+        let interval_secs = interval_secs.clamp(
+            config.min_background_interval_secs,
+            config.max_background_interval_secs.max(config.min_background_interval_secs),
+        );
+        let last_flush_count = manager.storage.flush_count();
         Self {
             manager,
-            interval_secs,
+            schedule: std::sync::Mutex::new(AdaptiveSchedule { interval_secs, last_flush_count }),
             stopped: Mutex::new(false),
         }
     }
     
+    pub fn current_interval(&self) -> Duration {
+        Duration::from_secs(self.schedule.lock().unwrap().interval_secs)
+    }
+    
+    // Re-tunes the interval from the flushes seen since the last call
+    pub fn adjust_interval(&self) -> Duration {
+        let config = self.manager.config();
+        let flush_count = self.manager.storage.flush_count();
+        let mut schedule = self.schedule.lock().unwrap();
+        let flushes = flush_count - schedule.last_flush_count;
+        schedule.last_flush_count = flush_count;
+        
+        if flushes >= BUSY_FLUSHES_PER_INTERVAL {
+            schedule.interval_secs = (schedule.interval_secs / 2).max(config.min_background_interval_secs);
+        } else if flushes == 0 {
+            schedule.interval_secs = schedule.interval_secs
+                .saturating_mul(2)
+                .min(config.max_background_interval_secs)
+                .max(config.min_background_interval_secs);
+        }
+        debug!("Background compaction interval now {}s ({} flushes)", schedule.interval_secs, flushes);
+        Duration::from_secs(schedule.interval_secs)
+    }
+    
     pub async fn start(&self) -> Result<()> {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(self.interval_secs));
-        
         loop {
-            interval.tick().await;
+            tokio::time::sleep(self.current_interval()).await;
             
             // Check if we should stop
             if *self.stopped.lock().await {
@@ -335,6 +681,7 @@ impl BackgroundCompactor {
             if let Err(e) = self.manager.trigger_compaction().await {
                 warn!("Background compaction failed: {}", e);
             }
+            self.adjust_interval();
         }
         
         Ok(())
@@ -343,4 +690,12 @@ impl BackgroundCompactor {
     pub async fn stop(&self) {
         *self.stopped.lock().await = true;
     }
+    
+    // Runs `start` under the supervisor so a panicking compaction restarts the loop
+    pub fn spawn_supervised(self: Arc<Self>, supervisor: &TaskSupervisor) -> JoinHandle<Result<()>> {
+        supervisor.supervise("background-compactor", move || {
+            let compactor = Arc::clone(&self);
+            async move { compactor.start().await }
+        })
+    }
 }
\ No newline at end of file
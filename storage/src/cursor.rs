@@ -0,0 +1,194 @@
+use crate::{into_value, LsmStorage, SSTable, ScanBounds};
+use rust_db_core::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// SSTable files held open by live cursors. Compaction defers deleting a pinned
+// file until the last cursor holding it drops.
+#[derive(Default)]
+pub(crate) struct PinnedFiles {
+    counts: HashMap<PathBuf, usize>,
+    doomed: HashSet<PathBuf>,
+}
+
+impl PinnedFiles {
+    pub(crate) fn pin(&mut self, path: &Path) {
+        *self.counts.entry(path.to_path_buf()).or_insert(0) += 1;
+    }
+
+    // The file's path if this was its last pin and compaction has retired it
+    fn unpin(&mut self, path: &Path) -> Option<PathBuf> {
+        let count = self.counts.get_mut(path)?;
+        *count -= 1;
+        if *count > 0 {
+            return None;
+        }
+        self.counts.remove(path);
+        self.doomed.take(path)
+    }
+
+    // Whether the caller may delete `path` now; otherwise it's deleted on unpin
+    pub(crate) fn retire(&mut self, path: &Path) -> bool {
+        if self.counts.contains_key(path) {
+            self.doomed.insert(path.to_path_buf());
+            false
+        } else {
+            true
+        }
+    }
+}
+
+// A table being merged by the cursor, and its next entry
+struct TableSource {
+    table: SSTable,
+    pos: usize,
+}
+
+// Iterates a prefix over the SSTables and memtable contents as they were when
+// the cursor was created. Flushes and compactions that happen meanwhile don't
+// change what it yields, and the tables it reads stay on disk until it drops.
+// Yields the same entries as `LsmStorage::scan`.
+pub struct ScanCursor {
+    prefix: Vec<u8>,
+    tables: Vec<TableSource>,
+    // Snapshot of the memtable's cells, which are newer than every table;
+    // reversed so the next entry is at the end
+    memtable: Vec<(Vec<u8>, Vec<u8>)>,
+    pins: Arc<Mutex<PinnedFiles>>,
+}
+
+impl ScanCursor {
+    pub(crate) fn new(
+        prefix: &[u8],
+        tables: Vec<SSTable>,
+        mut memtable: Vec<(Vec<u8>, Vec<u8>)>,
+        pins: Arc<Mutex<PinnedFiles>>,
+    ) -> Self {
+        let bounds = ScanBounds::Prefix(prefix);
+        let tables = tables
+            .into_iter()
+            .map(|table| {
+                let pos = table.index.partition_point(|(k, _)| k.as_slice() < bounds.start());
+                TableSource { table, pos }
+            })
+            .collect();
+        memtable.reverse();
+        Self {
+            prefix: prefix.to_vec(),
+            tables,
+            memtable,
+            pins,
+        }
+    }
+
+    fn table_key<'a>(&self, source: &'a TableSource) -> Option<&'a [u8]> {
+        let (key, _) = source.table.index.get(source.pos)?;
+        ScanBounds::Prefix(&self.prefix).still_within(key).then_some(key.as_slice())
+    }
+
+    pub async fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        // Tombstones are passed over
+        while let Some((key, cell)) = self.next_cell()? {
+            if let Some(value) = into_value(cell) {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_cell(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let smallest_table_key = self
+            .tables
+            .iter()
+            .filter_map(|source| self.table_key(source))
+            .min()
+            .map(|key| key.to_vec());
+        let memtable_key = self.memtable.last().map(|(key, _)| key.clone());
+        let key = match (smallest_table_key, memtable_key) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(key), None) | (None, Some(key)) => key,
+            (None, None) => return Ok(None),
+        };
+
+        // Every source positioned on `key` moves past it; the newest write wins
+        let mut newest: Option<(u64, Vec<u8>)> = None;
+        for i in 0..self.tables.len() {
+            if self.table_key(&self.tables[i]) != Some(key.as_slice()) {
+                continue;
+            }
+            let source = &mut self.tables[i];
+            let entry = source.table.entry_at(source.table.index[source.pos].1)?;
+            source.pos += 1;
+            if newest.as_ref().is_none_or(|(ts, _)| entry.timestamp > *ts) {
+                newest = Some((entry.timestamp, entry.value));
+            }
+        }
+        if self.memtable.last().is_some_and(|(k, _)| *k == key) {
+            let (_, value) = self.memtable.pop().unwrap();
+            newest = Some((u64::MAX, value));
+        }
+        Ok(newest.map(|(_, value)| (key, value)))
+    }
+
+    // Drains the cursor into a vector
+    pub async fn collect(mut self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut results = Vec::new();
+        while let Some(entry) = self.next().await? {
+            results.push(entry);
+        }
+        Ok(results)
+    }
+}
+
+impl Drop for ScanCursor {
+    fn drop(&mut self) {
+        let mut pins = self.pins.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        for source in &self.tables {
+            if let Some(path) = pins.unpin(&source.table.path) {
+                if let Err(e) = source.table.delete_files() {
+                    log::warn!("Failed to remove retired SSTable {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+impl LsmStorage {
+    // Opens a cursor over `prefix` that's unaffected by later flushes and compactions
+    pub fn scan_cursor(&self, prefix: &[u8]) -> Result<ScanCursor> {
+        // Holding the memtable lock keeps a flush from freezing entries between
+        // the snapshot and the table list. Frozen memtables are read before the
+        // tables, so one that finishes flushing meanwhile is seen at least once.
+        let memtable = self.active_memtable();
+        let mut snapshot: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        for frozen in self.frozen_memtables() {
+            snapshot.extend(frozen.memtable.scan(prefix));
+        }
+        snapshot.extend(memtable.scan(prefix));
+        for (key, _) in memtable.scan_operands(prefix) {
+            if let Some(folded) = self.get_from_memtable(&memtable, &key)? {
+                snapshot.insert(key, folded);
+            }
+        }
+
+        // Pinned under the table-list lock, so compaction sees the pins as soon
+        // as it can have replaced any of these tables
+        let levels = self.sstable_levels.read().unwrap();
+        let tables: Vec<SSTable> = levels
+            .values()
+            .flatten()
+            .filter(|table| table.may_contain_prefix(prefix))
+            .cloned()
+            .collect();
+        let mut pins = self.pinned_sstables.lock().unwrap();
+        for table in &tables {
+            pins.pin(&table.path);
+        }
+        drop(pins);
+        drop(levels);
+
+        let snapshot = snapshot.into_iter().collect();
+        Ok(ScanCursor::new(prefix, tables, snapshot, Arc::clone(&self.pinned_sstables)))
+    }
+}
@@ -0,0 +1,221 @@
+use crate::FileData;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use rust_db_core::{DbError, Result};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+const ENCRYPTED_SSTABLE_MAGIC: u32 = 0x5353_5445; // "SSTE"
+const ENCRYPTED_SSTABLE_FOOTER_LEN: usize = 16;
+
+// Plaintext bytes per sealed SSTable block; the last block may be shorter
+pub(crate) const SSTABLE_BLOCK_SIZE: usize = 4096;
+
+// Bound into every tag, so a sealed WAL batch can't pass for an SSTable or back
+const SSTABLE_AAD: &[u8] = b"rustdb sstable";
+const WAL_AAD: &[u8] = b"rustdb wal batch";
+
+// AES-256-GCM for SSTables and WAL batches at rest, set with
+// `StorageConfig::with_encryption_key`. Each sealed blob is a fresh random
+// nonce, the ciphertext and its tag, so a wrong key or a flipped bit fails
+// to open rather than decoding to garbage.
+//
+// A sealed SSTable is its plaintext file cut into blocks of
+// `SSTABLE_BLOCK_SIZE` bytes, each sealed on its own with its position bound
+// into the tag, followed by a clear footer: the plaintext length (u64), the
+// block size (u32) and a magic number (u32). Reads decrypt only the blocks
+// they touch, so the file stays mapped rather than held decrypted in memory.
+// Bloom filters and the key index are built in memory as the table opens, so
+// they never reach the disk in the clear.
+#[derive(Clone)]
+pub struct AtRestEncryption {
+    key: Arc<LessSafeKey>,
+}
+
+impl std::fmt::Debug for AtRestEncryption {
+    // Never prints the key
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AtRestEncryption").finish_non_exhaustive()
+    }
+}
+
+impl AtRestEncryption {
+    pub fn new(key: &[u8; 32]) -> Self {
+        let key = UnboundKey::new(&AES_256_GCM, key).expect("AES-256 keys are 32 bytes");
+        Self { key: Arc::new(LessSafeKey::new(key)) }
+    }
+
+    fn seal(&self, mut plaintext: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|e| DbError::Encryption(format!("Failed to generate nonce: {:?}", e)))?;
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut plaintext)
+            .map_err(|e| DbError::Encryption(format!("Encryption failed: {:?}", e)))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + plaintext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&plaintext);
+        Ok(sealed)
+    }
+
+    // None if the blob doesn't open under this key, tampered or not
+    fn open(&self, sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut plaintext = ciphertext.to_vec();
+        let len = self.key.open_in_place(nonce, Aad::from(aad), &mut plaintext).ok()?.len();
+        plaintext.truncate(len);
+        Some(plaintext)
+    }
+
+    // Block `index` of a table, sealed
+    pub(crate) fn seal_sstable_block(&self, index: usize, block: Vec<u8>) -> Result<Vec<u8>> {
+        self.seal(block, &block_aad(index))
+    }
+
+    // Closes a table of `plaintext_len` bytes sealed block by block
+    pub(crate) fn sealed_sstable_footer(plaintext_len: usize) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(ENCRYPTED_SSTABLE_FOOTER_LEN);
+        footer.extend_from_slice(&(plaintext_len as u64).to_le_bytes());
+        footer.extend_from_slice(&(SSTABLE_BLOCK_SIZE as u32).to_le_bytes());
+        footer.extend_from_slice(&ENCRYPTED_SSTABLE_MAGIC.to_le_bytes());
+        footer
+    }
+
+    pub(crate) fn seal_wal_batch(&self, payload: Vec<u8>) -> Result<Vec<u8>> {
+        self.seal(payload, WAL_AAD)
+    }
+
+    // The payload of a batch sealed by `seal_wal_batch`. The batch already
+    // passed its CRC, so failing to open means the key is wrong.
+    pub(crate) fn open_wal_batch(encryption: Option<&Self>, path: &Path, sealed: &[u8]) -> Result<Vec<u8>> {
+        let Some(encryption) = encryption else {
+            return Err(DbError::Encryption(format!("WAL {:?} is encrypted and no key was configured", path)));
+        };
+        encryption
+            .open(sealed, WAL_AAD)
+            .ok_or_else(|| DbError::Encryption(format!("Failed to decrypt WAL {:?}: wrong key or corrupt data", path)))
+    }
+}
+
+// Bound into each block's tag, so blocks can't be swapped within a table
+fn block_aad(index: usize) -> Vec<u8> {
+    let mut aad = SSTABLE_AAD.to_vec();
+    aad.extend_from_slice(&(index as u64).to_le_bytes());
+    aad
+}
+
+// An SSTable file sealed block by block, read by decrypting the blocks a read
+// touches. The last block decrypted is kept, since scans read entries in order
+// and several fit in a block.
+pub(crate) struct SealedTable {
+    path: PathBuf,
+    file: FileData,
+    encryption: AtRestEncryption,
+    plaintext_len: usize,
+    block_size: usize,
+    last_block: Mutex<Option<(usize, Arc<Vec<u8>>)>>,
+}
+
+impl SealedTable {
+    // None if `file` isn't sealed. Fails if it is and `encryption` is None,
+    // or if its footer doesn't match its length.
+    pub(crate) fn open(encryption: Option<&AtRestEncryption>, path: &Path, file: &FileData) -> Result<Option<Self>> {
+        let data = (**file).as_ref();
+        let Some(footer_start) = data.len().checked_sub(ENCRYPTED_SSTABLE_FOOTER_LEN) else {
+            return Ok(None);
+        };
+        let footer = &data[footer_start..];
+        if u32::from_le_bytes(footer[12..16].try_into().unwrap()) != ENCRYPTED_SSTABLE_MAGIC {
+            return Ok(None);
+        }
+        let Some(encryption) = encryption else {
+            return Err(DbError::Encryption(format!("SSTable {:?} is encrypted and no key was configured", path)));
+        };
+        let plaintext_len = u64::from_le_bytes(footer[0..8].try_into().unwrap()) as usize;
+        let block_size = u32::from_le_bytes(footer[8..12].try_into().unwrap()) as usize;
+        let blocks = if block_size == 0 { usize::MAX } else { plaintext_len.div_ceil(block_size) };
+        let sealed_len = blocks
+            .checked_mul(NONCE_LEN + AES_256_GCM.tag_len())
+            .and_then(|overhead| overhead.checked_add(plaintext_len));
+        if sealed_len != Some(footer_start) {
+            return Err(DbError::Encryption(format!("SSTable {:?} is truncated", path)));
+        }
+        Ok(Some(SealedTable {
+            path: path.to_path_buf(),
+            file: Arc::clone(file),
+            encryption: encryption.clone(),
+            plaintext_len,
+            block_size,
+            last_block: Mutex::new(None),
+        }))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.plaintext_len
+    }
+
+    pub(crate) fn file(&self) -> &[u8] {
+        (*self.file).as_ref()
+    }
+
+    // The plaintext from `offset` to the end of the table
+    pub(crate) fn reader(&self, offset: usize) -> SealedReader<'_> {
+        SealedReader { table: self, pos: offset, block: None }
+    }
+
+    fn block(&self, index: usize) -> Result<Arc<Vec<u8>>> {
+        let mut last = self.last_block.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((cached, block)) = last.as_ref() {
+            if *cached == index {
+                return Ok(Arc::clone(block));
+            }
+        }
+        let sealed_block = self.block_size + NONCE_LEN + AES_256_GCM.tag_len();
+        let start = index * sealed_block;
+        let end = (start + sealed_block).min(self.file().len() - ENCRYPTED_SSTABLE_FOOTER_LEN);
+        let block = self
+            .encryption
+            .open(&self.file()[start..end], &block_aad(index))
+            .map(Arc::new)
+            .ok_or_else(|| DbError::Encryption(format!(
+                "Failed to decrypt block {} of SSTable {:?}: wrong key or corrupt data",
+                index, self.path
+            )))?;
+        *last = Some((index, Arc::clone(&block)));
+        Ok(block)
+    }
+}
+
+pub(crate) struct SealedReader<'a> {
+    table: &'a SealedTable,
+    pos: usize,
+    block: Option<(usize, Arc<Vec<u8>>)>,
+}
+
+impl Read for SealedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.table.plaintext_len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.pos / self.table.block_size;
+        if self.block.as_ref().is_none_or(|(held, _)| *held != index) {
+            let block = self.table.block(index).map_err(io::Error::other)?;
+            self.block = Some((index, block));
+        }
+        let block = &self.block.as_ref().unwrap().1;
+        let available = block.get(self.pos % self.table.block_size..).unwrap_or_default();
+        if available.is_empty() {
+            return Err(io::Error::other(format!("Block {} of SSTable {:?} is short", index, self.table.path)));
+        }
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
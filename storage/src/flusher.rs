@@ -0,0 +1,61 @@
+use crate::supervisor::TaskSupervisor;
+use crate::LsmStorage;
+use log::warn;
+use rust_db_core::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+// Shortest sleep between checks, so a flush that keeps failing doesn't spin
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+// Flushes the memtable once it's older than `StorageConfig::with_max_memtable_age`,
+// for when no write comes along to notice. Sleeps until the current table
+// comes due, or for a quarter of the age while the table is empty. Returns
+// straight away if there's no age limit.
+pub struct BackgroundFlusher {
+    storage: LsmStorage,
+    stopped: Mutex<bool>,
+}
+
+impl BackgroundFlusher {
+    pub fn new(storage: LsmStorage) -> Self {
+        Self {
+            storage,
+            stopped: Mutex::new(false),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let Some(max_age) = self.storage.max_memtable_age else {
+            return Ok(());
+        };
+        loop {
+            let due_in = self.storage.memtable_overdue_in(&self.storage.active_memtable());
+            let wait = due_in.unwrap_or(max_age / 4).max(MIN_CHECK_INTERVAL);
+            tokio::time::sleep(wait).await;
+
+            if *self.stopped.lock().await {
+                break;
+            }
+
+            if let Err(e) = self.storage.schedule_flush() {
+                warn!("Background flush of an old memtable failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn stop(&self) {
+        *self.stopped.lock().await = true;
+    }
+
+    // Runs `start` under the supervisor so a panicking flush restarts the loop
+    pub fn spawn_supervised(self: Arc<Self>, supervisor: &TaskSupervisor) -> JoinHandle<Result<()>> {
+        supervisor.supervise("background-flusher", move || {
+            let flusher = Arc::clone(&self);
+            async move { flusher.start().await }
+        })
+    }
+}
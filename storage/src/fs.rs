@@ -0,0 +1,116 @@
+use memmap::Mmap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// A whole file's bytes, read in place
+pub type FileData = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+// Where `LsmStorage` keeps its SSTables and WAL, set with
+// `StorageConfig::with_file_system`. `LocalFs` is the default; other backends,
+// such as an object store, only need whole-file writes, appends for the WAL,
+// and renames. Directories need not exist as such: a backend may create them
+// implicitly and treat `create_dir_all` as a no-op.
+pub trait FileSystem: Send + Sync + std::fmt::Debug {
+    // The whole file, for tables read in place. `LocalFs` maps it.
+    fn open(&self, path: &Path) -> io::Result<FileData>;
+
+    // Fills `buf` from `offset`, returning how much was read: less than
+    // `buf.len()` only at the end of the file
+    fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    // Opens the file for appending, creating it if it's missing
+    fn append(&self, path: &Path) -> io::Result<Box<dyn AppendFile>>;
+
+    // Replaces the file with `data`, which is durable once this returns
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    // Files and directories directly under `dir`; NotFound if there's no `dir`
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()>;
+}
+
+// A file open for appends, such as the live WAL
+pub trait AppendFile: Send + Sync {
+    // Appends `data`, which has reached the backend once this returns
+    fn append(&mut self, data: &[u8]) -> io::Result<()>;
+
+    // Makes everything appended so far durable. A backend whose appends are
+    // durable once they return has nothing more to do.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Plain files under `std::fs`, with tables memory-mapped
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalFs;
+
+impl FileSystem for LocalFs {
+    fn open(&self, path: &Path) -> io::Result<FileData> {
+        let file = File::open(path)?;
+        // Empty files can't be mapped
+        if file.metadata()?.len() == 0 {
+            return Ok(Arc::new(Vec::new()));
+        }
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Arc::new(mmap))
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        use std::io::{Read, Seek, SeekFrom};
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn AppendFile>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(data)?;
+        file.sync_all()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect()
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(dir)
+    }
+}
+
+impl AppendFile for File {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
@@ -1,15 +1,26 @@
 use rust_db_core::{DbError,Result,GcConfig,GcStats,VersionTimestamp};
 use super::mvcc::MvccStorage;
 use std::{collections::HashMap, sync::Arc};
+use std::sync::atomic::{AtomicBool,Ordering};
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use log::{info,warn,debug};
+use crate::supervisor::TaskSupervisor;
 
 pub struct GarbageCollector{
     config:GcConfig,
     mvcc_storage:Arc<MvccStorage>,
-    is_collecting:Mutex<bool>,
+    is_collecting:AtomicBool,
 }
 
+// Resets the in-progress flag even if a GC run panics
+struct CollectingGuard<'a>(&'a AtomicBool);
+
+impl Drop for CollectingGuard<'_>{
+    fn drop(&mut self){
+        self.0.store(false,Ordering::SeqCst);
+    }
+}
 
 
 impl GarbageCollector{
@@ -17,18 +28,15 @@ impl GarbageCollector{
         Self{
             config,
             mvcc_storage,
-            is_collecting:Mutex::new(false),
+            is_collecting:AtomicBool::new(false),
         }
     }
 
     pub async fn run_garbage_collection(&self)->Result<GcStats>{
-        let mut collecting = self.is_collecting.lock().await;
-        if *collecting{
+        if self.is_collecting.swap(true,Ordering::SeqCst){
             return Err(DbError::GarbageCollection("GC  already in progress".to_string()));
         }
-
-        *collecting = true;
-        drop(collecting);
+        let _collecting = CollectingGuard(&self.is_collecting);
 
         let start_time = std::time::Instant::now();
         let mut stats = GcStats{
@@ -41,24 +49,50 @@ impl GarbageCollector{
 
         let oldest_snapshot_ts = self.get_oldest_active_snapshot().await;
         let retention_threshold = self.calculate_retention_threshold().await;
+        self.mvcc_storage.drop_base_seeds(oldest_snapshot_ts);
 
         let versions_to_remove = self.find_obsolete_versions(oldest_snapshot_ts,retention_threshold).await;
 
         for(key, version_index) in versions_to_remove{
-            if let Err(e) = self.remove_version(&key,version_index).await{
-                warn!("Failed to remove version for key {:?}: {}",key,e);
-            }else{
-                stats.versions_removed+=1;
+            match self.remove_version(&key,version_index).await{
+                Ok(bytes)=>{
+                    stats.versions_removed+=1;
+                    stats.space_reclaimed+=bytes;
+                }
+                Err(e)=>warn!("Failed to remove version for key {:?}: {}",key,e),
             }
         }
 
         stats.duration_ms = start_time.elapsed().as_millis() as u64;
         info!("Garbage collection completed {:?}",stats);
 
-        *self.is_collecting.lock().await = false;
         Ok(stats)
     }
 
+    // Dry run of `run_garbage_collection`: the same selection, reporting what
+    // would be removed and reclaimed while leaving every version in place
+    pub async fn estimate_gc(&self)->Result<GcStats>{
+        let start_time = std::time::Instant::now();
+        let oldest_snapshot_ts = self.get_oldest_active_snapshot().await;
+        let retention_threshold = self.calculate_retention_threshold().await;
+        let versions_to_remove = self.find_obsolete_versions(oldest_snapshot_ts,retention_threshold).await;
+
+        let version_store = self.mvcc_storage.get_version_store();
+        let space_reclaimed = versions_to_remove.iter()
+            .filter_map(|(key,index)| version_store.get(key)?.get(*index).map(|v| Self::version_size(key,&v.value)))
+            .sum();
+
+        Ok(GcStats{
+            versions_removed:versions_to_remove.len(),
+            space_reclaimed,
+            duration_ms:start_time.elapsed().as_millis() as u64,
+        })
+    }
+
+    fn version_size(key:&[u8],value:&[u8])->u64{
+        (key.len()+value.len()) as u64
+    }
+
     async fn get_oldest_active_snapshot(&self) -> VersionTimestamp{
         self.mvcc_storage.get_oldest_snapshot_timestamp()
     }
@@ -84,7 +118,8 @@ impl GarbageCollector{
                 continue;
             }
 
-            for (index,version) in versions.iter().enumerate(){
+            // Base-value versions go in `drop_base_seeds` once nothing needs them
+            for (index,version) in versions.iter().enumerate().filter(|(_,version)| !MvccStorage::is_base_seed(version)){
                 if version.created_ts <retention_threshold && version.created_ts<oldest_snapshot_ts && versions.len()> self.config.min_versions_to_keep as usize {
                     obsolete_versions.insert(key.clone(),index);
                     break;
@@ -94,16 +129,22 @@ impl GarbageCollector{
         obsolete_versions
     }
 
-    async fn remove_version(&self,key:&[u8],version_index:usize)->Result<()>{
+    // Returns the bytes freed, zero if the version was already gone
+    async fn remove_version(&self,key:&[u8],version_index:usize)->Result<u64>{
         let mut version_store = self.mvcc_storage.get_version_store_mut();
 
         if let Some(versions) = version_store.get_mut(key){
             if version_index <versions.len(){
+                self.mvcc_storage.spill_version(key,&versions[version_index])?;
                 let removed_version = versions.remove(version_index);
                 debug!("Removed version for key {:?} created at {}", key,removed_version.created_ts.as_u64());
+                // The removed value was current until its successor was written
+                let lost_until = versions.get(version_index).map_or(VersionTimestamp::now(),|next| next.created_ts);
+                self.mvcc_storage.advance_history_horizon(lost_until);
+                return Ok(Self::version_size(key,&removed_version.value));
             }
         }
-        Ok(())
+        Ok(0)
     }
 }
 
@@ -142,4 +183,12 @@ impl BackgroundGc{
     pub async fn stop(&self){
         *self.stopped.lock().await=true;
     }
+
+    // Runs `start` under the supervisor so a panicking GC pass restarts the loop
+    pub fn spawn_supervised(self:Arc<Self>,supervisor:&TaskSupervisor)->JoinHandle<Result<()>>{
+        supervisor.supervise("background-gc",move ||{
+            let gc = Arc::clone(&self);
+            async move { gc.start().await }
+        })
+    }
 }
\ No newline at end of file
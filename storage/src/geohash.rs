@@ -0,0 +1,83 @@
+// Geohash encoding for the geo index. A geohash interleaves longitude and
+// latitude bits (longitude first) and writes them five at a time in base32,
+// so points sharing a prefix lie in the same cell and prefixes nest.
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+// Characters stored per index entry; about 5m x 5m cells
+pub const INDEX_PRECISION: usize = 9;
+
+// Upper bound on cells scanned for one bounding-box query
+const MAX_COVER_CELLS: usize = 64;
+
+pub fn encode(lat: f64, lon: f64, precision: usize) -> String {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+
+    for _ in 0..precision {
+        let mut idx = 0usize;
+        for _ in 0..5 {
+            let (range, value) = if even { (&mut lon_range, lon) } else { (&mut lat_range, lat) };
+            let mid = (range.0 + range.1) / 2.0;
+            idx <<= 1;
+            if value >= mid {
+                idx |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+        hash.push(BASE32[idx] as char);
+    }
+    hash
+}
+
+// Cell height and width in degrees for hashes of `precision` characters
+fn cell_size(precision: usize) -> (f64, f64) {
+    let bits = 5 * precision as i32;
+    let lon_bits = (bits + 1) / 2;
+    let lat_bits = bits / 2;
+    (180.0 / 2f64.powi(lat_bits), 360.0 / 2f64.powi(lon_bits))
+}
+
+// Geohash prefixes whose cells together cover the box, using the longest
+// prefix length that keeps the cell count within `MAX_COVER_CELLS`
+pub fn covering_prefixes(min: (f64, f64), max: (f64, f64)) -> Vec<String> {
+    let (min_lat, min_lon) = (min.0.clamp(-90.0, 90.0), min.1.clamp(-180.0, 180.0));
+    let (max_lat, max_lon) = (max.0.clamp(-90.0, 90.0), max.1.clamp(-180.0, 180.0));
+
+    let mut precision = INDEX_PRECISION;
+    let (lat_cells, lon_cells) = loop {
+        let (height, width) = cell_size(precision);
+        let lat_cells = cell_index(max_lat, -90.0, height) - cell_index(min_lat, -90.0, height) + 1;
+        let lon_cells = cell_index(max_lon, -180.0, width) - cell_index(min_lon, -180.0, width) + 1;
+        if lat_cells * lon_cells <= MAX_COVER_CELLS || precision == 1 {
+            break (lat_cells, lon_cells);
+        }
+        precision -= 1;
+    };
+
+    let (height, width) = cell_size(precision);
+    let first_lat = cell_index(min_lat, -90.0, height);
+    let first_lon = cell_index(min_lon, -180.0, width);
+    let mut prefixes = Vec::with_capacity(lat_cells * lon_cells);
+    for i in 0..lat_cells {
+        for j in 0..lon_cells {
+            // Encode each cell's centre to get its prefix
+            let lat = -90.0 + (first_lat + i) as f64 * height + height / 2.0;
+            let lon = -180.0 + (first_lon + j) as f64 * width + width / 2.0;
+            prefixes.push(encode(lat, lon, precision));
+        }
+    }
+    prefixes.sort();
+    prefixes.dedup();
+    prefixes
+}
+
+fn cell_index(value: f64, origin: f64, size: f64) -> usize {
+    let cells = ((value - origin) / size).floor() as usize;
+    // The top edge belongs to the last cell rather than one past it
+    cells.min((((origin.abs() * 2.0) / size).round() as usize).saturating_sub(1))
+}
@@ -0,0 +1,34 @@
+use siphasher::sip::SipHasher13;
+use std::hash::Hasher;
+
+// Hash used for SSTable bloom filters and hash index keys. FNV-1a is much
+// cheaper than SipHash on short keys but offers no protection against
+// adversarially chosen keys, so SipHash stays available for that case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashFn {
+    #[default]
+    Fnv1a,
+    SipHash,
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl HashFn {
+    pub fn hash(&self, bytes: &[u8]) -> u64 {
+        match self {
+            HashFn::Fnv1a => bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &b| {
+                (hash ^ b as u64).wrapping_mul(FNV_PRIME)
+            }),
+            // Hash index keys are these digests, so the algorithm and keys are
+            // pinned rather than left to std's `DefaultHasher`, which may change
+            // between releases. SipHash-1-3 with zero keys is what it computes
+            // today, so indexes built before keep their keys.
+            HashFn::SipHash => {
+                let mut hasher = SipHasher13::new_with_keys(0, 0);
+                hasher.write(bytes);
+                hasher.finish()
+            }
+        }
+    }
+}
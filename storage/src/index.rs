@@ -1,31 +1,93 @@
-use std::collections::HashMap;
-use crate::LsmStorage;
-use rust_db_core::{Result, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use crate::geohash;
+use crate::hash::HashFn;
+use crate::{LsmStorage, WriteBatch};
+use rust_db_core::{DbError, DecodeKeyComponent, KeyComponent, Result, Value};
 
 #[derive(Debug, Clone)]
 pub struct IndexDescriptor {
     pub name: String,
+    // A field name, or a dotted path such as `address.city`
     pub field: String,
     pub index_type: IndexType,
+    // No two records may hold the same value; writes that would are refused
+    // with a `DbError::Schema`
+    pub unique: bool,
+}
+
+impl IndexDescriptor {
+    // A non-unique index; see `unique`
+    pub fn new(name: &str, field: &str, index_type: IndexType) -> Self {
+        Self { name: name.to_string(), field: field.to_string(), index_type, unique: false }
+    }
+
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum IndexType {
     Hash,
     BTree,
+    // Indexes `Value::Point`s by geohash for bounding-box queries
+    Geo,
+}
+
+// Pulls the indexed field's value out of a stored record
+pub type FieldExtractor = Arc<dyn Fn(&[u8]) -> Result<Option<Value>> + Send + Sync>;
+
+// Records under `key_prefix` whose entries an index maintains on write
+#[derive(Clone)]
+struct IndexSource {
+    key_prefix: Vec<u8>,
+    extract: FieldExtractor,
+}
+
+// Result of comparing an index against the records it's built from. `missing`
+// holds record keys with no index entry, `orphaned` holds index entries whose
+// record is gone or no longer has the indexed value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexVerifyReport {
+    pub index_name: String,
+    pub records_checked: usize,
+    pub missing: Vec<Vec<u8>>,
+    pub orphaned: Vec<Vec<u8>>,
+    pub repaired: bool,
 }
 
+impl IndexVerifyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+#[derive(Clone)]
 pub struct IndexManager {
     indexes: HashMap<String, IndexDescriptor>,
+    sources: HashMap<String, IndexSource>,
+    hash_fn: HashFn,
 }
 
 impl IndexManager {
     pub fn new() -> Self {
         Self {
             indexes: HashMap::new(),
+            sources: HashMap::new(),
+            hash_fn: HashFn::default(),
         }
     }
 
+    pub fn hash_fn(&self) -> HashFn {
+        self.hash_fn
+    }
+
+    pub fn set_hash_fn(&mut self, hash_fn: HashFn) {
+        self.hash_fn = hash_fn;
+    }
+
     pub async fn create_index(&mut self, descriptor: IndexDescriptor) -> Result<()> {
         self.indexes.insert(descriptor.name.clone(), descriptor);
         Ok(())
@@ -33,6 +95,83 @@ impl IndexManager {
 
     pub async fn drop_index(&mut self, index_name: &str) -> Result<()> {
         self.indexes.remove(index_name);
+        self.sources.remove(index_name);
+        Ok(())
+    }
+
+    pub fn register_source(&mut self, index_name: &str, key_prefix: &[u8], extract: FieldExtractor) -> Result<()> {
+        self.descriptor(index_name)?;
+        self.sources.insert(
+            index_name.to_string(),
+            IndexSource {
+                key_prefix: key_prefix.to_vec(),
+                extract,
+            },
+        );
+        Ok(())
+    }
+
+    // Adds entries for a newly written record to every index sourced from its key
+    pub async fn index_record(&self, storage: &LsmStorage, record_key: &[u8], data: &[u8]) -> Result<()> {
+        for index_key in self.index_entries(record_key, data)? {
+            storage.put(&index_key, record_key).await?;
+        }
+        Ok(())
+    }
+
+    // Whether any index is sourced from `record_key`, so writing it changes index entries
+    pub(crate) fn indexes_key(&self, record_key: &[u8]) -> bool {
+        self.sources.values().any(|source| record_key.starts_with(&source.key_prefix))
+    }
+
+    // Keys of the entries `index_record` would write, each of which maps to
+    // `record_key`. `data` is a live record's value, tag already stripped, so
+    // an empty one is indexed like any other; deleted records never get here,
+    // as `get` and `batch_record_write` hand them over as None.
+    pub(crate) fn index_entries(&self, record_key: &[u8], data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut entries = Vec::new();
+        for (index_name, source) in &self.sources {
+            if !record_key.starts_with(&source.key_prefix) {
+                continue;
+            }
+            if let Some(field_value) = (source.extract)(data)? {
+                let descriptor = self.descriptor(index_name)?;
+                entries.push(self.build_index_key(descriptor, &field_value, record_key)?);
+            }
+        }
+        Ok(entries)
+    }
+
+    // Adds a write of `record_key` to `batch` with the index changes it makes:
+    // entries for `previous` that `value` doesn't keep are deleted and those for
+    // `value` are put. A `value` of None deletes the record.
+    pub(crate) fn batch_record_write(
+        &self,
+        batch: &mut WriteBatch,
+        record_key: &[u8],
+        previous: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<()> {
+        let previous = match previous {
+            Some(data) => self.index_entries(record_key, data)?,
+            None => Vec::new(),
+        };
+        let current = match value {
+            Some(data) => {
+                batch.put(record_key, data);
+                self.index_entries(record_key, data)?
+            }
+            None => {
+                batch.delete(record_key);
+                Vec::new()
+            }
+        };
+        for index_key in previous.iter().filter(|index_key| !current.contains(index_key)) {
+            batch.delete(index_key);
+        }
+        for index_key in &current {
+            batch.put(index_key, record_key);
+        }
         Ok(())
     }
 
@@ -43,11 +182,11 @@ impl IndexManager {
         record_key: &[u8],
         field_value: &Value,
     ) -> Result<()> {
-        if let Some(descriptor) = self.indexes.get(index_name) {
-            let index_key = self.build_index_key(&descriptor.name, field_value, record_key);
-            storage.put(&index_key, &[]).await?;
-        }
-        Ok(())
+        self.check_unique(storage, index_name, field_value, |key| key == record_key).await?;
+        let descriptor = self.descriptor(index_name)?;
+        let index_key = self.build_index_key(descriptor, field_value, record_key)?;
+        // The value must be non-empty, or the entry would read as a deleted one
+        storage.put(&index_key, record_key).await
     }
 
     pub async fn lookup_index(
@@ -56,40 +195,340 @@ impl IndexManager {
         index_name: &str,
         value: &Value,
     ) -> Result<Vec<Vec<u8>>> {
-        let prefix = self.build_index_prefix(index_name, value);
+        let descriptor = self.descriptor(index_name)?;
+        let prefix = self.build_index_prefix(descriptor, value)?;
         let records = storage.scan(&prefix).await?;
+        // A hash index files entries under the value's digest, which colliding
+        // values share, so with a registered source each record is read back
+        // and kept only if it holds `value`
+        let source = self.sources.get(index_name).filter(|_| matches!(descriptor.index_type, IndexType::Hash));
+
+        let mut record_keys = Vec::new();
+        for (key, _) in records {
+            let record_key = key[prefix.len()..].to_vec();
+            if let Some(source) = source {
+                let Some(data) = storage.get(&record_key).await? else {
+                    continue;
+                };
+                if (source.extract)(&data)?.as_ref() != Some(value) {
+                    continue;
+                }
+            }
+            record_keys.push(record_key);
+        }
+        Ok(record_keys)
+    }
+
+    // Whether any record has `value` in the index, for uniqueness checks. Walks
+    // the value's entries with a cursor and stops at the first live one, so
+    // values shared by many records cost no more than unique ones. With a
+    // registered source, entries left behind by updates and hash collisions are
+    // ruled out by reading the record back.
+    pub async fn index_contains(&self, storage: &LsmStorage, index_name: &str, value: &Value) -> Result<bool> {
+        Ok(self.find_holder(storage, index_name, value, |_| false).await?.is_some())
+    }
 
-        let record_keys = records
-            .into_iter()
-            .map(|(key, _)| self.extract_record_key(&key))
-            .collect();
+    // The first record holding `value` in the index, passing over those `skip` accepts
+    async fn find_holder(
+        &self,
+        storage: &LsmStorage,
+        index_name: &str,
+        value: &Value,
+        skip: impl Fn(&[u8]) -> bool,
+    ) -> Result<Option<Vec<u8>>> {
+        let descriptor = self.descriptor(index_name)?;
+        let prefix = self.build_index_prefix(descriptor, value)?;
+        let source = self.sources.get(index_name);
+        let mut cursor = storage.scan_cursor(&prefix)?;
+        while let Some((_, record_key)) = cursor.next().await? {
+            if skip(&record_key) {
+                continue;
+            }
+            let Some(source) = source else {
+                return Ok(Some(record_key));
+            };
+            if let Some(data) = storage.get(&record_key).await? {
+                if (source.extract)(&data)?.as_ref() == Some(value) {
+                    return Ok(Some(record_key));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn is_unique(&self, index_name: &str) -> bool {
+        self.indexes.get(index_name).is_some_and(|descriptor| descriptor.unique)
+    }
+
+    // The (index, value) pairs a record would claim in unique indexes sourced
+    // from its key. As in `index_entries`, `data` is a live value, even if empty.
+    pub(crate) fn unique_values(&self, record_key: &[u8], data: &[u8]) -> Result<Vec<(String, Value)>> {
+        let mut claims = Vec::new();
+        for (index_name, source) in &self.sources {
+            if !record_key.starts_with(&source.key_prefix) || !self.descriptor(index_name)?.unique {
+                continue;
+            }
+            if let Some(field_value) = (source.extract)(data)? {
+                claims.push((index_name.clone(), field_value));
+            }
+        }
+        Ok(claims)
+    }
 
+    // Fails if the index is unique and a record other than those `skip`
+    // accepts already holds `value`. Callers hold `UniqueLocks::lock` for the
+    // value from this check until their write lands.
+    pub(crate) async fn check_unique(
+        &self,
+        storage: &LsmStorage,
+        index_name: &str,
+        value: &Value,
+        skip: impl Fn(&[u8]) -> bool,
+    ) -> Result<()> {
+        if !self.descriptor(index_name)?.unique {
+            return Ok(());
+        }
+        match self.find_holder(storage, index_name, value, skip).await? {
+            Some(_) => Err(unique_violation(index_name)),
+            None => Ok(()),
+        }
+    }
+
+    // One `lookup_index` per value, for `In` filters. Keys listed under several
+    // values come back once, in the order first seen.
+    pub async fn get_by_index_multi(
+        &self,
+        storage: &LsmStorage,
+        index_name: &str,
+        values: &[Value],
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut seen = HashSet::new();
+        let mut record_keys = Vec::new();
+        for value in values {
+            for key in self.lookup_index(storage, index_name, value).await? {
+                if seen.insert(key.clone()) {
+                    record_keys.push(key);
+                }
+            }
+        }
         Ok(record_keys)
     }
 
-    fn build_index_key(&self, index_name: &str, field_value: &Value, record_key: &[u8]) -> Vec<u8> {
-        let value_bytes = bincode::serialize(field_value).unwrap();
-        let mut key = Vec::new();
-        key.extend(b"index:");
-        key.extend(index_name.as_bytes());
-        key.extend(b":");
-        key.extend(&value_bytes);
-        key.extend(b":");
-        key.extend(record_key);
-        key
+    // Record keys of every point indexed in a geohash cell overlapping the box;
+    // callers filter these candidates against the exact coordinates
+    pub async fn lookup_geo(
+        &self,
+        storage: &LsmStorage,
+        index_name: &str,
+        min: (f64, f64),
+        max: (f64, f64),
+    ) -> Result<Vec<Vec<u8>>> {
+        let descriptor = self.descriptor(index_name)?;
+        if !matches!(descriptor.index_type, IndexType::Geo) {
+            return Err(DbError::Query(format!("index {} is not a geo index", index_name)));
+        }
+
+        let mut record_keys = Vec::new();
+        for cell in geohash::covering_prefixes(min, max) {
+            let mut prefix = Vec::new();
+            prefix.extend(b"index:");
+            prefix.extend(index_name.as_bytes());
+            prefix.extend(b":");
+            prefix.extend(cell.as_bytes());
+
+            for (key, _) in storage.scan(&prefix).await? {
+                record_keys.push(self.extract_record_key(&key));
+            }
+        }
+        record_keys.sort();
+        record_keys.dedup();
+        Ok(record_keys)
     }
 
-    fn build_index_prefix(&self, index_name: &str, value: &Value) -> Vec<u8> {
-        let value_bytes = bincode::serialize(value).unwrap();
+    // Recomputes the entries an index should hold from its registered record
+    // source and diffs them against the stored ones, optionally fixing the index
+    pub async fn verify_index(&self, storage: &LsmStorage, index_name: &str, repair: bool) -> Result<IndexVerifyReport> {
+        let descriptor = self.descriptor(index_name)?;
+        let source = self.sources.get(index_name).ok_or_else(|| {
+            DbError::Query(format!("index {} has no registered record source", index_name))
+        })?;
+
+        let mut report = IndexVerifyReport {
+            index_name: index_name.to_string(),
+            ..IndexVerifyReport::default()
+        };
+        // Expected index key -> record key
+        let mut expected = BTreeMap::new();
+        for (record_key, data) in storage.scan(&source.key_prefix).await? {
+            if record_key.starts_with(b"index:") {
+                continue;
+            }
+            report.records_checked += 1;
+            if let Some(field_value) = (source.extract)(&data)? {
+                let index_key = self.build_index_key(descriptor, &field_value, &record_key)?;
+                expected.insert(index_key, record_key);
+            }
+        }
+
+        let mut index_prefix = Vec::new();
+        index_prefix.extend(b"index:");
+        index_prefix.extend(index_name.as_bytes());
+        index_prefix.extend(b":");
+        for (index_key, _) in storage.scan(&index_prefix).await? {
+            if expected.remove(&index_key).is_none() {
+                report.orphaned.push(index_key);
+            }
+        }
+        let missing: Vec<(Vec<u8>, Vec<u8>)> = expected.into_iter().collect();
+        report.missing = missing.iter().map(|(_, record_key)| record_key.clone()).collect();
+
+        if repair && !report.is_consistent() {
+            for (index_key, record_key) in &missing {
+                storage.put(index_key, record_key).await?;
+            }
+            for index_key in &report.orphaned {
+                storage.delete(index_key).await?;
+            }
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+
+    // A BTree index on `field` maintained for `table`'s records. Only BTree
+    // index keys hold the value itself; hash and geo keys hold a digest or cell.
+    pub fn covering_index(&self, table: &str, field: &str) -> Option<String> {
+        self.index_on(table, field, |index_type| matches!(index_type, IndexType::BTree))
+    }
+
+    // An index on `table`'s `field` that answers equality lookups, as Hash and
+    // BTree indexes do
+    pub fn lookup_index_on(&self, table: &str, field: &str) -> Option<String> {
+        self.index_on(table, field, |index_type| !matches!(index_type, IndexType::Geo))
+    }
+
+    fn index_on(&self, table: &str, field: &str, usable: impl Fn(&IndexType) -> bool) -> Option<String> {
+        self.sources.iter().find_map(|(index_name, source)| {
+            let descriptor = self.indexes.get(index_name)?;
+            let table_prefix = source.key_prefix.strip_suffix(b":").unwrap_or(&source.key_prefix);
+            let found = usable(&descriptor.index_type)
+                && descriptor.field == field
+                && table_prefix == table.as_bytes();
+            found.then(|| index_name.clone())
+        })
+    }
+
+    // Every (value, record key) pair in a BTree index, decoded from the index
+    // alone, in index order: by value as `Value::sort_cmp` orders them, then by
+    // record key. Only entries written outside `insert`, `delete` and commits
+    // can be stale; `repair_index` finds those.
+    pub async fn scan_index_values(&self, storage: &LsmStorage, index_name: &str) -> Result<Vec<(Value, Vec<u8>)>> {
+        let prefix = self.btree_prefix(index_name)?;
+        storage.scan(&prefix).await?.into_iter().map(|entry| Self::decode_btree_entry(&prefix, entry)).collect()
+    }
+
+    // Up to `limit` entries of `scan_index_values`, starting just past the
+    // `after` entry when given, read with a range scan that stops at the limit
+    pub async fn scan_index_values_after(
+        &self,
+        storage: &LsmStorage,
+        index_name: &str,
+        after: Option<(&Value, &[u8])>,
+        limit: usize,
+    ) -> Result<Vec<(Value, Vec<u8>)>> {
+        let prefix = self.btree_prefix(index_name)?;
+        let start = match after {
+            Some((value, record_key)) => {
+                let mut start = self.build_index_key(self.descriptor(index_name)?, value, record_key)?;
+                start.push(0);
+                start
+            }
+            None => prefix.clone(),
+        };
+        // Past every key under the prefix, which ends in ':'
+        let mut end = prefix.clone();
+        *end.last_mut().unwrap() += 1;
+        let entries = storage.scan_range_limited(&start, &end, limit).await?;
+        entries.into_iter().map(|entry| Self::decode_btree_entry(&prefix, entry)).collect()
+    }
+
+    // `scan_index_values`, for callers building their own operators on an index
+    pub async fn scan_index(&self, storage: &LsmStorage, index_name: &str) -> Result<Vec<(Value, Vec<u8>)>> {
+        self.scan_index_values(storage, index_name).await
+    }
+
+    // The prefix every entry of a BTree index is stored under
+    fn btree_prefix(&self, index_name: &str) -> Result<Vec<u8>> {
+        let descriptor = self.descriptor(index_name)?;
+        if !matches!(descriptor.index_type, IndexType::BTree) {
+            return Err(DbError::Query(format!("index {} doesn't store its values", index_name)));
+        }
         let mut prefix = Vec::new();
         prefix.extend(b"index:");
         prefix.extend(index_name.as_bytes());
         prefix.extend(b":");
+        Ok(prefix)
+    }
+
+    fn decode_btree_entry(prefix: &[u8], (index_key, record_key): (Vec<u8>, Vec<u8>)) -> Result<(Value, Vec<u8>)> {
+        // The value's encoding may itself contain ':', so decode rather than split
+        let mut encoded = &index_key[prefix.len()..];
+        Ok((Value::decode_key(&mut encoded)?, record_key))
+    }
+
+    pub fn descriptor_field(&self, index_name: &str) -> Result<String> {
+        Ok(self.descriptor(index_name)?.field.clone())
+    }
+
+    fn descriptor(&self, index_name: &str) -> Result<&IndexDescriptor> {
+        self.indexes
+            .get(index_name)
+            .ok_or_else(|| DbError::Query(format!("index not found: {}", index_name)))
+    }
+
+    // Encoding of an indexed value inside index keys. Hash indexes store the hex
+    // digest, which keeps the key short and free of the ':' separator. BTree
+    // indexes store the value's `KeyComponent` encoding, which sorts like the
+    // values and never prefixes another value's, so a range scan over an index
+    // reads its entries in value order.
+    fn encode_value(&self, descriptor: &IndexDescriptor, field_value: &Value) -> Result<Vec<u8>> {
+        match (&descriptor.index_type, field_value) {
+            (IndexType::Geo, Value::Point { lat, lon }) => {
+                Ok(geohash::encode(*lat, *lon, geohash::INDEX_PRECISION).into_bytes())
+            }
+            (IndexType::Geo, other) => Err(DbError::Query(format!(
+                "geo index {} can only index points, got {:?}",
+                descriptor.name, other
+            ))),
+            (IndexType::Hash, _) => {
+                let value_bytes = bincode::serialize(field_value).unwrap();
+                Ok(format!("{:016x}", self.hash_fn.hash(&value_bytes)).into_bytes())
+            }
+            (IndexType::BTree, _) => {
+                let mut value_bytes = Vec::new();
+                field_value.encode_key(&mut value_bytes);
+                Ok(value_bytes)
+            }
+        }
+    }
+
+    fn build_index_key(&self, descriptor: &IndexDescriptor, field_value: &Value, record_key: &[u8]) -> Result<Vec<u8>> {
+        let mut key = self.build_index_prefix(descriptor, field_value)?;
+        key.extend(record_key);
+        Ok(key)
+    }
+
+    fn build_index_prefix(&self, descriptor: &IndexDescriptor, value: &Value) -> Result<Vec<u8>> {
+        let value_bytes = self.encode_value(descriptor, value)?;
+        let mut prefix = Vec::new();
+        prefix.extend(b"index:");
+        prefix.extend(descriptor.name.as_bytes());
+        prefix.extend(b":");
         prefix.extend(&value_bytes);
         prefix.extend(b":");
-        prefix
+        Ok(prefix)
     }
 
+    // Geohash cells never contain ':', so geo index keys can be split
     fn extract_record_key(&self, index_key: &[u8]) -> Vec<u8> {
         // Extract the record key part from the index key
         // Format: "index:{name}:{value}:{record_key}"
@@ -100,4 +539,76 @@ impl IndexManager {
             Vec::new()
         }
     }
-}
\ No newline at end of file
+}
+
+// Locks held from a unique index check until the write it allowed lands. Each
+// (index, value) pair maps to one of a fixed set of stripes, so a write only
+// waits on others claiming a value in the same stripe, and writes claiming no
+// unique values take none.
+pub(crate) struct UniqueLocks {
+    stripes: Vec<tokio::sync::Mutex<()>>,
+}
+
+const UNIQUE_LOCK_STRIPES: usize = 64;
+
+impl UniqueLocks {
+    pub(crate) fn new() -> Self {
+        Self { stripes: (0..UNIQUE_LOCK_STRIPES).map(|_| tokio::sync::Mutex::new(())).collect() }
+    }
+
+    pub(crate) async fn lock(&self, claims: &[(String, Value)]) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        let stripes = claims.iter().map(|(index_name, value)| Self::stripe(index_name, value)).collect();
+        lock_stripes(&self.stripes, stripes).await
+    }
+
+    fn stripe(index_name: &str, value: &Value) -> usize {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        index_name.hash(&mut hasher);
+        bincode::serialize(value).unwrap_or_default().hash(&mut hasher);
+        hasher.finish() as usize % UNIQUE_LOCK_STRIPES
+    }
+}
+
+// Locks held from reading the value a write replaces until the write lands,
+// so two writers to one record can't both work out index changes from the
+// same previous value. Keys map to stripes as in `UniqueLocks`.
+pub(crate) struct RecordLocks {
+    stripes: Vec<tokio::sync::Mutex<()>>,
+}
+
+const RECORD_LOCK_STRIPES: usize = 64;
+
+impl RecordLocks {
+    pub(crate) fn new() -> Self {
+        Self { stripes: (0..RECORD_LOCK_STRIPES).map(|_| tokio::sync::Mutex::new(())).collect() }
+    }
+
+    pub(crate) async fn lock<'a>(&self, record_keys: impl IntoIterator<Item = &'a [u8]>) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        let stripes = record_keys.into_iter().map(Self::stripe).collect();
+        lock_stripes(&self.stripes, stripes).await
+    }
+
+    fn stripe(record_key: &[u8]) -> usize {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        record_key.hash(&mut hasher);
+        hasher.finish() as usize % RECORD_LOCK_STRIPES
+    }
+}
+
+// Taken in stripe order, so two writers locking overlapping stripes can't
+// each hold a lock the other is waiting on
+async fn lock_stripes(locks: &[tokio::sync::Mutex<()>], mut stripes: Vec<usize>) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+    stripes.sort_unstable();
+    stripes.dedup();
+    let mut guards = Vec::with_capacity(stripes.len());
+    for stripe in stripes {
+        guards.push(locks[stripe].lock().await);
+    }
+    guards
+}
+
+pub(crate) fn unique_violation(index_name: &str) -> DbError {
+    DbError::Schema(format!("unique constraint violated: {}", index_name))
+}
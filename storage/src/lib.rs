@@ -1,391 +1,2482 @@
-use rust_db_core::{Database, DbError, MvccDatabase, Result, Transaction, TransactionState, CompactionConfig, CompactionStats, GcConfig, GcStats};
+use rust_db_core::{Database, DbError, FieldAccess, MvccDatabase, Result, Schema, Transaction, TransactionState, BudgetedCompaction, CompactionConfig, CompactionStats, GcConfig, GcStats};
 use std::collections::{BTreeMap, HashMap};
-use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
-use memmap::Mmap;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use crossbeam_skiplist::SkipMap;
 use serde::{Serialize, Deserialize};
 use lazy_static::lazy_static;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 mod mvcc;
-pub use mvcc::{MvccStorage, TransactionManager};
+pub use mvcc::{MvccStorage, TransactionInfo, TransactionManager, VersionEncoding};
 
 mod index;
-pub use index::{IndexDescriptor, IndexManager, IndexType};
+mod geohash;
+pub use index::{FieldExtractor, IndexDescriptor, IndexManager, IndexType, IndexVerifyReport};
+use index::{RecordLocks, UniqueLocks};
 
 mod compaction;
 mod garbage_collector;
 mod security_layer;
+mod supervisor;
+mod negative_cache;
+mod batch;
+mod merge;
+mod hash;
+mod bloom;
+mod cursor;
+mod changes;
+mod tenant;
+mod backup;
+mod metrics;
+mod throttle;
+mod arena;
+mod recovery;
+mod repair;
+mod flusher;
+mod encryption;
+mod fs;
+mod schemas;
+mod read_view;
 
-pub use compaction::{CompactionManager,BackgroundCompactor};
+pub use compaction::{CompactionManager,BackgroundCompactor,CompactionProgressFn};
 pub use garbage_collector::{GarbageCollector,BackgroundGc};
 pub use security_layer::SecurityLayer;
+pub use supervisor::{SupervisorConfig, TaskSupervisor};
+pub use batch::WriteBatch;
+pub use merge::{CounterMergeOperator, MergeOperator};
+pub use hash::HashFn;
+pub use bloom::BloomFilter;
+use bloom::PrefixBloom;
+pub use cursor::ScanCursor;
+pub use changes::{ChangeEvent, ChangeKind, ChangeReceiver};
+use changes::ChangeFeed;
+pub use tenant::Tenant;
+pub use backup::BackupManifest;
+pub use recovery::RecoveryReport;
+pub use repair::{RepairAction, RepairReport};
+pub use flusher::BackgroundFlusher;
+pub use encryption::AtRestEncryption;
+use encryption::SealedTable;
+pub use fs::{AppendFile, FileData, FileSystem, LocalFs};
+pub use metrics::StorageStats;
+use metrics::WriteCounters;
+use negative_cache::NegativeCache;
+use arena::{Arena, ArenaSlice};
+use schemas::SchemaRegistry;
+use read_view::ReadView;
 
 lazy_static! {
     static ref FLUSH_THRESHOLD: usize = 1024 * 1024; // 1MB
 }
 
+// Past this many memtables waiting on their SSTables, writers flush inline
+const MAX_FROZEN_MEMTABLES: usize = 4;
+
+const SSTABLE_MAGIC: u32 = 0x5353_5442; // "SSTB"
+// Version 2 tags values; see `VALUE_TAG`
+const SSTABLE_FORMAT_VERSION: u32 = 2;
+const SSTABLE_FOOTER_LEN: usize = 16;
+
+// Number of recently-missed keys remembered by the read path
+const NEGATIVE_CACHE_CAPACITY: usize = 1024;
+
+// Values are held in the memtable, the WAL and SSTables behind this one-byte
+// tag, so an empty value is stored as one byte and can't be mistaken for a
+// tombstone, which is stored as no bytes at all. `put` adds the tag; `get` and
+// the scans strip it and leave tombstones out.
+const VALUE_TAG: u8 = 1;
+
+// A value as it's stored
+pub(crate) fn encode_cell(value: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(value.len() + 1);
+    cell.push(VALUE_TAG);
+    cell.extend_from_slice(value);
+    cell
+}
+
+// The value a stored cell holds, or None for a tombstone
+fn decode_cell(cell: &[u8]) -> Option<&[u8]> {
+    cell.split_first().map(|(_, value)| value)
+}
+
+pub(crate) fn into_value(mut cell: Vec<u8>) -> Option<Vec<u8>> {
+    if cell.is_empty() {
+        return None;
+    }
+    cell.remove(0);
+    Some(cell)
+}
+
+// Tags the value of a cell written before values carried a tag
+fn tag_legacy_cell(value: Vec<u8>) -> Vec<u8> {
+    if value.is_empty() {
+        value
+    } else {
+        encode_cell(&value)
+    }
+}
+
 // Write-Ahead Log for durability
+//
+// The log is a sequence of batches. Each batch is a fixed header (magic, entry
+// count, payload length, CRC32 of the payload) followed by the payload: every
+// entry as a u32 length prefix and its bincode encoding. Replay stops at the
+// first batch that is short or fails its checksum, so a torn write loses the
+// whole batch rather than part of it. Put entries hold the stored cell, tag
+// included; batches under the legacy magic predate the tag, and their values
+// are tagged as they're replayed. With encryption on, the payload is sealed
+// by `AtRestEncryption` and the batch gets its own magic; the length and
+// checksum are of the sealed payload.
 pub struct WriteAheadLog {
-    file: BufWriter<File>,
+    file: Box<dyn AppendFile>,
     path: PathBuf,
+    encryption: Option<AtRestEncryption>,
 }
 
+const WAL_BATCH_MAGIC: u32 = 0x5741_4C43; // "WALC"
+const LEGACY_WAL_BATCH_MAGIC: u32 = 0x5741_4C42; // "WALB"
+const ENCRYPTED_WAL_BATCH_MAGIC: u32 = 0x5741_4C45; // "WALE"
+const WAL_BATCH_HEADER_LEN: usize = 16;
+
 impl WriteAheadLog {
     pub fn new(path: &Path) -> Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
+        Self::open_in(&LocalFs, path)
+    }
+    
+    // Appends to the log at `path` on `fs`, creating it if it's missing
+    pub fn open_in(fs: &dyn FileSystem, path: &Path) -> Result<Self> {
+        let file = fs.append(path)
+            .map_err(DbError::storage)?;
             
         Ok(WriteAheadLog {
-            file: BufWriter::new(file),
+            file,
             path: path.to_path_buf(),
+            encryption: None,
         })
     }
     
+    // Seals batches written from now on; see `StorageConfig::with_encryption_key`
+    pub fn with_encryption(mut self, encryption: Option<AtRestEncryption>) -> Self {
+        self.encryption = encryption;
+        self
+    }
+    
+    // Starts an empty log at `path`, discarding any existing one
+    pub fn create(path: &Path) -> Result<Self> {
+        Self::create_in(&LocalFs, path)
+    }
+    
+    pub fn create_in(fs: &dyn FileSystem, path: &Path) -> Result<Self> {
+        fs.write(path, &[]).map_err(DbError::storage)?;
+        Self::open_in(fs, path)
+    }
+    
     pub fn write_entry(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        let entry = WalEntry {
+        self.write_batch(&[WalEntry::new(key, value)]).map(|_| ())
+    }
+    
+    // Appends all entries as a single checksummed batch in one write,
+    // returning how many bytes that added to the log
+    pub fn write_batch(&mut self, entries: &[WalEntry]) -> Result<usize> {
+        let mut payload = Vec::new();
+        for entry in entries {
+            let encoded = bincode::serialize(entry)
+                .map_err(DbError::serialization)?;
+            payload.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&encoded);
+        }
+        
+        let (magic, payload) = match &self.encryption {
+            Some(encryption) => (ENCRYPTED_WAL_BATCH_MAGIC, encryption.seal_wal_batch(payload)?),
+            None => (WAL_BATCH_MAGIC, payload),
+        };
+        
+        let mut batch = Vec::with_capacity(WAL_BATCH_HEADER_LEN + payload.len());
+        batch.extend_from_slice(&magic.to_le_bytes());
+        batch.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        batch.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        batch.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        batch.extend_from_slice(&payload);
+        
+        self.file.append(&batch)
+            .map_err(DbError::storage)?;
+            
+        Ok(batch.len())
+    }
+    
+    // Reads back every complete batch in the log, in write order
+    pub fn replay(path: &Path) -> Result<Vec<WalEntry>> {
+        Self::replay_with(&LocalFs, path, None)
+    }
+    
+    // `replay` for a log that may hold encrypted batches. Fails, rather than
+    // treating the rest of the log as torn, on a batch `encryption` can't open.
+    pub fn replay_with(fs: &dyn FileSystem, path: &Path, encryption: Option<&AtRestEncryption>) -> Result<Vec<WalEntry>> {
+        let file = match fs.open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(DbError::storage(e)),
+        };
+        let data = (*file).as_ref();
+        
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while let Some((batch, next)) = Self::read_batch(path, data, pos, encryption)? {
+            entries.extend(batch);
+            pos = next;
+        }
+        if pos < data.len() {
+            log::warn!("Discarding {} bytes of incomplete WAL batch in {:?}", data.len() - pos, path);
+        }
+        Ok(entries)
+    }
+    
+    // Decodes the batch starting at `pos`, returning None if it is torn or corrupt
+    fn read_batch(
+        path: &Path,
+        data: &[u8],
+        pos: usize,
+        encryption: Option<&AtRestEncryption>,
+    ) -> Result<Option<(Vec<WalEntry>, usize)>> {
+        let Some(header) = data.get(pos..pos + WAL_BATCH_HEADER_LEN) else {
+            return Ok(None);
+        };
+        let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        let magic = field(0);
+        if !matches!(magic, WAL_BATCH_MAGIC | LEGACY_WAL_BATCH_MAGIC | ENCRYPTED_WAL_BATCH_MAGIC) {
+            return Ok(None);
+        }
+        let (count, payload_len, checksum) = (field(1) as usize, field(2) as usize, field(3));
+        
+        let payload_start = pos + WAL_BATCH_HEADER_LEN;
+        let Some(payload) = data.get(payload_start..payload_start + payload_len) else {
+            return Ok(None);
+        };
+        if crc32fast::hash(payload) != checksum {
+            return Ok(None);
+        }
+        let opened;
+        let payload = if magic == ENCRYPTED_WAL_BATCH_MAGIC {
+            opened = AtRestEncryption::open_wal_batch(encryption, path, payload)?;
+            opened.as_slice()
+        } else {
+            payload
+        };
+        
+        let Some(mut entries) = Self::decode_entries(payload, count) else {
+            return Ok(None);
+        };
+        if magic == LEGACY_WAL_BATCH_MAGIC {
+            for entry in entries.iter_mut().filter(|entry| entry.kind == WalEntryKind::Put) {
+                entry.value = tag_legacy_cell(std::mem::take(&mut entry.value));
+            }
+        }
+        Ok(Some((entries, payload_start + payload_len)))
+    }
+    
+    // The `count` length-prefixed entries of a batch payload
+    fn decode_entries(payload: &[u8], count: usize) -> Option<Vec<WalEntry>> {
+        let mut entries: Vec<WalEntry> = Vec::with_capacity(count);
+        let mut offset = 0;
+        while offset < payload.len() {
+            let len = u32::from_le_bytes(payload.get(offset..offset + 4)?.try_into().ok()?) as usize;
+            offset += 4;
+            entries.push(bincode::deserialize(payload.get(offset..offset + len)?).ok()?);
+            offset += len;
+        }
+        (entries.len() == count).then_some(entries)
+    }
+    
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalEntryKind {
+    Put,
+    // `value` is an operand for the registered `MergeOperator`
+    Merge,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub timestamp: u64,
+    pub kind: WalEntryKind,
+}
+
+impl WalEntry {
+    pub fn new(key: &[u8], value: &[u8]) -> Self {
+        WalEntry {
             key: key.to_vec(),
             value: value.to_vec(),
             timestamp: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_micros() as u64,
-        };
-        
-        bincode::serialize_into(&mut self.file, &entry)
-            .map_err(|e| DbError::Serialization(e.to_string()))?;
-            
-        self.file.flush()
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        Ok(())
+            kind: WalEntryKind::Put,
+        }
+    }
+    
+    pub fn merge(key: &[u8], operand: &[u8]) -> Self {
+        WalEntry {
+            kind: WalEntryKind::Merge,
+            ..Self::new(key, operand)
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct WalEntry {
-    key: Vec<u8>,
-    value: Vec<u8>,
-    timestamp: u64,
+// Keys selected by a scan: everything under a prefix, or the half-open range [start, end)
+#[derive(Clone, Copy)]
+enum ScanBounds<'a> {
+    Prefix(&'a [u8]),
+    Range(&'a [u8], &'a [u8]),
+}
+
+impl<'a> ScanBounds<'a> {
+    fn start(&self) -> &'a [u8] {
+        match *self {
+            ScanBounds::Prefix(prefix) => prefix,
+            ScanBounds::Range(start, _) => start,
+        }
+    }
+    
+    // Whether a key at or after `start` is still selected; the first false ends the scan
+    fn still_within(&self, key: &[u8]) -> bool {
+        match *self {
+            ScanBounds::Prefix(prefix) => key.starts_with(prefix),
+            ScanBounds::Range(_, end) => key < end,
+        }
+    }
+    
+    fn contains(&self, key: &[u8]) -> bool {
+        key >= self.start() && self.still_within(key)
+    }
 }
 
-// MemTable for in-memory storage
+// In-memory write buffer on concurrent skiplists, so writers insert without
+// excluding each other and reads never block. `LsmStorage` only takes its
+// memtable lock exclusively to swap in a fresh table on flush (and to make a
+// write batch visible all at once). Keys and values live in an arena freed
+// with the table, and reads copy them out.
 pub struct MemTable {
-    data: BTreeMap<Vec<u8>, Vec<u8>>,
-    size: usize,
+    data: SkipMap<ArenaSlice, ArenaSlice>,
+    // Merge operands written since the key's last put, oldest first
+    operands: SkipMap<ArenaSlice, Mutex<Vec<ArenaSlice>>>,
+    size: AtomicUsize,
+    // When the first write landed; writes replayed by `open` count from then
+    first_write: OnceLock<Instant>,
+    // Declared last so it outlives the slices in the skiplists
+    arena: Arena,
 }
 
 impl MemTable {
     pub fn new() -> Self {
         MemTable {
-            data: BTreeMap::new(),
-            size: 0,
+            data: SkipMap::new(),
+            operands: SkipMap::new(),
+            size: AtomicUsize::new(0),
+            first_write: OnceLock::new(),
+            arena: Arena::new(),
         }
     }
     
-    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
-        self.size += key.len() + value.len();
-        self.data.insert(key, value);
+    pub fn insert(&self, key: &[u8], value: &[u8]) {
+        self.size.fetch_add(key.len() + value.len(), Ordering::Relaxed);
+        self.first_write.get_or_init(Instant::now);
+        // A put replaces whatever the earlier operands would have merged into
+        self.operands.remove(key);
+        self.data.insert(self.arena.alloc(key), self.arena.alloc(value));
+    }
+    
+    pub fn push_operand(&self, key: &[u8], operand: &[u8]) {
+        self.size.fetch_add(key.len() + operand.len(), Ordering::Relaxed);
+        self.first_write.get_or_init(Instant::now);
+        let operand = self.arena.alloc(operand);
+        let entry = match self.operands.get(key) {
+            Some(entry) => entry,
+            None => self.operands.get_or_insert_with(self.arena.alloc(key), || Mutex::new(Vec::new())),
+        };
+        entry.value().lock().unwrap_or_else(PoisonError::into_inner).push(operand);
+    }
+    
+    pub fn operands(&self, key: &[u8]) -> Option<Vec<Vec<u8>>> {
+        self.operands.get(key).map(|entry| Self::copy_operands(entry.value()))
+    }
+    
+    pub fn scan_operands(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+        self.operands_within(ScanBounds::Prefix(prefix), usize::MAX)
+    }
+    
+    fn operands_within(&self, bounds: ScanBounds<'_>, limit: usize) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+        self.operands
+            .range::<[u8], _>((Bound::Included(bounds.start()), Bound::Unbounded))
+            .take_while(|entry| bounds.still_within(entry.key().as_bytes()))
+            .take(limit)
+            .map(|entry| (entry.key().as_bytes().to_vec(), Self::copy_operands(entry.value())))
+            .collect()
+    }
+    
+    fn copy_operands(operands: &Mutex<Vec<ArenaSlice>>) -> Vec<Vec<u8>> {
+        let operands = operands.lock().unwrap_or_else(PoisonError::into_inner);
+        operands.iter().map(|operand| operand.as_bytes().to_vec()).collect()
     }
     
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.data.get(key).cloned()
+        self.data.get(key).map(|entry| entry.value().as_bytes().to_vec())
+    }
+    
+    // Inserts only if the key has no value yet, atomically with respect to
+    // concurrent inserts, so it can't overwrite a newer write. Returns whether it did.
+    pub fn insert_if_absent(&self, key: &[u8], value: &[u8]) -> bool {
+        // Saves copying into the arena when the key is plainly there already
+        if self.data.contains_key(key) {
+            return false;
+        }
+        let size = key.len() + value.len();
+        let mut inserted = false;
+        self.data.get_or_insert_with(self.arena.alloc(key), || {
+            inserted = true;
+            self.arena.alloc(value)
+        });
+        if inserted {
+            self.size.fetch_add(size, Ordering::Relaxed);
+            self.first_write.get_or_init(Instant::now);
+        }
+        inserted
+    }
+    
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.data.contains_key(key) || self.operands.contains_key(key)
     }
     
     pub fn scan(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.scan_within(ScanBounds::Prefix(prefix), usize::MAX)
+    }
+    
+    // Entries inserted while the scan runs may or may not be included
+    fn scan_within(&self, bounds: ScanBounds<'_>, limit: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
         self.data
-            .range(prefix.to_vec()..)
-            .take_while(|(k, _)| k.starts_with(prefix))
-            .map(|(k, v)| (k.clone(), v.clone()))
+            .range::<[u8], _>((Bound::Included(bounds.start()), Bound::Unbounded))
+            .take_while(|entry| bounds.still_within(entry.key().as_bytes()))
+            .take(limit)
+            .map(|entry| (entry.key().as_bytes().to_vec(), entry.value().as_bytes().to_vec()))
             .collect()
     }
     
     pub fn should_flush(&self) -> bool {
-        self.size > *FLUSH_THRESHOLD
+        self.size.load(Ordering::Relaxed) > *FLUSH_THRESHOLD
+    }
+    
+    // Time since the first write, None while the table is empty
+    pub fn age(&self) -> Option<Duration> {
+        self.first_write.get().map(Instant::elapsed)
     }
     
     pub fn len(&self) -> usize {
-        self.data.len()
+        let operand_only = self
+            .operands
+            .iter()
+            .filter(|entry| !self.data.contains_key(entry.key()))
+            .count();
+        self.data.len() + operand_only
+    }
+    
+    // Applies the writes in `later`, which were all made after this table's.
+    // Its puts go first since each one already follows the operands it drops.
+    fn absorb(&self, later: &MemTable) {
+        for entry in later.data.iter() {
+            self.insert(entry.key().as_bytes(), entry.value().as_bytes());
+        }
+        for entry in later.operands.iter() {
+            for operand in Self::copy_operands(entry.value()) {
+                self.push_operand(entry.key().as_bytes(), &operand);
+            }
+        }
     }
 }
 
 // SSTable (Sorted String Table) for disk storage
+//
+// Entries are bincode-encoded `SSTableEntry` records in key order. Each entry
+// carries the timestamp of the write that produced it so readers can pick the
+// newest value when a key appears in several tables.
+//
+// A fixed footer closes the file: the length of the entry section (u64), the
+// format version (u32) and a magic number (u32). Readers reject versions they
+// don't know instead of misparsing them. Values in tables older than version 2
+// have no tag, and are tagged as they're read. An encrypted table seals this
+// whole layout in fixed-size blocks; see `AtRestEncryption`.
 #[derive(Clone)]
 pub struct SSTable {
     pub path: PathBuf,
-    data: Arc<Mmap>,
+    fs: Arc<dyn FileSystem>,
+    data: Arc<TableBytes>,
+    index: Arc<TableIndex>,
+    bloom: Arc<BloomFilter>,
+    // Built when the storage is configured with `StorageConfig::with_prefix_bloom`
+    prefix_bloom: Option<Arc<PrefixBloom>>,
+    // CRC32 of the file as written, for tables produced by compaction
+    checksum: Option<u32>,
+    // Set by the first read, once the checksum has been compared
+    checksum_ok: Arc<OnceLock<bool>>,
+    // From the footer: 0 for tables written before it had one, and below 2
+    // for those written before values were tagged
+    version: u32,
+    // Entries that delete their key, counted while indexing
+    tombstones: usize,
+    pub file_size: u64,
+    pub level: u32,
+}
+
+// Sorted (key, byte offset) pairs for every entry in a table's file
+type TableIndex = Vec<(Vec<u8>, usize)>;
+
+// Summary of a table on disk: where it lives and which keys it covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SSTableMeta {
+    pub path: PathBuf,
+    pub level: u32,
+    pub entry_count: usize,
+    pub min_key: Vec<u8>,
+    pub max_key: Vec<u8>,
     pub file_size: u64,
+}
+
+// A table's contents, for tuning compaction. See `LsmStorage::sstable_report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SSTableStats {
+    pub path: PathBuf,
     pub level: u32,
+    pub entry_count: usize,
+    pub tombstone_count: usize,
+    // Both empty for an empty table
+    pub min_key: Vec<u8>,
+    pub max_key: Vec<u8>,
+    pub file_size: u64,
+    // Other tables at this level or the next whose key ranges intersect this
+    // one's, and their total size: roughly what compacting this table would
+    // rewrite alongside it. Ranges can intersect without sharing keys, so it's
+    // an upper bound. Only `sstable_report` fills these in.
+    pub overlapping_tables: usize,
+    pub overlapping_bytes: u64,
+}
+
+// A table's file as read. A plain one is read in place; an encrypted one a
+// block at a time, decrypting as it goes.
+enum TableBytes {
+    Stored(FileData),
+    Sealed(SealedTable),
+}
+
+impl TableBytes {
+    // The file as it's stored, still encrypted if it was
+    fn file(&self) -> &[u8] {
+        match self {
+            TableBytes::Stored(file) => (**file).as_ref(),
+            TableBytes::Sealed(table) => table.file(),
+        }
+    }
+    
+    // Length of the plaintext
+    fn len(&self) -> usize {
+        match self {
+            TableBytes::Stored(file) => (**file).as_ref().len(),
+            TableBytes::Sealed(table) => table.len(),
+        }
+    }
+    
+    // Decodes the entry, or footer, starting at `offset`
+    fn decode_at<T: serde::de::DeserializeOwned>(&self, path: &Path, offset: usize) -> Result<T> {
+        let entry = match self {
+            TableBytes::Stored(file) => bincode::deserialize(&(**file).as_ref()[offset..]),
+            TableBytes::Sealed(table) => bincode::deserialize_from(table.reader(offset)),
+        };
+        entry.map_err(|e| corrupt_sstable(path, e))
+    }
+}
+
+// A decoding error, or the decryption error behind it
+fn corrupt_sstable(path: &Path, e: bincode::Error) -> DbError {
+    underlying_error(e).unwrap_or_else(|e| DbError::Storage(format!("Corrupt SSTable {:?}: {}", path, e), Some(Box::new(e))))
+}
+
+// The `DbError` an I/O error passed through bincode, if it carries one
+fn underlying_error(e: bincode::Error) -> std::result::Result<DbError, bincode::Error> {
+    if let bincode::ErrorKind::Io(io_error) = &*e {
+        if io_error.get_ref().is_some_and(|inner| inner.is::<DbError>()) {
+            let bincode::ErrorKind::Io(io_error) = *e else { unreachable!() };
+            return Ok(*io_error.into_inner().unwrap().downcast::<DbError>().unwrap());
+        }
+    }
+    Err(e)
+}
+
+#[derive(Serialize, Deserialize)]
+struct SSTableEntry {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    timestamp: u64,
+}
+
+// Collapses each run of one key to its newest write (the later one on a tie),
+// so a table never holds two entries for a key. Expects entries sorted by key.
+struct NewestPerKey<I: Iterator> {
+    entries: std::iter::Peekable<I>,
+}
+
+impl<I: Iterator<Item = (Vec<u8>, Vec<u8>, u64)>> Iterator for NewestPerKey<I> {
+    type Item = (Vec<u8>, Vec<u8>, u64);
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut newest = self.entries.next()?;
+        while let Some(entry) = self.entries.next_if(|next| next.0 == newest.0) {
+            if entry.2 >= newest.2 {
+                newest = entry;
+            }
+        }
+        Some(newest)
+    }
+}
+
+// Same encoding as `SSTableEntry`, borrowed straight out of the mmap
+#[derive(Deserialize)]
+struct SSTableEntryRef<'a> {
+    #[allow(dead_code)]
+    key: &'a [u8],
+    value: &'a [u8],
+    timestamp: u64,
+}
+
+// Writes a table's plaintext in `SSTABLE_BLOCK_SIZE` blocks as they fill,
+// sealing each one first if the table is encrypted, so a flush or compaction
+// holds one block of the file rather than all of it
+struct TableWriter<'a> {
+    file: Box<dyn AppendFile>,
+    encryption: Option<&'a AtRestEncryption>,
+    block: Vec<u8>,
+    blocks: usize,
+    // Plaintext bytes taken so far
+    written: u64,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a> TableWriter<'a> {
+    fn new(file: Box<dyn AppendFile>, encryption: Option<&'a AtRestEncryption>) -> Self {
+        TableWriter {
+            file,
+            encryption,
+            block: Vec::with_capacity(encryption::SSTABLE_BLOCK_SIZE),
+            blocks: 0,
+            written: 0,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+    
+    fn push(&mut self, mut data: &[u8]) -> Result<()> {
+        self.written += data.len() as u64;
+        while !data.is_empty() {
+            let take = data.len().min(encryption::SSTABLE_BLOCK_SIZE - self.block.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == encryption::SSTABLE_BLOCK_SIZE {
+                self.write_block()?;
+            }
+        }
+        Ok(())
+    }
+    
+    fn write_block(&mut self) -> Result<()> {
+        let block = std::mem::replace(&mut self.block, Vec::with_capacity(encryption::SSTABLE_BLOCK_SIZE));
+        let stored = match self.encryption {
+            Some(encryption) => encryption.seal_sstable_block(self.blocks, block)?,
+            None => block,
+        };
+        self.blocks += 1;
+        self.append(&stored)
+    }
+    
+    fn append(&mut self, stored: &[u8]) -> Result<()> {
+        self.hasher.update(stored);
+        self.file.append(stored)
+            .map_err(DbError::storage)
+    }
+    
+    // Writes the last, partial block and the encryption footer, if any, and
+    // makes the file durable
+    fn finish(mut self) -> Result<u32> {
+        if !self.block.is_empty() {
+            self.write_block()?;
+        }
+        if self.encryption.is_some() {
+            let footer = AtRestEncryption::sealed_sstable_footer(self.written as usize);
+            self.append(&footer)?;
+        }
+        self.file.sync()
+            .map_err(DbError::storage)?;
+        Ok(self.hasher.finalize())
+    }
+}
+
+// So entries can be serialized straight into the table
+impl std::io::Write for TableWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.push(data).map_err(std::io::Error::other)?;
+        Ok(data.len())
+    }
+    
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 impl SSTable {
-    pub fn from_memtable(path: &Path, memtable: &MemTable) -> Result<Self> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        // Serialize memtable to file
-        for (key, value) in &memtable.data {
-            let entry = (key, value);
-            bincode::serialize_into(&mut file, &entry)
-                .map_err(|e| DbError::Serialization(e.to_string()))?;
+    pub fn from_memtable(
+        path: &Path,
+        memtable: &MemTable,
+        timestamp: u64,
+        hash_fn: HashFn,
+        fs: &Arc<dyn FileSystem>,
+        encryption: Option<&AtRestEncryption>,
+    ) -> Result<Self> {
+        let entries = memtable
+            .data
+            .iter()
+            .map(|entry| (entry.key().as_bytes().to_vec(), entry.value().as_bytes().to_vec(), timestamp));
+        Self::write_entries(path, entries, fs.as_ref(), encryption)?;
+        Self::open_with(path, 0, hash_fn, fs, encryption)
+    }
+    
+    // Writes a level-0 table from entries in any order. A key given more than
+    // once keeps only its newest write.
+    pub fn from_entries<I>(path: &Path, entries: I, hash_fn: HashFn) -> Result<Self>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>, u64)>,
+    {
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        // Stable, so a key's writes stay in order for ties on timestamp
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Self::write_entries(path, entries.into_iter(), &LocalFs, None)?;
+        Self::open(path, 0, hash_fn)
+    }
+    
+    pub async fn create(
+        path: &Path,
+        data: BTreeMap<Vec<u8>, ValueWithTimestamp>,
+        level: u32,
+        hash_fn: HashFn,
+        record_checksum: bool,
+        fs: &Arc<dyn FileSystem>,
+        encryption: Option<&AtRestEncryption>,
+    ) -> Result<Self> {
+        let entries = data.into_iter().map(|(key, v)| (key, v.value, v.timestamp));
+        let checksum = Self::write_entries(path, entries, fs.as_ref(), encryption)?;
+        if record_checksum {
+            fs.write(&Self::checksum_path_for(path), &checksum.to_le_bytes())
+                .map_err(DbError::storage)?;
+        }
+        Self::open_with(path, level, hash_fn, fs, encryption)
+    }
+    
+    // Streams the table to `fs` a block at a time, returning the CRC32 of the
+    // file as stored
+    fn write_entries<I>(path: &Path, entries: I, fs: &dyn FileSystem, encryption: Option<&AtRestEncryption>) -> Result<u32>
+    where
+        I: Iterator<Item = (Vec<u8>, Vec<u8>, u64)>,
+    {
+        if let Some(parent) = path.parent() {
+            fs.create_dir_all(parent)
+                .map_err(DbError::storage)?;
         }
+        fs.write(path, &[])
+            .map_err(DbError::storage)?;
+        let mut writer = TableWriter::new(fs.append(path).map_err(DbError::storage)?, encryption);
         
-        file.flush()
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        // Get file size
-        let metadata = std::fs::metadata(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-        let file_size = metadata.len();
-            
-        // Memory map the file for fast reads
-        let file = OpenOptions::new()
-            .read(true)
-            .open(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        unsafe {
-            let data = Mmap::map(&file)
-                .map_err(|e| DbError::Storage(e.to_string()))?;
-                
-            Ok(SSTable {
-                path: path.to_path_buf(),
-                data: Arc::new(data),
-                file_size,
-                level: 0,
-            })
+        for (key, value, timestamp) in (NewestPerKey { entries: entries.peekable() }) {
+            let entry = SSTableEntry { key, value, timestamp };
+            bincode::serialize_into(&mut writer, &entry)
+                .map_err(|e| underlying_error(e).unwrap_or_else(DbError::serialization))?;
+        }
+        
+        let mut footer = Vec::with_capacity(SSTABLE_FOOTER_LEN);
+        footer.extend_from_slice(&writer.written.to_le_bytes());
+        footer.extend_from_slice(&SSTABLE_FORMAT_VERSION.to_le_bytes());
+        footer.extend_from_slice(&SSTABLE_MAGIC.to_le_bytes());
+        writer.push(&footer)?;
+        writer.finish()
+    }
+    
+    // Checksums live beside the table, e.g. `sst_42.bin` -> `sst_42.crc`
+    pub(crate) fn checksum_path_for(path: &Path) -> PathBuf {
+        path.with_extension("crc")
+    }
+    
+    // Removes the table's file and its checksum, if it has one
+    pub(crate) fn delete_files(&self) -> std::io::Result<()> {
+        self.fs.remove(&self.path)?;
+        match self.fs.remove(&Self::checksum_path_for(&self.path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+    
+    fn read_checksum(fs: &dyn FileSystem, path: &Path) -> Result<Option<u32>> {
+        // One byte spare, to catch a file longer than a checksum
+        let mut bytes = [0u8; 5];
+        match fs.read_at(&Self::checksum_path_for(path), 0, &mut bytes) {
+            Ok(4) => Ok(Some(u32::from_le_bytes(bytes[..4].try_into().unwrap()))),
+            Ok(_) => Err(DbError::Storage(format!("Malformed checksum file for SSTable {:?}", path), None)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(DbError::storage(e)),
+        }
+    }
+    
+    // The first read of a checksummed table hashes the whole file; later reads
+    // reuse the outcome
+    fn verify_checksum(&self) -> Result<()> {
+        let Some(expected) = self.checksum else {
+            return Ok(());
+        };
+        if *self.checksum_ok.get_or_init(|| crc32fast::hash(self.data.file()) == expected) {
+            Ok(())
+        } else {
+            Err(DbError::Storage(format!("Checksum mismatch in SSTable {:?}", self.path), None))
+        }
+    }
+    
+    // Memory-maps an existing table, indexing its keys and building its bloom filter
+    pub fn open(path: &Path, level: u32, hash_fn: HashFn) -> Result<Self> {
+        Self::open_with(path, level, hash_fn, &(Arc::new(LocalFs) as Arc<dyn FileSystem>), None)
+    }
+    
+    // `open` for a table on `fs` that may be encrypted. An encrypted one stays
+    // mapped too; its blocks are decrypted as reads reach them.
+    pub fn open_with(
+        path: &Path,
+        level: u32,
+        hash_fn: HashFn,
+        fs: &Arc<dyn FileSystem>,
+        encryption: Option<&AtRestEncryption>,
+    ) -> Result<Self> {
+        let file = fs.open(path)
+            .map_err(DbError::storage)?;
+        let file_size = (*file).as_ref().len() as u64;
+        let data = match SealedTable::open(encryption, path, &file)? {
+            Some(sealed) => TableBytes::Sealed(sealed),
+            None => TableBytes::Stored(file),
+        };
+        
+        let (entries_len, version) = Self::entry_section(path, &data)?;
+        let (index, tombstones) = match &data {
+            TableBytes::Stored(file) => Self::index_entries(path, (**file).as_ref(), entries_len)?,
+            TableBytes::Sealed(sealed) => Self::index_entries(path, sealed.reader(0), entries_len)?,
+        };
+        let bloom = BloomFilter::from_keys(index.iter().map(|(key, _)| key.as_slice()), hash_fn);
+        
+        Ok(SSTable {
+            path: path.to_path_buf(),
+            fs: Arc::clone(fs),
+            data: Arc::new(data),
+            index: Arc::new(index),
+            bloom: Arc::new(bloom),
+            prefix_bloom: None,
+            checksum: Self::read_checksum(fs.as_ref(), path)?,
+            checksum_ok: Arc::new(OnceLock::new()),
+            version,
+            tombstones,
+            file_size,
+            level,
+        })
+    }
+    
+    // (key, offset) for each entry in the first `len` bytes of `entries`, and
+    // how many of them are tombstones
+    fn index_entries(path: &Path, entries: impl std::io::Read, len: usize) -> Result<(TableIndex, usize)> {
+        let mut remaining = std::io::Read::take(entries, len as u64);
+        let mut index = Vec::new();
+        let mut tombstones = 0;
+        while remaining.limit() > 0 {
+            let offset = len - remaining.limit() as usize;
+            let entry: SSTableEntry = bincode::deserialize_from(&mut remaining)
+                .map_err(|e| corrupt_sstable(path, e))?;
+            // Tagged or not, a tombstone is stored as no bytes
+            if entry.value.is_empty() {
+                tombstones += 1;
+            }
+            index.push((entry.key, offset));
+        }
+        Ok((index, tombstones))
+    }
+    
+    // How many bytes hold entries and the footer's format version, after
+    // checking it. Files from before the footer was added have no magic and are
+    // all entries; they count as version 0.
+    fn entry_section(path: &Path, data: &TableBytes) -> Result<(usize, u32)> {
+        let Some(footer_start) = data.len().checked_sub(SSTABLE_FOOTER_LEN) else {
+            return Ok((data.len(), 0));
+        };
+        let footer: [u8; SSTABLE_FOOTER_LEN] = data.decode_at(path, footer_start)?;
+        let magic = u32::from_le_bytes(footer[12..16].try_into().unwrap());
+        if magic != SSTABLE_MAGIC {
+            return Ok((data.len(), 0));
+        }
+        
+        match u32::from_le_bytes(footer[8..12].try_into().unwrap()) {
+            version @ 1..=SSTABLE_FORMAT_VERSION => {
+                let entries_len = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+                if entries_len != footer_start as u64 {
+                    return Err(DbError::Storage(format!(
+                        "Corrupt SSTable {:?}: footer records {} bytes of entries, found {}",
+                        path, entries_len, footer_start
+                    ), None));
+                }
+                Ok((footer_start, version))
+            }
+            version => Err(DbError::Storage(format!(
+                "unsupported sstable version {} in {:?} (this build reads up to {})",
+                version, path, SSTABLE_FORMAT_VERSION
+            ), None)),
+        }
+    }
+    
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+    
+    pub fn meta(&self) -> SSTableMeta {
+        SSTableMeta {
+            path: self.path.clone(),
+            level: self.level,
+            entry_count: self.len(),
+            min_key: self.index.first().map(|(k, _)| k.clone()).unwrap_or_default(),
+            max_key: self.index.last().map(|(k, _)| k.clone()).unwrap_or_default(),
+            file_size: self.file_size,
+        }
+    }
+    
+    // Counts come from indexing the table when it was opened, so this reads
+    // nothing. The overlap fields are left at zero.
+    pub fn stats(&self) -> SSTableStats {
+        let meta = self.meta();
+        SSTableStats {
+            path: meta.path,
+            level: meta.level,
+            entry_count: meta.entry_count,
+            tombstone_count: self.tombstones,
+            min_key: meta.min_key,
+            max_key: meta.max_key,
+            file_size: meta.file_size,
+            overlapping_tables: 0,
+            overlapping_bytes: 0,
+        }
+    }
+    
+    fn untagged(&self) -> bool {
+        self.version < 2
+    }
+    
+    // Whether the table was written whole by this format: a flush cut short
+    // leaves no footer, or an empty file that reads as a legacy table
+    pub(crate) fn is_complete(&self) -> bool {
+        self.version == SSTABLE_FORMAT_VERSION && !self.is_empty()
+    }
+    
+    fn entry_at(&self, offset: usize) -> Result<SSTableEntry> {
+        let mut entry: SSTableEntry = self.data.decode_at(&self.path, offset)?;
+        if self.untagged() {
+            entry.value = tag_legacy_cell(entry.value);
+        }
+        Ok(entry)
+    }
+    
+    // The value a cell read by `get_ref` holds, or None for a tombstone
+    fn value_of<'a>(&self, cell: &'a [u8]) -> Option<&'a [u8]> {
+        if self.untagged() {
+            (!cell.is_empty()).then_some(cell)
+        } else {
+            decode_cell(cell)
+        }
+    }
+    
+    // Rebuilds the bloom filters after the storage switches hash functions
+    fn rebuild_bloom(&mut self, hash_fn: HashFn) {
+        let keys = self.index.iter().map(|(key, _)| key.as_slice());
+        self.bloom = Arc::new(BloomFilter::from_keys(keys, hash_fn));
+        if let Some(len) = self.prefix_bloom.as_ref().map(|bloom| bloom.len()) {
+            self.build_prefix_bloom(len, hash_fn);
+        }
+    }
+    
+    fn build_prefix_bloom(&mut self, len: usize, hash_fn: HashFn) {
+        let keys = self.index.iter().map(|(key, _)| key.as_slice());
+        self.prefix_bloom = Some(Arc::new(PrefixBloom::from_sorted_keys(keys, len, hash_fn)));
+    }
+    
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom.may_contain(key)
+    }
+    
+    // False means no key in the table starts with `prefix`. Always true for a
+    // table without a prefix bloom.
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        self.prefix_bloom.as_ref().is_none_or(|bloom| bloom.may_contain_prefix(prefix))
+    }
+    
+    // Whether a scan over `bounds` could find anything here
+    fn may_overlap(&self, bounds: ScanBounds<'_>) -> bool {
+        match bounds {
+            ScanBounds::Prefix(prefix) => self.may_contain_prefix(prefix),
+            ScanBounds::Range(..) => true,
+        }
+    }
+    
+    pub fn get(&self, key: &[u8]) -> Result<Option<ValueWithTimestamp>> {
+        Ok(self.get_ref(key)?.map(|(cell, timestamp)| {
+            let value = cell.into_owned();
+            ValueWithTimestamp {
+                value: if self.untagged() { tag_legacy_cell(value) } else { value },
+                timestamp,
+            }
+        }))
+    }
+    
+    // The key's cell and timestamp, borrowed straight out of the mmap unless
+    // the table is encrypted
+    fn get_ref(&self, key: &[u8]) -> Result<Option<(std::borrow::Cow<'_, [u8]>, u64)>> {
+        self.verify_checksum()?;
+        if !self.bloom.may_contain(key) {
+            return Ok(None);
+        }
+        let Ok(pos) = self.index.binary_search_by(|(k, _)| k.as_slice().cmp(key)) else {
+            return Ok(None);
+        };
+        let offset = self.index[pos].1;
+        match &*self.data {
+            TableBytes::Stored(file) => {
+                let entry: SSTableEntryRef<'_> = bincode::deserialize(&(**file).as_ref()[offset..])
+                    .map_err(|e| corrupt_sstable(&self.path, e))?;
+                Ok(Some((std::borrow::Cow::Borrowed(entry.value), entry.timestamp)))
+            }
+            TableBytes::Sealed(_) => {
+                let entry: SSTableEntry = self.data.decode_at(&self.path, offset)?;
+                Ok(Some((std::borrow::Cow::Owned(entry.value), entry.timestamp)))
+            }
+        }
+    }
+    
+    pub fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, ValueWithTimestamp)>> {
+        self.scan_within(ScanBounds::Prefix(prefix), usize::MAX)
+    }
+    
+    pub fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, ValueWithTimestamp)>> {
+        self.scan_within(ScanBounds::Range(start, end), usize::MAX)
+    }
+    
+    fn scan_within(&self, bounds: ScanBounds<'_>, limit: usize) -> Result<Vec<(Vec<u8>, ValueWithTimestamp)>> {
+        self.verify_checksum()?;
+        let start = self.index.partition_point(|(k, _)| k.as_slice() < bounds.start());
+        let mut results = Vec::new();
+        for (key, offset) in self.index[start..].iter().take(limit) {
+            if !bounds.still_within(key) {
+                break;
+            }
+            let entry = self.entry_at(*offset)?;
+            results.push((entry.key, ValueWithTimestamp { value: entry.value, timestamp: entry.timestamp }));
+        }
+        Ok(results)
+    }
+    
+    pub async fn iter(&self) -> Result<Vec<(Vec<u8>, ValueWithTimestamp)>> {
+        self.scan(&[])
+    }
+}
+
+// `value` is the cell as stored: tagged, or empty for a tombstone
+#[derive(Clone, Debug)]
+pub struct ValueWithTimestamp {
+    pub value: Vec<u8>,
+    pub timestamp: u64,
+}
+
+// Where an `LsmStorage` keeps its files. SSTables always live under
+// `base_path`; the WAL defaults to `base_path/wal.bin` but can sit on another
+// device, since its sequential appends compete with SSTable reads.
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub base_path: PathBuf,
+    pub wal_path: Option<PathBuf>,
+    pub lock: LockConfig,
+    // See `without_wal`
+    pub wal_enabled: bool,
+    // See `with_prefix_bloom`
+    pub prefix_bloom_len: Option<usize>,
+    // See `with_wal_archive`
+    pub archive_wal: bool,
+    // See `with_max_memtable_age`
+    pub max_memtable_age: Option<Duration>,
+    // See `with_encryption_key`
+    pub encryption: Option<AtRestEncryption>,
+    // See `with_file_system`
+    pub file_system: Arc<dyn FileSystem>,
+    // See `with_recovery_parallelism`
+    pub recovery_parallelism: usize,
+}
+
+// Bounded retries for the memtable and WAL locks that writes take, so a writer
+// stuck behind a pathologically long holder fails instead of blocking forever.
+// Retry `n` sleeps `base_backoff * 2^n` plus up to as much again in jitter.
+#[derive(Debug, Clone)]
+pub struct LockConfig {
+    pub max_retries: u32,
+    pub base_backoff: Duration,
+}
+
+impl Default for LockConfig {
+    // Gives up after about a minute of waiting
+    fn default() -> Self {
+        Self {
+            max_retries: 16,
+            base_backoff: Duration::from_millis(1),
+        }
+    }
+}
+
+impl LockConfig {
+    // Calls `try_lock` until it returns a guard, failing with "lock timeout"
+    // once the retries run out. The backoff sleeps on the runtime's timer, so
+    // a waiting writer doesn't tie up a worker thread.
+    pub async fn acquire<G>(&self, mut try_lock: impl FnMut() -> Option<G>) -> Result<G> {
+        let mut backoff = self.base_backoff;
+        for _ in 0..self.max_retries {
+            if let Some(guard) = try_lock() {
+                return Ok(guard);
+            }
+            let jitter = backoff.mul_f64(rand::random::<f64>());
+            tokio::time::sleep(backoff + jitter).await;
+            backoff = backoff.saturating_mul(2);
+        }
+        try_lock().ok_or_else(|| DbError::Storage("lock timeout".to_string(), None))
+    }
+}
+
+impl StorageConfig {
+    pub fn new(base_path: &Path) -> Self {
+        Self {
+            base_path: base_path.to_path_buf(),
+            wal_path: None,
+            lock: LockConfig::default(),
+            wal_enabled: true,
+            prefix_bloom_len: None,
+            archive_wal: false,
+            max_memtable_age: None,
+            encryption: None,
+            file_system: Arc::new(LocalFs),
+            recovery_parallelism: 1,
+        }
+    }
+    
+    // Writes skip the WAL entirely, for bulk imports and caches that can be
+    // rebuilt. NOT crash safe: anything not yet flushed to an SSTable is lost
+    // if the process dies, since recovery has no log to replay.
+    pub fn without_wal(mut self) -> Self {
+        self.wal_enabled = false;
+        self
+    }
+    
+    // Gives every SSTable a bloom filter over the first `len` bytes of its keys,
+    // which prefix scans consult to skip tables that can't match. Pick the
+    // length of the prefixes scanned most, such as a `{table}:` prefix; shorter
+    // scan prefixes read every table as before.
+    pub fn with_prefix_bloom(mut self, len: usize) -> Self {
+        self.prefix_bloom_len = Some(len.max(1));
+        self
+    }
+    
+    // Moves each WAL segment into `{wal}.archive/` once its writes are flushed,
+    // rather than deleting it, so `LsmStorage::rebuild_from_wal` can replay the
+    // whole history if the SSTables are lost. Nothing prunes the archive.
+    pub fn with_wal_archive(mut self) -> Self {
+        self.archive_wal = true;
+        self
+    }
+    
+    // Flushes a memtable once its first write is `age` old, however small it
+    // is, bounding what `open` has to replay after a crash. Checked on each
+    // write, and by a `BackgroundFlusher` for tables that stop getting writes.
+    pub fn with_max_memtable_age(mut self, age: Duration) -> Self {
+        self.max_memtable_age = Some(age);
+        self
+    }
+    
+    // Encrypts new SSTables and WAL batches with AES-256-GCM under `key`.
+    // Files written without encryption stay readable; encrypted ones fail to
+    // open without the same key. There's no key rotation: data written under
+    // a key needs that key for as long as it's around.
+    pub fn with_encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.encryption = Some(AtRestEncryption::new(&key));
+        self
+    }
+    
+    // Keeps SSTables and the WAL on `fs` instead of local files. Paths are
+    // still built from `base_path` and the WAL path, and handed to `fs` as is.
+    pub fn with_file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.file_system = fs;
+        self
+    }
+    
+    // Replays WAL segments left by unfinished flushes on up to `threads`
+    // threads when opening, rather than one after another. Only pays off with
+    // several segments: the live log is replayed by one thread either way.
+    pub fn with_recovery_parallelism(mut self, threads: usize) -> Self {
+        self.recovery_parallelism = threads.max(1);
+        self
+    }
+    
+    pub fn with_lock_config(mut self, lock: LockConfig) -> Self {
+        self.lock = lock;
+        self
+    }
+    
+    pub fn with_wal_path(mut self, wal_path: &Path) -> Self {
+        self.wal_path = Some(wal_path.to_path_buf());
+        self
+    }
+    
+    pub fn resolved_wal_path(&self) -> PathBuf {
+        self.wal_path.clone().unwrap_or_else(|| self.base_path.join("wal.bin"))
+    }
+}
+
+// A full memtable swapped out by a flush, readable until its SSTable is installed
+struct FrozenMemTable {
+    memtable: MemTable,
+    timestamp: u64,
+    // The WAL holding its writes, deleted once the SSTable is installed
+    wal_segment: PathBuf,
+}
+
+// The memtable guard if the lock was free, ignoring poisoning as
+// `active_memtable` does
+fn try_memtable_guard<G>(result: std::result::Result<G, TryLockError<G>>) -> Option<G> {
+    match result {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+// Main LSM Storage Engine
+#[derive(Clone)]
+pub struct LsmStorage {
+    memtable: Arc<RwLock<MemTable>>,
+    // Oldest first; reads check these between the memtable and the SSTables
+    frozen_memtables: Arc<RwLock<Vec<Arc<FrozenMemTable>>>>,
+    // Held while writing frozen memtables out, so they're written in order
+    flush_lock: Arc<Mutex<()>>,
+    wal: Arc<RwLock<WriteAheadLog>>,
+    // SSTables by level, oldest first within each level
+    sstable_levels: Arc<RwLock<HashMap<u32, Vec<SSTable>>>>,
+    // Bumped whenever a table is added to or removed from `sstable_levels`
+    sstable_generation: Arc<AtomicU64>,
+    base_path: PathBuf,
+    wal_path: PathBuf,
+    last_flush_ts: Arc<AtomicU64>,
+    flush_count: Arc<AtomicU64>,
+    // SSTables read by prefix and range scans, after prefix blooms ruled some out
+    sstables_scanned: Arc<AtomicU64>,
+    prefix_bloom_len: Option<usize>,
+    index_manager: Arc<RwLock<IndexManager>>,
+    compaction_manager: Option<Arc<CompactionManager>>,
+    // Set by a flush that pushed the table count past `max_total_sstables`
+    compaction_pending: Arc<AtomicBool>,
+    negative_cache: Arc<Mutex<NegativeCache>>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    pinned_sstables: Arc<Mutex<cursor::PinnedFiles>>,
+    changes: Arc<ChangeFeed>,
+    lock_config: LockConfig,
+    wal_enabled: bool,
+    archive_wal: bool,
+    max_memtable_age: Option<Duration>,
+    encryption: Option<AtRestEncryption>,
+    fs: Arc<dyn FileSystem>,
+    write_counters: Arc<WriteCounters>,
+    unique_locks: Arc<UniqueLocks>,
+    record_locks: Arc<RecordLocks>,
+    // Reject typed rows whose `Schema::fingerprint` differs from the table's first
+    validate_schemas: bool,
+    schemas: Arc<SchemaRegistry>,
+    read_repair: bool,
+}
+
+impl LsmStorage {
+    pub fn new(path: &Path) -> Result<Self> {
+        Self::open(StorageConfig::new(path))
+    }
+    
+    // Writes that reached the WAL but not an SSTable are replayed into the memtable.
+    // WAL segments left by flushes that didn't finish are replayed first and
+    // flushed straight away.
+    pub fn open(config: StorageConfig) -> Result<Self> {
+        let path = config.base_path.as_path();
+        let fs = Arc::clone(&config.file_system);
+        fs.create_dir_all(path)
+            .map_err(DbError::storage)?;
+        
+        let wal_path = config.resolved_wal_path();
+        if let Some(wal_dir) = wal_path.parent() {
+            fs.create_dir_all(wal_dir)
+                .map_err(DbError::storage)?;
+        }
+        let segments = Self::wal_segments(fs.as_ref(), &wal_path)?;
+        let logs: Vec<PathBuf> = segments.iter().cloned().chain(std::iter::once(wal_path.clone())).collect();
+        let memtable = Self::replay_logs(fs.as_ref(), &logs, config.encryption.as_ref(), config.recovery_parallelism)?;
+        let wal = WriteAheadLog::open_in(fs.as_ref(), &wal_path)?.with_encryption(config.encryption.clone());
+        let mut sstable_levels = Self::discover_sstables(&fs, path, config.encryption.as_ref())?;
+        let schemas = SchemaRegistry::load(fs.as_ref(), path)?;
+        if let Some(len) = config.prefix_bloom_len {
+            for sstable in sstable_levels.values_mut().flatten() {
+                sstable.build_prefix_bloom(len, HashFn::default());
+            }
+        }
+        let storage = LsmStorage {
+            memtable: Arc::new(RwLock::new(memtable)),
+            frozen_memtables: Arc::new(RwLock::new(Vec::new())),
+            flush_lock: Arc::new(Mutex::new(())),
+            wal: Arc::new(RwLock::new(wal)),
+            sstable_levels: Arc::new(RwLock::new(sstable_levels)),
+            sstable_generation: Arc::new(AtomicU64::new(0)),
+            base_path: path.to_path_buf(),
+            wal_path,
+            last_flush_ts: Arc::new(AtomicU64::new(0)),
+            flush_count: Arc::new(AtomicU64::new(0)),
+            sstables_scanned: Arc::new(AtomicU64::new(0)),
+            prefix_bloom_len: config.prefix_bloom_len,
+            index_manager: Arc::new(RwLock::new(IndexManager::new())),
+            compaction_manager: None,
+            compaction_pending: Arc::new(AtomicBool::new(false)),
+            negative_cache: Arc::new(Mutex::new(NegativeCache::new(NEGATIVE_CACHE_CAPACITY))),
+            merge_operator: None,
+            pinned_sstables: Arc::new(Mutex::new(cursor::PinnedFiles::default())),
+            changes: Arc::new(ChangeFeed::new()),
+            lock_config: config.lock.clone(),
+            wal_enabled: config.wal_enabled,
+            archive_wal: config.archive_wal,
+            max_memtable_age: config.max_memtable_age,
+            encryption: config.encryption.clone(),
+            fs,
+            write_counters: Arc::new(WriteCounters::default()),
+            unique_locks: Arc::new(UniqueLocks::new()),
+            record_locks: Arc::new(RecordLocks::new()),
+            validate_schemas: false,
+            schemas: Arc::new(schemas),
+            read_repair: false,
+        };
+        if !segments.is_empty() {
+            storage.flush_memtable()?;
+            for segment in &segments {
+                storage.retire_wal_segment(segment)?;
+            }
+        }
+        Ok(storage)
+    }
+    
+    // Replays `logs`, oldest first, into one memtable. With `parallelism` above
+    // one they're split into that many runs of consecutive logs, each replayed
+    // on its own thread into its own table; the tables are then folded
+    // together oldest first, so later writes still win.
+    fn replay_logs(
+        fs: &dyn FileSystem,
+        logs: &[PathBuf],
+        encryption: Option<&AtRestEncryption>,
+        parallelism: usize,
+    ) -> Result<MemTable> {
+        let run_len = logs.len().div_ceil(parallelism.max(1)).max(1);
+        if run_len >= logs.len() {
+            return Self::replay_into_memtable(fs, logs, encryption);
+        }
+        
+        let shards = std::thread::scope(|scope| {
+            let workers: Vec<_> = logs
+                .chunks(run_len)
+                .map(|run| scope.spawn(move || Self::replay_into_memtable(fs, run, encryption)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let mut shards = shards.into_iter();
+        let memtable = shards.next().unwrap_or_else(MemTable::new);
+        for shard in shards {
+            memtable.absorb(&shard);
+        }
+        Ok(memtable)
+    }
+    
+    fn replay_into_memtable(fs: &dyn FileSystem, logs: &[PathBuf], encryption: Option<&AtRestEncryption>) -> Result<MemTable> {
+        let memtable = MemTable::new();
+        for log in logs {
+            for entry in WriteAheadLog::replay_with(fs, log, encryption)? {
+                match entry.kind {
+                    WalEntryKind::Put => memtable.insert(&entry.key, &entry.value),
+                    WalEntryKind::Merge => memtable.push_operand(&entry.key, &entry.value),
+                }
+            }
+        }
+        Ok(memtable)
+    }
+    
+    // A flush renames the WAL to `{wal}.{flush timestamp}` and starts a new one
+    fn wal_segment_path(wal_path: &Path, timestamp: u64) -> PathBuf {
+        let mut segment = wal_path.as_os_str().to_os_string();
+        segment.push(format!(".{}", timestamp));
+        PathBuf::from(segment)
+    }
+    
+    // Segments beside `wal_path`, oldest first
+    fn wal_segments(fs: &dyn FileSystem, wal_path: &Path) -> Result<Vec<PathBuf>> {
+        let (Some(dir), Some(name)) = (wal_path.parent(), wal_path.file_name()) else {
+            return Ok(Vec::new());
+        };
+        // A bare file name's parent is ""
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let prefix = format!("{}.", name.to_string_lossy());
+        let mut segments = Vec::new();
+        for path in fs.list(dir).map_err(DbError::storage)? {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let timestamp = file_name
+                .strip_prefix(&prefix)
+                .and_then(|suffix| suffix.parse::<u64>().ok());
+            if let Some(timestamp) = timestamp {
+                segments.push((timestamp, path));
+            }
+        }
+        segments.sort();
+        Ok(segments.into_iter().map(|(_, path)| path).collect())
+    }
+    
+    // Where `with_wal_archive` keeps flushed segments, under their own names
+    fn wal_archive_dir(wal_path: &Path) -> PathBuf {
+        let mut dir = wal_path.as_os_str().to_os_string();
+        dir.push(".archive");
+        PathBuf::from(dir)
+    }
+    
+    // Every log still holding writes for `wal_path`, oldest first: archived
+    // segments, then those a flush didn't finish, then the live log
+    fn wal_history(fs: &dyn FileSystem, wal_path: &Path) -> Result<Vec<PathBuf>> {
+        let archive = Self::wal_archive_dir(wal_path);
+        let mut logs = Vec::new();
+        if let Some(name) = wal_path.file_name().filter(|_| fs.list(&archive).is_ok()) {
+            logs.extend(Self::wal_segments(fs, &archive.join(name))?);
+        }
+        logs.extend(Self::wal_segments(fs, wal_path)?);
+        logs.push(wal_path.to_path_buf());
+        Ok(logs)
+    }
+    
+    // Drops a segment whose writes are all in SSTables, or archives it
+    fn retire_wal_segment(&self, segment: &Path) -> Result<()> {
+        Self::retire_wal_segment_in(self.fs.as_ref(), &self.wal_path, self.archive_wal, segment)
+    }
+    
+    pub(crate) fn retire_wal_segment_in(fs: &dyn FileSystem, wal_path: &Path, archive_wal: bool, segment: &Path) -> Result<()> {
+        if !archive_wal {
+            return Self::remove_wal_segment(fs, segment);
+        }
+        let archive = Self::wal_archive_dir(wal_path);
+        fs.create_dir_all(&archive).map_err(DbError::storage)?;
+        let Some(name) = segment.file_name() else {
+            return Ok(());
+        };
+        match fs.rename(segment, &archive.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(DbError::Storage(format!("Failed to archive WAL segment: {}", e), Some(Box::new(e))))
+            }
+            _ => Ok(()),
+        }
+    }
+    
+    fn remove_wal_segment(fs: &dyn FileSystem, segment: &Path) -> Result<()> {
+        match fs.remove(segment) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(DbError::Storage(format!("Failed to remove WAL segment: {}", e), Some(Box::new(e))))
+            }
+            _ => Ok(()),
+        }
+    }
+    
+    pub fn with_compaction(mut self, config: CompactionConfig) -> Self {
+        let storage_arc = Arc::new(self.clone());
+        self.compaction_manager = Some(Arc::new(CompactionManager::new(storage_arc, config)));
+        self
+    }
+    
+    // Records each table's `Schema::fingerprint` on its first `insert_row` or
+    // `replace_table`, and rejects later rows of a different shape, including
+    // values written with `Database::insert` under `{table}:` whose fields differ
+    pub fn with_schema_validation(mut self) -> Self {
+        self.validate_schemas = true;
+        self
+    }
+    
+    // Copies a value found in an SSTable forward into the memtable when older
+    // tables also hold the key, so later reads stop there and the next flush
+    // carries the resolved value. Reads return the same results either way.
+    pub fn with_read_repair(mut self) -> Self {
+        self.read_repair = true;
+        self
+    }
+    
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+    
+    // Hash for SSTable bloom filters and hash index keys. Entries already written
+    // to a hash index aren't rehashed, so pick this before creating indexes.
+    pub fn with_hash_fn(self, hash_fn: HashFn) -> Self {
+        self.index_manager.write().unwrap().set_hash_fn(hash_fn);
+        for sstables in self.sstable_levels.write().unwrap().values_mut() {
+            for sstable in sstables.iter_mut() {
+                sstable.rebuild_bloom(hash_fn);
+            }
+        }
+        self
+    }
+    
+    pub fn hash_fn(&self) -> HashFn {
+        self.index_manager.read().unwrap().hash_fn()
+    }
+    
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+    
+    pub fn wal_path(&self) -> &Path {
+        &self.wal_path
+    }
+    
+    pub(crate) fn encryption(&self) -> Option<&AtRestEncryption> {
+        self.encryption.as_ref()
+    }
+    
+    pub(crate) fn file_system(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+    
+    // SSTables live under `base_path/L{level}/`; the level comes from the directory
+    pub fn level_dir(base_path: &Path, level: u32) -> PathBuf {
+        base_path.join(format!("L{}", level))
+    }
+    
+    pub(crate) fn sstable_path(&self, level: u32) -> PathBuf {
+        Self::level_dir(&self.base_path, level).join(format!("sst_{}.bin", self.next_flush_ts()))
+    }
+    
+    // Write timestamps are wall-clock micros, bumped so that two flushes never share one
+    fn next_flush_ts(&self) -> u64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+        let prev = self.last_flush_ts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+            .unwrap();
+        now.max(prev + 1)
+    }
+    
+    fn discover_sstables(
+        fs: &Arc<dyn FileSystem>,
+        base_path: &Path,
+        encryption: Option<&AtRestEncryption>,
+    ) -> Result<HashMap<u32, Vec<SSTable>>> {
+        let mut levels = HashMap::new();
+        let entries = fs.list(base_path)
+            .map_err(DbError::storage)?;
+        
+        for entry in entries {
+            let name = entry.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let Some(level) = name.strip_prefix('L').and_then(|l| l.parse::<u32>().ok()) else {
+                continue;
+            };
+            let files = match fs.list(&entry) {
+                Ok(files) => files,
+                // A file that happens to be named like a level
+                Err(e) if e.kind() == std::io::ErrorKind::NotADirectory => continue,
+                Err(e) => return Err(DbError::storage(e)),
+            };
+            
+            let mut paths: Vec<PathBuf> = files
+                .into_iter()
+                .filter(|p| {
+                    let file_name = p.file_name().unwrap_or_default().to_string_lossy();
+                    file_name.starts_with("sst_") && file_name.ends_with(".bin")
+                })
+                .collect();
+            // File names embed the creation timestamp, so this sorts oldest first
+            paths.sort();
+            
+            let mut sstables = Vec::with_capacity(paths.len());
+            for path in paths {
+                sstables.push(SSTable::open_with(&path, level, HashFn::default(), fs, encryption)?);
+            }
+            if !sstables.is_empty() {
+                levels.insert(level, sstables);
+            }
+        }
+        Ok(levels)
+    }
+    
+    pub async fn trigger_compaction(&self) -> Result<CompactionStats> {
+        if let Some(ref manager) = self.compaction_manager {
+            manager.trigger_compaction().await
+        } else {
+            Err(DbError::Storage("Compaction manager not initialized".to_string(), None))
+        }
+    }
+    
+    pub async fn major_compaction(&self) -> Result<CompactionStats> {
+        if let Some(ref manager) = self.compaction_manager {
+            manager.major_compaction().await
+        } else {
+            Err(DbError::Storage("Compaction manager not initialized".to_string(), None))
+        }
+    }
+    
+    pub async fn trigger_compaction_budgeted(&self, budget: std::time::Duration) -> Result<BudgetedCompaction> {
+        if let Some(ref manager) = self.compaction_manager {
+            manager.trigger_compaction_budgeted(budget).await
+        } else {
+            Err(DbError::Storage("Compaction manager not initialized".to_string(), None))
+        }
+    }
+    
+    pub async fn trigger_compaction_with_progress(&self, on_progress: &CompactionProgressFn<'_>) -> Result<CompactionStats> {
+        if let Some(ref manager) = self.compaction_manager {
+            manager.trigger_compaction_with_progress(on_progress).await
+        } else {
+            Err(DbError::Storage("Compaction manager not initialized".to_string(), None))
+        }
+    }
+    
+    pub async fn add_sstable(&self, sstable: SSTable, level: u32) -> Result<()> {
+        let sstable = self.with_prefix_bloom(sstable);
+        let mut levels = self.sstable_levels.write().unwrap();
+        levels.entry(level).or_insert_with(Vec::new).push(sstable);
+        self.sstable_generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+    
+    pub fn get_sstables_at_level(&self, level: u32) -> Vec<SSTable> {
+        let levels = self.sstable_levels.read().unwrap();
+        levels.get(&level).cloned().unwrap_or_default()
+    }
+    
+    // Memtable flushes since this storage was opened
+    pub fn flush_count(&self) -> u64 {
+        self.flush_count.load(Ordering::SeqCst)
+    }
+    
+    // SSTables read by scans since this storage was opened; tables a prefix
+    // bloom ruled out aren't counted
+    pub fn sstables_scanned(&self) -> u64 {
+        self.sstables_scanned.load(Ordering::Relaxed)
+    }
+    
+    // Builds the table's prefix bloom if the storage is configured for one. Every
+    // table goes through here before it's installed.
+    fn with_prefix_bloom(&self, mut sstable: SSTable) -> SSTable {
+        if let Some(len) = self.prefix_bloom_len {
+            sstable.build_prefix_bloom(len, self.hash_fn());
+        }
+        sstable
+    }
+    
+    pub fn sstable_count(&self) -> usize {
+        self.sstable_levels.read().unwrap().values().map(Vec::len).sum()
+    }
+    
+    pub fn get_all_sstables(&self) -> Vec<SSTable> {
+        let levels = self.sstable_levels.read().unwrap();
+        let mut level_ids: Vec<&u32> = levels.keys().collect();
+        level_ids.sort();
+        level_ids.into_iter().flat_map(|l| levels[l].iter().cloned()).collect()
+    }
+    
+    // `SSTable::stats` for every table, in `get_all_sstables` order, with
+    // each table's overlap with the others at its level and the next
+    pub fn sstable_report(&self) -> Vec<SSTableStats> {
+        let tables: Vec<SSTableStats> = self.get_all_sstables().iter().map(SSTable::stats).collect();
+        let mut report = tables.clone();
+        for (i, stats) in report.iter_mut().enumerate().filter(|(_, stats)| stats.entry_count > 0) {
+            for (j, other) in tables.iter().enumerate() {
+                let adjacent = other.level == stats.level || other.level == stats.level + 1;
+                let intersects = other.min_key <= stats.max_key && stats.min_key <= other.max_key;
+                if i != j && adjacent && other.entry_count > 0 && intersects {
+                    stats.overlapping_tables += 1;
+                    stats.overlapping_bytes += other.file_size;
+                }
+            }
+        }
+        report
+    }
+    
+    // Swaps compaction inputs for their merged outputs in one step so readers
+    // never observe a state with both or neither
+    pub(crate) fn replace_sstables(&self, inputs: &[SSTable], outputs: Vec<SSTable>) {
+        let outputs: Vec<SSTable> = outputs.into_iter().map(|output| self.with_prefix_bloom(output)).collect();
+        let mut levels = self.sstable_levels.write().unwrap();
+        for tables in levels.values_mut() {
+            tables.retain(|t| !inputs.iter().any(|i| i.path == t.path));
+        }
+        for output in outputs {
+            levels.entry(output.level).or_default().push(output);
+        }
+        levels.retain(|_, tables| !tables.is_empty());
+        self.sstable_generation.fetch_add(1, Ordering::SeqCst);
+    }
+    
+    // Changes whenever the set of tables does, so an unchanged value means
+    // there's nothing new to compact
+    pub(crate) fn sstable_generation(&self) -> u64 {
+        self.sstable_generation.load(Ordering::SeqCst)
+    }
+    
+    // Deletes a table that compaction has replaced, or leaves it for the last
+    // cursor reading it to delete
+    pub(crate) fn retire_sstable(&self, sstable: &SSTable) -> Result<()> {
+        if !self.pinned_sstables.lock().unwrap().retire(&sstable.path) {
+            return Ok(());
+        }
+        sstable.delete_files()
+            .map_err(|e| DbError::Storage(format!("Failed to remove old SSTable: {}", e), Some(Box::new(e))))
+    }
+    
+    // Too many tables on disk: make this writer wait for compaction to catch up
+    async fn apply_backpressure(&self) -> Result<()> {
+        let Some(ref manager) = self.compaction_manager else {
+            return Ok(());
+        };
+        if self.compaction_pending.swap(false, Ordering::SeqCst) {
+            manager.force_compaction().await?;
+        }
+        
+        let config = manager.config();
+        let level0 = || self.sstable_levels.read().unwrap().get(&0).map_or(0, Vec::len);
+        if level0() >= config.l0_stop_threshold {
+            return Err(DbError::Storage(format!(
+                "write stall: {} L0 tables, writes resume below {}",
+                level0(),
+                config.l0_stop_threshold
+            ), None));
+        }
+        // Poll rather than compact here, leaving the catching up to the compactor
+        let deadline = Instant::now() + Duration::from_millis(config.l0_stall_max_delay_ms);
+        while level0() >= config.l0_stall_threshold && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    }
+    
+    // Stores `value`, which may be empty; see `VALUE_TAG`
+    pub async fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_cell(key, encode_cell(value)).await
+    }
+    
+    // Writes a tombstone, after which `get` returns None
+    pub async fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write_cell(key, Vec::new()).await
+    }
+    
+    async fn write_cell(&self, key: &[u8], cell: Vec<u8>) -> Result<()> {
+        self.apply_backpressure().await?;
+        
+        // Writers share the memtable lock and insert concurrently. Logging under
+        // it keeps a flush from rotating the WAL between the log write and the insert.
+        let should_flush = {
+            let (memtable, wal) = self.write_locks(|| try_memtable_guard(self.memtable.try_read())).await?;
+            // Write to WAL first (for durability)
+            self.log_writes(wal, &[WalEntry::new(key, &cell)])?;
+            let deleted = cell.is_empty();
+            memtable.insert(key, &cell);
+            // Only after the insert, so a reader's miss can't be cached past it; see `remember_miss`
+            self.negative_cache.lock().unwrap().invalidate(key);
+            self.changes.publish(key, deleted);
+            self.flush_due(&memtable)
+        };
+        
+        // Flush to SSTable if threshold reached
+        if should_flush {
+            self.schedule_flush()?;
+        }
+        
+        Ok(())
+    }
+    
+    // Applies every write in the batch with a single WAL append and flush
+    pub async fn write_batch(&self, batch: &WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.apply_backpressure().await?;
+        
+        // Exclusive, so readers see either none or all of the batch
+        let should_flush = {
+            let (memtable, wal) = self.write_locks(|| try_memtable_guard(self.memtable.try_write())).await?;
+            self.log_writes(wal, batch.entries())?;
+            let mut negative_cache = self.negative_cache.lock().unwrap();
+            for entry in batch.entries() {
+                negative_cache.invalidate(&entry.key);
+                memtable.insert(&entry.key, &entry.value);
+                self.changes.publish(&entry.key, entry.value.is_empty());
+            }
+            self.flush_due(&memtable)
+        };
+        
+        if should_flush {
+            self.schedule_flush()?;
+        }
+        
+        Ok(())
+    }
+    
+    // None for a key that was never written or was deleted
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.get_cell(key)?.and_then(into_value))
+    }
+    
+    fn get_cell(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        // Holding the shared lock keeps a flush from moving the key out of the
+        // memtable mid-lookup
+        let memtable = self.active_memtable();
+        if self.negative_cache.lock().unwrap().contains(key) {
+            return Ok(None);
+        }
+        
+        // Check memtable first
+        if let Some(value) = self.get_from_memtable(&memtable, key)? {
+            return Ok(Some(value));
+        }
+        
+        if let Some(value) = self.get_from_frozen(key) {
+            return Ok(Some(value));
+        }
+        let (found, versions) = self.newest_in_sstables(key)?;
+        if let Some(value) = found {
+            if self.read_repair && versions > 1 {
+                self.repair_shadowed(&memtable, key, &value);
+            }
+            return Ok(Some(value));
+        }
+        
+        self.remember_miss(&memtable, key);
+        Ok(None)
+    }
+    
+    // Only fills a gap in the memtable: a write that lands first is newer than
+    // anything flushed, and wins
+    fn repair_shadowed(&self, memtable: &MemTable, key: &[u8], value: &[u8]) {
+        if memtable.insert_if_absent(key, value) {
+            log::debug!("Read repair copied {} bytes forward for a shadowed key", value.len());
+        }
+    }
+    
+    // Caches a miss unless a concurrent write has landed since we looked. Writers
+    // invalidate the cache after inserting into the memtable, so re-checking the
+    // memtable under the cache lock can't race with them.
+    fn remember_miss(&self, memtable: &MemTable, key: &[u8]) {
+        let mut negative_cache = self.negative_cache.lock().unwrap();
+        if !memtable.contains_key(key) {
+            negative_cache.insert(key);
+        }
+    }
+    
+    fn active_memtable(&self) -> RwLockReadGuard<'_, MemTable> {
+        // Nothing panics while holding the lock with the table half-updated, so
+        // a poisoned lock still guards a usable memtable
+        self.memtable.read().unwrap_or_else(PoisonError::into_inner)
+    }
+    
+    fn exclusive_memtable(&self) -> RwLockWriteGuard<'_, MemTable> {
+        self.memtable.write().unwrap_or_else(PoisonError::into_inner)
+    }
+    
+    // Writers take their locks through `lock_config`, so contention ends in an
+    // error rather than an unbounded wait: the memtable lock `take_memtable`
+    // tries for, and the WAL's unless it's disabled. Neither is waited on while
+    // the other is held; if either is busy both are let go before backing off.
+    async fn write_locks<G>(
+        &self,
+        take_memtable: impl Fn() -> Option<G>,
+    ) -> Result<(G, Option<RwLockWriteGuard<'_, WriteAheadLog>>)> {
+        let attempt = || {
+            let memtable = take_memtable()?;
+            if !self.wal_enabled {
+                return Some(Ok((memtable, None)));
+            }
+            match self.wal.try_write() {
+                Ok(wal) => Some(Ok((memtable, Some(wal)))),
+                Err(TryLockError::Poisoned(e)) => Some(Err(DbError::Storage(format!("WAL lock error: {}", e), None))),
+                Err(TryLockError::WouldBlock) => None,
+            }
+        };
+        self.lock_config.acquire(attempt).await?
+    }
+    
+    // Counts the entries as user writes, and appends them to the WAL unless it's disabled
+    fn log_writes(&self, wal: Option<RwLockWriteGuard<'_, WriteAheadLog>>, entries: &[WalEntry]) -> Result<()> {
+        let user_bytes = entries
+            .iter()
+            .map(|entry| match entry.kind {
+                // The value tag is ours, not the user's
+                WalEntryKind::Put => entry.key.len() + entry.value.len().saturating_sub(1),
+                WalEntryKind::Merge => entry.key.len() + entry.value.len(),
+            })
+            .sum();
+        self.write_counters.record_user(user_bytes);
+        let Some(mut wal) = wal else {
+            return Ok(());
+        };
+        let logged = wal.write_batch(entries)?;
+        self.write_counters.record_wal(logged);
+        Ok(())
+    }
+    
+    // `get` followed by deserializing the value, except that SSTable hits are
+    // deserialized in place from the mmap instead of through a copied `Vec`
+    pub async fn get_into<T: serde::de::DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>> {
+        let memtable = self.active_memtable();
+        if self.negative_cache.lock().unwrap().contains(key) {
+            return Ok(None);
+        }
+        
+        let buffered = match self.get_from_memtable(&memtable, key)? {
+            Some(data) => Some(data),
+            None => self.get_from_frozen(key),
+        };
+        if let Some(cell) = buffered {
+            let Some(data) = decode_cell(&cell) else {
+                return Ok(None);
+            };
+            return bincode::deserialize(data)
+                .map(Some)
+                .map_err(DbError::serialization);
+        }
+        
+        let sstables = self.get_all_sstables();
+        let mut newest: Option<(&SSTable, std::borrow::Cow<'_, [u8]>, u64)> = None;
+        for sstable in &sstables {
+            if let Some((cell, timestamp)) = sstable.get_ref(key)? {
+                if newest.as_ref().is_none_or(|(_, _, newest_ts)| timestamp > *newest_ts) {
+                    newest = Some((sstable, cell, timestamp));
+                }
+            }
+        }
+        match newest {
+            Some((sstable, cell, _)) => match sstable.value_of(&cell) {
+                Some(data) => bincode::deserialize(data)
+                    .map(Some)
+                    .map_err(DbError::serialization),
+                None => Ok(None),
+            },
+            None => {
+                self.remember_miss(&memtable, key);
+                Ok(None)
+            }
+        }
+    }
+    
+    // The key's value as of the memtable, folding in any pending merge operands
+    fn get_from_memtable(&self, memtable: &MemTable, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match memtable.operands(key) {
+            Some(operands) => {
+                let base = match memtable.get(key) {
+                    Some(value) => Some(value),
+                    None => self.get_flushed(key)?,
+                };
+                self.apply_merge(key, base, &operands).map(Some)
+            }
+            None => Ok(memtable.get(key)),
+        }
+    }
+    
+    // Newest write for the key below the active memtable
+    fn get_flushed(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        // Frozen memtables before SSTables: one leaves the list only after its
+        // SSTable is installed, so its entries are always in one or the other
+        if let Some(value) = self.get_from_frozen(key) {
+            return Ok(Some(value));
+        }
+        self.get_from_sstables(key)
+    }
+    
+    // Merge operands were folded in when these were frozen, so only their data counts
+    fn get_from_frozen(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.frozen_memtables()
+            .iter()
+            .rev()
+            .find_map(|frozen| frozen.memtable.get(key))
+    }
+    
+    fn frozen_memtables(&self) -> Vec<Arc<FrozenMemTable>> {
+        self.frozen_memtables.read().unwrap().clone()
+    }
+    
+    // Memtables frozen by a flush whose SSTables aren't installed yet
+    pub fn pending_flushes(&self) -> usize {
+        self.frozen_memtables.read().unwrap().len()
+    }
+    
+    // Newest write for the key across all levels
+    fn get_from_sstables(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.newest_in_sstables(key)?.0)
+    }
+    
+    // The newest value across SSTables, and how many tables hold the key
+    fn newest_in_sstables(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, usize)> {
+        let mut newest: Option<ValueWithTimestamp> = None;
+        let mut versions = 0;
+        for sstable in self.get_all_sstables() {
+            if let Some(found) = sstable.get(key)? {
+                versions += 1;
+                if newest.as_ref().is_none_or(|n| found.timestamp > n.timestamp) {
+                    newest = Some(found);
+                }
+            }
+        }
+        Ok((newest.map(|found| found.value), versions))
+    }
+    
+    // Folds pending merge operands over the key's base cell into a new cell; a
+    // tombstone base means the operands start from nothing
+    fn apply_merge(&self, key: &[u8], base: Option<Vec<u8>>, operands: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let operator = self
+            .merge_operator
+            .as_ref()
+            .ok_or_else(|| DbError::Storage("No merge operator registered".to_string(), None))?;
+        let base = base.as_deref().and_then(decode_cell);
+        Ok(encode_cell(&operator.merge(key, base, operands)?))
+    }
+    
+    // Records `operand` for the registered merge operator to fold into the key's value
+    // on read and flush, so read-modify-write updates don't race each other
+    pub async fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        if self.merge_operator.is_none() {
+            return Err(DbError::Storage("No merge operator registered".to_string(), None));
+        }
+        self.apply_backpressure().await?;
+        
+        let should_flush = {
+            let (memtable, wal) = self.write_locks(|| try_memtable_guard(self.memtable.try_read())).await?;
+            self.log_writes(wal, &[WalEntry::merge(key, operand)])?;
+            memtable.push_operand(key, operand);
+            self.negative_cache.lock().unwrap().invalidate(key);
+            self.flush_due(&memtable)
+        };
+        
+        if should_flush {
+            self.schedule_flush()?;
+        }
+        
+        Ok(())
+    }
+    
+    // A handle whose reads and writes stay within tenant `id`'s namespace
+    pub fn tenant(&self, id: &str) -> Tenant {
+        Tenant::new(self.clone(), id)
+    }
+    
+    // A feed of every put and delete from now on, including those in write
+    // batches. Merges aren't published, since they don't carry the new value.
+    pub fn subscribe(&self) -> ChangeReceiver {
+        self.changes.subscribe()
+    }
+    
+    // Number of reads answered by the negative cache without probing storage
+    pub fn negative_cache_hits(&self) -> u64 {
+        self.negative_cache.lock().unwrap().hits()
+    }
+    
+    pub async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_within(ScanBounds::Prefix(prefix), usize::MAX)
+    }
+    
+    // Keys in [start, end), read only from the parts of each table that overlap the range
+    pub async fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_within(ScanBounds::Range(start, end), usize::MAX)
+    }
+    
+    // The first `limit` keys in [start, end). Those are among the first `limit`
+    // of every table, so no more than that is read from each.
+    pub async fn scan_range_limited(&self, start: &[u8], end: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_within(ScanBounds::Range(start, end), limit)
+    }
+    
+    // Entries across the memtable and every SSTable, without reading any of
+    // them. Overwritten keys count once per copy and deleted keys still count,
+    // so this is an upper bound on `exact_key_count`.
+    pub fn estimated_key_count(&self) -> usize {
+        let memtable = self.active_memtable();
+        let frozen: usize = self.frozen_memtables().iter().map(|f| f.memtable.len()).sum();
+        let on_disk: usize = self.get_all_sstables().iter().map(SSTable::len).sum();
+        memtable.len() + frozen + on_disk
+    }
+
+    // Write counters and the current footprint. Sizing the live data merges
+    // every table, as `exact_key_count` does.
+    pub async fn storage_stats(&self) -> Result<StorageStats> {
+        let disk_bytes = self.get_all_sstables().iter().map(|sst| sst.file_size).sum();
+        let live_data_bytes = self
+            .scan_within(ScanBounds::Prefix(&[]), usize::MAX)?
+            .iter()
+            .map(|(key, value)| (key.len() + value.len()) as u64)
+            .sum();
+        Ok(self.write_counters.snapshot(disk_bytes, live_data_bytes))
+    }
+    
+    pub(crate) fn record_compaction_write(&self, bytes: u64) {
+        self.write_counters.record_compaction(bytes);
+    }
+    
+    // Live keys, found by merging every table
+    pub async fn exact_key_count(&self) -> Result<usize> {
+        Ok(self.scan_within(ScanBounds::Prefix(&[]), usize::MAX)?.len())
+    }
+    
+    // The first `limit` live values within `bounds`, untagged. Tombstones take
+    // up room in a pass over the cells, so a range scan goes on past them until
+    // `limit` values turn up or the range runs out.
+    fn scan_within(&self, bounds: ScanBounds<'_>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut results = Vec::new();
+        let mut wanted = limit;
+        let mut cells = self.scan_cells_within(bounds, wanted)?;
+        loop {
+            let exhausted = cells.len() < wanted;
+            let last = cells.last().map(|(key, _)| key.clone());
+            results.extend(cells.into_iter().filter_map(|(key, cell)| Some((key, into_value(cell)?))));
+            wanted = limit - results.len();
+            let (ScanBounds::Range(_, end), Some(mut start)) = (bounds, last) else {
+                return Ok(results);
+            };
+            if exhausted || wanted == 0 {
+                return Ok(results);
+            }
+            start.push(0);
+            cells = self.scan_cells_within(ScanBounds::Range(&start, end), wanted)?;
         }
     }
-    
-    pub fn get(&self, _key: &[u8]) -> Option<Vec<u8>> {
-        // Simplified - in real implementation would use bloom filter and sparse index
-        // For now, we'll implement proper scanning in the next iteration
-        None
-    }
-    
-    pub async fn iter(&self) -> Result<Vec<(Vec<u8>, ValueWithTimestamp)>> {
-        // Simplified implementation - returns all key-value pairs
-        // In production, this would be a streaming iterator
-        let mut entries = Vec::new();
-        let cursor = 0;
+
+    // Newest cell for each key within `bounds`, tombstones included
+    fn scan_cells_within(&self, bounds: ScanBounds<'_>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        // Hold the memtable lock so a concurrent flush can't move entries out from under us
+        let memtable = self.active_memtable();
+        // Listed before the SSTables, so a table finishing its flush meanwhile
+        // is seen at least once
+        let frozen = self.frozen_memtables();
         
-        while cursor < self.data.len() {
-            if let Ok((key, value)) = bincode::deserialize_from(&self.data[cursor..]as &[u8]) {
-                entries.push((key, ValueWithTimestamp { value, timestamp: 0 }));
-                // Move cursor - simplified, in production would track exact position
-                break;
-            } else {
-                break;
+        // Scan SSTables, keeping the newest write for each key
+        let mut merged: BTreeMap<Vec<u8>, ValueWithTimestamp> = BTreeMap::new();
+        let mut shadowed: Vec<Vec<u8>> = Vec::new();
+        for sstable in self.get_all_sstables().into_iter().filter(|sstable| sstable.may_overlap(bounds)) {
+            self.sstables_scanned.fetch_add(1, Ordering::Relaxed);
+            for (key, value) in sstable.scan_within(bounds, limit)? {
+                match merged.get(&key) {
+                    Some(existing) if existing.timestamp >= value.timestamp => {
+                        shadowed.push(key);
+                    }
+                    Some(_) => {
+                        shadowed.push(key.clone());
+                        merged.insert(key, value);
+                    }
+                    None => {
+                        merged.insert(key, value);
+                    }
+                }
             }
         }
         
-        Ok(entries)
-    }
-    
-    pub async fn create(path: &Path, data: BTreeMap<Vec<u8>, ValueWithTimestamp>) -> Result<Self> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        // Serialize data to file
-        for (key, value_with_ts) in &data {
-            let entry = (key, &value_with_ts.value);
-            bincode::serialize_into(&mut file, &entry)
-                .map_err(|e| DbError::Serialization(e.to_string()))?;
+        // Frozen memtables already hold something newer for their keys
+        if self.read_repair {
+            shadowed.sort();
+            shadowed.dedup();
+            for key in shadowed {
+                if frozen.iter().any(|frozen| frozen.memtable.get(&key).is_some()) {
+                    continue;
+                }
+                self.repair_shadowed(&memtable, &key, &merged[&key].value);
+            }
         }
         
-        file.flush()
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        // Get file size
-        let metadata = std::fs::metadata(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-        let file_size = metadata.len();
-            
-        // Memory map the file
-        let file = OpenOptions::new()
-            .read(true)
-            .open(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        unsafe {
-            let mmap_data = Mmap::map(&file)
-                .map_err(|e| DbError::Storage(e.to_string()))?;
-                
-            Ok(SSTable {
-                path: path.to_path_buf(),
-                data: Arc::new(mmap_data),
-                file_size,
-                level: 0,
-            })
+        // Memtable entries are newer than anything already flushed
+        let mut results: BTreeMap<Vec<u8>, Vec<u8>> = merged
+            .into_iter()
+            .map(|(key, value)| (key, value.value))
+            .collect();
+        for frozen in &frozen {
+            results.extend(frozen.memtable.scan_within(bounds, limit));
+        }
+        results.extend(memtable.scan_within(bounds, limit));
+        
+        // A key whose base was cut off by `limit` sorts after `limit` others, so
+        // the truncation below drops it along with its wrongly folded value
+        for (key, operands) in memtable.operands_within(bounds, limit) {
+            let folded = self.apply_merge(&key, results.remove(&key), &operands)?;
+            results.insert(key, folded);
         }
+        
+        Ok(results.into_iter().take(limit).collect())
     }
-}
-
-#[derive(Clone, Debug)]
-pub struct ValueWithTimestamp {
-    pub value: Vec<u8>,
-    pub timestamp: u64,
-}
-
-// Main LSM Storage Engine
-#[derive(Clone)]
-pub struct LsmStorage {
-    memtable: Arc<RwLock<MemTable>>,
-    wal: Arc<RwLock<WriteAheadLog>>,
-    sstables: Arc<RwLock<Vec<SSTable>>>,
-    sstable_levels: Arc<RwLock<HashMap<u32, Vec<SSTable>>>>,
-    base_path: PathBuf,
-    index_manager: Arc<RwLock<IndexManager>>,
-    compaction_manager: Option<Arc<CompactionManager>>,
-}
-
-impl LsmStorage {
-    pub fn new(path: &Path) -> Result<Self> {
-        std::fs::create_dir_all(path)
-            .map_err(|e| DbError::Storage(e.to_string()))?;
-            
-        let wal_path = path.join("wal.bin");
-        let wal = WriteAheadLog::new(&wal_path)?;
-        let storage = LsmStorage {
-            memtable: Arc::new(RwLock::new(MemTable::new())),
-            wal: Arc::new(RwLock::new(wal)),
-            sstables: Arc::new(RwLock::new(Vec::new())),
-            sstable_levels: Arc::new(RwLock::new(HashMap::new())),
-            base_path: path.to_path_buf(),
-            index_manager: Arc::new(RwLock::new(IndexManager::new())),
-            compaction_manager: None,
-        };
-        Ok(storage)
+    
+    // Forces the memtable out to a new level-0 SSTable, returning None if it was
+    // empty. Also waits for flushes already running in the background.
+    pub async fn flush(&self) -> Result<Option<SSTableMeta>> {
+        self.flush_memtable()
     }
     
-    pub fn with_compaction(mut self, config: CompactionConfig) -> Self {
-        let storage_arc = Arc::new(self.clone());
-        self.compaction_manager = Some(Arc::new(CompactionManager::new(storage_arc, config)));
-        self
+    fn flush_memtable(&self) -> Result<Option<SSTableMeta>> {
+        let frozen = self.freeze_memtable(true)?;
+        let written = self.write_frozen_memtables()?;
+        let Some(frozen) = frozen else {
+            return Ok(None);
+        };
+        let path = self.flush_path(frozen.timestamp);
+        // Not in `written` if a background flush got to it first
+        let meta = written.into_iter().find(|meta| meta.path == path).or_else(|| {
+            self.get_sstables_at_level(0)
+                .iter()
+                .find(|sstable| sstable.path == path)
+                .map(SSTable::meta)
+        });
+        Ok(meta)
     }
     
-    pub fn base_path(&self) -> &Path {
-        &self.base_path
+    // Whether the memtable is full, or has held writes for longer than
+    // `StorageConfig::with_max_memtable_age` allows
+    fn flush_due(&self, memtable: &MemTable) -> bool {
+        memtable.should_flush() || self.memtable_overdue_in(memtable) == Some(Duration::ZERO)
     }
     
-    pub async fn trigger_compaction(&self) -> Result<CompactionStats> {
-        if let Some(ref manager) = self.compaction_manager {
-            manager.trigger_compaction().await
-        } else {
-            Err(DbError::Storage("Compaction manager not initialized".to_string()))
-        }
+    // How long until the memtable is too old to keep, zero once it is. None
+    // if it's empty or there's no age limit.
+    fn memtable_overdue_in(&self, memtable: &MemTable) -> Option<Duration> {
+        let max_age = self.max_memtable_age?;
+        memtable.age().map(|age| max_age.saturating_sub(age))
     }
     
-    pub async fn add_sstable(&self, sstable: SSTable, level: u32) -> Result<()> {
-        let mut levels = self.sstable_levels.write().unwrap();
-        levels.entry(level).or_insert_with(Vec::new).push(sstable);
+    // Called by a writer that filled the memtable, or found it too old. The
+    // SSTable is written on the runtime's blocking pool, so neither the write
+    // that triggered the flush nor the async workers wait on its file I/O.
+    fn schedule_flush(&self) -> Result<()> {
+        if self.freeze_memtable(false)?.is_none() {
+            return Ok(());
+        }
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) if self.pending_flushes() <= MAX_FROZEN_MEMTABLES => runtime,
+            // Flushing has fallen behind, or there's nothing to run it on
+            _ => return self.write_frozen_memtables().map(|_| ()),
+        };
+        let storage = self.clone();
+        runtime.spawn_blocking(move || {
+            if let Err(e) = storage.write_frozen_memtables() {
+                log::error!("Background flush failed: {}", e);
+            }
+        });
         Ok(())
     }
     
-    pub fn get_sstables_at_level(&self, level: u32) -> Vec<SSTable> {
-        let levels = self.sstable_levels.read().unwrap();
-        levels.get(&level).cloned().unwrap_or_default()
+    fn flush_path(&self, timestamp: u64) -> PathBuf {
+        Self::level_dir(&self.base_path, 0).join(format!("sst_{}.bin", timestamp))
     }
     
-    pub async fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        // Write to WAL first (for durability)
-        self.wal.write()
-            .map_err(|e| DbError::Storage(format!("WAL lock error: {}", e)))?
-            .write_entry(key, value)?;
+    // Swaps in an empty memtable and WAL, returning the old memtable once it's
+    // readable from the frozen list. None if there was nothing to flush, or if
+    // `force` is false and another writer already swapped the full table out.
+    fn freeze_memtable(&self, force: bool) -> Result<Option<Arc<FrozenMemTable>>> {
+        let mut memtable = self.exclusive_memtable();
+        
+        if memtable.len() == 0 || !(force || self.flush_due(&memtable)) {
+            return Ok(None);
+        }
+        
+        // SSTables only hold full values, so fold pending merge operands first
+        let folded = memtable
+            .scan_operands(&[])
+            .into_iter()
+            .map(|(key, operands)| {
+                let base = match memtable.get(&key) {
+                    Some(value) => Some(value),
+                    None => self.get_flushed(&key)?,
+                };
+                let value = self.apply_merge(&key, base, &operands)?;
+                Ok((key, value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (key, value) in folded {
+            memtable.insert(&key, &value);
+        }
         
-        // Write to memtable
+        // Later writes go to a fresh WAL; this one is kept until the SSTable is
+        // installed, and replayed by `open` if that never happens
+        let timestamp = self.next_flush_ts();
+        let wal_segment = Self::wal_segment_path(&self.wal_path, timestamp);
         {
-            let mut memtable = self.memtable.write().unwrap();
-            memtable.insert(key.to_vec(), value.to_vec());
-            
-            // Flush to SSTable if threshold reached
-            if memtable.should_flush() {
-                self.flush_memtable()?;
-            }
+            let mut wal = self.wal.write().unwrap();
+            self.fs.rename(&self.wal_path, &wal_segment)
+                .map_err(|e| DbError::Storage(format!("Failed to rotate WAL: {}", e), Some(Box::new(e))))?;
+            *wal = WriteAheadLog::create_in(self.fs.as_ref(), &self.wal_path)?.with_encryption(self.encryption.clone());
         }
         
-        Ok(())
+        let frozen = Arc::new(FrozenMemTable {
+            memtable: std::mem::replace(&mut *memtable, MemTable::new()),
+            timestamp,
+            wal_segment,
+        });
+        self.frozen_memtables.write().unwrap().push(Arc::clone(&frozen));
+        Ok(Some(frozen))
     }
     
-    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        // Check memtable first
-        {
-            let memtable = self.memtable.read().unwrap();
-            if let Some(value) = memtable.get(key) {
-                return Ok(Some(value));
+    // Writes frozen memtables to level-0 SSTables, oldest first, returning what
+    // it wrote. A failed table stays frozen, and is retried by the next flush.
+    fn write_frozen_memtables(&self) -> Result<Vec<SSTableMeta>> {
+        let _writing = self.flush_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut written = Vec::new();
+        loop {
+            let Some(frozen) = self.frozen_memtables.read().unwrap().first().cloned() else {
+                return Ok(written);
+            };
+            let path = self.flush_path(frozen.timestamp);
+            let sstable = self.with_prefix_bloom(SSTable::from_memtable(
+                &path,
+                &frozen.memtable,
+                frozen.timestamp,
+                self.hash_fn(),
+                &self.fs,
+                self.encryption(),
+            )?);
+            written.push(sstable.meta());
+            self.write_counters.record_flush(sstable.file_size);
+            
+            // The table is fsynced by now. It's installed and the frozen entry
+            // dropped under both locks, so anyone holding either sees the swap
+            // as one step, and readers never miss the entries in between.
+            {
+                let mut levels = self.sstable_levels.write().unwrap();
+                let mut frozen_list = self.frozen_memtables.write().unwrap();
+                levels.entry(0).or_default().push(sstable);
+                frozen_list.remove(0);
+                self.sstable_generation.fetch_add(1, Ordering::SeqCst);
             }
-        }
-        
-        // Check SSTables (from newest to oldest)
-        let sstables = self.sstables.read().unwrap();
-        for sstable in sstables.iter().rev() {
-            if let Some(value) = sstable.get(key) {
-                return Ok(Some(value));
+            self.flush_count.fetch_add(1, Ordering::SeqCst);
+            if let Some(ref manager) = self.compaction_manager {
+                if self.sstable_count() > manager.config().max_total_sstables {
+                    self.compaction_pending.store(true, Ordering::SeqCst);
+                }
             }
+            self.retire_wal_segment(&frozen.wal_segment)?;
         }
-        
-        Ok(None)
     }
     
-    pub async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        let mut results = Vec::new();
-        
-        // Scan memtable
-        {
-            let memtable = self.memtable.read().unwrap();
-            results.extend(memtable.scan(prefix));
+    // Writes `row` under `{table}:` and its `Schema::key`, indexed as `insert` does
+    pub async fn insert_row<T: Schema + Serialize>(&self, row: &T) -> Result<()> {
+        row.validate()?;
+        self.check_schema(row)?;
+        let row_key = row.key();
+        if row_key.is_empty() {
+            return Err(DbError::Schema(format!(
+                "{} has no key fields, so its rows can't be addressed",
+                T::table_name()
+            )));
         }
-        
-        // Scan SSTables
-        let sstables = self.sstables.read().unwrap();
-        for _sstable in sstables.iter() {
-            // Simplified - in real implementation would properly scan SSTable
-        }
-        
-        // Deduplicate (newer values override older ones)
-        results.sort_by(|a, b| a.0.cmp(&b.0));
-        results.dedup_by(|a, b| a.0 == b.0);
-        
-        Ok(results)
+        let mut key = format!("{}:", T::table_name()).into_bytes();
+        key.extend(row_key);
+        Database::insert(self, &key, row).await
     }
     
-    fn flush_memtable(&self) -> Result<()> {
-        let mut memtable = self.memtable.write().unwrap();
-        
-        if memtable.len() == 0 {
+    // With schema validation on, records the table's fingerprint if it has
+    // none yet, and otherwise fails unless `T` matches it
+    fn check_schema<T: Schema + Serialize>(&self, row: &T) -> Result<()> {
+        let Some(fingerprint) = T::fingerprint().filter(|_| self.validate_schemas) else {
             return Ok(());
+        };
+        self.schemas.check_or_record(self.fs.as_ref(), T::table_name(), fingerprint, row)
+    }
+    
+    // Replaces every row under `{table}:` with `rows`, keyed by `Schema::key`.
+    // The new rows and tombstones for the old ones are written to one SSTable in
+    // a staging directory, then installed while the memtable is locked
+    // exclusively, so readers go from the old rows to the new ones in one step.
+    // Writes to the table that race the swap land after it. Indexes over the
+    // table aren't rewritten; run `repair_index` afterwards.
+    pub async fn replace_table<T: Schema + Serialize>(&self, rows: impl IntoIterator<Item = T>) -> Result<()> {
+        let prefix = format!("{}:", T::table_name()).into_bytes();
+        let mut data = BTreeMap::new();
+        for row in rows {
+            row.validate()?;
+            if data.is_empty() {
+                self.check_schema(&row)?;
+            }
+            let row_key = row.key();
+            if row_key.is_empty() {
+                return Err(DbError::Schema(format!(
+                    "{} has no key fields, so its rows can't be addressed",
+                    T::table_name()
+                )));
+            }
+            let mut key = prefix.clone();
+            key.extend(row_key);
+            let value = bincode::serialize(&row)
+                .map_err(DbError::serialization)?;
+            data.insert(key, value);
         }
         
-        // Create new SSTable from current memtable
-        let sstable_path = self.base_path.join(format!(
-            "sst_{}.bin",
-            SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros()
-        ));
-        
-        let sstable = SSTable::from_memtable(&sstable_path, &memtable)?;
-        
-        // Add to SSTable list
-        self.sstables.write().unwrap().push(sstable);
-        
-        // Clear memtable
-        *memtable = MemTable::new();
+        // Old rows still in the memtable would shadow the new table
+        self.flush_memtable()?;
+        let timestamp = self.next_flush_ts();
+        let mut entries: BTreeMap<Vec<u8>, ValueWithTimestamp> = BTreeMap::new();
+        for (key, _) in self.scan(&prefix).await? {
+            if !data.contains_key(&key) {
+                entries.insert(key, ValueWithTimestamp { value: Vec::new(), timestamp });
+            }
+        }
+        let new_keys: Vec<Vec<u8>> = data.keys().cloned().collect();
+        self.write_counters.record_user(data.iter().map(|(key, value)| key.len() + value.len()).sum());
+        entries.extend(data.into_iter().map(|(key, value)| {
+            (key, ValueWithTimestamp { value: encode_cell(&value), timestamp })
+        }));
+        if entries.is_empty() {
+            return Ok(());
+        }
         
-        // Clear WAL (in production, we'd use segment-based WAL)
-        let wal_path = self.base_path.join("wal.bin");
-        let new_wal = WriteAheadLog::new(&wal_path)?;
-        *self.wal.write().unwrap() = new_wal;
+        // Build under `staging/`, which discovery ignores, then move into L0
+        let staging_dir = self.base_path.join("staging");
+        self.fs.create_dir_all(&staging_dir)
+            .map_err(DbError::storage)?;
+        let file_name = format!("sst_{}.bin", timestamp);
+        let staging_path = staging_dir.join(&file_name);
+        SSTable::create(&staging_path, entries, 0, self.hash_fn(), false, &self.fs, self.encryption()).await?;
+        let level_dir = Self::level_dir(&self.base_path, 0);
+        self.fs.create_dir_all(&level_dir)
+            .map_err(DbError::storage)?;
+        let final_path = level_dir.join(&file_name);
+        self.fs.rename(&staging_path, &final_path)
+            .map_err(DbError::storage)?;
+        let sstable = self.with_prefix_bloom(SSTable::open_with(&final_path, 0, self.hash_fn(), &self.fs, self.encryption())?);
+        self.write_counters.record_flush(sstable.file_size);
         
+        let _memtable = self.exclusive_memtable();
+        self.sstable_levels.write().unwrap().entry(0).or_default().push(sstable);
+        self.sstable_generation.fetch_add(1, Ordering::SeqCst);
+        let mut negative_cache = self.negative_cache.lock().unwrap();
+        for key in &new_keys {
+            negative_cache.invalidate(key);
+        }
         Ok(())
     }
 
@@ -395,19 +2486,140 @@ impl LsmStorage {
         index_mgr.create_index(descriptor).await
     }
 
+    // Copy of the index definitions, so no lock is held across storage awaits
+    fn index_snapshot(&self) -> IndexManager {
+        self.index_manager.read().unwrap().clone()
+    }
+    
+    pub async fn update_index(
+        &self,
+        index_name: &str,
+        record_key: &[u8],
+        value: &rust_db_core::Value,
+    ) -> Result<()> {
+        let index_mgr = self.index_snapshot();
+        let claims = if index_mgr.is_unique(index_name) { vec![(index_name.to_string(), value.clone())] } else { Vec::new() };
+        let _unique = self.unique_locks.lock(&claims).await;
+        index_mgr.update_index(self, index_name, record_key, value).await
+    }
+    
+    // Held from a unique index check until the write it allowed lands, so two
+    // writers can't both pass the check for the same value
+    pub(crate) async fn unique_write_guard(&self, claims: &[(String, rust_db_core::Value)]) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        self.unique_locks.lock(claims).await
+    }
+    
+    // Held from reading the values a write replaces until it lands; see `RecordLocks`.
+    // Callers holding a `unique_write_guard` take it first.
+    pub(crate) async fn record_write_guard<'a>(&self, record_keys: impl IntoIterator<Item = &'a [u8]>) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        self.record_locks.lock(record_keys).await
+    }
+    
+    // Writes a record, or deletes it when `value` is None, in one batch with
+    // the index changes it makes. Only a record an index is sourced from needs
+    // the value it replaces, whose entries go unless the new value keeps them;
+    // that value is read under the record's lock, so it can't go stale before
+    // the batch lands.
+    async fn write_record(&self, index_mgr: &IndexManager, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        if !index_mgr.indexes_key(key) {
+            index_mgr.batch_record_write(&mut batch, key, None, value)?;
+            return self.write_batch(&batch).await;
+        }
+        let _record = self.record_write_guard([key]).await;
+        let previous = self.get(key).await?;
+        index_mgr.batch_record_write(&mut batch, key, previous.as_deref(), value)?;
+        self.write_batch(&batch).await
+    }
+    
+    // Keeps the index up to date for records of type `T` stored under `key_prefix`,
+    // both on `insert` and when a transaction commits. The indexed field may be
+    // a dotted path into `#[nested]` structs, e.g. `address.city`.
+    pub fn index_records<T>(&self, index_name: &str, key_prefix: &[u8]) -> Result<()>
+    where
+        T: serde::de::DeserializeOwned + FieldAccess + 'static,
+    {
+        let mut index_mgr = self.index_manager.write().unwrap();
+        let field = index_mgr.descriptor_field(index_name)?;
+        let extract: FieldExtractor = Arc::new(move |data: &[u8]| {
+            let item: T = bincode::deserialize(data)
+                .map_err(DbError::serialization)?;
+            Ok(item.get_path(&field))
+        });
+        index_mgr.register_source(index_name, key_prefix, extract)
+    }
+    
+    pub async fn index_record(&self, record_key: &[u8], data: &[u8]) -> Result<()> {
+        let index_mgr = self.index_snapshot();
+        index_mgr.index_record(self, record_key, data).await
+    }
+    
+    // See `IndexManager::index_contains`; check it before inserting to keep a field unique
+    pub async fn index_contains(&self, index_name: &str, value: &rust_db_core::Value) -> Result<bool> {
+        let index_mgr = self.index_snapshot();
+        index_mgr.index_contains(self, index_name, value).await
+    }
+    
+    // See `IndexManager::scan_index`
+    pub async fn scan_index(&self, index_name: &str) -> Result<Vec<(rust_db_core::Value, Vec<u8>)>> {
+        let index_mgr = self.index_snapshot();
+        index_mgr.scan_index(self, index_name).await
+    }
+    
+    // Reports index entries missing for, or orphaned from, the records under the
+    // index's registered source; see `index_records`
+    pub async fn verify_index(&self, index_name: &str) -> Result<IndexVerifyReport> {
+        let index_mgr = self.index_snapshot();
+        index_mgr.verify_index(self, index_name, false).await
+    }
+    
+    // As `verify_index`, then writes the missing entries and deletes the orphaned ones
+    pub async fn repair_index(&self, index_name: &str) -> Result<IndexVerifyReport> {
+        let index_mgr = self.index_snapshot();
+        index_mgr.verify_index(self, index_name, true).await
+    }
+    
+    // Records whose indexed point lies within the box, corners given as (lat, lon)
+    pub async fn query_in_bbox<T: serde::de::DeserializeOwned + FieldAccess>(
+        &self,
+        index_name: &str,
+        min: (f64, f64),
+        max: (f64, f64),
+    ) -> Result<Vec<T>> {
+        let index_mgr = self.index_snapshot();
+        let field = index_mgr.descriptor_field(index_name)?;
+        let candidates = index_mgr.lookup_geo(self, index_name, min, max).await?;
+        
+        let mut results = Vec::new();
+        for key in candidates {
+            let Some(data) = self.get(&key).await? else {
+                continue;
+            };
+            let item: T = bincode::deserialize(&data)
+                .map_err(DbError::serialization)?;
+            // Geohash cells overhang the box, so check the exact coordinates
+            if let Some(rust_db_core::Value::Point { lat, lon }) = item.get_path(&field) {
+                if lat >= min.0 && lat <= max.0 && lon >= min.1 && lon <= max.1 {
+                    results.push(item);
+                }
+            }
+        }
+        Ok(results)
+    }
+    
     pub async fn get_by_index<T: serde::de::DeserializeOwned>(
         &self,
         index_name: &str,
         value: &rust_db_core::Value,
     ) -> Result<Vec<T>> {
-        let index_mgr = self.index_manager.read().unwrap();
+        let index_mgr = self.index_snapshot();
         let record_keys = index_mgr.lookup_index(self, index_name, value).await?;
 
         let mut results = Vec::new();
         for key in record_keys {
             if let Some(data) = self.get(&key).await? {
                 let item: T = bincode::deserialize(&data)
-                    .map_err(|e| DbError::Serialization(e.to_string()))?;
+                    .map_err(DbError::serialization)?;
                 results.push(item);
             }
         }
@@ -420,30 +2632,81 @@ impl LsmStorage {
 #[async_trait::async_trait]
 impl Database for LsmStorage {
     async fn insert<T: Serialize + Send + Sync>(&self, key: &[u8], value: &T) -> Result<()> {
+        if self.validate_schemas {
+            // Rows live under `{table}:`, as `insert_row` writes them
+            if let Some(table) = key.iter().position(|&b| b == b':').and_then(|end| std::str::from_utf8(&key[..end]).ok()) {
+                self.schemas.check_fields(table, value)?;
+            }
+        }
         let serialized = bincode::serialize(value)
-            .map_err(|e| DbError::Serialization(e.to_string()))?;
-        self.put(key, &serialized).await
+            .map_err(DbError::serialization)?;
+        let index_mgr = self.index_snapshot();
+        let claims = index_mgr.unique_values(key, &serialized)?;
+        let _unique = self.unique_write_guard(&claims).await;
+        for (index_name, field_value) in &claims {
+            index_mgr.check_unique(self, index_name, field_value, |holder| holder == key).await?;
+        }
+        self.write_record(&index_mgr, key, Some(&serialized)).await
     }
     
     async fn get<T: serde::de::DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>> {
-        match self.get(key).await? {
-            Some(data) => {
-                let value = bincode::deserialize(&data)
-                    .map_err(|e| DbError::Serialization(e.to_string()))?;
-                Ok(Some(value))
-            }
-            None => Ok(None),
-        }
+        self.get_into(key).await
     }
     
     async fn delete(&self, key: &[u8]) -> Result<()> {
-        // Tombstone marker for deletion
-        self.put(key, &[]).await
+        let index_mgr = self.index_snapshot();
+        self.write_record(&index_mgr, key, None).await
     }
     
     async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         self.scan(prefix).await
     }
+    
+    async fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_range(start, end).await
+    }
+    
+    async fn scan_range_limited(&self, start: &[u8], end: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_range_limited(start, end, limit).await
+    }
+    
+    async fn scan_covering_index(&self, table: &str, field: &str) -> Result<Option<Vec<(rust_db_core::Value, Vec<u8>)>>> {
+        let index_mgr = self.index_snapshot();
+        match index_mgr.covering_index(table, field) {
+            Some(index_name) => Ok(Some(index_mgr.scan_index_values(self, &index_name).await?)),
+            None => Ok(None),
+        }
+    }
+    
+    async fn scan_covering_index_after(
+        &self,
+        table: &str,
+        field: &str,
+        after: Option<(&rust_db_core::Value, &[u8])>,
+        limit: usize,
+    ) -> Result<Option<Vec<(rust_db_core::Value, Vec<u8>)>>> {
+        let index_mgr = self.index_snapshot();
+        match index_mgr.covering_index(table, field) {
+            Some(index_name) => Ok(Some(index_mgr.scan_index_values_after(self, &index_name, after, limit).await?)),
+            None => Ok(None),
+        }
+    }
+    
+    async fn lookup_index_keys(&self, table: &str, field: &str, values: &[rust_db_core::Value]) -> Result<Option<Vec<Vec<u8>>>> {
+        let index_mgr = self.index_snapshot();
+        match index_mgr.lookup_index_on(table, field) {
+            Some(index_name) => Ok(Some(index_mgr.get_by_index_multi(self, &index_name, values).await?)),
+            None => Ok(None),
+        }
+    }
+    
+    async fn exact_key_count(&self) -> Result<usize> {
+        self.exact_key_count().await
+    }
+    
+    async fn estimated_key_count(&self) -> Result<usize> {
+        Ok(self.estimated_key_count())
+    }
 }
 
 // Update LsmStorage to implement MvccDatabase
@@ -456,17 +2719,15 @@ impl MvccDatabase for LsmStorage {
     }
     
     async fn commit_transaction(&self, mut transaction: Transaction) -> Result<()> {
-        // Apply all writes from the transaction
+        // Apply all writes from the transaction, with their index changes, as one batch
+        let index_mgr = self.index_snapshot();
+        let _records = self.record_write_guard(transaction.writes.keys().map(Vec::as_slice)).await;
+        let mut batch = WriteBatch::new();
         for (key, value_opt) in &transaction.writes {
-            match value_opt {
-                Some(value) => {
-                    self.put(key, value).await?;
-                }
-                None => {
-                    self.delete(key).await?;
-                }
-            }
+            let previous = self.get(key).await?;
+            index_mgr.batch_record_write(&mut batch, key, previous.as_deref(), value_opt.as_deref())?;
         }
+        self.write_batch(&batch).await?;
         
         transaction.state = TransactionState::Committed;
         Ok(())
@@ -481,56 +2742,137 @@ impl MvccDatabase for LsmStorage {
     async fn get_for_transaction<T: serde::de::DeserializeOwned>(
         &self,
         key: &[u8],
-        _transaction: &Transaction,
+        transaction: &Transaction,
     ) -> Result<Option<T>> {
-        // For now, use regular get - in full MVCC this would check transaction visibility
-        <Self as Database>::get(self, key).await
+        transaction.stats.record_read();
+        // No version store here, so the transaction's own writes shadow the base storage
+        match transaction.writes.get(key) {
+            Some(Some(value)) => {
+                let value = bincode::deserialize(value)
+                    .map_err(DbError::serialization)?;
+                Ok(Some(value))
+            }
+            Some(None) => Ok(None),
+            None => <Self as Database>::get(self, key).await,
+        }
     }
     
     async fn scan_for_transaction(
         &self,
         prefix: &[u8],
-        _transaction: &Transaction,
+        transaction: &Transaction,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_for_transaction_within(ScanBounds::Prefix(prefix), transaction)
+    }
+    
+    async fn scan_range_for_transaction(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        transaction: &Transaction,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        // For now, use regular scan
-        <Self as Database>::scan(self, prefix).await
+        self.scan_for_transaction_within(ScanBounds::Range(start, end), transaction)
+    }
+}
+
+impl LsmStorage {
+    fn scan_for_transaction_within(&self, bounds: ScanBounds<'_>, transaction: &Transaction) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = self.scan_within(bounds, usize::MAX)?.into_iter().collect();
+        transaction.stats.record_scan(merged.len());
+        mvcc::overlay_pending_writes(&mut merged, bounds, transaction);
+        Ok(merged.into_iter().collect())
     }
 }
 // Enhanced LsmStorage with MVCC support
 pub struct MvccLsmStorage {
     base_storage: LsmStorage,
     transaction_manager: Arc<TransactionManager>,
-    mvcc_storage: Option<Arc<MvccStorage>>,
+    mvcc_storage: Arc<MvccStorage>,
     garbage_collector: Option<Arc<GarbageCollector>>,
+    // See `set_read_staleness`
+    read_view: ReadView,
 }
 
 impl MvccLsmStorage {
     pub fn new(path: &std::path::Path) -> Result<Self> {
         let base_storage = LsmStorage::new(path)?;
         let transaction_manager = Arc::new(TransactionManager::new());
+        let mvcc_storage = Arc::new(MvccStorage::with_transaction_manager(
+            base_storage.clone(),
+            Arc::clone(&transaction_manager),
+        ));
         
         Ok(Self {
             base_storage,
             transaction_manager,
-            mvcc_storage: None,
+            mvcc_storage,
             garbage_collector: None,
+            read_view: ReadView::new(),
         })
     }
     
     pub fn with_garbage_collection(mut self, config: GcConfig) -> Result<Self> {
-        let base_storage_clone = self.base_storage.clone();
-        let mvcc_storage = Arc::new(MvccStorage::new(base_storage_clone));
-        let gc = Arc::new(GarbageCollector::new(Arc::clone(&mvcc_storage), config));
-        self.mvcc_storage = Some(mvcc_storage);
+        let gc = Arc::new(GarbageCollector::new(Arc::clone(&self.mvcc_storage), config));
         self.garbage_collector = Some(gc);
         Ok(self)
     }
     
+    // See `MvccStorage::spill_versions`
+    pub fn with_version_spill(self, encoding: VersionEncoding) -> Result<Self> {
+        self.mvcc_storage.spill_versions(encoding)?;
+        Ok(self)
+    }
+    
+    // Caps how many distinct keys one transaction may write, so a runaway one
+    // fails at `put` instead of buffering its whole write set in memory
+    pub fn with_max_transaction_writes(self, limit: usize) -> Self {
+        self.transaction_manager.set_max_transaction_writes(Some(limit));
+        self
+    }
+    
+    // See `set_read_staleness`
+    pub fn with_read_staleness(self, bound: Duration) -> Self {
+        self.set_read_staleness(bound);
+        self
+    }
+    
+    // Lets `get`, `scan` and `scan_range` answer from results read up to
+    // `bound` ago, including ones read before this storage's own later writes.
+    // Zero, the default, reads storage every time. Transactions and
+    // `scan_range_limited` always read the latest.
+    pub fn set_read_staleness(&self, bound: Duration) {
+        self.read_view.set_bound(bound);
+    }
+    
+    pub fn read_staleness(&self) -> Duration {
+        self.read_view.bound()
+    }
+    
+    pub fn base_storage(&self) -> &LsmStorage {
+        &self.base_storage
+    }
+    
+    pub fn transaction_manager(&self) -> &Arc<TransactionManager> {
+        &self.transaction_manager
+    }
+    
+    pub fn mvcc_storage(&self) -> &Arc<MvccStorage> {
+        &self.mvcc_storage
+    }
+    
     pub async fn run_garbage_collection(&self) -> Result<GcStats> {
         if let Some(ref gc) = self.garbage_collector {
             gc.run_garbage_collection().await
         } else {
-            Err(DbError::Storage("Garbage collector not initialized".to_string()))
+            Err(DbError::Storage("Garbage collector not initialized".to_string(), None))
+        }
+    }
+    
+    pub async fn estimate_gc(&self) -> Result<GcStats> {
+        if let Some(ref gc) = self.garbage_collector {
+            gc.estimate_gc().await
+        } else {
+            Err(DbError::Storage("Garbage collector not initialized".to_string(), None))
         }
     }
 }
@@ -538,19 +2880,72 @@ impl MvccLsmStorage {
 #[async_trait::async_trait]
 impl Database for MvccLsmStorage {
     async fn insert<T: Serialize + Send + Sync>(&self, key: &[u8], value: &T) -> Result<()> {
-        self.base_storage.insert(key, value).await
+        let serialized = bincode::serialize(value)
+            .map_err(DbError::serialization)?;
+        self.mvcc_storage
+            .apply_direct_write(key, Some(serialized), self.base_storage.insert(key, value))
+            .await
     }
     
     async fn get<T: serde::de::DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>> {
-        <LsmStorage as Database>::get(&self.base_storage, key).await
+        if self.read_view.bound().is_zero() {
+            return <LsmStorage as Database>::get(&self.base_storage, key).await;
+        }
+        match self.read_view.get(key, self.base_storage.get(key)).await? {
+            Some(data) => bincode::deserialize(&data).map(Some).map_err(DbError::serialization),
+            None => Ok(None),
+        }
     }
     
     async fn delete(&self, key: &[u8]) -> Result<()> {
-        self.base_storage.delete(key).await
+        self.mvcc_storage
+            .apply_direct_write(key, None, Database::delete(&self.base_storage, key))
+            .await
     }
     
     async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        self.base_storage.scan(prefix).await
+        if self.read_view.bound().is_zero() {
+            return self.base_storage.scan(prefix).await;
+        }
+        self.read_view.scan(prefix, self.base_storage.scan(prefix)).await
+    }
+    
+    async fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if self.read_view.bound().is_zero() {
+            return self.base_storage.scan_range(start, end).await;
+        }
+        self.read_view.scan_range(start, end, self.base_storage.scan_range(start, end)).await
+    }
+    
+    // Always the latest data, so a cursor paging through never mixes snapshots
+    async fn scan_range_limited(&self, start: &[u8], end: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.base_storage.scan_range_limited(start, end, limit).await
+    }
+    
+    async fn scan_covering_index(&self, table: &str, field: &str) -> Result<Option<Vec<(rust_db_core::Value, Vec<u8>)>>> {
+        self.base_storage.scan_covering_index(table, field).await
+    }
+    
+    async fn scan_covering_index_after(
+        &self,
+        table: &str,
+        field: &str,
+        after: Option<(&rust_db_core::Value, &[u8])>,
+        limit: usize,
+    ) -> Result<Option<Vec<(rust_db_core::Value, Vec<u8>)>>> {
+        self.base_storage.scan_covering_index_after(table, field, after, limit).await
+    }
+    
+    async fn lookup_index_keys(&self, table: &str, field: &str, values: &[rust_db_core::Value]) -> Result<Option<Vec<Vec<u8>>>> {
+        self.base_storage.lookup_index_keys(table, field, values).await
+    }
+    
+    async fn exact_key_count(&self) -> Result<usize> {
+        self.base_storage.exact_key_count().await
+    }
+    
+    async fn estimated_key_count(&self) -> Result<usize> {
+        Ok(self.base_storage.estimated_key_count())
     }
 }
 
@@ -561,21 +2956,7 @@ impl MvccDatabase for MvccLsmStorage {
     }
     
     async fn commit_transaction(&self, mut transaction: Transaction) -> Result<()> {
-        // Apply all writes from the transaction to the base storage
-        for (key, value_opt) in &transaction.writes {
-            match value_opt {
-                Some(value) => {
-                    self.base_storage.put(key, value).await?;
-                }
-                None => {
-                    self.base_storage.delete(key).await?;
-                }
-            }
-        }
-        
-        // Mark transaction as committed
-        self.transaction_manager.commit_transaction(&mut transaction)?;
-        Ok(())
+        self.mvcc_storage.commit_batch(&mut transaction).await
     }
     
     async fn rollback_transaction(&self, mut transaction: Transaction) -> Result<()> {
@@ -586,19 +2967,34 @@ impl MvccDatabase for MvccLsmStorage {
     async fn get_for_transaction<T: serde::de::DeserializeOwned>(
         &self,
         key: &[u8],
-        _transaction: &Transaction,
+        transaction: &Transaction,
     ) -> Result<Option<T>> {
-        // For simplified MVCC, just use base storage
-        // In full implementation, this would check version visibility
-        <LsmStorage as Database>::get(&self.base_storage, key).await
+        self.mvcc_storage.get_for_transaction(key, transaction).await
     }
     
     async fn scan_for_transaction(
         &self,
         prefix: &[u8],
-        _transaction: &Transaction,
+        transaction: &Transaction,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.mvcc_storage.scan_versions(prefix, transaction).await
+    }
+    
+    async fn scan_range_for_transaction(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        transaction: &Transaction,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        // For simplified MVCC, just use base storage
-        self.base_storage.scan(prefix).await
+        self.mvcc_storage.scan_range_versions(start, end, transaction).await
+    }
+    
+    fn abandon_transaction(&self, mut transaction: Transaction) {
+        if self.transaction_manager.rollback_transaction(&mut transaction).is_ok() {
+            log::warn!(
+                "Transaction {:?} was dropped without commit or rollback and has been rolled back",
+                transaction.id
+            );
+        }
     }
 }
\ No newline at end of file
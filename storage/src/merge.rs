@@ -0,0 +1,37 @@
+use rust_db_core::{DbError, Result};
+
+// Folds merge operands into a key's value. `existing` is None when the key has
+// no value (or was deleted); `operands` are in the order they were written.
+// Used by `LsmStorage::merge` for read-free updates such as counters.
+pub trait MergeOperator: Send + Sync {
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Result<Vec<u8>>;
+}
+
+// Values and operands are little-endian i64s; merging adds the operands
+pub struct CounterMergeOperator;
+
+impl CounterMergeOperator {
+    pub fn encode(value: i64) -> Vec<u8> {
+        value.to_le_bytes().to_vec()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<i64> {
+        let bytes: [u8; 8] = bytes
+            .try_into()
+            .map_err(|e| DbError::Serialization(format!("Counter value must be 8 bytes, got {}", bytes.len()), Some(Box::new(e))))?;
+        Ok(i64::from_le_bytes(bytes))
+    }
+}
+
+impl MergeOperator for CounterMergeOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let mut total = match existing {
+            Some(bytes) => Self::decode(bytes)?,
+            None => 0,
+        };
+        for operand in operands {
+            total = total.wrapping_add(Self::decode(operand)?);
+        }
+        Ok(Self::encode(total))
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Bytes written so far, by who wrote them. Shared by every clone of a storage
+// and by its compaction manager.
+#[derive(Default)]
+pub(crate) struct WriteCounters {
+    user: AtomicU64,
+    wal: AtomicU64,
+    flush: AtomicU64,
+    compaction: AtomicU64,
+}
+
+impl WriteCounters {
+    // Keys plus values (or merge operands) handed to a write call
+    pub(crate) fn record_user(&self, bytes: usize) {
+        self.user.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_wal(&self, bytes: usize) {
+        self.wal.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    // SSTables written from the memtable or by `replace_table`
+    pub(crate) fn record_flush(&self, bytes: u64) {
+        self.flush.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_compaction(&self, bytes: u64) {
+        self.compaction.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, disk_bytes: u64, live_data_bytes: u64) -> StorageStats {
+        StorageStats {
+            user_bytes_written: self.user.load(Ordering::Relaxed),
+            wal_bytes_written: self.wal.load(Ordering::Relaxed),
+            flush_bytes_written: self.flush.load(Ordering::Relaxed),
+            compaction_bytes_written: self.compaction.load(Ordering::Relaxed),
+            disk_bytes,
+            live_data_bytes,
+        }
+    }
+}
+
+// Write counters since the storage was opened, and the current footprint, for
+// tuning compaction. See `LsmStorage::storage_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    pub user_bytes_written: u64,
+    pub wal_bytes_written: u64,
+    pub flush_bytes_written: u64,
+    pub compaction_bytes_written: u64,
+    // Size of every SSTable file now
+    pub disk_bytes: u64,
+    // Keys and values of the live records, tombstones excluded
+    pub live_data_bytes: u64,
+}
+
+impl StorageStats {
+    // WAL appends, flushes and compaction rewrites
+    pub fn disk_bytes_written(&self) -> u64 {
+        self.wal_bytes_written + self.flush_bytes_written + self.compaction_bytes_written
+    }
+
+    // Bytes written to disk per byte the user wrote; None before any writes
+    pub fn write_amplification(&self) -> Option<f64> {
+        (self.user_bytes_written > 0).then(|| self.disk_bytes_written() as f64 / self.user_bytes_written as f64)
+    }
+
+    // Bytes on disk per byte of live data; None when nothing is live
+    pub fn space_amplification(&self) -> Option<f64> {
+        (self.live_data_bytes > 0).then(|| self.disk_bytes as f64 / self.live_data_bytes as f64)
+    }
+}
@@ -1,49 +1,328 @@
 use rust_db_core::{
     DbError, Result, Transaction, TransactionId, VersionTimestamp, 
-    VersionedRecord, TransactionState
+    VersionedRecord, TransactionState, TransactionStats, Value
 };
-use super::LsmStorage;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::sync::atomic::AtomicU64;
+use super::{AppendFile, FileSystem, IndexManager, LsmStorage, ScanBounds, WriteBatch};
+use crate::index::unique_violation;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::collections::HashSet;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+
+enum VersionLookup{
+    Visible(VersionedRecord),
+    Deleted,
+    // No version is decisive for this snapshot, so the base storage value applies
+    Unversioned,
+}
+
+// Applies a transaction's uncommitted writes within `bounds` on top of `merged`:
+// puts replace whatever was there, deletes remove the key
+pub(crate) fn overlay_pending_writes(
+    merged:&mut BTreeMap<Vec<u8>,Vec<u8>>,
+    bounds:ScanBounds<'_>,
+    transaction:&Transaction
+){
+    for (key,write) in transaction.writes.iter().filter(|(key,_)| bounds.contains(key)){
+        match write{
+            Some(value) => {
+                merged.insert(key.clone(),value.clone());
+            }
+            None => {
+                merged.remove(key);
+            }
+        }
+    }
+}
+
+// How a `VersionedRecord` is laid out as bytes. `Bincode` spends fixed-width
+// fields on both ids and timestamps, 32 bytes plus an 8-byte length before the
+// value. `Compact` writes a flags byte, then varints: the creating id and
+// timestamp, the expiring id and timestamp (as a delta from creation) only when
+// the version has expired, and the value's length and bytes unless it's empty.
+// Chosen with `MvccLsmStorage::with_version_spill` for versions GC spills.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum VersionEncoding{
+    #[default]
+    Bincode,
+    Compact,
+}
+
+const VERSION_EXPIRED:u8 = 1;
+const VERSION_EMPTY:u8 = 2;
+
+impl VersionEncoding{
+    pub fn encode(&self,record:&VersionedRecord)->Result<Vec<u8>>{
+        match self{
+            VersionEncoding::Bincode => bincode::serialize(record).map_err(DbError::serialization),
+            VersionEncoding::Compact => Ok(Self::encode_compact(record)),
+        }
+    }
+
+    pub fn decode(&self,bytes:&[u8])->Result<VersionedRecord>{
+        match self{
+            VersionEncoding::Bincode => bincode::deserialize(bytes).map_err(DbError::serialization),
+            VersionEncoding::Compact => Self::decode_compact(bytes),
+        }
+    }
+
+    fn encode_compact(record:&VersionedRecord)->Vec<u8>{
+        let expired = record.expired_tx.as_u64() != 0 || record.expired_ts.as_u64() != 0;
+        let mut flags = 0;
+        if expired{
+            flags |= VERSION_EXPIRED;
+        }
+        if record.value.is_empty(){
+            flags |= VERSION_EMPTY;
+        }
+
+        let mut out = Vec::with_capacity(record.value.len()+24);
+        out.push(flags);
+        write_varint(&mut out,record.created_tx.as_u64());
+        write_varint(&mut out,record.created_ts.as_u64());
+        if expired{
+            write_varint(&mut out,record.expired_tx.as_u64());
+            // Wrapping, so a clock that stepped backwards still round-trips
+            write_varint(&mut out,record.expired_ts.as_u64().wrapping_sub(record.created_ts.as_u64()));
+        }
+        if !record.value.is_empty(){
+            write_varint(&mut out,record.value.len() as u64);
+            out.extend_from_slice(&record.value);
+        }
+        out
+    }
+
+    fn decode_compact(bytes:&[u8])->Result<VersionedRecord>{
+        let (&flags,mut rest) = bytes.split_first()
+            .ok_or_else(|| DbError::Serialization("empty version record".to_string(), None))?;
+        let created_tx = read_varint(&mut rest)?;
+        let created_ts = read_varint(&mut rest)?;
+        let (expired_tx,expired_ts) = if flags & VERSION_EXPIRED != 0{
+            let expired_tx = read_varint(&mut rest)?;
+            (expired_tx,read_varint(&mut rest)?.wrapping_add(created_ts))
+        }else{
+            (0,0)
+        };
+        let value = if flags & VERSION_EMPTY != 0{
+            Vec::new()
+        }else{
+            let len = read_varint(&mut rest)? as usize;
+            if rest.len() < len{
+                return Err(DbError::Serialization("truncated version record value".to_string(), None));
+            }
+            let (value,tail) = rest.split_at(len);
+            rest = tail;
+            value.to_vec()
+        };
+        if !rest.is_empty(){
+            return Err(DbError::Serialization(format!("{} trailing bytes after version record",rest.len()), None));
+        }
+        Ok(VersionedRecord{
+            value,
+            created_tx:TransactionId::from_u64(created_tx),
+            expired_tx:TransactionId::from_u64(expired_tx),
+            created_ts:VersionTimestamp::from_u64(created_ts),
+            expired_ts:VersionTimestamp::from_u64(expired_ts),
+        })
+    }
+}
+
+// LEB128: seven bits per byte, low bits first, high bit set on all but the last
+fn write_varint(out:&mut Vec<u8>,mut value:u64){
+    while value >= 0x80{
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes:&mut &[u8])->Result<u64>{
+    let mut value = 0u64;
+    for (i,&byte) in bytes.iter().enumerate().take(10){
+        value |= u64::from(byte & 0x7f) << (7*i);
+        if byte & 0x80 == 0{
+            *bytes = &bytes[i+1..];
+            return Ok(value);
+        }
+    }
+    Err(DbError::Serialization("truncated or overlong varint in version record".to_string(), None))
+}
 
+pub(crate) const VERSION_SPILL_FILE:&str = "versions.spill";
+
+// Versions garbage collection pruned from memory, appended to a file so
+// `get_as_of` can still answer for the times they were current. Each frame is
+// the encoding's tag (0 bincode, 1 compact), then the key and the encoded
+// record, each behind a varint length, so a file written under one encoding
+// still reads after switching to the other.
+struct VersionSpill{
+    fs:Arc<dyn FileSystem>,
+    path:PathBuf,
+    encoding:VersionEncoding,
+    file:Mutex<Box<dyn AppendFile>>,
+}
 
+impl VersionSpill{
+    fn open(fs:Arc<dyn FileSystem>,path:PathBuf,encoding:VersionEncoding)->Result<Self>{
+        let file = fs.append(&path).map_err(DbError::storage)?;
+        Ok(Self{fs,path,encoding,file:Mutex::new(file)})
+    }
 
+    fn append(&self,key:&[u8],record:&VersionedRecord)->Result<()>{
+        let encoded = self.encoding.encode(record)?;
+        let mut frame = Vec::with_capacity(key.len()+encoded.len()+11);
+        frame.push(match self.encoding{
+            VersionEncoding::Bincode => 0,
+            VersionEncoding::Compact => 1,
+        });
+        write_varint(&mut frame,key.len() as u64);
+        frame.extend_from_slice(key);
+        write_varint(&mut frame,encoded.len() as u64);
+        frame.extend_from_slice(&encoded);
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.append(&frame).map_err(DbError::storage)?;
+        file.sync().map_err(DbError::storage)
+    }
 
+    // Every spilled version of `key`, in the order they were spilled
+    fn versions(&self,key:&[u8])->Result<Vec<VersionedRecord>>{
+        let data = self.fs.open(&self.path).map_err(DbError::storage)?;
+        let mut rest = (*data).as_ref();
+        let mut found = Vec::new();
+        while let Some((&tag,tail)) = rest.split_first(){
+            rest = tail;
+            let spilled_key = take_bytes(&mut rest)?;
+            let record = take_bytes(&mut rest)?;
+            if spilled_key!=key{
+                continue;
+            }
+            let encoding = match tag{
+                0 => VersionEncoding::Bincode,
+                1 => VersionEncoding::Compact,
+                tag => return Err(DbError::Serialization(format!("unknown version encoding {} in {:?}",tag,self.path), None)),
+            };
+            found.push(encoding.decode(record)?);
+        }
+        Ok(found)
+    }
+}
+
+// A varint length and that many bytes
+fn take_bytes<'a>(bytes:&mut &'a [u8])->Result<&'a [u8]>{
+    let len = read_varint(bytes)? as usize;
+    if bytes.len()<len{
+        return Err(DbError::Serialization("truncated frame in version spill".to_string(), None));
+    }
+    let (taken,rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+// Keys written by a committed transaction, kept while older snapshots may still commit
+struct CommittedWrites{
+    commit_ts:VersionTimestamp,
+    scopes:Vec<Vec<u8>>,
+    keys:HashSet<Vec<u8>>,
+}
+
+// What the manager knows about a transaction still in flight. The stats are
+// shared with the `Transaction`, so they follow its writes.
+struct ActiveTransaction{
+    snapshot_ts:VersionTimestamp,
+    stats:Arc<TransactionStats>,
+}
+
+// A point-in-time view of one open transaction, for debugging contention
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct TransactionInfo{
+    pub id:TransactionId,
+    pub snapshot_ts:VersionTimestamp,
+    // Time since the transaction began
+    pub age:Duration,
+    // Distinct keys written so far
+    pub write_set_size:usize,
+}
 
 pub struct TransactionManager{
-    active_transactions: RwLock<HashSet<TransactionId>>,
+    active_transactions: RwLock<HashMap<TransactionId,ActiveTransaction>>,
     committed_transactions: RwLock<HashMap<TransactionId,VersionTimestamp>>,
+    committed_writes: RwLock<Vec<CommittedWrites>>,
     _next_tx_id:Arc<AtomicU64>,
+    // Handed to every transaction begun from here on; see `Transaction::max_writes`
+    max_transaction_writes:RwLock<Option<usize>>,
 }
 
 impl TransactionManager{
     pub fn new()->Self{
         Self{
-            active_transactions:RwLock::new(HashSet::new()),
+            active_transactions:RwLock::new(HashMap::new()),
             committed_transactions:RwLock::new(HashMap::new()),
+            committed_writes:RwLock::new(Vec::new()),
             _next_tx_id:Arc::new(AtomicU64::new(1)),
+            max_transaction_writes:RwLock::new(None),
         }
     }
 
+    pub fn max_transaction_writes(&self)->Option<usize>{
+        *self.max_transaction_writes.read().unwrap()
+    }
+
+    // Transactions already open keep the limit they began with
+    pub fn set_max_transaction_writes(&self,limit:Option<usize>){
+        *self.max_transaction_writes.write().unwrap() = limit;
+    }
+
     pub fn begin_transaction(&self)->Transaction{
         let tx_id = TransactionId::new();
-        self.active_transactions.write().unwrap().insert(tx_id);
-
         let snapshot_ts = self.get_latest_commit_timestamp();
+        let stats = Arc::new(TransactionStats::new());
+        self.active_transactions.write().unwrap().insert(tx_id,ActiveTransaction{
+            snapshot_ts,
+            stats:Arc::clone(&stats),
+        });
 
         Transaction{
             id:tx_id,
             snapshot_ts,
             state:TransactionState::Active,
             writes:HashMap::new(),
+            scopes:Vec::new(),
+            stats,
+            max_writes:self.max_transaction_writes(),
         }
     }
 
+    // Run before a commit applies anything: rejects writes outside the declared
+    // scope, and first-committer-wins conflicts with transactions that committed
+    // after our snapshot. Transactions with disjoint scopes are never compared.
+    pub fn validate_commit(&self,transaction:&Transaction)->Result<()>{
+        if let Some(key) = transaction.writes.keys().find(|key| !transaction.in_scope(key)){
+            return Err(DbError::Transaction(format!(
+                "Write to key {} is outside the transaction scope",String::from_utf8_lossy(key)
+            )));
+        }
+
+        let committed_writes = self.committed_writes.read().unwrap();
+        for committed in committed_writes.iter().filter(|c| c.commit_ts > transaction.snapshot_ts){
+            if !transaction.scopes_overlap(&committed.scopes){
+                continue;
+            }
+            if let Some(key) = transaction.writes.keys().find(|key| committed.keys.contains(*key)){
+                return Err(DbError::TransactionConflict(format!(
+                    "Key {} was modified by a transaction that committed after this snapshot",
+                    String::from_utf8_lossy(key)
+                )));
+            }
+        }
+        Ok(())
+    }
+
     pub fn commit_transaction(&self,transaction:&mut Transaction)->Result<()>{
         let tx_id = transaction.id;
-        if !self.active_transactions.read().unwrap().contains(&tx_id){
+        if !self.active_transactions.read().unwrap().contains_key(&tx_id){
             return Err(DbError::Transaction("Transaction not active".to_string()));
         }
 
@@ -52,14 +331,28 @@ impl TransactionManager{
         let commit_ts = VersionTimestamp::now();
         self.committed_transactions.write().unwrap().insert(tx_id,commit_ts);
 
-        self.active_transactions.write().unwrap().remove(&tx_id);
+        let mut committed_writes = self.committed_writes.write().unwrap();
+        if !transaction.writes.is_empty(){
+            committed_writes.push(CommittedWrites{
+                commit_ts,
+                scopes:transaction.scopes.clone(),
+                keys:transaction.writes.keys().cloned().collect(),
+            });
+        }
+
+        let mut active = self.active_transactions.write().unwrap();
+        active.remove(&tx_id);
+        // With nothing in flight, no future commit can conflict with what's logged
+        if active.is_empty(){
+            committed_writes.clear();
+        }
 
         Ok(())
     }
 
     pub fn rollback_transaction(&self,transaction:&mut Transaction)->Result<()>{
         let tx_id = transaction.id;
-        if !self.active_transactions.read().unwrap().contains(&tx_id){
+        if !self.active_transactions.read().unwrap().contains_key(&tx_id){
             return Err(DbError::Transaction("Transaction not active".to_string()));
         }
 
@@ -69,6 +362,23 @@ impl TransactionManager{
         Ok(())
     }
 
+    pub fn is_transaction_active(&self,tx_id:TransactionId)->bool{
+        self.active_transactions.read().unwrap().contains_key(&tx_id)
+    }
+
+    // Every transaction still open, oldest first
+    pub fn list_active(&self)->Vec<TransactionInfo>{
+        let active = self.active_transactions.read().unwrap();
+        let mut infos:Vec<TransactionInfo> = active.iter().map(|(id,tx)| TransactionInfo{
+            id:*id,
+            snapshot_ts:tx.snapshot_ts,
+            age:tx.stats.started().elapsed(),
+            write_set_size:tx.stats.write_set_size(),
+        }).collect();
+        infos.sort_by_key(|info| std::cmp::Reverse(info.age));
+        infos
+    }
+
     pub fn is_transaction_committed(&self,tx_id:TransactionId)->bool{
         self.committed_transactions.read().unwrap().contains_key(&tx_id)
     }
@@ -86,7 +396,15 @@ impl TransactionManager{
 pub struct MvccStorage{
     base_storage:LsmStorage,
     transaction_manager:Arc<TransactionManager>,
-    version_store:RwLock<HashMap<Vec<u8>,Vec<VersionedRecord>>>,
+    // Ordered by key so prefix scans walk a contiguous range
+    version_store:RwLock<BTreeMap<Vec<u8>,Vec<VersionedRecord>>>,
+    // Versions visible before this timestamp may have been garbage collected
+    history_horizon:AtomicU64,
+    // Held from validation until a commit's writes are all in, so commits apply
+    // one at a time in commit-timestamp order
+    commit_lock:tokio::sync::Mutex<()>,
+    // See `spill_versions`
+    version_spill:OnceLock<VersionSpill>,
 }
 
 impl MvccStorage{
@@ -94,35 +412,158 @@ impl MvccStorage{
         Self{
             base_storage,
             transaction_manager:Arc::new(TransactionManager::new()),
-            version_store:RwLock::new(HashMap::new()),
+            version_store:RwLock::new(BTreeMap::new()),
+            history_horizon:AtomicU64::new(0),
+            commit_lock:tokio::sync::Mutex::new(()),
+            version_spill:OnceLock::new(),
         }
     }
 
-    pub async fn get_version(
+    pub fn with_transaction_manager(base_storage:LsmStorage,transaction_manager:Arc<TransactionManager>)->Self{
+        Self{
+            base_storage,
+            transaction_manager,
+            version_store:RwLock::new(BTreeMap::new()),
+            history_horizon:AtomicU64::new(0),
+            commit_lock:tokio::sync::Mutex::new(()),
+            version_spill:OnceLock::new(),
+        }
+    }
+
+    // Keeps the versions garbage collection prunes in `versions.spill` beside
+    // the base storage's levels, written with `encoding`, rather than dropping
+    // them. Can only be turned on once.
+    pub fn spill_versions(&self,encoding:VersionEncoding)->Result<()>{
+        let fs = Arc::clone(&self.base_storage.fs);
+        let spill = VersionSpill::open(fs,self.base_storage.base_path().join(VERSION_SPILL_FILE),encoding)?;
+        self.version_spill.set(spill)
+            .map_err(|_| DbError::Storage("version spilling is already on".to_string(), None))
+    }
 
+    // Called by GC before it prunes `record` from memory
+    pub(crate) fn spill_version(&self,key:&[u8],record:&VersionedRecord)->Result<()>{
+        match self.version_spill.get(){
+            Some(spill) => spill.append(key,record),
+            None => Ok(()),
+        }
+    }
+
+    // Layered read shared by the raw and typed lookups: the transaction's own
+    // writes win, then the newest version visible to its snapshot, then base storage.
+    // Tombstones at any layer resolve to None.
+    pub async fn get_version(
         &self,
         key: &[u8],
         transaction:&Transaction
     )->Result<Option<VersionedRecord>>{
-        let versions = self.version_store.read().unwrap();
+        if let Some(write) = transaction.writes.get(key){
+            return Ok(write.as_ref().map(|value| VersionedRecord::new(value.clone(),transaction.id)));
+        }
 
-        if let Some(version_list) = versions.get(key){
-            for version in version_list.iter().rev(){
-                if version.is_visible(transaction.id,transaction.snapshot_ts){
-                    return Ok(Some(version.clone()));
+        {
+            let versions = self.version_store.read().unwrap();
+            if let Some(version_list) = versions.get(key){
+                match Self::lookup_visible(version_list,transaction){
+                    VersionLookup::Visible(record) => return Ok(Some(record)),
+                    VersionLookup::Deleted => return Ok(None),
+                    VersionLookup::Unversioned => {}
                 }
             }
         }
 
-        if let Some(data) = self.base_storage.get(key).await?{
-            let record = VersionedRecord::new(data,TransactionId::new());
+        Ok(self.base_storage.get(key).await?.map(|data| VersionedRecord::new(data,TransactionId::new())))
+    }
 
-            return Ok(Some(record));
+    pub async fn get_for_transaction<T:DeserializeOwned>(
+        &self,
+        key:&[u8],
+        transaction:&Transaction
+    )->Result<Option<T>>{
+        transaction.stats.record_read();
+        match self.get_version(key,transaction).await?{
+            Some(record) => {
+                let value = bincode::deserialize(&record.value)
+                    .map_err(DbError::serialization)?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
         }
+    }
 
+    // Time-travel read: the value the key held at `ts`, outside any transaction.
+    // Only writes committed through transactions are versioned, so keys written
+    // directly have no history and read as None. Reads from before what GC
+    // pruned fail, unless the pruned versions were spilled.
+    pub fn get_as_of(&self,key:&[u8],ts:VersionTimestamp)->Result<Option<Vec<u8>>>{
+        // Held while reading the spill, so GC can't move a version out from
+        // under the read
+        let versions = self.version_store.read().unwrap();
+        let in_memory = versions.get(key).map(Vec::as_slice).unwrap_or_default();
+        let horizon = self.history_horizon.load(Ordering::SeqCst);
+        let merged;
+        let version_list = match self.version_spill.get(){
+            _ if ts.as_u64()>=horizon => in_memory,
+            Some(spill) => {
+                let mut all = spill.versions(key)?;
+                all.extend(in_memory.iter().cloned());
+                all.sort_by_key(|version| version.created_ts);
+                merged = all;
+                &merged
+            }
+            None => return Err(DbError::GarbageCollection(format!(
+                "versions before {} have been garbage collected, requested {}",horizon,ts.as_u64()
+            ))),
+        };
+        for version in version_list.iter().rev(){
+            if version.created_ts>ts{
+                continue;
+            }
+            let expired = version.expired_tx.as_u64()!=0 && version.expired_ts<=ts;
+            if expired{
+                return Ok(None);
+            }
+            return Ok(Some(version.value.clone()));
+        }
         Ok(None)
     }
 
+    // The base value a commit preserved for older snapshots; see `record_versions`
+    pub(crate) fn is_base_seed(version:&VersionedRecord)->bool{
+        version.created_ts.as_u64()==0 && version.created_tx.as_u64()==0
+    }
+
+    // Drops base-value versions once no snapshot predates the version that
+    // replaced them. They were never history, so the horizon stays put.
+    pub(crate) fn drop_base_seeds(&self,oldest_snapshot_ts:VersionTimestamp){
+        let mut versions = self.version_store.write().unwrap();
+        for list in versions.values_mut(){
+            let superseded = list.len()>1 && list.get(1).is_some_and(|next| next.created_ts<=oldest_snapshot_ts);
+            if superseded && Self::is_base_seed(&list[0]){
+                list.remove(0);
+            }
+        }
+    }
+
+    // Called by GC after pruning a version: reads before `ts` can no longer be answered
+    pub(crate) fn advance_history_horizon(&self,ts:VersionTimestamp){
+        self.history_horizon.fetch_max(ts.as_u64(),Ordering::SeqCst);
+    }
+
+    fn lookup_visible(version_list:&[VersionedRecord],transaction:&Transaction)->VersionLookup{
+        for version in version_list.iter().rev(){
+            if version.is_visible(transaction.id,transaction.snapshot_ts){
+                return VersionLookup::Visible(version.clone());
+            }
+
+            // Expired before our snapshot: the key was deleted as of the snapshot
+            let expired = version.expired_tx.as_u64() != 0 && version.expired_ts <= transaction.snapshot_ts;
+            if version.created_ts <= transaction.snapshot_ts && expired{
+                return VersionLookup::Deleted;
+            }
+        }
+        VersionLookup::Unversioned
+    }
+
     pub async fn put_version(
         &self,
         key:&[u8],
@@ -154,45 +595,232 @@ impl MvccStorage{
         Ok(())
     }
 
-     pub async fn apply_transaction_writes(&self, transaction: &Transaction) -> Result<()> {
+    // A write made outside any transaction, committed as a one-key transaction of
+    // its own: snapshots older than it keep reading the value it replaced, and
+    // transactions that also wrote the key conflict with it. `write` makes the
+    // base storage change once the versions are in; if it fails they're put back.
+    pub async fn apply_direct_write<F>(&self,key:&[u8],value:Option<Vec<u8>>,write:F)->Result<()>
+    where
+        F:std::future::Future<Output=Result<()>>,
+    {
+        let _committing = self.commit_lock.lock().await;
+        let mut single = self.transaction_manager.begin_transaction();
+        single.writes.insert(key.to_vec(),value);
+        let previous = self.base_storage.get(key).await?;
+        let saved = self.save_versions(&single);
+        self.record_versions(&single,&HashMap::from([(key.to_vec(),previous)]));
+
+        if let Err(e) = write.await{
+            self.restore_versions(saved);
+            self.transaction_manager.rollback_transaction(&mut single)?;
+            return Err(e);
+        }
+        self.transaction_manager.commit_transaction(&mut single)
+    }
+
+    // The version lists of the keys `transaction` writes, for `restore_versions`
+    fn save_versions(&self,transaction:&Transaction)->Vec<(Vec<u8>,Option<Vec<VersionedRecord>>)>{
+        let versions = self.version_store.read().unwrap();
+        transaction.writes.keys().map(|key| (key.clone(),versions.get(key).cloned())).collect()
+    }
+
+    // Takes back what `record_versions` added after a write that didn't reach base storage
+    fn restore_versions(&self,saved:Vec<(Vec<u8>,Option<Vec<VersionedRecord>>)>){
+        let mut versions = self.version_store.write().unwrap();
+        for (key,list) in saved{
+            match list{
+                Some(list) => versions.insert(key,list),
+                None => versions.remove(&key),
+            };
+        }
+    }
+
+    pub async fn apply_transaction_writes(&self, transaction: &Transaction) -> Result<()> {
+        self.record_versions(transaction, &HashMap::new());
+        for (key, value_opt) in &transaction.writes {
+            if let Some(value) = value_opt {
+                self.base_storage.index_record(key, value).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // One lock acquisition and one timestamp for the whole write set, so a
+    // reader sees either all of the commit or none of it. `previous` holds the
+    // base storage values being replaced (None if absent); a key with no
+    // versions yet gets its previous value as a version from the beginning of
+    // time, so snapshots older than the commit keep reading it once base
+    // storage holds the new value. An absent key's is expired from the start.
+    fn record_versions(&self, transaction: &Transaction, previous: &HashMap<Vec<u8>, Option<Vec<u8>>>) {
+        let mut versions = self.version_store.write().unwrap();
+        let commit_ts = VersionTimestamp::now();
+        for (key, value) in previous {
+            let list = versions.entry(key.clone()).or_default();
+            if list.is_empty() {
+                let expired_tx = if value.is_some() { 0 } else { transaction.id.as_u64() };
+                list.push(VersionedRecord {
+                    value: value.clone().unwrap_or_default(),
+                    created_tx: TransactionId::from_u64(0),
+                    expired_tx: TransactionId::from_u64(expired_tx),
+                    created_ts: VersionTimestamp::from_u64(0),
+                    expired_ts: VersionTimestamp::from_u64(0),
+                });
+            }
+        }
         for (key, value_opt) in &transaction.writes {
             match value_opt {
                 Some(value) => {
-                    self.put_version(key, value.clone(), transaction).await?;
+                    let mut record = VersionedRecord::new(value.clone(), transaction.id);
+                    record.created_ts = commit_ts;
+                    versions.entry(key.clone()).or_default().push(record);
                 }
                 None => {
-                    self.mark_version_expired(key, transaction).await?;
+                    // Already expired means already deleted, and when stays as it was
+                    let latest = versions.get_mut(key).and_then(|list| list.last_mut());
+                    if let Some(latest_version) = latest.filter(|version| version.expired_tx.as_u64() == 0) {
+                        latest_version.mark_expired(transaction.id);
+                        latest_version.expired_ts = commit_ts;
+                    }
                 }
             }
         }
-        Ok(())
+    }
+
+    // Commits the whole transaction at once: conflicts are checked before anything
+    // is applied, the versions go in under one version-store lock, and the records
+    // with their index entries reach base storage as a single write batch. A
+    // conflict rolls the transaction back.
+    pub async fn commit_batch(&self, transaction: &mut Transaction) -> Result<()> {
+        let _committing = self.commit_lock.lock().await;
+        if let Err(e) = self.transaction_manager.validate_commit(transaction) {
+            self.transaction_manager.rollback_transaction(transaction)?;
+            return Err(e);
+        }
+
+        // Built first, so a record that fails to index leaves nothing half-applied.
+        // Entries for the value being replaced go too, unless the new value keeps them.
+        let index_mgr = self.base_storage.index_snapshot();
+        let claims = match Self::unique_claims(&index_mgr, transaction) {
+            Ok(claims) => claims,
+            Err(e) => {
+                self.transaction_manager.rollback_transaction(transaction)?;
+                return Err(e);
+            }
+        };
+        let _unique = self.base_storage.unique_write_guard(&claims).await;
+        // Writes outside transactions read the values they replace under these
+        // too, so neither works from a value the other is about to replace
+        let _records = self.base_storage.record_write_guard(transaction.writes.keys().map(Vec::as_slice)).await;
+        if let Err(e) = self.check_unique_writes(&index_mgr, &claims, transaction).await {
+            self.transaction_manager.rollback_transaction(transaction)?;
+            return Err(e);
+        }
+        let (batch, replaced) = match self.build_batch(&index_mgr, transaction).await {
+            Ok(built) => built,
+            Err(e) => {
+                self.transaction_manager.rollback_transaction(transaction)?;
+                return Err(e);
+            }
+        };
+
+        // Versions go in first, so a snapshot older than the commit finds the
+        // value it replaces before base storage loses it. A failed write takes
+        // them back out and the transaction rolls back.
+        let saved = self.save_versions(transaction);
+        self.record_versions(transaction, &replaced);
+        if let Err(e) = self.base_storage.write_batch(&batch).await {
+            self.restore_versions(saved);
+            self.transaction_manager.rollback_transaction(transaction)?;
+            return Err(e);
+        }
+        self.transaction_manager.commit_transaction(transaction)
+    }
+
+    // The write set as one batch with its index updates, and the values it replaces
+    async fn build_batch(&self, index_mgr: &IndexManager, transaction: &Transaction) -> Result<(WriteBatch, HashMap<Vec<u8>, Option<Vec<u8>>>)> {
+        let mut batch = WriteBatch::new();
+        let mut replaced = HashMap::new();
+        for (key, value_opt) in &transaction.writes {
+            let previous_value = self.base_storage.get(key).await?;
+            index_mgr.batch_record_write(&mut batch, key, previous_value.as_deref(), value_opt.as_deref())?;
+            replaced.insert(key.clone(), previous_value);
+        }
+        Ok((batch, replaced))
     }
     
+    // The unique index values the write set claims. It mustn't claim one twice.
+    fn unique_claims(index_mgr: &IndexManager, transaction: &Transaction) -> Result<Vec<(String, Value)>> {
+        let mut claimed: Vec<(String, Value)> = Vec::new();
+        for (key, value) in transaction.writes.iter().filter_map(|(key, value)| Some((key, value.as_ref()?))) {
+            for claim in index_mgr.unique_values(key, value)? {
+                if claimed.contains(&claim) {
+                    return Err(unique_violation(&claim.0));
+                }
+                claimed.push(claim);
+            }
+        }
+        Ok(claimed)
+    }
+
+    // Each value claimed must be free, or held only by records the write set rewrites
+    async fn check_unique_writes(&self, index_mgr: &IndexManager, claims: &[(String, Value)], transaction: &Transaction) -> Result<()> {
+        for (index_name, value) in claims {
+            index_mgr.check_unique(&self.base_storage, index_name, value, |holder| transaction.writes.contains_key(holder)).await?;
+        }
+        Ok(())
+    }
+
+    // Scan counterpart of `get_version`: base storage, overlaid by versions visible
+    // to the snapshot, overlaid by the transaction's pending writes. Deleted keys are dropped.
     pub async fn scan_versions(
         &self,
         prefix: &[u8],
         transaction: &Transaction,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        let mut results = Vec::new();
-        let versions = self.version_store.read().unwrap();
-        
-        for (key, version_list) in versions.iter() {
-            if !key.starts_with(prefix) {
-                continue;
-            }
-            
-            // Find the latest visible version
-            for version in version_list.iter().rev() {
-                if version.is_visible(transaction.id, transaction.snapshot_ts) {
-                    if !version.value.is_empty() { // Skip tombstones
-                        results.push((key.clone(), version.value.clone()));
+        self.scan_versions_within(ScanBounds::Prefix(prefix), transaction)
+    }
+
+    // `scan_versions` over the keys in [start, end)
+    pub async fn scan_range_versions(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        transaction: &Transaction,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_versions_within(ScanBounds::Range(start, end), transaction)
+    }
+
+    fn scan_versions_within(
+        &self,
+        bounds: ScanBounds<'_>,
+        transaction: &Transaction,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = self.base_storage
+            .scan_within(bounds, usize::MAX)?
+            .into_iter()
+            .collect();
+
+        {
+            let versions = self.version_store.read().unwrap();
+            let in_bounds = versions
+                .range(bounds.start().to_vec()..)
+                .take_while(|(key, _)| bounds.still_within(key));
+            for (key, version_list) in in_bounds {
+                match Self::lookup_visible(version_list, transaction) {
+                    VersionLookup::Visible(record) => {
+                        merged.insert(key.clone(), record.value);
                     }
-                    break;
+                    VersionLookup::Deleted => {
+                        merged.remove(key);
+                    }
+                    VersionLookup::Unversioned => {}
                 }
             }
         }
-        
-        Ok(results)
+        transaction.stats.record_scan(merged.len());
+
+        overlay_pending_writes(&mut merged, bounds, transaction);
+        Ok(merged.into_iter().collect())
     }
     
     pub fn get_oldest_snapshot_timestamp(&self) -> VersionTimestamp {
@@ -200,7 +828,7 @@ impl MvccStorage{
         if active_txs.is_empty() {
             VersionTimestamp::now()
         } else {
-            let oldest_snapshot = active_txs.iter().map(|tx_id| {
+            let oldest_snapshot = active_txs.keys().map(|tx_id| {
                 self.transaction_manager.get_commit_timestamp(*tx_id).unwrap_or(VersionTimestamp::from_u64(0))
             })
             .min().unwrap_or(VersionTimestamp::now());
@@ -208,11 +836,11 @@ impl MvccStorage{
         }
     }
     
-    pub fn get_version_store(&self) -> std::sync::RwLockReadGuard<HashMap<Vec<u8>, Vec<VersionedRecord>>> {
+    pub fn get_version_store(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<Vec<u8>, Vec<VersionedRecord>>> {
         self.version_store.read().unwrap()
     }
     
-    pub fn get_version_store_mut(&self) -> std::sync::RwLockWriteGuard<HashMap<Vec<u8>, Vec<VersionedRecord>>> {
+    pub fn get_version_store_mut(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<Vec<u8>, Vec<VersionedRecord>>> {
         self.version_store.write().unwrap()
     }
 }
\ No newline at end of file
@@ -0,0 +1,62 @@
+use std::collections::{HashSet, VecDeque};
+
+// Bounded set of keys recently confirmed absent from the memtable and every
+// SSTable. Entries are evicted oldest-first; writers must `invalidate` a key
+// before it becomes visible so the cache never reports a stale miss.
+pub struct NegativeCache {
+    keys: HashSet<Vec<u8>>,
+    order: VecDeque<Vec<u8>>,
+    capacity: usize,
+    hits: u64,
+}
+
+impl NegativeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+            hits: 0,
+        }
+    }
+
+    pub fn contains(&mut self, key: &[u8]) -> bool {
+        let found = self.keys.contains(key);
+        if found {
+            self.hits += 1;
+        }
+        found
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        if self.capacity == 0 || !self.keys.insert(key.to_vec()) {
+            return;
+        }
+        self.order.push_back(key.to_vec());
+
+        while self.keys.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.keys.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+
+        // Invalidated keys leave stale entries behind in `order`; drop them
+        // once they outnumber the live ones
+        if self.order.len() > self.capacity * 2 {
+            let keys = &self.keys;
+            let mut seen = HashSet::new();
+            self.order.retain(|k| keys.contains(k) && seen.insert(k.clone()));
+        }
+    }
+
+    pub fn invalidate(&mut self, key: &[u8]) {
+        self.keys.remove(key);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
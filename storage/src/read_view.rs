@@ -0,0 +1,121 @@
+use rust_db_core::Result;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+// Most bytes of keys and values a view holds; reads past it go to storage
+const READ_VIEW_CAPACITY: usize = 4 * 1024 * 1024;
+
+type Records = Vec<(Vec<u8>, Vec<u8>)>;
+
+#[derive(PartialEq, Eq, Hash)]
+enum ScanKey {
+    Prefix(Vec<u8>),
+    Range(Vec<u8>, Vec<u8>),
+}
+
+// Results of reads outside transactions, served again until the view is
+// `bound` old and then dropped whole. Each result was the latest when it was
+// read, and a scan is cached as one result, so it never mixes points in time.
+// A hit is a map lookup, where the storage read it replaces walks the memtable
+// and SSTables. A zero bound, the default, turns the view off.
+pub(crate) struct ReadView {
+    bound_nanos: AtomicU64,
+    view: RwLock<View>,
+}
+
+struct View {
+    taken: Instant,
+    gets: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    scans: HashMap<ScanKey, Records>,
+    bytes: usize,
+}
+
+impl View {
+    fn new() -> Self {
+        Self { taken: Instant::now(), gets: HashMap::new(), scans: HashMap::new(), bytes: 0 }
+    }
+}
+
+impl ReadView {
+    pub(crate) fn new() -> Self {
+        Self { bound_nanos: AtomicU64::new(0), view: RwLock::new(View::new()) }
+    }
+
+    pub(crate) fn bound(&self) -> Duration {
+        Duration::from_nanos(self.bound_nanos.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_bound(&self, bound: Duration) {
+        self.bound_nanos.store(bound.as_nanos().min(u64::MAX as u128) as u64, Ordering::Relaxed);
+        // Results cached under the old bound may be older than the new one allows
+        *self.view.write().unwrap_or_else(PoisonError::into_inner) = View::new();
+    }
+
+    pub(crate) async fn get<F>(&self, key: &[u8], read: F) -> Result<Option<Vec<u8>>>
+    where
+        F: Future<Output = Result<Option<Vec<u8>>>>,
+    {
+        if let Some(cached) = self.lookup(|view| view.gets.get(key).cloned()) {
+            return Ok(cached);
+        }
+        let started = Instant::now();
+        let value = read.await?;
+        let bytes = key.len() + value.as_ref().map_or(0, Vec::len);
+        self.remember(started, bytes, |view| {
+            view.gets.insert(key.to_vec(), value.clone());
+        });
+        Ok(value)
+    }
+
+    pub(crate) async fn scan<F>(&self, prefix: &[u8], read: F) -> Result<Records>
+    where
+        F: Future<Output = Result<Records>>,
+    {
+        self.scan_cached(ScanKey::Prefix(prefix.to_vec()), read).await
+    }
+
+    pub(crate) async fn scan_range<F>(&self, start: &[u8], end: &[u8], read: F) -> Result<Records>
+    where
+        F: Future<Output = Result<Records>>,
+    {
+        self.scan_cached(ScanKey::Range(start.to_vec(), end.to_vec()), read).await
+    }
+
+    async fn scan_cached<F>(&self, scan: ScanKey, read: F) -> Result<Records>
+    where
+        F: Future<Output = Result<Records>>,
+    {
+        if let Some(cached) = self.lookup(|view| view.scans.get(&scan).cloned()) {
+            return Ok(cached);
+        }
+        let started = Instant::now();
+        let records = read.await?;
+        let bytes = records.iter().map(|(key, value)| key.len() + value.len()).sum();
+        self.remember(started, bytes, |view| {
+            view.scans.insert(scan, records.clone());
+        });
+        Ok(records)
+    }
+
+    // A result the view holds, if it's still within the bound
+    fn lookup<T>(&self, find: impl FnOnce(&View) -> Option<T>) -> Option<T> {
+        let view = self.view.read().unwrap_or_else(PoisonError::into_inner);
+        (view.taken.elapsed() <= self.bound()).then(|| find(&view)).flatten()
+    }
+
+    // Keeps a result read from `started` on. One read before the view was
+    // taken could be older than the view claims, so it isn't kept.
+    fn remember(&self, started: Instant, bytes: usize, insert: impl FnOnce(&mut View)) {
+        let mut view = self.view.write().unwrap_or_else(PoisonError::into_inner);
+        if view.taken.elapsed() > self.bound() {
+            *view = View::new();
+        }
+        if view.taken <= started && view.bytes + bytes <= READ_VIEW_CAPACITY {
+            view.bytes += bytes;
+            insert(&mut view);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{FileSystem, HashFn, LsmStorage, SSTable, StorageConfig};
+use rust_db_core::{DbError, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// What `LsmStorage::repair` did to a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepairAction {
+    // Left by a write that never finished: anything under `staging/`, or a
+    // `.tmp` file in the data or a level directory
+    RemovedTempFile(PathBuf),
+    // A checksum whose table is gone; the table is listed in `missing`
+    RemovedOrphanChecksum(PathBuf),
+    // A WAL segment whose level-0 table was installed before the crash; moved
+    // to the archive instead under `StorageConfig::with_wal_archive`
+    RemovedFlushedWalSegment(PathBuf),
+    // A level-0 table cut short by a crash, even one that opens, such as an
+    // empty file, whose WAL segment survives for `open` to flush it again
+    RemovedTornSSTable(PathBuf),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    pub actions: Vec<RepairAction>,
+    // Tables a checksum file vouches for that aren't there. Usually a
+    // compaction that crashed while deleting its inputs, but possibly a lost
+    // table; `verify_recovery` after opening tells which.
+    pub missing: Vec<PathBuf>,
+    // Tables that don't open or fail their checksum, with nothing to rebuild
+    // them from. They're left alone, as they may hold the only copy of their
+    // data, and `open` fails until they're dealt with.
+    pub unreadable: Vec<PathBuf>,
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        self.actions.is_empty() && self.missing.is_empty() && self.unreadable.is_empty()
+    }
+}
+
+impl LsmStorage {
+    pub fn repair(path: &Path) -> Result<RepairReport> {
+        Self::repair_with(&StorageConfig::new(path))
+    }
+
+    // Tidies a data directory after a crash, before it's opened; never run it
+    // on one that's open. There's no separate manifest: the level directories
+    // are the record of which tables are live, so this reconciles them with
+    // the WAL segments and checksums beside them. Tables that open and WAL
+    // segments not yet flushed are never touched.
+    pub fn repair_with(config: &StorageConfig) -> Result<RepairReport> {
+        let fs = config.file_system.as_ref();
+        let base_path = config.base_path.as_path();
+        let wal_path = config.resolved_wal_path();
+        let segments = Self::wal_segments(fs, &wal_path)?;
+        let mut report = RepairReport::default();
+        let remove = |path: &Path| fs.remove(path).map_err(DbError::storage);
+
+        for file in list_or_empty(fs, &base_path.join("staging"))? {
+            remove(&file)?;
+            report.actions.push(RepairAction::RemovedTempFile(file));
+        }
+
+        let mut level_dirs = Vec::new();
+        for entry in list_or_empty(fs, base_path)? {
+            let name = file_name(&entry);
+            if name.ends_with(".tmp") {
+                remove(&entry)?;
+                report.actions.push(RepairAction::RemovedTempFile(entry));
+            } else if let Some(level) = name.strip_prefix('L').and_then(|l| l.parse::<u32>().ok()) {
+                level_dirs.push((level, entry));
+            }
+        }
+        level_dirs.sort();
+
+        for (level, dir) in level_dirs {
+            let mut files = match fs.list(&dir) {
+                Ok(files) => files,
+                Err(e) if e.kind() == ErrorKind::NotADirectory => continue,
+                Err(e) => return Err(DbError::storage(e)),
+            };
+            files.sort();
+            for file in &files {
+                let name = file_name(file);
+                if name.ends_with(".tmp") {
+                    remove(file)?;
+                    report.actions.push(RepairAction::RemovedTempFile(file.clone()));
+                } else if name.starts_with("sst_") && name.ends_with(".crc") {
+                    let table = file.with_extension("bin");
+                    if !files.contains(&table) {
+                        remove(file)?;
+                        report.actions.push(RepairAction::RemovedOrphanChecksum(file.clone()));
+                        report.missing.push(table);
+                    }
+                } else if let Some(timestamp) = name.strip_prefix("sst_").and_then(|n| n.strip_suffix(".bin")) {
+                    // Flushes name a level-0 table and its WAL segment after the same timestamp
+                    let segment = timestamp
+                        .parse()
+                        .ok()
+                        .filter(|_| level == 0)
+                        .map(|timestamp| Self::wal_segment_path(&wal_path, timestamp))
+                        .filter(|segment| segments.contains(segment));
+                    // Its segment only goes once the table is known to hold every write
+                    let opened = SSTable::open_with(file, level, HashFn::default(), &config.file_system, config.encryption.as_ref())
+                        .and_then(|table| table.verify_checksum().map(|()| table.is_complete()));
+                    match (opened, segment) {
+                        (Ok(true), Some(segment)) => {
+                            Self::retire_wal_segment_in(fs, &wal_path, config.archive_wal, &segment)?;
+                            report.actions.push(RepairAction::RemovedFlushedWalSegment(segment));
+                        }
+                        (Ok(_), None) => {}
+                        (Ok(false) | Err(DbError::Storage(..) | DbError::Serialization(..)), Some(_)) => {
+                            remove(file)?;
+                            report.actions.push(RepairAction::RemovedTornSSTable(file.clone()));
+                        }
+                        (Err(DbError::Storage(..) | DbError::Serialization(..)), None) => {
+                            report.unreadable.push(file.clone());
+                        }
+                        // A missing or wrong key says nothing about the file
+                        (Err(e), _) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        if !report.is_clean() {
+            log::warn!(
+                "Repair of {:?} took {} actions; {} tables missing, {} unreadable",
+                base_path,
+                report.actions.len(),
+                report.missing.len(),
+                report.unreadable.len()
+            );
+        }
+        Ok(report)
+    }
+}
+
+fn list_or_empty(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<PathBuf>> {
+    match fs.list(dir) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        listed => listed.map_err(DbError::storage),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
@@ -0,0 +1,237 @@
+use crate::FileSystem;
+use rust_db_core::{DbError, Result};
+use serde::ser::{self, Impossible, SerializeStruct};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+pub(crate) const SCHEMAS_FILE: &str = "schemas.bin";
+
+// What a table's rows look like: the `Schema::fingerprint` of the first typed
+// row written to it, and the field names serde writes for that row, which is
+// all an untyped `Database::insert` can be checked against
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TableSchema {
+    fingerprint: String,
+    fields: Vec<String>,
+}
+
+// The recorded schemas, kept in `schemas.bin` next to the level directories
+// rather than in the keyspace, so scans never see them and writes can't
+// replace them. Checks and records happen under one lock, so two first
+// writers of a table can't both record their own shape.
+#[derive(Debug)]
+pub(crate) struct SchemaRegistry {
+    path: PathBuf,
+    tables: Mutex<BTreeMap<String, TableSchema>>,
+}
+
+impl SchemaRegistry {
+    pub(crate) fn load(fs: &dyn FileSystem, dir: &Path) -> Result<Self> {
+        let path = dir.join(SCHEMAS_FILE);
+        let tables = match fs.open(&path) {
+            Ok(data) => bincode::deserialize((*data).as_ref()).map_err(DbError::serialization)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(DbError::storage(e)),
+        };
+        Ok(SchemaRegistry { path, tables: Mutex::new(tables) })
+    }
+
+    // Records `fingerprint` for `table` if it has none yet, and otherwise
+    // fails unless it matches
+    pub(crate) fn check_or_record<T: Serialize>(
+        &self,
+        fs: &dyn FileSystem,
+        table: &str,
+        fingerprint: &str,
+        row: &T,
+    ) -> Result<()> {
+        let mut tables = self.tables.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(schema) = tables.get(table) {
+            if schema.fingerprint != fingerprint {
+                return Err(mismatch(table, &schema.fingerprint, fingerprint));
+            }
+            return Ok(());
+        }
+        let fields = field_names(row).unwrap_or_default().into_iter().map(String::from).collect();
+        tables.insert(table.to_string(), TableSchema { fingerprint: fingerprint.to_string(), fields });
+        if let Err(e) = self.persist(fs, &tables) {
+            tables.remove(table);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Fails if `table` has a recorded schema and `row` doesn't serialize to a
+    // struct with the same fields. Tables without one accept anything.
+    pub(crate) fn check_fields<T: Serialize>(&self, table: &str, row: &T) -> Result<()> {
+        let tables = self.tables.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(schema) = tables.get(table) else {
+            return Ok(());
+        };
+        match field_names(row) {
+            Some(fields) if fields.iter().eq(schema.fields.iter()) => Ok(()),
+            Some(fields) => Err(mismatch(table, &schema.fingerprint, &fields.join(","))),
+            None => Err(mismatch(table, &schema.fingerprint, "a value that isn't a struct")),
+        }
+    }
+
+    // Written aside and renamed over the old file, so a crash leaves one or the other
+    fn persist(&self, fs: &dyn FileSystem, tables: &BTreeMap<String, TableSchema>) -> Result<()> {
+        let bytes = bincode::serialize(tables).map_err(DbError::serialization)?;
+        let staging = self.path.with_extension("bin.tmp");
+        fs.write(&staging, &bytes).map_err(DbError::storage)?;
+        fs.rename(&staging, &self.path).map_err(DbError::storage)
+    }
+}
+
+fn mismatch(table: &str, stored: &str, found: &str) -> DbError {
+    DbError::Schema(format!("{} rows have fields {{{}}}, not {{{}}}", table, stored, found))
+}
+
+// The field names serde writes for `value`, in order, if it's a struct
+fn field_names<T: Serialize + ?Sized>(value: &T) -> Option<Vec<&'static str>> {
+    let mut fields = None;
+    value.serialize(FieldNames { fields: &mut fields }).ok()?;
+    fields
+}
+
+// A serializer that stops at the top level: a struct records its field names
+// without serializing their values, and anything else records nothing
+struct FieldNames<'a> {
+    fields: &'a mut Option<Vec<&'static str>>,
+}
+
+#[derive(Debug)]
+struct NotAStruct;
+
+impl fmt::Display for NotAStruct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not a struct")
+    }
+}
+
+impl std::error::Error for NotAStruct {}
+
+impl ser::Error for NotAStruct {
+    fn custom<M: fmt::Display>(_: M) -> Self {
+        NotAStruct
+    }
+}
+
+impl SerializeStruct for FieldNames<'_> {
+    type Ok = ();
+    type Error = NotAStruct;
+
+    fn serialize_field<V: Serialize + ?Sized>(&mut self, key: &'static str, _: &V) -> std::result::Result<(), NotAStruct> {
+        self.fields.get_or_insert_with(Vec::new).push(key);
+        Ok(())
+    }
+
+    fn end(self) -> std::result::Result<(), NotAStruct> {
+        Ok(())
+    }
+}
+
+macro_rules! not_a_struct {
+    ($($method:ident($($ty:ty),*);)*) => {
+        $(fn $method(self, $(_: $ty),*) -> std::result::Result<(), NotAStruct> {
+            Err(NotAStruct)
+        })*
+    };
+}
+
+impl<'a> ser::Serializer for FieldNames<'a> {
+    type Ok = ();
+    type Error = NotAStruct;
+    type SerializeSeq = Impossible<(), NotAStruct>;
+    type SerializeTuple = Impossible<(), NotAStruct>;
+    type SerializeTupleStruct = Impossible<(), NotAStruct>;
+    type SerializeTupleVariant = Impossible<(), NotAStruct>;
+    type SerializeMap = Impossible<(), NotAStruct>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), NotAStruct>;
+
+    not_a_struct! {
+        serialize_bool(bool);
+        serialize_i8(i8);
+        serialize_i16(i16);
+        serialize_i32(i32);
+        serialize_i64(i64);
+        serialize_u8(u8);
+        serialize_u16(u16);
+        serialize_u32(u32);
+        serialize_u64(u64);
+        serialize_f32(f32);
+        serialize_f64(f64);
+        serialize_char(char);
+        serialize_str(&str);
+        serialize_bytes(&[u8]);
+        serialize_none();
+        serialize_unit();
+        serialize_unit_struct(&'static str);
+        serialize_unit_variant(&'static str, u32, &'static str);
+    }
+
+    fn serialize_some<V: Serialize + ?Sized>(self, _: &V) -> std::result::Result<(), NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_newtype_struct<V: Serialize + ?Sized>(self, _: &'static str, _: &V) -> std::result::Result<(), NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_newtype_variant<V: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &V,
+    ) -> std::result::Result<(), NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> std::result::Result<Self::SerializeSeq, NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_tuple(self, _: usize) -> std::result::Result<Self::SerializeTuple, NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> std::result::Result<Self::SerializeTupleStruct, NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> std::result::Result<Self::SerializeTupleVariant, NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> std::result::Result<Self::SerializeMap, NotAStruct> {
+        Err(NotAStruct)
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> std::result::Result<Self, NotAStruct> {
+        *self.fields = Some(Vec::new());
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> std::result::Result<Self::SerializeStructVariant, NotAStruct> {
+        Err(NotAStruct)
+    }
+}
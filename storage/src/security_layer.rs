@@ -241,7 +241,7 @@ impl<D: Database> SecurityLayer<D> {
     fn encrypt<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
         if !self.encryption_config.enabled {
             return bincode::serialize(value)
-                .map_err(|e| DbError::Serialization(e.to_string()));
+                .map_err(DbError::serialization);
         }
 
         let key = self
@@ -251,7 +251,7 @@ impl<D: Database> SecurityLayer<D> {
 
         // Serialize value
         let plaintext = bincode::serialize(value)
-            .map_err(|e| DbError::Serialization(e.to_string()))?;
+            .map_err(DbError::serialization)?;
 
         // Generate random nonce
         let mut nonce_bytes = [0u8; 12];
@@ -384,7 +384,7 @@ impl<D: Database> Database for SecurityLayer<D> {
                     Some(data) => {
                         let decrypted = self.decrypt(&data)?;
                         let result: T = bincode::deserialize(&decrypted)
-                            .map_err(|e| DbError::Serialization(e.to_string()))?;
+                            .map_err(DbError::serialization)?;
                         Ok(Some(result))
                     }
                     None => Ok(None),
@@ -0,0 +1,92 @@
+use rust_db_core::{DbError, Result};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use log::{error, warn};
+
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    pub max_restarts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+// Watches background loops (compaction, GC) and restarts them when they panic.
+// A loop that returns normally, with `Ok` or `Err`, is not restarted.
+pub struct TaskSupervisor {
+    config: SupervisorConfig,
+    restarts: Arc<AtomicU32>,
+}
+
+impl TaskSupervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self {
+            config,
+            restarts: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    // Total restarts across every task spawned by this supervisor
+    pub fn restart_count(&self) -> u32 {
+        self.restarts.load(Ordering::SeqCst)
+    }
+
+    // Spawns `task()` and respawns it with exponential backoff each time it panics,
+    // giving up with an error once `max_restarts` is exhausted
+    pub fn supervise<F, Fut>(&self, name: &str, mut task: F) -> JoinHandle<Result<()>>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let config = self.config.clone();
+        let restarts = Arc::clone(&self.restarts);
+        let name = name.to_string();
+
+        tokio::spawn(async move {
+            let mut attempts = 0u32;
+            let mut backoff_ms = config.initial_backoff_ms;
+
+            loop {
+                match tokio::spawn(task()).await {
+                    Ok(result) => return result,
+                    Err(e) if e.is_panic() => {
+                        attempts += 1;
+                        if attempts > config.max_restarts {
+                            error!("Background task '{}' panicked {} times, giving up", name, attempts);
+                            return Err(DbError::Storage(format!(
+                                "Background task '{}' exceeded {} restarts",
+                                name, config.max_restarts
+                            ), Some(Box::new(e))));
+                        }
+
+                        restarts.fetch_add(1, Ordering::SeqCst);
+                        warn!(
+                            "Background task '{}' panicked, restarting in {}ms (attempt {}/{})",
+                            name, backoff_ms, attempts, config.max_restarts
+                        );
+                        tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+                        backoff_ms = backoff_ms.saturating_mul(2).min(config.max_backoff_ms);
+                    }
+                    Err(e) => {
+                        return Err(DbError::Storage(format!(
+                            "Background task '{}' was cancelled: {}",
+                            name, e
+                        ), Some(Box::new(e))));
+                    }
+                }
+            }
+        })
+    }
+}
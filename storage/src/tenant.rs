@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+use rust_db_core::{Database, KeyBuilder, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::LsmStorage;
+
+// One tenant's view of a shared `LsmStorage`. Every key is stored under
+// `tenant:` and the tenant id encoded as a key component, which is terminated,
+// so no tenant's keys are a prefix of another's, and keys come back from scans with the namespace stripped.
+// Compaction, GC and indexes still run over the whole store.
+#[derive(Clone)]
+pub struct Tenant {
+    storage: LsmStorage,
+    namespace: Vec<u8>,
+}
+
+impl Tenant {
+    pub(crate) fn new(storage: LsmStorage, id: &str) -> Self {
+        let mut namespace = b"tenant:".to_vec();
+        namespace.extend(KeyBuilder::new().push(id).finish());
+        Self { storage, namespace }
+    }
+
+    // The bytes every key of this tenant is stored under
+    pub fn namespace(&self) -> &[u8] {
+        &self.namespace
+    }
+
+    fn scoped(&self, key: &[u8]) -> Vec<u8> {
+        let mut scoped = self.namespace.clone();
+        scoped.extend_from_slice(key);
+        scoped
+    }
+
+    fn unscoped(&self, records: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let len = self.namespace.len();
+        records
+            .into_iter()
+            .map(|(mut key, value)| {
+                key.drain(..len);
+                (key, value)
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Database for Tenant {
+    async fn insert<T: Serialize + Send + Sync>(&self, key: &[u8], value: &T) -> Result<()> {
+        Database::insert(&self.storage, &self.scoped(key), value).await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>> {
+        Database::get(&self.storage, &self.scoped(key)).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        Database::delete(&self.storage, &self.scoped(key)).await
+    }
+
+    async fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let records = self.storage.scan(&self.scoped(prefix)).await?;
+        Ok(self.unscoped(records))
+    }
+
+    async fn scan_range(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let records = self.storage.scan_range(&self.scoped(start), &self.scoped(end)).await?;
+        Ok(self.unscoped(records))
+    }
+
+    async fn scan_range_limited(&self, start: &[u8], end: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let records = self
+            .storage
+            .scan_range_limited(&self.scoped(start), &self.scoped(end), limit)
+            .await?;
+        Ok(self.unscoped(records))
+    }
+}
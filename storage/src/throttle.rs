@@ -0,0 +1,42 @@
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Token bucket over bytes. Holds at most a tenth of a second's worth, so a
+// large burst after an idle spell is still paced. A caller asking for more
+// than is there takes the bucket into debt and sleeps until it's paid off;
+// whoever comes next waits behind that debt too. Time is read from tokio's
+// clock, so a runtime with paused time drives the bucket deterministically.
+pub(crate) struct IoRateLimiter {
+    bytes_per_sec: f64,
+    burst: f64,
+    // Bytes available as of the instant, negative while in debt
+    state: Mutex<(f64, Instant)>,
+}
+
+impl IoRateLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec as f64;
+        let burst = bytes_per_sec / 10.0;
+        Self {
+            bytes_per_sec,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    pub(crate) async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let (available, last) = &mut *state;
+            let now = Instant::now();
+            *available = (*available + now.duration_since(*last).as_secs_f64() * self.bytes_per_sec).min(self.burst);
+            *last = now;
+            *available -= bytes as f64;
+            (*available < 0.0).then(|| Duration::from_secs_f64(-*available / self.bytes_per_sec))
+        };
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Runs in its own test binary: the counting allocator sees every allocation in
+// the process, so nothing else may run alongside the measured section. Tests
+// here take `MEASURING` to keep out of each other's way.
+use crossbeam_skiplist::SkipMap;
+use rust_db_core::Database;
+use rust_db_storage::{LsmStorage, MemTable};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+
+struct CountingAlloc;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static MEASURING: Mutex<()> = Mutex::const_new(());
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// Allocator calls made while running `f`
+fn count_allocations(f: impl FnOnce()) -> usize {
+    ALLOCATIONS.store(0, Ordering::SeqCst);
+    COUNTING.store(true, Ordering::SeqCst);
+    f();
+    COUNTING.store(false, Ordering::SeqCst);
+    ALLOCATIONS.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn test_sstable_get_deserializes_without_copying_value() {
+    let _measuring = MEASURING.lock().await;
+    const SIZE: usize = 4 * 1024 * 1024;
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap();
+    storage.insert(b"blob", &"x".repeat(SIZE)).await.unwrap();
+    storage.flush().await.unwrap();
+
+    ALLOCATED.store(0, Ordering::SeqCst);
+    COUNTING.store(true, Ordering::SeqCst);
+    let value: Option<String> = Database::get(&storage, b"blob").await.unwrap();
+    COUNTING.store(false, Ordering::SeqCst);
+
+    assert_eq!(value.map(|v| v.len()), Some(SIZE));
+    // The String itself is one full-size allocation; a copied Vec would be a second
+    let allocated = ALLOCATED.load(Ordering::SeqCst);
+    assert!(allocated < SIZE + SIZE / 2, "allocated {} bytes for a {} byte value", allocated, SIZE);
+}
+
+#[test]
+fn test_memtable_arena_allocates_far_less_than_a_vec_per_entry() {
+    let _measuring = MEASURING.blocking_lock();
+    const ENTRIES: usize = 10_000;
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..ENTRIES)
+        .map(|i| (format!("key:{:06}", i).into_bytes(), (i as u64).to_le_bytes().to_vec()))
+        .collect();
+
+    let memtable = MemTable::new();
+    let arena_calls = count_allocations(|| {
+        for (key, value) in &entries {
+            memtable.insert(key, value);
+        }
+    });
+    assert_eq!(memtable.len(), ENTRIES);
+    assert_eq!(memtable.get(b"key:000042"), Some(42u64.to_le_bytes().to_vec()));
+
+    // The layout the arena replaced: a key Vec and a value Vec per entry
+    let baseline = SkipMap::new();
+    let baseline_calls = count_allocations(|| {
+        for (key, value) in &entries {
+            baseline.insert(key.clone(), value.clone());
+        }
+    });
+    assert_eq!(baseline.len(), ENTRIES);
+
+    // Both pay for a skiplist node per entry; only the baseline adds two Vecs
+    assert!(
+        arena_calls * 2 < baseline_calls,
+        "arena memtable made {} allocator calls, per-Vec baseline {}",
+        arena_calls,
+        baseline_calls
+    );
+}
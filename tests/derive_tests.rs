@@ -0,0 +1,11 @@
+#[test]
+fn test_skipped_field_needs_no_value_conversion() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/skip_field.rs");
+}
+
+#[test]
+fn test_unserializable_indexed_field_names_the_field() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/index_not_serialize.rs");
+}
@@ -0,0 +1,486 @@
+use rust_db_core::{Database, DbError, FieldAccess, MvccDatabase, TransactionContext, Value};
+use rust_db_storage::{BloomFilter, HashFn, IndexDescriptor, IndexType, LsmStorage, MvccLsmStorage};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Store {
+    name: String,
+    lat: f64,
+    lon: f64,
+}
+
+impl FieldAccess for Store {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "name" => Some(Value::String(self.name.clone())),
+            "location" => Some(Value::Point { lat: self.lat, lon: self.lon }),
+            _ => None,
+        }
+    }
+}
+
+fn setup() -> (TempDir, LsmStorage) {
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap();
+    (dir, storage)
+}
+
+#[tokio::test]
+async fn test_geo_index_bounding_box() {
+    let (_dir, storage) = setup();
+    storage
+        .create_index(IndexDescriptor::new("idx_store_location", "location", IndexType::Geo))
+        .await
+        .unwrap();
+
+    let stores = vec![
+        Store { name: "soho".to_string(), lat: 51.5136, lon: -0.1365 },
+        Store { name: "camden".to_string(), lat: 51.5390, lon: -0.1426 },
+        Store { name: "greenwich".to_string(), lat: 51.4826, lon: -0.0077 },
+        Store { name: "paris".to_string(), lat: 48.8566, lon: 2.3522 },
+        // Just outside the box, likely in a cell the query still scans
+        Store { name: "edge".to_string(), lat: 51.5501, lon: -0.1400 },
+    ];
+    for store in &stores {
+        let key = format!("Store:{}", store.name).into_bytes();
+        storage.insert(&key, store).await.unwrap();
+        let location = store.get_field("location").unwrap();
+        storage.update_index("idx_store_location", &key, &location).await.unwrap();
+    }
+
+    // Central London
+    let mut found: Vec<Store> = storage
+        .query_in_bbox("idx_store_location", (51.48, -0.15), (51.55, 0.0))
+        .await
+        .unwrap();
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+    let names: Vec<&str> = found.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["camden", "greenwich", "soho"]);
+
+    let none: Vec<Store> = storage
+        .query_in_bbox("idx_store_location", (10.0, 10.0), (11.0, 11.0))
+        .await
+        .unwrap();
+    assert!(none.is_empty());
+}
+
+#[tokio::test]
+async fn test_transaction_commit_updates_index() {
+    let dir = TempDir::new().unwrap();
+    let storage = MvccLsmStorage::new(dir.path()).unwrap();
+    let base = storage.base_storage();
+    base.create_index(IndexDescriptor::new("idx_store_name", "name", IndexType::Hash)).await.unwrap();
+    base.index_records::<Store>("idx_store_name", b"Store:").unwrap();
+
+    let store = Store { name: "soho".to_string(), lat: 51.5136, lon: -0.1365 };
+    let name = Value::String("soho".to_string());
+    let mut tx = TransactionContext::new(&storage).await.unwrap();
+    tx.transaction_mut()
+        .put(b"Store:soho".to_vec(), bincode::serialize(&store).unwrap()).unwrap();
+
+    // Nothing is indexed until the commit applies the write
+    assert!(base.get_by_index::<Store>("idx_store_name", &name).await.unwrap().is_empty());
+    tx.commit().await.unwrap();
+
+    let found: Vec<Store> = base.get_by_index("idx_store_name", &name).await.unwrap();
+    assert_eq!(found, vec![store]);
+}
+
+#[tokio::test]
+async fn test_insert_and_delete_replace_index_entries() {
+    let (_dir, storage) = setup();
+    storage
+        .create_index(IndexDescriptor::new("idx_store_name", "name", IndexType::Hash))
+        .await
+        .unwrap();
+    storage.index_records::<Store>("idx_store_name", b"Store:").unwrap();
+    let soho = Value::String("soho".to_string());
+    let camden = Value::String("camden".to_string());
+
+    storage.insert(b"Store:1", &Store { name: "soho".to_string(), lat: 51.5136, lon: -0.1365 }).await.unwrap();
+    let renamed = Store { name: "camden".to_string(), lat: 51.5136, lon: -0.1365 };
+    storage.insert(b"Store:1", &renamed).await.unwrap();
+    assert!(storage.get_by_index::<Store>("idx_store_name", &soho).await.unwrap().is_empty());
+    assert_eq!(storage.get_by_index::<Store>("idx_store_name", &camden).await.unwrap(), vec![renamed]);
+
+    // Deleting the record takes its entry with it, leaving nothing behind
+    Database::delete(&storage, b"Store:1").await.unwrap();
+    assert!(storage.get_by_index::<Store>("idx_store_name", &camden).await.unwrap().is_empty());
+    assert!(storage.scan(b"").await.unwrap().is_empty());
+}
+
+// Serializes to no bytes at all
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Marker;
+
+impl FieldAccess for Marker {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "kind" => Some(Value::String("marker".to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_empty_record_values_are_indexed() {
+    let (_dir, storage) = setup();
+    storage.create_index(IndexDescriptor::new("idx_marker_kind", "kind", IndexType::BTree)).await.unwrap();
+    storage.index_records::<Marker>("idx_marker_kind", b"Marker:").unwrap();
+    let kind = Value::String("marker".to_string());
+
+    storage.insert(b"Marker:1", &Marker).await.unwrap();
+    assert_eq!(storage.get(b"Marker:1").await.unwrap(), Some(Vec::new()));
+    assert_eq!(storage.get_by_index::<Marker>("idx_marker_kind", &kind).await.unwrap(), vec![Marker]);
+
+    Database::delete(&storage, b"Marker:1").await.unwrap();
+    assert!(storage.get_by_index::<Marker>("idx_marker_kind", &kind).await.unwrap().is_empty());
+    assert!(storage.verify_index("idx_marker_kind").await.unwrap().is_consistent());
+}
+
+#[tokio::test]
+async fn test_commit_batch_applies_writes_and_index_entries_together() {
+    let dir = TempDir::new().unwrap();
+    let storage = MvccLsmStorage::new(dir.path()).unwrap();
+    let base = storage.base_storage();
+    base.create_index(IndexDescriptor::new("idx_store_name", "name", IndexType::Hash)).await.unwrap();
+    base.index_records::<Store>("idx_store_name", b"Store:").unwrap();
+    let stale = Store { name: "closed".to_string(), lat: 0.0, lon: 0.0 };
+    base.insert(b"Store:closed", &stale).await.unwrap();
+
+    let soho = Store { name: "soho".to_string(), lat: 51.5136, lon: -0.1365 };
+    let camden = Store { name: "camden".to_string(), lat: 51.5390, lon: -0.1426 };
+    let mut changes = base.subscribe();
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.put(b"Store:soho".to_vec(), bincode::serialize(&soho).unwrap()).unwrap();
+    tx.put(b"Store:camden".to_vec(), bincode::serialize(&camden).unwrap()).unwrap();
+    tx.delete(b"Store:closed".to_vec()).unwrap();
+
+    // A conflicting commit is refused before any of it is applied
+    let mut rival = storage.begin_transaction().await.unwrap();
+    rival.put(b"Store:soho".to_vec(), bincode::serialize(&camden).unwrap()).unwrap();
+    storage.commit_transaction(tx).await.unwrap();
+    let seen = std::iter::from_fn(|| changes.try_recv()).count();
+    // Three records, two new index entries, and the deleted record's entry
+    assert_eq!(seen, 6);
+    assert!(storage.commit_transaction(rival).await.is_err());
+    assert!(changes.try_recv().is_none());
+
+    let found: Vec<Store> = base.get_by_index("idx_store_name", &Value::String("soho".to_string())).await.unwrap();
+    assert_eq!(found, vec![soho.clone()]);
+    let found: Vec<Store> = base.get_by_index("idx_store_name", &Value::String("camden".to_string())).await.unwrap();
+    assert_eq!(found, vec![camden]);
+    assert_eq!(storage.get::<Store>(b"Store:soho").await.unwrap(), Some(soho));
+    assert_eq!(base.get(b"Store:closed").await.unwrap().filter(|v| !v.is_empty()), None);
+    let report = base.verify_index("idx_store_name").await.unwrap();
+    assert!(report.is_consistent(), "{:?}", report);
+}
+
+// Hash index keys hold these digests, so they mustn't change between builds
+#[test]
+fn test_hash_fn_digests_are_stable() {
+    assert_eq!(HashFn::SipHash.hash(b"alice@example.com"), 0xa810_b556_82d9_a68d);
+    assert_eq!(HashFn::Fnv1a.hash(b""), 0xcbf2_9ce4_8422_2325);
+}
+
+#[tokio::test]
+async fn test_hash_index_lookups_under_each_hash_fn() {
+    for hash_fn in [HashFn::Fnv1a, HashFn::SipHash] {
+        let dir = TempDir::new().unwrap();
+        let storage = LsmStorage::new(dir.path()).unwrap().with_hash_fn(hash_fn);
+        assert_eq!(storage.hash_fn(), hash_fn);
+        storage
+            .create_index(IndexDescriptor::new("idx_store_name", "name", IndexType::Hash))
+            .await
+            .unwrap();
+        storage.index_records::<Store>("idx_store_name", b"Store:").unwrap();
+
+        for name in ["soho", "camden", "greenwich"] {
+            let store = Store { name: name.to_string(), lat: 51.5, lon: -0.1 };
+            storage.insert(format!("Store:{}", name).as_bytes(), &store).await.unwrap();
+        }
+        storage.flush().await.unwrap();
+
+        let found: Vec<Store> = storage
+            .get_by_index("idx_store_name", &Value::String("camden".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1, "{:?}", hash_fn);
+        assert_eq!(found[0].name, "camden");
+        let missing: Vec<Store> = storage
+            .get_by_index("idx_store_name", &Value::String("paris".to_string()))
+            .await
+            .unwrap();
+        assert!(missing.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_hash_index_lookup_rules_out_colliding_values() {
+    let (_dir, storage) = setup();
+    storage
+        .create_index(IndexDescriptor::new("idx_store_name", "name", IndexType::Hash))
+        .await
+        .unwrap();
+    storage.index_records::<Store>("idx_store_name", b"Store:").unwrap();
+    for name in ["soho", "camden"] {
+        let store = Store { name: name.to_string(), lat: 51.5, lon: -0.1 };
+        storage.insert(format!("Store:{}", name).as_bytes(), &store).await.unwrap();
+    }
+    // Camden filed under soho's digest too, as a colliding value would be
+    let soho = Value::String("soho".to_string());
+    storage.update_index("idx_store_name", b"Store:camden", &soho).await.unwrap();
+
+    let found: Vec<Store> = storage.get_by_index("idx_store_name", &soho).await.unwrap();
+    assert_eq!(found.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["soho"]);
+    let keys = storage.lookup_index_keys("Store", "name", std::slice::from_ref(&soho)).await.unwrap().unwrap();
+    assert_eq!(keys, vec![b"Store:soho".to_vec()]);
+}
+
+#[test]
+fn test_bloom_filter_has_no_false_negatives() {
+    let keys: Vec<Vec<u8>> = (0..1000u32).map(|i| format!("key{}", i).into_bytes()).collect();
+    for hash_fn in [HashFn::Fnv1a, HashFn::SipHash] {
+        let bloom = BloomFilter::from_keys(keys.iter().map(|k| k.as_slice()), hash_fn);
+        assert_eq!(bloom.hash_fn(), hash_fn);
+        assert!(keys.iter().all(|k| bloom.may_contain(k)));
+
+        let false_positives = (0..1000u32)
+            .filter(|i| bloom.may_contain(format!("absent{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 50, "{:?}: {} false positives", hash_fn, false_positives);
+    }
+    // The fast hash is what storage uses unless told otherwise
+    assert_eq!(HashFn::default(), HashFn::Fnv1a);
+}
+
+#[tokio::test]
+async fn test_verify_index_reports_and_repairs_drift() {
+    let (_dir, storage) = setup();
+    storage
+        .create_index(IndexDescriptor::new("idx_store_name", "name", IndexType::BTree))
+        .await
+        .unwrap();
+    storage.index_records::<Store>("idx_store_name", b"Store:").unwrap();
+    for name in ["soho", "camden", "greenwich"] {
+        let store = Store { name: name.to_string(), lat: 51.5, lon: -0.1 };
+        storage.insert(format!("Store:{}", name).as_bytes(), &store).await.unwrap();
+    }
+
+    let report = storage.verify_index("idx_store_name").await.unwrap();
+    assert_eq!(report.records_checked, 3);
+    assert!(report.is_consistent(), "{:?}", report);
+
+    // Drop camden's entry and leave one pointing at a record that never existed
+    let camden = Value::String("camden".to_string());
+    let entries = storage.scan(b"index:idx_store_name:").await.unwrap();
+    let camden_entry = entries.iter().find(|(_, v)| v.as_slice() == b"Store:camden").unwrap();
+    storage.delete(&camden_entry.0).await.unwrap();
+    storage.put(b"index:idx_store_name:stale:Store:ghost", b"Store:ghost").await.unwrap();
+    assert!(storage.get_by_index::<Store>("idx_store_name", &camden).await.unwrap().is_empty());
+
+    let report = storage.verify_index("idx_store_name").await.unwrap();
+    assert_eq!(report.missing, vec![b"Store:camden".to_vec()]);
+    assert_eq!(report.orphaned, vec![b"index:idx_store_name:stale:Store:ghost".to_vec()]);
+    assert!(!report.repaired);
+
+    let repaired = storage.repair_index("idx_store_name").await.unwrap();
+    assert!(repaired.repaired);
+    assert!(storage.verify_index("idx_store_name").await.unwrap().is_consistent());
+    let found: Vec<Store> = storage.get_by_index("idx_store_name", &camden).await.unwrap();
+    assert_eq!(found.len(), 1);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Member {
+    id: u64,
+    email: String,
+}
+
+impl FieldAccess for Member {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "email" => Some(Value::String(self.email.clone())),
+            _ => None,
+        }
+    }
+}
+
+// Inserts the member unless another member already has the email
+async fn insert_unique(storage: &LsmStorage, member: &Member) -> rust_db_core::Result<()> {
+    let email = Value::String(member.email.clone());
+    let key = format!("Member:{}", member.id).into_bytes();
+    if storage.index_contains("idx_member_email", &email).await? {
+        let owner: Vec<Member> = storage.get_by_index("idx_member_email", &email).await?;
+        if owner.iter().any(|owner| owner.id != member.id) {
+            return Err(DbError::Query(format!("email {} is already taken", member.email)));
+        }
+    }
+    storage.insert(&key, member).await
+}
+
+#[tokio::test]
+async fn test_unique_email_enforced_with_index_contains() {
+    let (_dir, storage) = setup();
+    storage
+        .create_index(IndexDescriptor::new("idx_member_email", "email", IndexType::Hash))
+        .await
+        .unwrap();
+    storage.index_records::<Member>("idx_member_email", b"Member:").unwrap();
+
+    let ada = Member { id: 1, email: "ada@example.com".to_string() };
+    let email = Value::String(ada.email.clone());
+    assert!(!storage.index_contains("idx_member_email", &email).await.unwrap());
+    insert_unique(&storage, &ada).await.unwrap();
+    assert!(storage.index_contains("idx_member_email", &email).await.unwrap());
+
+    let copycat = Member { id: 2, email: ada.email.clone() };
+    let err = insert_unique(&storage, &copycat).await.unwrap_err();
+    assert!(matches!(err, DbError::Query(_)), "{:?}", err);
+    assert!(storage.get(b"Member:2").await.unwrap().is_none());
+
+    // Ada moves to a new address; the insert replaces her old entry, so the old
+    // email is free again
+    let moved = Member { id: 1, email: "ada@example.org".to_string() };
+    insert_unique(&storage, &moved).await.unwrap();
+    assert!(!storage.index_contains("idx_member_email", &email).await.unwrap());
+    insert_unique(&storage, &copycat).await.unwrap();
+
+    let missing = storage.index_contains("idx_nope", &email).await.unwrap_err();
+    assert!(matches!(missing, DbError::Query(_)));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_unique_index_admits_one_of_two_concurrent_inserts() {
+    use std::sync::Arc;
+
+    let dir = TempDir::new().unwrap();
+    let storage = Arc::new(MvccLsmStorage::new(dir.path()).unwrap());
+    let base = storage.base_storage();
+    base.create_index(IndexDescriptor::new("idx_member_email", "email", IndexType::Hash).unique()).await.unwrap();
+    base.index_records::<Member>("idx_member_email", b"Member:").unwrap();
+
+    for round in 0..20u64 {
+        let email = format!("user{}@example.com", round);
+        let tasks: Vec<_> = (0..2u64)
+            .map(|i| {
+                let storage = Arc::clone(&storage);
+                let member = Member { id: round * 2 + i, email: email.clone() };
+                tokio::spawn(async move {
+                    Database::insert(&*storage, format!("Member:{}", member.id).as_bytes(), &member).await
+                })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1, "{:?}", results);
+        let err = results.into_iter().find_map(|r| r.err()).unwrap();
+        assert!(
+            matches!(&err, DbError::Schema(msg) if msg == "unique constraint violated: idx_member_email"),
+            "{:?}",
+            err
+        );
+        let holders: Vec<Member> = base.get_by_index("idx_member_email", &Value::String(email)).await.unwrap();
+        assert_eq!(holders.len(), 1);
+    }
+
+    // Rewriting a record with its own value is fine; a transaction can't claim
+    // a taken value, but can take one over from a record it deletes
+    let holder: Vec<Member> = base
+        .get_by_index("idx_member_email", &Value::String("user0@example.com".to_string()))
+        .await
+        .unwrap();
+    let holder = holder[0].clone();
+    let holder_key = format!("Member:{}", holder.id).into_bytes();
+    Database::insert(&*storage, &holder_key, &holder).await.unwrap();
+
+    let thief = Member { id: 100, email: holder.email.clone() };
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.put(b"Member:100".to_vec(), bincode::serialize(&thief).unwrap()).unwrap();
+    let err = storage.commit_transaction(tx).await.unwrap_err();
+    assert!(matches!(err, DbError::Schema(_)), "{:?}", err);
+
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.delete(holder_key).unwrap();
+    tx.put(b"Member:100".to_vec(), bincode::serialize(&thief).unwrap()).unwrap();
+    storage.commit_transaction(tx).await.unwrap();
+    assert!(base.index_contains("idx_member_email", &Value::String(holder.email)).await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_writes_to_one_record_leave_one_index_entry() {
+    use std::sync::Arc;
+
+    let dir = TempDir::new().unwrap();
+    let storage = Arc::new(LsmStorage::new(dir.path()).unwrap());
+    storage.create_index(IndexDescriptor::new("idx_member_email", "email", IndexType::BTree)).await.unwrap();
+    storage.index_records::<Member>("idx_member_email", b"Member:").unwrap();
+
+    // Each write replaces the entry of the value before it, so one read from a
+    // value another writer was replacing would leave that value's entry behind
+    let tasks: Vec<_> = (0..200u64)
+        .map(|i| {
+            let storage = Arc::clone(&storage);
+            tokio::spawn(async move {
+                let member = Member { id: 1, email: format!("user{}@example.com", i) };
+                if i % 5 == 4 {
+                    Database::delete(&*storage, b"Member:1").await
+                } else {
+                    Database::insert(&*storage, b"Member:1", &member).await
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    let report = storage.verify_index("idx_member_email").await.unwrap();
+    assert!(report.is_consistent(), "{:?}", report);
+    let current: Option<Member> = Database::get(&*storage, b"Member:1").await.unwrap();
+    let entries = storage.scan_index("idx_member_email").await.unwrap();
+    assert_eq!(entries.len(), usize::from(current.is_some()));
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Person {
+    age: i64,
+}
+
+impl FieldAccess for Person {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "age" => Some(Value::Int(self.age)),
+            _ => None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_scan_index_returns_entries_sorted_by_value() {
+    let (_dir, storage) = setup();
+    storage
+        .create_index(IndexDescriptor::new("idx_person_age", "age", IndexType::BTree))
+        .await
+        .unwrap();
+    storage.index_records::<Person>("idx_person_age", b"Person:").unwrap();
+
+    // Negative and multi-byte ages, which the little-endian key encoding misorders
+    for (id, age) in [("a", 300), ("b", 7), ("c", -5), ("d", 42), ("e", 7)] {
+        let key = format!("Person:{}", id).into_bytes();
+        storage.insert(&key, &Person { age }).await.unwrap();
+    }
+
+    let entries = storage.scan_index("idx_person_age").await.unwrap();
+    let expected: Vec<(Value, Vec<u8>)> = [(-5, "c"), (7, "b"), (7, "e"), (42, "d"), (300, "a")]
+        .into_iter()
+        .map(|(age, id)| (Value::Int(age), format!("Person:{}", id).into_bytes()))
+        .collect();
+    assert_eq!(entries, expected);
+
+    let missing = storage.scan_index("idx_nope").await.unwrap_err();
+    assert!(matches!(missing, DbError::Query(_)));
+}
@@ -0,0 +1,154 @@
+use proptest::prelude::*;
+use rust_db_core::{KeyBuilder, KeyComponent, KeyReader, TruncatedKey, Value};
+
+fn encode<C: KeyComponent>(value: C) -> Vec<u8> {
+    KeyBuilder::new().push(&value).finish()
+}
+
+proptest! {
+    #[test]
+    fn i64_keys_sort_like_values(a in any::<i64>(), b in any::<i64>()) {
+        prop_assert_eq!(encode(a).cmp(&encode(b)), a.cmp(&b));
+    }
+
+    // ANY covers NaNs, infinities, zeros and subnormals as well as normal values
+    #[test]
+    fn f64_keys_sort_like_total_cmp(a in proptest::num::f64::ANY, b in proptest::num::f64::ANY) {
+        prop_assert_eq!(encode(a).cmp(&encode(b)), a.total_cmp(&b));
+    }
+
+    #[test]
+    fn numeric_keys_round_trip(i in any::<i64>(), u in any::<u32>(), f in proptest::num::f64::ANY) {
+        let key = KeyBuilder::new().push(&i).push(&u).push(&f).finish();
+        let mut reader = KeyReader::new(&key);
+        prop_assert_eq!(reader.read::<i64>().unwrap(), i);
+        prop_assert_eq!(reader.read::<u32>().unwrap(), u);
+        prop_assert_eq!(reader.read::<f64>().unwrap().to_bits(), f.to_bits());
+        prop_assert!(reader.is_empty());
+    }
+
+    // Bytes drawn mostly from around the escape byte, where the encoding could go wrong
+    #[test]
+    fn string_and_byte_keys_sort_like_values(
+        a in ".*",
+        b in ".*",
+        x in proptest::collection::vec(prop_oneof![Just(0u8), Just(1u8), Just(0xFFu8), any::<u8>()], 0..8),
+        y in proptest::collection::vec(prop_oneof![Just(0u8), Just(1u8), Just(0xFFu8), any::<u8>()], 0..8),
+    ) {
+        prop_assert_eq!(encode(a.clone()).cmp(&encode(b.clone())), a.cmp(&b));
+        prop_assert_eq!(encode(x.clone()).cmp(&encode(y.clone())), x.cmp(&y));
+        // A trailing component doesn't disturb the order of the leading one
+        let with_id = |bytes: &Vec<u8>, id: u64| KeyBuilder::new().push(bytes).push(&id).finish();
+        prop_assert_eq!(with_id(&x, 9).cmp(&with_id(&y, 1)), x.cmp(&y).then(9.cmp(&1)));
+        let key = with_id(&x, 9);
+        let mut reader = KeyReader::new(&key);
+        prop_assert_eq!(reader.read::<Vec<u8>>().unwrap(), x);
+        prop_assert_eq!(reader.read::<u64>().unwrap(), 9);
+    }
+
+    // Ints and floats equal as numbers may come in either order, as `sort_cmp` doesn't separate them
+    #[test]
+    fn value_keys_sort_like_sort_cmp(a in value_strategy(), b in value_strategy()) {
+        let ordering = a.sort_cmp(&b);
+        if ordering.is_ne() {
+            prop_assert_eq!(encode(a.clone()).cmp(&encode(b.clone())), ordering);
+        }
+        // Read back through the key, as NaN floats don't equal themselves
+        let key = KeyBuilder::new().push(&a).push(&7u64).finish();
+        let mut reader = KeyReader::new(&key);
+        prop_assert_eq!(encode(reader.read::<Value>().unwrap()), encode(a));
+        prop_assert_eq!(reader.read::<u64>().unwrap(), 7);
+    }
+
+    #[test]
+    fn composite_keys_round_trip(id in any::<i32>(), name in ".*", flag in any::<bool>()) {
+        let key = KeyBuilder::new().push(&id).push(&name).push(&flag).finish();
+        let mut reader = KeyReader::new(&key);
+        prop_assert_eq!(reader.read::<i32>().unwrap(), id);
+        prop_assert_eq!(reader.read::<String>().unwrap(), name);
+        prop_assert_eq!(reader.read::<bool>().unwrap(), flag);
+    }
+}
+
+fn value_strategy() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        prop_oneof![any::<i64>(), -3i64..3].prop_map(Value::Int),
+        prop_oneof![proptest::num::f64::ANY, (-3i64..3).prop_map(|i| i as f64)].prop_map(Value::Float),
+        "[a:\\x00]{0,4}".prop_map(Value::String),
+        (proptest::num::f64::ANY, proptest::num::f64::ANY).prop_map(|(lat, lon)| Value::Point { lat, lon }),
+    ];
+    leaf.prop_recursive(2, 8, 3, |inner| proptest::collection::vec(inner, 0..3).prop_map(Value::List))
+}
+
+#[test]
+fn test_numeric_boundaries_sort_in_order() {
+    let ints = [i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX];
+    let encoded: Vec<Vec<u8>> = ints.iter().map(|&i| encode(i)).collect();
+    assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+
+    let floats = [
+        f64::NEG_INFINITY,
+        f64::MIN,
+        -1.0,
+        -f64::MIN_POSITIVE,
+        -0.0,
+        0.0,
+        f64::MIN_POSITIVE,
+        1.0,
+        f64::MAX,
+        f64::INFINITY,
+        f64::NAN,
+    ];
+    let encoded: Vec<Vec<u8>> = floats.iter().map(|&f| encode(f)).collect();
+    assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn test_truncated_key_is_an_error() {
+    let key = encode(42u64);
+    let mut reader = KeyReader::new(&key[..5]);
+    let err = reader.read::<u64>().unwrap_err();
+    assert_eq!(err.to_string(), "Serialization error: key truncated: needed 8 more bytes, 5 left");
+    let source = std::error::Error::source(&err).and_then(|e| e.downcast_ref::<TruncatedKey>());
+    assert_eq!(source, Some(&TruncatedKey::Bytes { needed: 8, left: 5 }));
+
+    let key = encode("a\0b");
+    assert_eq!(key, b"a\0\xFFb\0\0");
+    for end in 0..key.len() {
+        assert!(KeyReader::new(&key[..end]).read::<String>().is_err(), "{:?}", &key[..end]);
+    }
+}
+
+#[tokio::test]
+async fn test_scan_decoded_parses_numeric_ids() {
+    use rust_db_core::{Database, DbError};
+    use rust_db_storage::LsmStorage;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap();
+    let prefix = encode("order");
+    for id in [70_000u64, 7, 300, 1] {
+        let key = KeyBuilder::new().push("order").push(&id).finish();
+        storage.put(&key, &id.to_le_bytes()).await.unwrap();
+    }
+    let order_id = |key: &[u8]| {
+        let mut reader = KeyReader::new(key);
+        reader.read::<String>()?;
+        let id = reader.read::<u64>()?;
+        if !reader.is_empty() {
+            return Err(DbError::Query("trailing key components".to_string()));
+        }
+        Ok(id)
+    };
+
+    let orders = storage.scan_decoded(&prefix, order_id).await.unwrap();
+    let ids: Vec<u64> = orders.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids, vec![1, 7, 300, 70_000]);
+    assert!(orders.iter().all(|(id, value)| value.as_slice() == id.to_le_bytes()));
+
+    let odd_key = KeyBuilder::new().push("order").push(&5u64).push("line").finish();
+    storage.put(&odd_key, b"").await.unwrap();
+    assert!(matches!(storage.scan_decoded(&prefix, order_id).await, Err(DbError::Query(_))));
+}
@@ -1,7 +1,12 @@
-use rust_db_core::{Database, Operator, Value, FieldAccess, Schema};
-use rust_db_query::QueryExt;
-use rust_db_storage::LsmStorage;
+use rust_db_core::{Database, DbError, KeyBuilder, Operator, Value, FieldAccess, Schema, TransactionContext};
+use rust_db_query::testing::InMemoryDb;
+use rust_db_query::{AggFn, CachingDatabase, CancellationToken, FilterExpr, QueryCache, QueryExt, QueryPlan, TransactionalQueryExt};
+use rust_db_storage::{IndexDescriptor, IndexType, LsmStorage, MvccLsmStorage};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tempfile::TempDir;
 
 // A test schema that implements Schema + FieldAccess manually
@@ -44,7 +49,16 @@ fn setup() -> (TempDir, LsmStorage) {
     (dir, storage)
 }
 
-async fn seed_users(storage: &LsmStorage) {
+// Runs a `check_*` function against the LSM engine and then `InMemoryDb`
+macro_rules! on_each_backend {
+    ($check:ident) => {{
+        let (_dir, storage) = setup();
+        $check(&storage).await;
+        $check(&InMemoryDb::new()).await;
+    }};
+}
+
+async fn seed_users<D: Database>(storage: &D) {
     let users = vec![
         TestUser { id: 1, name: "Alice".to_string(), age: 30, active: true },
         TestUser { id: 2, name: "Bob".to_string(), age: 25, active: false },
@@ -57,19 +71,20 @@ async fn seed_users(storage: &LsmStorage) {
     }
 }
 
-#[tokio::test]
-async fn test_query_all() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn check_query_all<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage.query::<TestUser>().execute().await.unwrap();
     assert_eq!(results.len(), 4);
 }
 
 #[tokio::test]
-async fn test_query_filter_gt() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn test_query_all() {
+    on_each_backend!(check_query_all);
+}
+
+async fn check_query_filter_gt<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -83,9 +98,12 @@ async fn test_query_filter_gt() {
 }
 
 #[tokio::test]
-async fn test_query_filter_eq() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn test_query_filter_gt() {
+    on_each_backend!(check_query_filter_gt);
+}
+
+async fn check_query_filter_eq<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -99,9 +117,12 @@ async fn test_query_filter_eq() {
 }
 
 #[tokio::test]
-async fn test_query_filter_bool() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn test_query_filter_eq() {
+    on_each_backend!(check_query_filter_eq);
+}
+
+async fn check_query_filter_bool<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -115,9 +136,12 @@ async fn test_query_filter_bool() {
 }
 
 #[tokio::test]
-async fn test_query_filter_contains() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn test_query_filter_bool() {
+    on_each_backend!(check_query_filter_bool);
+}
+
+async fn check_query_filter_contains<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -131,9 +155,12 @@ async fn test_query_filter_contains() {
 }
 
 #[tokio::test]
-async fn test_query_limit() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn test_query_filter_contains() {
+    on_each_backend!(check_query_filter_contains);
+}
+
+async fn check_query_limit<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -146,9 +173,117 @@ async fn test_query_limit() {
 }
 
 #[tokio::test]
-async fn test_query_combined_filters() {
+async fn test_query_limit() {
+    on_each_backend!(check_query_limit);
+}
+
+async fn check_group_by_aggregates<D: Database>(storage: &D) {
+    seed_users(storage).await;
+
+    let totals = storage
+        .query::<TestUser>()
+        .group_by("active")
+        .aggregate("age", AggFn::Sum)
+        .await
+        .unwrap();
+    let expected = HashMap::from([(Value::Bool(false), Value::Int(25)), (Value::Bool(true), Value::Int(93))]);
+    assert_eq!(totals, expected);
+
+    // Filters apply before grouping
+    let counts = storage
+        .query::<TestUser>()
+        .filter("age", Operator::Gt, Value::Int(26))
+        .group_by("active")
+        .aggregate("age", AggFn::Count)
+        .await
+        .unwrap();
+    assert_eq!(counts, HashMap::from([(Value::Bool(true), Value::Int(3))]));
+
+    let averages = storage
+        .query::<TestUser>()
+        .group_by("active")
+        .aggregate("age", AggFn::Avg)
+        .await
+        .unwrap();
+    assert_eq!(averages[&Value::Bool(true)], Value::Float(31.0));
+
+    let err = storage
+        .query::<TestUser>()
+        .group_by("active")
+        .aggregate("name", AggFn::Sum)
+        .await;
+    assert!(matches!(err, Err(DbError::Query(_))));
+}
+
+#[tokio::test]
+async fn test_group_by_aggregates() {
+    on_each_backend!(check_group_by_aggregates);
+}
+
+#[tokio::test]
+async fn test_query_limit_edges() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    seed_users(&db.inner).await;
+
+    let none = db.query::<TestUser>().limit(0).execute().await.unwrap();
+    assert!(none.is_empty());
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0, "limit(0) shouldn't scan");
+
+    let one = db.query::<TestUser>().limit(1).execute().await.unwrap();
+    assert_eq!(one.len(), 1);
+
+    let unmatched = db
+        .query::<TestUser>()
+        .filter("name", Operator::Eq, Value::String("Zed".to_string()))
+        .limit(1)
+        .execute()
+        .await
+        .unwrap();
+    assert!(unmatched.is_empty());
+}
+
+#[tokio::test]
+async fn test_query_cache_serves_repeats_until_a_write() {
     let (_dir, storage) = setup();
     seed_users(&storage).await;
+    let db = CachingDatabase::new(storage, Arc::new(QueryCache::new(16, Duration::from_secs(60))));
+
+    let active = || {
+        db.query::<TestUser>()
+            .filter("active", Operator::Eq, Value::Bool(true))
+            .filter("age", Operator::Gte, Value::Int(30))
+    };
+    let first = active().execute_cached(db.cache()).await.unwrap();
+    assert_eq!(first.len(), 2);
+
+    // A write that bypasses the cache shows the second run came from it; the
+    // filters are listed in the other order but normalize to the same query
+    let sneaky = TestUser { id: 5, name: "Eve".to_string(), age: 40, active: true };
+    db.inner().insert(b"TestUser:5", &sneaky).await.unwrap();
+    let second = db
+        .query::<TestUser>()
+        .filter("age", Operator::Gte, Value::Int(30))
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .execute_cached(db.cache())
+        .await
+        .unwrap();
+    assert_eq!(second, first);
+    assert_eq!((db.cache().hits(), db.cache().misses()), (1, 1));
+
+    let frank = TestUser { id: 6, name: "Frank".to_string(), age: 50, active: true };
+    db.insert(b"TestUser:6", &frank).await.unwrap();
+    let third = active().execute_cached(db.cache()).await.unwrap();
+    assert_eq!(third.len(), 4);
+    assert_eq!(db.cache().misses(), 2);
+}
+
+async fn check_query_combined_filters<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -164,9 +299,12 @@ async fn test_query_combined_filters() {
 }
 
 #[tokio::test]
-async fn test_query_no_results() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn test_query_combined_filters() {
+    on_each_backend!(check_query_combined_filters);
+}
+
+async fn check_query_no_results<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -179,9 +317,12 @@ async fn test_query_no_results() {
 }
 
 #[tokio::test]
-async fn test_query_startswith() {
-    let (_dir, storage) = setup();
-    seed_users(&storage).await;
+async fn test_query_no_results() {
+    on_each_backend!(check_query_no_results);
+}
+
+async fn check_query_startswith<D: Database>(storage: &D) {
+    seed_users(storage).await;
 
     let results = storage
         .query::<TestUser>()
@@ -195,9 +336,995 @@ async fn test_query_startswith() {
 }
 
 #[tokio::test]
-async fn test_query_empty_table() {
-    let (_dir, storage) = setup();
+async fn test_query_startswith() {
+    on_each_backend!(check_query_startswith);
+}
+
+async fn check_query_empty_table<D: Database>(storage: &D) {
     // No data seeded
     let results = storage.query::<TestUser>().execute().await.unwrap();
     assert_eq!(results.len(), 0);
 }
+
+#[tokio::test]
+async fn test_query_empty_table() {
+    on_each_backend!(check_query_empty_table);
+}
+
+// Filters, ordering and limits; run against each backend
+async fn check_query_builder_suite<D: Database>(db: &D) {
+    seed_users(db).await;
+    let ids = |users: Vec<TestUser>| users.into_iter().map(|u| u.id).collect::<Vec<_>>();
+
+    assert_eq!(ids(db.query::<TestUser>().execute().await.unwrap()), vec![1, 2, 3, 4]);
+    let older = db.query::<TestUser>().filter("age", Operator::Gt, Value::Int(28)).execute().await.unwrap();
+    assert_eq!(ids(older), vec![1, 3]);
+    let active = db
+        .query::<TestUser>()
+        .filter("age", Operator::Gte, Value::Int(28))
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(ids(active), vec![1, 3, 4]);
+    let names = db
+        .query::<TestUser>()
+        .filter("name", Operator::StartsWith, Value::String("Ch".to_string()))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(ids(names), vec![3]);
+    let youngest = db.query::<TestUser>().order_by("age").limit(2).execute().await.unwrap();
+    assert_eq!(ids(youngest), vec![2, 4]);
+    let (oldest, _) = db.query::<TestUser>().order_by_desc("age").limit(1).execute_with_plan().await.unwrap();
+    assert_eq!(ids(oldest), vec![3]);
+    assert!(db.query::<TestUser>().limit(0).execute().await.unwrap().is_empty());
+
+    db.delete(b"TestUser:2").await.unwrap();
+    assert_eq!(ids(db.query::<TestUser>().execute().await.unwrap()), vec![1, 3, 4]);
+}
+
+#[tokio::test]
+async fn test_query_builder_suite_on_both_backends() {
+    let (_dir, storage) = setup();
+    check_query_builder_suite(&storage).await;
+    check_query_builder_suite(&InMemoryDb::new()).await;
+}
+
+#[tokio::test]
+async fn test_in_memory_db_transaction_isolation() {
+    let db = InMemoryDb::new();
+    let alice = TestUser { id: 1, name: "Alice".to_string(), age: 30, active: true };
+    let bob = TestUser { id: 2, name: "Bob".to_string(), age: 25, active: false };
+    db.insert(b"TestUser:1", &alice).await.unwrap();
+
+    let mut tx = TransactionContext::new(&db).await.unwrap();
+    tx.transaction_mut().put(b"TestUser:2".to_vec(), bincode::serialize(&bob).unwrap()).unwrap();
+    // Written after the snapshot, so invisible to the transaction
+    let aged_alice = TestUser { age: 31, ..alice.clone() };
+    db.insert(b"TestUser:1", &aged_alice).await.unwrap();
+
+    let in_tx = db.query_within_transaction::<TestUser>(tx.transaction()).execute().await.unwrap();
+    assert_eq!(in_tx, vec![alice.clone(), bob.clone()]);
+    assert_eq!(db.query::<TestUser>().execute().await.unwrap(), vec![aged_alice.clone()]);
+    tx.commit().await.unwrap();
+    assert_eq!(db.query::<TestUser>().execute().await.unwrap(), vec![aged_alice, bob]);
+
+    // First committer wins
+    let mut first = TransactionContext::new(&db).await.unwrap();
+    let mut second = TransactionContext::new(&db).await.unwrap();
+    first.transaction_mut().delete(b"TestUser:1".to_vec()).unwrap();
+    second.transaction_mut().put(b"TestUser:1".to_vec(), bincode::serialize(&alice).unwrap()).unwrap();
+    first.commit().await.unwrap();
+    assert!(matches!(second.commit().await, Err(rust_db_core::DbError::TransactionConflict(_))));
+    assert_eq!(db.get::<TestUser>(b"TestUser:1").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_transactional_query_sees_pending_writes() {
+    let dir = TempDir::new().unwrap();
+    let storage = MvccLsmStorage::new(dir.path()).unwrap();
+    let alice = TestUser { id: 1, name: "Alice".to_string(), age: 30, active: true };
+    let bob = TestUser { id: 2, name: "Bob".to_string(), age: 25, active: false };
+    storage.insert(b"TestUser:1", &alice).await.unwrap();
+    storage.insert(b"TestUser:2", &bob).await.unwrap();
+
+    let mut tx = TransactionContext::new(&storage).await.unwrap();
+    let eve = TestUser { id: 3, name: "Eve".to_string(), age: 40, active: true };
+    tx.transaction_mut().put(b"TestUser:3".to_vec(), bincode::serialize(&eve).unwrap()).unwrap();
+    tx.transaction_mut().delete(b"TestUser:2".to_vec()).unwrap();
+
+    let in_tx = storage
+        .query_within_transaction::<TestUser>(tx.transaction())
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(in_tx, vec![alice.clone(), eve]);
+
+    // Nothing leaks outside the transaction before commit
+    let outside = storage.query::<TestUser>().execute().await.unwrap();
+    assert_eq!(outside, vec![alice, bob]);
+    tx.rollback().await.unwrap();
+}
+
+// Compares dotted version strings numerically, component by component
+fn version_collation(a: &Value, b: &Value) -> std::cmp::Ordering {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => {
+            let parse = |s: &str| s.split('.').map(|p| p.parse::<u64>().unwrap_or(0)).collect::<Vec<_>>();
+            parse(a).cmp(&parse(b))
+        }
+        _ => a.sort_cmp(b),
+    }
+}
+
+async fn seed_versions(storage: &LsmStorage) {
+    for (id, version) in [(1, "1.9"), (2, "1.10"), (3, "1.2")] {
+        let user = TestUser { id, name: version.to_string(), age: 1, active: true };
+        storage.insert(format!("TestUser:{}", id).as_bytes(), &user).await.unwrap();
+    }
+}
+
+fn names(users: &[TestUser]) -> Vec<&str> {
+    users.iter().map(|u| u.name.as_str()).collect()
+}
+
+#[tokio::test]
+async fn test_query_order_by_with_collation() {
+    let (_dir, storage) = setup();
+    seed_versions(&storage).await;
+
+    let default_order = storage.query::<TestUser>().order_by("name").execute().await.unwrap();
+    assert_eq!(names(&default_order), vec!["1.10", "1.2", "1.9"]);
+
+    let collated = storage
+        .query::<TestUser>()
+        .collate("name", version_collation)
+        .order_by("name")
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(names(&collated), vec!["1.2", "1.9", "1.10"]);
+
+    let newer = storage
+        .query::<TestUser>()
+        .collate("name", version_collation)
+        .filter("name", Operator::Gt, Value::String("1.9".to_string()))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(names(&newer), vec!["1.10"]);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Account {
+    id: u64,
+    balance: i64,
+}
+
+impl Schema for Account {
+    fn validate(&self) -> rust_db_core::Result<()> {
+        Ok(())
+    }
+    fn table_name() -> &'static str {
+        "Account"
+    }
+    fn indexes(&self) -> std::collections::HashMap<String, Vec<u8>> {
+        std::collections::HashMap::new()
+    }
+    fn key(&self) -> Vec<u8> {
+        KeyBuilder::new().push(&self.id).finish()
+    }
+}
+
+impl FieldAccess for Account {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "id" => Some(Value::Int(self.id as i64)),
+            "balance" => Some(Value::Int(self.balance)),
+            _ => None,
+        }
+    }
+}
+
+// Counts the rows handed back by scans, to see how much of the table a query reads
+struct CountingDb {
+    inner: LsmStorage,
+    rows_read: AtomicUsize,
+    // Most rows returned by a single scan
+    largest_read: AtomicUsize,
+}
+
+impl CountingDb {
+    fn record_read(&self, rows: usize) {
+        self.rows_read.fetch_add(rows, Ordering::SeqCst);
+        self.largest_read.fetch_max(rows, Ordering::SeqCst);
+    }
+}
+
+#[async_trait::async_trait]
+impl Database for CountingDb {
+    async fn insert<T: Serialize + Send + Sync>(&self, key: &[u8], value: &T) -> rust_db_core::Result<()> {
+        Database::insert(&self.inner, key, value).await
+    }
+    async fn get<T: serde::de::DeserializeOwned>(&self, key: &[u8]) -> rust_db_core::Result<Option<T>> {
+        Database::get(&self.inner, key).await
+    }
+    async fn delete(&self, key: &[u8]) -> rust_db_core::Result<()> {
+        Database::delete(&self.inner, key).await
+    }
+    async fn scan(&self, prefix: &[u8]) -> rust_db_core::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let rows = self.inner.scan(prefix).await?;
+        self.record_read(rows.len());
+        Ok(rows)
+    }
+    async fn scan_range(&self, start: &[u8], end: &[u8]) -> rust_db_core::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let rows = self.inner.scan_range(start, end).await?;
+        self.record_read(rows.len());
+        Ok(rows)
+    }
+    async fn scan_range_limited(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        limit: usize,
+    ) -> rust_db_core::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let rows = self.inner.scan_range_limited(start, end, limit).await?;
+        self.record_read(rows.len());
+        Ok(rows)
+    }
+    async fn scan_covering_index(
+        &self,
+        table: &str,
+        field: &str,
+    ) -> rust_db_core::Result<Option<Vec<(Value, Vec<u8>)>>> {
+        self.inner.scan_covering_index(table, field).await
+    }
+    async fn scan_covering_index_after(
+        &self,
+        table: &str,
+        field: &str,
+        after: Option<(&Value, &[u8])>,
+        limit: usize,
+    ) -> rust_db_core::Result<Option<Vec<(Value, Vec<u8>)>>> {
+        self.inner.scan_covering_index_after(table, field, after, limit).await
+    }
+    async fn lookup_index_keys(
+        &self,
+        table: &str,
+        field: &str,
+        values: &[Value],
+    ) -> rust_db_core::Result<Option<Vec<Vec<u8>>>> {
+        self.inner.lookup_index_keys(table, field, values).await
+    }
+}
+
+#[tokio::test]
+async fn test_query_key_range_reads_only_the_range() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    for id in 0..1000u64 {
+        let account = Account { id, balance: id as i64 % 7 };
+        let mut key = b"Account:".to_vec();
+        key.extend(account.key());
+        db.insert(&key, &account).await.unwrap();
+        // Spread the rows over several SSTables as well as the memtable
+        if id % 300 == 299 {
+            db.inner.flush().await.unwrap();
+        }
+    }
+
+    let accounts = db.query::<Account>().key_range(&100u64, &200u64).execute().await.unwrap();
+    let ids: Vec<u64> = accounts.iter().map(|a| a.id).collect();
+    assert_eq!(ids, (100..200).collect::<Vec<u64>>());
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 100);
+
+    // Other filters still apply to the rows in range
+    let zero_balance = db
+        .query::<Account>()
+        .key_range(&100u64, &200u64)
+        .filter("balance", Operator::Eq, Value::Int(0))
+        .execute()
+        .await
+        .unwrap();
+    assert!(zero_balance.iter().all(|a| a.balance == 0 && (100..200).contains(&a.id)));
+    assert_eq!(zero_balance.len(), 14);
+}
+
+#[tokio::test]
+async fn test_select_indexed_field_reads_no_records() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    db.inner
+        .create_index(IndexDescriptor::new("idx_user_name", "name", IndexType::BTree))
+        .await
+        .unwrap();
+    db.inner.index_records::<TestUser>("idx_user_name", b"TestUser:").unwrap();
+    seed_users(&db.inner).await;
+    let extra = TestUser { id: 5, name: "Alicia".to_string(), age: 41, active: false };
+    db.insert(b"TestUser:5", &extra).await.unwrap();
+
+    let names = db
+        .query::<TestUser>()
+        .filter("name", Operator::StartsWith, Value::String("Ali".to_string()))
+        .order_by("name")
+        .select(&["name"])
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(
+        names,
+        vec![vec![Value::String("Alice".to_string())], vec![Value::String("Alicia".to_string())]]
+    );
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0, "answered from the index");
+
+    // Updates and deletes take their old values out of the index
+    let renamed = TestUser { name: "Bea".to_string(), ..extra };
+    db.insert(b"TestUser:5", &renamed).await.unwrap();
+    db.delete(b"TestUser:1").await.unwrap();
+    let names = db
+        .query::<TestUser>()
+        .order_by("name")
+        .select(&["name"])
+        .execute()
+        .await
+        .unwrap();
+    let expected: Vec<Vec<Value>> = ["Bea", "Bob", "Charlie", "Diana"]
+        .iter()
+        .map(|name| vec![Value::String(name.to_string())])
+        .collect();
+    assert_eq!(names, expected);
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0, "answered from the index");
+
+    // A filter on another field needs the records
+    let active = db
+        .query::<TestUser>()
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .select(&["name"])
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(active.len(), 2);
+    assert!(db.rows_read.load(Ordering::SeqCst) > 0);
+}
+
+#[tokio::test]
+async fn test_batched_scan_matches_single_scan() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    // Spread over two SSTables and the memtable, with overwrites and deletes
+    for id in 0..1000u64 {
+        let user = TestUser { id, name: format!("user{}", id), age: (id % 60) as u32, active: id % 3 == 0 };
+        db.insert(format!("TestUser:{:04}", id).as_bytes(), &user).await.unwrap();
+        if id == 400 || id == 800 {
+            db.inner.flush().await.unwrap();
+        }
+    }
+    for id in (0..1000u64).step_by(7) {
+        let user = TestUser { id, name: format!("user{}", id), age: 59, active: true };
+        db.insert(format!("TestUser:{:04}", id).as_bytes(), &user).await.unwrap();
+    }
+    for id in (0..1000u64).step_by(11) {
+        db.delete(format!("TestUser:{:04}", id).as_bytes()).await.unwrap();
+    }
+
+    let single = db
+        .query::<TestUser>()
+        .filter("age", Operator::Gt, Value::Int(50))
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .execute()
+        .await
+        .unwrap();
+    assert!(!single.is_empty());
+
+    db.largest_read.store(0, Ordering::SeqCst);
+    let batched = db
+        .query::<TestUser>()
+        .filter("age", Operator::Gt, Value::Int(50))
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .batch_size(16)
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(batched, single);
+    assert_eq!(db.largest_read.load(Ordering::SeqCst), 16);
+
+    // The limit stops the batches early
+    db.rows_read.store(0, Ordering::SeqCst);
+    let first = db.query::<TestUser>().limit(3).batch_size(16).execute().await.unwrap();
+    assert_eq!(first.len(), 3);
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 16);
+}
+
+#[tokio::test]
+async fn test_cancelled_scan_stops_early() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    for id in 0..5000u64 {
+        let user = TestUser { id, name: format!("user{}", id), age: (id % 60) as u32, active: true };
+        db.insert(format!("TestUser:{:05}", id).as_bytes(), &user).await.unwrap();
+    }
+
+    // Cancelled once a few batches are in, from a future sharing the task
+    let token = CancellationToken::new();
+    let query = db
+        .query::<TestUser>()
+        .filter("age", Operator::Gt, Value::Int(100))
+        .batch_size(100)
+        .cancel_on(token.clone())
+        .execute();
+    let cancel = async {
+        while db.rows_read.load(Ordering::SeqCst) < 300 {
+            tokio::task::yield_now().await;
+        }
+        token.cancel();
+    };
+    let started = std::time::Instant::now();
+    let (result, ()) = tokio::join!(query, cancel);
+    assert!(matches!(&result, Err(DbError::Query(msg)) if msg == "cancelled"), "{:?}", result);
+    assert!(db.rows_read.load(Ordering::SeqCst) < 1000);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // Without a batch size it still reads in batches; a token cancelled up
+    // front stops it before the first
+    db.rows_read.store(0, Ordering::SeqCst);
+    let token = CancellationToken::new();
+    token.cancel();
+    let result = db.query::<TestUser>().cancel_on(token).execute().await;
+    assert!(matches!(result, Err(DbError::Query(_))));
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0);
+
+    let all = db.query::<TestUser>().cancel_on(CancellationToken::new()).execute().await.unwrap();
+    assert_eq!(all.len(), 5000);
+    assert_eq!(db.largest_read.load(Ordering::SeqCst), 1024);
+}
+
+#[tokio::test]
+async fn test_limited_order_by_indexed_field_skips_sort() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    db.inner
+        .create_index(IndexDescriptor::new("idx_user_age", "age", IndexType::BTree))
+        .await
+        .unwrap();
+    db.inner.index_records::<TestUser>("idx_user_age", b"TestUser:").unwrap();
+    for id in 0..200u64 {
+        let user = TestUser { id, name: format!("user{}", id), age: 20 + (id * 37 % 50) as u32, active: id % 2 == 0 };
+        db.insert(format!("TestUser:{:03}", id).as_bytes(), &user).await.unwrap();
+    }
+    // Move entries around: a deleted youngest user and one who aged
+    db.delete(b"TestUser:000").await.unwrap();
+    let aged = TestUser { id: 50, name: "user50".to_string(), age: 90, active: true };
+    db.insert(b"TestUser:050", &aged).await.unwrap();
+
+    let (youngest, plan) = db
+        .query::<TestUser>()
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .order_by("age")
+        .limit(5)
+        .execute_with_plan()
+        .await
+        .unwrap();
+    assert_eq!(plan, QueryPlan { ordered_by_index: true, sorted_in_memory: false, index_lookup: false });
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0, "no table scan");
+
+    let expected = db
+        .inner
+        .query::<TestUser>()
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .order_by("age")
+        .limit(5)
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(youngest, expected);
+
+    let (oldest, plan) = db.query::<TestUser>().order_by_desc("age").limit(1).execute_with_plan().await.unwrap();
+    assert!(plan.ordered_by_index && !plan.sorted_in_memory);
+    assert_eq!(oldest, vec![aged]);
+
+    // Equal ages keep record-key order when descending too
+    let (oldest, _) = db.query::<TestUser>().order_by_desc("age").limit(10).execute_with_plan().await.unwrap();
+    let expected = db.inner.query::<TestUser>().order_by_desc("age").limit(10).execute().await.unwrap();
+    assert_eq!(oldest, expected);
+
+    // Without a limit the whole table is read, so it's sorted in memory
+    let (all, plan) = db.query::<TestUser>().order_by("age").execute_with_plan().await.unwrap();
+    assert_eq!(plan, QueryPlan { ordered_by_index: false, sorted_in_memory: true, index_lookup: false });
+    assert_eq!(all.len(), 199);
+}
+
+#[tokio::test]
+async fn test_tenants_are_isolated() {
+    let (_dir, storage) = setup();
+    let acme = storage.tenant("acme");
+    let globex = storage.tenant("globex");
+    // Would share a prefix with "acme" without the terminated namespace
+    let acme_eu = storage.tenant("acme:eu");
+
+    let alice = TestUser { id: 1, name: "Alice".to_string(), age: 30, active: true };
+    let bob = TestUser { id: 1, name: "Bob".to_string(), age: 40, active: false };
+    acme.insert(b"TestUser:1", &alice).await.unwrap();
+    globex.insert(b"TestUser:1", &bob).await.unwrap();
+    acme.insert(b"TestUser:2", &bob).await.unwrap();
+
+    assert_eq!(acme.get::<TestUser>(b"TestUser:1").await.unwrap(), Some(alice.clone()));
+    assert_eq!(globex.get::<TestUser>(b"TestUser:1").await.unwrap(), Some(bob.clone()));
+    assert_eq!(acme_eu.get::<TestUser>(b"TestUser:1").await.unwrap(), None);
+    assert_eq!(Database::get::<TestUser>(&storage, b"TestUser:1").await.unwrap(), None);
+
+    let keys: Vec<Vec<u8>> = acme.scan(b"TestUser:").await.unwrap().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"TestUser:1".to_vec(), b"TestUser:2".to_vec()]);
+    assert!(acme_eu.scan(b"").await.unwrap().is_empty());
+
+    let globex_users = globex.query::<TestUser>().execute().await.unwrap();
+    assert_eq!(globex_users, vec![bob.clone()]);
+    let acme_active = acme
+        .query::<TestUser>()
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(acme_active, vec![alice]);
+
+    globex.delete(b"TestUser:1").await.unwrap();
+    assert_eq!(globex.get::<TestUser>(b"TestUser:1").await.unwrap(), None);
+    assert_eq!(acme.query::<TestUser>().execute().await.unwrap().len(), 2);
+}
+
+// Counts how often the `bio` field is read, to see how many rows a filter on it saw
+static BIO_READS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Profile {
+    id: u64,
+    bio: String,
+}
+
+impl Schema for Profile {
+    fn validate(&self) -> rust_db_core::Result<()> {
+        Ok(())
+    }
+    fn table_name() -> &'static str {
+        "Profile"
+    }
+    fn indexes(&self) -> std::collections::HashMap<String, Vec<u8>> {
+        std::collections::HashMap::new()
+    }
+}
+
+impl FieldAccess for Profile {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "id" => Some(Value::Int(self.id as i64)),
+            "bio" => {
+                BIO_READS.fetch_add(1, Ordering::SeqCst);
+                Some(Value::String(self.bio.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+async fn check_cheap_filters_run_before_substring_filters<D: Database>(storage: &D) {
+    for id in 0..50u64 {
+        let profile = Profile { id, bio: format!("writes rust, profile {}", id) };
+        storage.insert(format!("Profile:{}", id).as_bytes(), &profile).await.unwrap();
+    }
+    let query = || {
+        storage
+            .query::<Profile>()
+            .filter("bio", Operator::Contains, Value::String("rust".to_string()))
+            .filter("id", Operator::Gte, Value::Int(10))
+            .filter("id", Operator::Eq, Value::Int(12))
+    };
+
+    BIO_READS.store(0, Ordering::SeqCst);
+    let in_given_order = query().preserve_filter_order().execute().await.unwrap();
+    assert_eq!(BIO_READS.load(Ordering::SeqCst), 50);
+
+    BIO_READS.store(0, Ordering::SeqCst);
+    let reordered = query().execute().await.unwrap();
+    // Only the row that passed the equality filter got as far as the substring match
+    assert_eq!(BIO_READS.load(Ordering::SeqCst), 1);
+    assert_eq!(reordered, in_given_order);
+    assert_eq!(reordered.len(), 1);
+    assert_eq!(reordered[0].id, 12);
+}
+
+#[tokio::test]
+async fn test_cheap_filters_run_before_substring_filters() {
+    on_each_backend!(check_cheap_filters_run_before_substring_filters);
+}
+
+async fn check_inapplicable_filter_is_an_error<D: Database>(storage: &D) {
+    seed_users(storage).await;
+
+    let result = storage
+        .query::<TestUser>()
+        .filter("age", Operator::Contains, Value::Int(1))
+        .execute()
+        .await;
+    match result {
+        Err(rust_db_core::DbError::Query(message)) => {
+            assert!(message.contains("Contains") && message.contains("age"), "{}", message)
+        }
+        other => panic!("expected a query error, got {:?}", other),
+    }
+    assert!(storage
+        .query::<TestUser>()
+        .filter("active", Operator::Gt, Value::Bool(true))
+        .limit(0)
+        .execute()
+        .await
+        .is_err());
+
+    // Equality applies to any value, and ranges to any value once collated
+    assert_eq!(storage.query::<TestUser>().filter("active", Operator::Eq, Value::Bool(true)).execute().await.unwrap().len(), 3);
+    storage
+        .query::<TestUser>()
+        .filter("name", Operator::Gt, Value::String("B".to_string()))
+        .collate("name", |a, b| a.sort_cmp(b))
+        .validate()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_inapplicable_filter_is_an_error() {
+    on_each_backend!(check_inapplicable_filter_is_an_error);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct User {
+    id: u64,
+    email: String,
+}
+
+impl Schema for User {
+    fn validate(&self) -> rust_db_core::Result<()> {
+        Ok(())
+    }
+    fn table_name() -> &'static str {
+        "User"
+    }
+    fn indexes(&self) -> std::collections::HashMap<String, Vec<u8>> {
+        std::collections::HashMap::new()
+    }
+}
+
+impl FieldAccess for User {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "id" => Some(Value::Int(self.id as i64)),
+            "email" => Some(Value::String(self.email.clone())),
+            _ => None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_in_filter_on_indexed_field_reads_only_listed_records() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    db.inner
+        .create_index(IndexDescriptor::new("idx_user_email", "email", IndexType::Hash))
+        .await
+        .unwrap();
+    db.inner.index_records::<User>("idx_user_email", b"User:").unwrap();
+    for id in 0..100u64 {
+        let user = User { id, email: format!("user{}@example.com", id) };
+        db.insert(format!("User:{:03}", id).as_bytes(), &user).await.unwrap();
+    }
+    // User 7 changed address, which takes them out of the index under the old one
+    let moved = User { id: 7, email: "moved@example.com".to_string() };
+    db.insert(b"User:007", &moved).await.unwrap();
+
+    let emails = ["user42@example.com", "user7@example.com", "user3@example.com", "user42@example.com"];
+    let values: Vec<Value> = emails.iter().map(|e| Value::String(e.to_string())).collect();
+    let candidates = db.inner.lookup_index_keys("User", "email", &values).await.unwrap().unwrap();
+    // User 42 once, and no one for the old address
+    assert_eq!(candidates.len(), 2);
+    let wanted = Value::List(values);
+
+    let (users, plan) = db
+        .query::<User>()
+        .filter("email", Operator::In, wanted.clone())
+        .execute_with_plan()
+        .await
+        .unwrap();
+    assert!(plan.index_lookup);
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0, "no table scan");
+    let ids: Vec<u64> = users.iter().map(|u| u.id).collect();
+    assert_eq!(ids, vec![3, 42]);
+
+    // Every way of running the query goes through the index
+    let one = db
+        .query::<User>()
+        .filter("email", Operator::Eq, Value::String("user42@example.com".to_string()))
+        .one()
+        .await
+        .unwrap();
+    assert_eq!(one.id, 42);
+    let selected = db.query::<User>().filter("email", Operator::In, wanted.clone()).select(&["id"]).execute().await.unwrap();
+    assert_eq!(selected, vec![vec![Value::Int(3)], vec![Value::Int(42)]]);
+    let plain = db.query::<User>().filter("email", Operator::In, wanted).execute().await.unwrap();
+    assert_eq!(plain, users);
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0, "no table scan");
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, rust_db_schema::Schema)]
+struct Address {
+    street: String,
+    city: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, rust_db_schema::Schema)]
+struct Resident {
+    id: u64,
+    #[nested]
+    address: Address,
+}
+
+#[tokio::test]
+async fn test_index_on_nested_field_path() {
+    let dir = TempDir::new().unwrap();
+    let db = CountingDb {
+        inner: LsmStorage::new(dir.path()).unwrap(),
+        rows_read: AtomicUsize::new(0),
+        largest_read: AtomicUsize::new(0),
+    };
+    db.inner
+        .create_index(IndexDescriptor::new("idx_resident_city", "address.city", IndexType::Hash))
+        .await
+        .unwrap();
+    db.inner.index_records::<Resident>("idx_resident_city", b"Resident:").unwrap();
+    let cities = ["Lisbon", "Oslo", "Quito"];
+    for id in 0..30u64 {
+        let address = Address { street: format!("{} Main St", id), city: cities[id as usize % 3].to_string() };
+        db.insert(format!("Resident:{:02}", id).as_bytes(), &Resident { id, address }).await.unwrap();
+    }
+
+    let resident = Resident { id: 1, address: Address { street: "x".to_string(), city: "Oslo".to_string() } };
+    assert_eq!(resident.get_path("address.city"), Some(Value::String("Oslo".to_string())));
+    assert_eq!(resident.get_path("address.zip"), None);
+    assert_eq!(resident.get_field("address"), None);
+
+    let oslo = Value::String("Oslo".to_string());
+    let (residents, plan) = db
+        .query::<Resident>()
+        .filter("address.city", Operator::Eq, oslo.clone())
+        .execute_with_plan()
+        .await
+        .unwrap();
+    assert!(plan.index_lookup);
+    assert_eq!(db.rows_read.load(Ordering::SeqCst), 0, "no table scan");
+    let ids: Vec<u64> = residents.iter().map(|r| r.id).collect();
+    assert_eq!(ids, (0..30).filter(|id| id % 3 == 1).collect::<Vec<_>>());
+
+    // The scan path resolves the same path
+    let scanned = db.inner.query::<Resident>().filter("address.city", Operator::Eq, oslo).execute().await.unwrap();
+    assert_eq!(scanned, residents);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Contact {
+    id: u64,
+    phone: Option<String>,
+}
+
+impl Schema for Contact {
+    fn validate(&self) -> rust_db_core::Result<()> {
+        Ok(())
+    }
+    fn table_name() -> &'static str {
+        "Contact"
+    }
+    fn indexes(&self) -> std::collections::HashMap<String, Vec<u8>> {
+        std::collections::HashMap::new()
+    }
+}
+
+impl FieldAccess for Contact {
+    fn get_field(&self, field_name: &str) -> Option<Value> {
+        match field_name {
+            "id" => Some(Value::Int(self.id as i64)),
+            "phone" => Some(self.phone.clone().map_or(Value::Null, Value::String)),
+            _ => None,
+        }
+    }
+}
+
+async fn check_filter_null_and_not_null<D: Database>(storage: &D) {
+    for id in 0..6u64 {
+        let phone = (id % 2 == 0).then(|| format!("555-010{}", id));
+        storage.insert(format!("Contact:{}", id).as_bytes(), &Contact { id, phone }).await.unwrap();
+    }
+    let ids = |contacts: Vec<Contact>| contacts.iter().map(|c| c.id).collect::<Vec<_>>();
+
+    let without = storage.query::<Contact>().filter_null("phone").execute().await.unwrap();
+    assert_eq!(ids(without), vec![1, 3, 5]);
+    let with = storage.query::<Contact>().filter_not_null("phone").execute().await.unwrap();
+    assert_eq!(ids(with), vec![0, 2, 4]);
+
+    // A field the schema doesn't have reads as null
+    let missing = storage.query::<Contact>().filter_null("email").execute().await.unwrap();
+    assert_eq!(missing.len(), 6);
+    assert!(storage.query::<Contact>().filter_not_null("email").execute().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_filter_null_and_not_null() {
+    on_each_backend!(check_filter_null_and_not_null);
+    let ids = |contacts: Vec<Contact>| contacts.iter().map(|c| c.id).collect::<Vec<_>>();
+
+    // Transactional queries agree, pending writes included
+    let db = InMemoryDb::new();
+    let mut tx = TransactionContext::new(&db).await.unwrap();
+    for id in 0..4u64 {
+        let contact = Contact { id, phone: (id < 2).then(|| "555-0100".to_string()) };
+        let key = format!("Contact:{}", id).into_bytes();
+        tx.transaction_mut().put(key, bincode::serialize(&contact).unwrap()).unwrap();
+    }
+    let without = db.query_within_transaction::<Contact>(tx.transaction()).filter_null("phone").execute().await.unwrap();
+    assert_eq!(ids(without), vec![2, 3]);
+    let with = db.query_within_transaction::<Contact>(tx.transaction()).filter_not_null("phone").execute().await.unwrap();
+    assert_eq!(ids(with), vec![0, 1]);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, rust_db_schema::Schema)]
+struct Author {
+    id: u64,
+    first: String,
+    last: String,
+}
+
+async fn check_filter_on_virtual_field<D: Database>(storage: &D) {
+    let names = [("Ada", "Lovelace"), ("Alan", "Turing"), ("Grace", "Hopper"), ("Ada", "Yonath")];
+    for (id, (first, last)) in names.iter().enumerate() {
+        let author = Author { id: id as u64, first: first.to_string(), last: last.to_string() };
+        storage.insert(format!("Author:{}", id).as_bytes(), &author).await.unwrap();
+    }
+    let full_name = |author: &Author| Value::String(format!("{} {}", author.first, author.last));
+
+    let found = storage
+        .query::<Author>()
+        .virtual_field("full_name", full_name)
+        .filter("full_name", Operator::Eq, Value::String("Ada Lovelace".to_string()))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(found.iter().map(|a| a.id).collect::<Vec<_>>(), vec![0]);
+
+    // Ordering and projection see it too
+    let rows = storage
+        .query::<Author>()
+        .virtual_field("full_name", full_name)
+        .filter("full_name", Operator::StartsWith, Value::String("Ada ".to_string()))
+        .order_by_desc("full_name")
+        .select(&["full_name", "id"])
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            vec![Value::String("Ada Yonath".to_string()), Value::Int(3)],
+            vec![Value::String("Ada Lovelace".to_string()), Value::Int(0)],
+        ]
+    );
+
+    // Without the virtual field there's nothing by that name to match
+    let unknown = storage
+        .query::<Author>()
+        .filter("full_name", Operator::Eq, Value::String("Ada Lovelace".to_string()))
+        .execute()
+        .await
+        .unwrap();
+    assert!(unknown.is_empty());
+}
+
+#[tokio::test]
+async fn test_filter_on_virtual_field() {
+    on_each_backend!(check_filter_on_virtual_field);
+}
+
+async fn check_one_and_optional_enforce_cardinality<D: Database>(storage: &D) {
+    seed_users(storage).await;
+    let by_name = |name: &str| storage.query::<TestUser>().filter("name", Operator::Eq, Value::String(name.to_string()));
+
+    assert_eq!(by_name("Bob").one().await.unwrap().id, 2);
+    assert_eq!(by_name("Bob").optional().await.unwrap().map(|user| user.id), Some(2));
+
+    assert!(matches!(by_name("Zed").one().await, Err(DbError::Query(_))));
+    assert_eq!(by_name("Zed").optional().await.unwrap(), None);
+
+    let active = || storage.query::<TestUser>().filter("active", Operator::Eq, Value::Bool(true));
+    assert!(matches!(active().one().await, Err(DbError::Query(_))));
+    assert!(matches!(active().optional().await, Err(DbError::Query(_))));
+}
+
+#[tokio::test]
+async fn test_one_and_optional_enforce_cardinality() {
+    on_each_backend!(check_one_and_optional_enforce_cardinality);
+}
+
+async fn check_filter_expressions_parse_to_their_programmatic_equivalent<D: Database>(storage: &D) {
+    seed_users(storage).await;
+    let ids = |mut users: Vec<TestUser>| {
+        users.sort_by_key(|user| user.id);
+        users.into_iter().map(|user| user.id).collect::<Vec<_>>()
+    };
+    let run = |expr: FilterExpr| storage.query::<TestUser>().filter_expr(expr).execute();
+
+    let age = |op, n| FilterExpr::filter("age", op, Value::Int(n));
+    let name = |op, s: &str| FilterExpr::filter("name", op, Value::String(s.to_string()));
+    let active = FilterExpr::filter("active", Operator::Eq, Value::Bool(true));
+    let cases = vec![
+        ("age > 25 AND name CONTAINS \"li\"", age(Operator::Gt, 25).and(name(Operator::Contains, "li")), vec![1, 3]),
+        // AND binds tighter than OR
+        ("age < 26 OR age >= 30 AND active = true", age(Operator::Lt, 26).or(age(Operator::Gte, 30).and(active.clone())), vec![1, 2, 3]),
+        ("(age < 26 OR age >= 30) AND active == true", age(Operator::Lt, 26).or(age(Operator::Gte, 30)).and(active.clone()), vec![1, 3]),
+        ("NOT active = true OR name STARTSWITH 'D'", (!active.clone()).or(name(Operator::StartsWith, "D")), vec![2, 4]),
+        ("not (age <= 28 and active = TRUE)", !(age(Operator::Lte, 28).and(active.clone())), vec![1, 2, 3]),
+        ("name IN ('Alice', \"Bob\") AND age != 30", FilterExpr::filter("name", Operator::In, Value::List(vec![Value::String("Alice".into()), Value::String("Bob".into())])).and(age(Operator::Ne, 30)), vec![2]),
+        ("email IS NULL AND age IS NOT NULL", FilterExpr::filter("email", Operator::IsNull, Value::Null).and(FilterExpr::filter("age", Operator::IsNotNull, Value::Null)), vec![1, 2, 3, 4]),
+    ];
+    for (input, programmatic, expected) in cases {
+        let parsed = FilterExpr::parse(input).unwrap();
+        assert_eq!(ids(run(parsed).await.unwrap()), expected, "{}", input);
+        assert_eq!(ids(run(programmatic).await.unwrap()), expected, "{}", input);
+    }
+
+    // Expressions combine with plain filters
+    let users = storage
+        .query::<TestUser>()
+        .filter("active", Operator::Eq, Value::Bool(true))
+        .filter_expr("age = 30 OR age = 28".parse().unwrap())
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(ids(users), vec![1, 4]);
+
+    for (input, position) in [
+        ("age > ", "position 6"),
+        ("age >> 3", "position 5"),
+        ("(age > 3", "position 8"),
+        ("name = \"open", "position 7"),
+        ("age > 3 active = true", "position 8"),
+        ("age ~ 3", "position 4"),
+    ] {
+        match FilterExpr::parse(input) {
+            Err(DbError::Query(message)) => assert!(message.contains(position), "{}: {}", input, message),
+            other => panic!("{} parsed as {:?}", input, other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_filter_expressions_parse_to_their_programmatic_equivalent() {
+    on_each_backend!(check_filter_expressions_parse_to_their_programmatic_equivalent);
+}
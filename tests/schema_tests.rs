@@ -0,0 +1,248 @@
+use rust_db_core::{Database, KeyBuilder, Operator, Schema, Value};
+use rust_db_query::QueryExt;
+use rust_db_schema::{IntoValueEnum, Schema};
+use rust_db_storage::LsmStorage;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Schema)]
+struct Transfer {
+    #[key]
+    from_account: u64,
+    #[key]
+    to_account: u64,
+    #[key]
+    timestamp: u64,
+    amount: f64,
+}
+
+fn record_key<T: Schema>(row: &T) -> Vec<u8> {
+    let mut key = format!("{}:", T::table_name()).into_bytes();
+    key.extend(row.key());
+    key
+}
+
+#[test]
+fn test_composite_key_declaration_order() {
+    let transfer = Transfer { from_account: 1, to_account: 2, timestamp: 3, amount: 10.0 };
+    let expected = KeyBuilder::new().push(&1u64).push(&2u64).push(&3u64).finish();
+    assert_eq!(transfer.key(), expected);
+}
+
+#[test]
+fn test_composite_key_preserves_order() {
+    let a = Transfer { from_account: 1, to_account: 9, timestamp: 500, amount: 0.0 };
+    let b = Transfer { from_account: 2, to_account: 1, timestamp: 1, amount: 0.0 };
+    let c = Transfer { from_account: 2, to_account: 1, timestamp: 256, amount: 0.0 };
+    assert!(a.key() < b.key());
+    assert!(b.key() < c.key());
+}
+
+#[tokio::test]
+async fn test_composite_key_prefix_scan() {
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap();
+
+    let transfers = vec![
+        Transfer { from_account: 1, to_account: 2, timestamp: 100, amount: 5.0 },
+        Transfer { from_account: 1, to_account: 3, timestamp: 50, amount: 7.5 },
+        Transfer { from_account: 2, to_account: 1, timestamp: 75, amount: 1.0 },
+        Transfer { from_account: 256, to_account: 1, timestamp: 10, amount: 2.0 },
+    ];
+    for transfer in &transfers {
+        storage.insert(&record_key(transfer), transfer).await.unwrap();
+    }
+
+    // Scan on the leading component only
+    let mut prefix = b"Transfer:".to_vec();
+    prefix.extend(KeyBuilder::new().push(&1u64).finish());
+    let results = storage.scan(&prefix).await.unwrap();
+
+    let found: Vec<Transfer> = results
+        .iter()
+        .map(|(_, v)| bincode::deserialize(v).unwrap())
+        .collect();
+    assert_eq!(found, vec![transfers[0].clone(), transfers[1].clone()]);
+}
+
+fn live_transfers(rows: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<Transfer> {
+    rows.into_iter()
+        .filter(|(_, v)| !v.is_empty())
+        .map(|(_, v)| bincode::deserialize(&v).unwrap())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_replace_table_swaps_rows_in_one_step() {
+    let dir = TempDir::new().unwrap();
+    let storage = std::sync::Arc::new(LsmStorage::new(dir.path()).unwrap());
+
+    let old: Vec<Transfer> = (0..50u64)
+        .map(|i| Transfer { from_account: i, to_account: 0, timestamp: i, amount: 1.0 })
+        .collect();
+    for (i, transfer) in old.iter().enumerate() {
+        storage.insert(&record_key(transfer), transfer).await.unwrap();
+        // Leave some of the old rows in SSTables and the rest in the memtable
+        if i == 24 {
+            storage.flush().await.unwrap();
+        }
+    }
+    let new: Vec<Transfer> = (25..75u64)
+        .map(|i| Transfer { from_account: i, to_account: 0, timestamp: i, amount: 2.0 })
+        .collect();
+
+    let reader = {
+        let storage = std::sync::Arc::clone(&storage);
+        tokio::spawn(async move {
+            loop {
+                let rows = live_transfers(storage.scan(b"Transfer:").await.unwrap());
+                assert_eq!(rows.len(), 50, "saw a partial swap");
+                let amounts: Vec<f64> = rows.iter().map(|t| t.amount).collect();
+                assert!(amounts.iter().all(|&a| a == amounts[0]), "saw old and new rows together");
+                if amounts[0] == 2.0 {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+    };
+
+    storage.replace_table(new.clone()).await.unwrap();
+    reader.await.unwrap();
+
+    assert_eq!(live_transfers(storage.scan(b"Transfer:").await.unwrap()), new);
+    let replaced: Option<Transfer> = Database::get(&*storage, &record_key(&new[0])).await.unwrap();
+    assert_eq!(replaced, Some(new[0].clone()));
+    let removed: Option<Transfer> = Database::get(&*storage, &record_key(&old[0])).await.unwrap();
+    assert_eq!(removed, None);
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, IntoValueEnum)]
+enum Status {
+    Pending,
+    Shipped,
+    Delivered,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Schema)]
+struct Order {
+    #[key]
+    id: u64,
+    #[index]
+    status: Status,
+}
+
+#[tokio::test]
+async fn test_enum_field_queried_by_variant_name() {
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap();
+
+    let orders = vec![
+        Order { id: 1, status: Status::Pending },
+        Order { id: 2, status: Status::Shipped },
+        Order { id: 3, status: Status::Delivered },
+        Order { id: 4, status: Status::Shipped },
+    ];
+    for order in &orders {
+        storage.insert(&record_key(order), order).await.unwrap();
+    }
+
+    let shipped = storage
+        .query::<Order>()
+        .filter("status", Operator::Eq, Value::String("Shipped".to_string()))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(shipped, vec![orders[1].clone(), orders[3].clone()]);
+
+    let not_pending = storage
+        .query::<Order>()
+        .filter("status", Operator::Ne, Value::String("Pending".to_string()))
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(not_pending.len(), 3);
+}
+
+// A second `Transfer`, so it shares the table name but not the shape
+mod reshaped {
+    use rust_db_schema::Schema;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Schema)]
+    pub struct Transfer {
+        #[key]
+        pub id: u64,
+        pub amount: String,
+    }
+}
+
+#[tokio::test]
+async fn test_schema_validation_rejects_a_differently_shaped_row() {
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap().with_schema_validation();
+    assert_ne!(Transfer::fingerprint(), reshaped::Transfer::fingerprint());
+
+    let transfer = Transfer { from_account: 1, to_account: 2, timestamp: 3, amount: 10.0 };
+    storage.insert_row(&transfer).await.unwrap();
+    storage.insert_row(&Transfer { timestamp: 4, ..transfer.clone() }).await.unwrap();
+
+    let reshaped = reshaped::Transfer { id: 7, amount: "ten".to_string() };
+    match storage.insert_row(&reshaped).await {
+        Err(rust_db_core::DbError::Schema(message)) => assert!(message.contains("amount:String"), "{}", message),
+        other => panic!("expected a schema error, got {:?}", other),
+    }
+    assert!(storage.replace_table(vec![reshaped.clone()]).await.is_err());
+
+    // Plain inserts under the table are held to its fields too
+    assert!(Database::insert(&storage, &record_key(&reshaped), &reshaped).await.is_err());
+    assert!(Database::insert(&storage, b"Transfer:raw", &42u64).await.is_err());
+    Database::insert(&storage, &record_key(&Transfer { timestamp: 5, ..transfer.clone() }), &transfer).await.unwrap();
+    Database::insert(&storage, b"Other:raw", &42u64).await.unwrap();
+
+    // The fingerprint is kept outside the keyspace
+    assert_eq!(storage.scan(b"Transfer:").await.unwrap().len(), 3);
+    assert_eq!(storage.scan(b"").await.unwrap().len(), 4);
+    let stored: Option<Transfer> = Database::get(&storage, &record_key(&transfer)).await.unwrap();
+    assert_eq!(stored, Some(transfer));
+
+    // and outlives the process
+    drop(storage);
+    let storage = LsmStorage::new(dir.path()).unwrap().with_schema_validation();
+    assert!(storage.insert_row(&reshaped).await.is_err());
+    drop(storage);
+
+    // Validation is opt-in
+    let storage = LsmStorage::new(dir.path()).unwrap();
+    storage.insert_row(&reshaped).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_first_writers_of_a_table_agree_on_its_schema() {
+    let dir = TempDir::new().unwrap();
+    let storage = std::sync::Arc::new(LsmStorage::new(dir.path()).unwrap().with_schema_validation());
+
+    let mut writers = Vec::new();
+    for i in 0..8u64 {
+        let storage = std::sync::Arc::clone(&storage);
+        writers.push(tokio::spawn(async move {
+            if i % 2 == 0 {
+                storage.insert_row(&Transfer { from_account: i, to_account: 0, timestamp: 0, amount: 1.0 }).await.is_ok()
+            } else {
+                storage.insert_row(&reshaped::Transfer { id: i, amount: "one".to_string() }).await.is_ok()
+            }
+        }));
+    }
+    let mut accepted = Vec::new();
+    for (i, writer) in writers.into_iter().enumerate() {
+        if writer.await.unwrap() {
+            accepted.push(i % 2);
+        }
+    }
+
+    // Only one shape got in, whichever came first
+    accepted.sort();
+    accepted.dedup();
+    assert_eq!(accepted.len(), 1, "both shapes were accepted");
+    assert_eq!(storage.scan(b"Transfer:").await.unwrap().len(), 4);
+}
@@ -33,13 +33,36 @@ async fn test_overwrite_key() {
 }
 
 #[tokio::test]
-async fn test_delete_tombstone() {
-    let (_dir, storage) = temp_storage();
-    storage.put(b"key", b"value").await.unwrap();
-    // Delete via tombstone (empty value)
-    storage.put(b"key", &[]).await.unwrap();
-    let val = storage.get(b"key").await.unwrap();
-    assert_eq!(val, Some(vec![])); // Tombstone returns empty
+async fn test_empty_value_is_not_a_tombstone() {
+    let dir = TempDir::new().unwrap();
+    {
+        let storage = LsmStorage::new(dir.path()).unwrap();
+        storage.put(b"empty", b"value").await.unwrap();
+        storage.put(b"empty", &[]).await.unwrap();
+        storage.put(b"deleted", b"value").await.unwrap();
+        storage.delete(b"deleted").await.unwrap();
+        storage.insert(b"unit", &()).await.unwrap();
+
+        assert_eq!(storage.get(b"empty").await.unwrap(), Some(vec![]));
+        assert_eq!(storage.get(b"deleted").await.unwrap(), None);
+        assert_eq!(Database::get::<()>(&storage, b"unit").await.unwrap(), Some(()));
+        let keys: Vec<Vec<u8>> = storage.scan(b"").await.unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![b"empty".to_vec(), b"unit".to_vec()]);
+
+        storage.flush().await.unwrap();
+        assert_eq!(storage.get(b"empty").await.unwrap(), Some(vec![]));
+        assert_eq!(storage.get(b"deleted").await.unwrap(), None);
+        assert_eq!(Database::get::<()>(&storage, b"unit").await.unwrap(), Some(()));
+
+        // Left in the WAL for the reopen below
+        storage.put(b"logged", &[]).await.unwrap();
+        storage.delete(b"empty").await.unwrap();
+    }
+
+    let storage = LsmStorage::new(dir.path()).unwrap();
+    assert_eq!(storage.get(b"logged").await.unwrap(), Some(vec![]));
+    assert_eq!(storage.get(b"empty").await.unwrap(), None);
+    assert_eq!(storage.get(b"deleted").await.unwrap(), None);
 }
 
 #[tokio::test]
@@ -86,9 +109,9 @@ async fn test_delete_via_trait() {
     let (_dir, storage) = temp_storage();
     storage.insert(b"key", &42u32).await.unwrap();
     Database::delete(&storage, b"key").await.unwrap();
-    // After delete (tombstone), scan should show empty value
+    // The tombstone reads as no value at all
     let raw = storage.get(b"key").await.unwrap();
-    assert_eq!(raw, Some(vec![]));
+    assert_eq!(raw, None);
 }
 
 #[tokio::test]
@@ -115,3 +138,1083 @@ async fn test_wal_exists_after_write() {
     let wal_path = dir.path().join("wal.bin");
     assert!(wal_path.exists(), "WAL file should exist after write");
 }
+
+#[tokio::test]
+async fn test_negative_cache_invalidated_on_write() {
+    let (_dir, storage) = temp_storage();
+
+    assert_eq!(storage.get(b"ghost").await.unwrap(), None);
+    assert_eq!(storage.negative_cache_hits(), 0);
+    assert_eq!(storage.get(b"ghost").await.unwrap(), None);
+    assert_eq!(storage.negative_cache_hits(), 1);
+
+    storage.put(b"ghost", b"boo").await.unwrap();
+    assert_eq!(storage.get(b"ghost").await.unwrap(), Some(b"boo".to_vec()));
+    assert_eq!(storage.negative_cache_hits(), 1);
+}
+
+#[tokio::test]
+async fn test_negative_cache_invalidated_on_delete() {
+    let (_dir, storage) = temp_storage();
+
+    assert_eq!(storage.get(b"gone").await.unwrap(), None);
+    Database::delete(&storage, b"gone").await.unwrap();
+    // The tombstone is found in the memtable, not a cached miss. The delete's
+    // own lookup of the value it replaces may have been one.
+    let hits = storage.negative_cache_hits();
+    assert_eq!(storage.get(b"gone").await.unwrap(), None);
+    assert_eq!(storage.negative_cache_hits(), hits);
+}
+
+#[tokio::test]
+async fn test_flush_returns_sstable_meta() {
+    let (_dir, storage) = temp_storage();
+    assert!(storage.flush().await.unwrap().is_none());
+
+    for key in [b"k3", b"k1", b"k5", b"k2"] {
+        storage.put(key, b"v").await.unwrap();
+    }
+    storage.put(b"k1", b"updated").await.unwrap();
+
+    let meta = storage.flush().await.unwrap().expect("memtable was not empty");
+    assert_eq!(meta.entry_count, 4);
+    assert_eq!(meta.min_key, b"k1".to_vec());
+    assert_eq!(meta.max_key, b"k5".to_vec());
+    assert_eq!(meta.level, 0);
+    assert!(meta.path.exists());
+    assert!(storage.flush().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_lookup_unknown_index_errors() {
+    use rust_db_core::{DbError, Value};
+    use rust_db_storage::{IndexDescriptor, IndexType};
+
+    let (_dir, storage) = temp_storage();
+    storage
+        .create_index(IndexDescriptor::new("idx_user_email", "email", IndexType::Hash))
+        .await
+        .unwrap();
+
+    let value = Value::String("bob@example.com".to_string());
+    let found = storage.get_by_index::<String>("idx_user_email", &value).await.unwrap();
+    assert!(found.is_empty());
+
+    let result = storage.get_by_index::<String>("idx_user_emial", &value).await;
+    assert!(matches!(result, Err(DbError::Query(msg)) if msg == "index not found: idx_user_emial"));
+}
+
+#[tokio::test]
+async fn test_wal_replay_discards_torn_batch() {
+    use rust_db_storage::{WalEntry, WriteAheadLog};
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("wal.bin");
+    let entries: Vec<WalEntry> = (0..500)
+        .map(|i| WalEntry::new(format!("key{}", i).as_bytes(), b"value"))
+        .collect();
+    {
+        let mut wal = WriteAheadLog::new(&path).unwrap();
+        wal.write_entry(b"before", b"1").unwrap();
+        wal.write_batch(&entries).unwrap();
+    }
+    assert_eq!(WriteAheadLog::replay(&path).unwrap().len(), 501);
+
+    // Cut the file in the middle of the 500-entry batch
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len / 2).unwrap();
+
+    let replayed = WriteAheadLog::replay(&path).unwrap();
+    assert_eq!(replayed.len(), 1);
+    assert_eq!(replayed[0].key, b"before".to_vec());
+}
+
+#[tokio::test]
+async fn test_write_batch_applies_all_entries() {
+    use rust_db_storage::WriteBatch;
+
+    let (_dir, storage) = temp_storage();
+    storage.put(b"gone", b"x").await.unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1").put(b"b", b"2").delete(b"gone");
+    storage.write_batch(&batch).await.unwrap();
+
+    assert_eq!(storage.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+    assert_eq!(storage.get(b"b").await.unwrap(), Some(b"2".to_vec()));
+    assert_eq!(storage.get(b"gone").await.unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_counter_merges() {
+    use rust_db_storage::CounterMergeOperator;
+    use std::sync::Arc;
+
+    let dir = TempDir::new().unwrap();
+    let storage = Arc::new(
+        LsmStorage::new(dir.path())
+            .unwrap()
+            .with_merge_operator(Arc::new(CounterMergeOperator)),
+    );
+    storage.put(b"hits", &CounterMergeOperator::encode(10)).await.unwrap();
+
+    let mut handles = Vec::new();
+    for task in 0..8 {
+        let storage = Arc::clone(&storage);
+        handles.push(tokio::spawn(async move {
+            for i in 0..100 {
+                storage.merge(b"hits", &CounterMergeOperator::encode(1)).await.unwrap();
+                // Flushing midway folds the operands seen so far into an SSTable value
+                if task == 0 && i == 50 {
+                    storage.flush().await.unwrap();
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    // Read through the inherent methods rather than the `Database` impl for `Arc`
+    let storage: &LsmStorage = &storage;
+    let total = storage.get(b"hits").await.unwrap().unwrap();
+    assert_eq!(CounterMergeOperator::decode(&total).unwrap(), 810);
+    let scanned = storage.scan(b"hits").await.unwrap();
+    assert_eq!(scanned, vec![(b"hits".to_vec(), total)]);
+
+    storage.flush().await.unwrap();
+    let flushed = storage.get(b"hits").await.unwrap().unwrap();
+    assert_eq!(CounterMergeOperator::decode(&flushed).unwrap(), 810);
+}
+
+#[tokio::test]
+async fn test_merge_without_operator_errors() {
+    let (_dir, storage) = temp_storage();
+    assert!(storage.merge(b"key", b"operand").await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_writers_and_readers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    const WRITERS: usize = 8;
+    const KEYS_PER_WRITER: usize = 2000;
+    let dir = TempDir::new().unwrap();
+    let storage = Arc::new(LsmStorage::new(dir.path()).unwrap());
+    // Per writer, how many of its keys are known to be written
+    let progress: Arc<Vec<AtomicUsize>> = Arc::new((0..WRITERS).map(|_| AtomicUsize::new(0)).collect());
+
+    let started = Instant::now();
+    let mut writers = Vec::new();
+    for w in 0..WRITERS {
+        let storage = Arc::clone(&storage);
+        let progress = Arc::clone(&progress);
+        writers.push(tokio::spawn(async move {
+            for i in 0..KEYS_PER_WRITER {
+                let key = format!("w{}:{:05}", w, i);
+                storage.put(key.as_bytes(), key.as_bytes()).await.unwrap();
+                progress[w].store(i + 1, Ordering::SeqCst);
+                // Swap the memtable out from under the other writers and readers
+                if w == 0 && i % 500 == 499 {
+                    storage.flush().await.unwrap();
+                }
+            }
+        }));
+    }
+
+    let mut readers = Vec::new();
+    for r in 0..4 {
+        let storage = Arc::clone(&storage);
+        let progress = Arc::clone(&progress);
+        readers.push(tokio::spawn(async move {
+            let mut checked = 0;
+            while progress.iter().any(|p| p.load(Ordering::SeqCst) < KEYS_PER_WRITER) {
+                let w = (checked + r) % WRITERS;
+                let written = progress[w].load(Ordering::SeqCst);
+                if written > 0 {
+                    // Anything a writer has finished must be readable right away
+                    let key = format!("w{}:{:05}", w, written - 1);
+                    let found = LsmStorage::get(&storage, key.as_bytes()).await.unwrap();
+                    assert_eq!(found, Some(key.into_bytes()));
+                    let prefix = format!("w{}:", w);
+                    assert!(storage.scan(prefix.as_bytes()).await.unwrap().len() >= written);
+                }
+                checked += 1;
+                tokio::task::yield_now().await;
+            }
+            checked
+        }));
+    }
+
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    for reader in readers {
+        assert!(reader.await.unwrap() > 0);
+    }
+    let elapsed = started.elapsed();
+
+    for w in 0..WRITERS {
+        let rows = storage.scan(format!("w{}:", w).as_bytes()).await.unwrap();
+        assert_eq!(rows.len(), KEYS_PER_WRITER);
+        assert!(rows.iter().all(|(k, v)| k == v));
+    }
+    // Generous bound: only catches writers and readers stalling on each other
+    assert!(elapsed < Duration::from_secs(60), "took {:?}", elapsed);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_reads_never_miss_a_key_while_it_flushes() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let dir = TempDir::new().unwrap();
+    let storage = Arc::new(LsmStorage::new(dir.path()).unwrap());
+    let done = Arc::new(AtomicBool::new(false));
+    // Written before any reader starts, so a miss can only come from a flush
+    storage.put(b"watched", b"value").await.unwrap();
+
+    let mut readers = Vec::new();
+    for _ in 0..3 {
+        let storage = Arc::clone(&storage);
+        let done = Arc::clone(&done);
+        readers.push(tokio::spawn(async move {
+            // The inherent `get`, rather than the `Database` impl for `Arc`
+            let storage: &LsmStorage = &storage;
+            let mut reads = 0;
+            while !done.load(Ordering::SeqCst) {
+                let value = storage.get(b"watched").await.unwrap();
+                assert!(value.is_some(), "key went missing during a flush");
+                reads += 1;
+                tokio::task::yield_now().await;
+            }
+            reads
+        }));
+    }
+
+    // Each round leaves the key only in the memtable, then moves it through the
+    // frozen list into a fresh SSTable
+    let storage: &LsmStorage = &storage;
+    for round in 0..50 {
+        storage.put(b"watched", format!("value{}", round).as_bytes()).await.unwrap();
+        storage.flush().await.unwrap();
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+        assert!(reader.await.unwrap() > 0);
+    }
+    assert_eq!(storage.pending_flushes(), 0);
+    assert_eq!(storage.get(b"watched").await.unwrap(), Some(b"value49".to_vec()));
+}
+
+#[tokio::test]
+async fn test_wal_on_separate_path() {
+    use rust_db_storage::StorageConfig;
+
+    let data_dir = TempDir::new().unwrap();
+    let wal_dir = TempDir::new().unwrap();
+    let wal_path = wal_dir.path().join("logs").join("db.wal");
+    let config = StorageConfig::new(data_dir.path()).with_wal_path(&wal_path);
+    {
+        let storage = LsmStorage::open(config.clone()).unwrap();
+        assert_eq!(storage.wal_path(), wal_path.as_path());
+        storage.put(b"flushed", b"1").await.unwrap();
+        storage.flush().await.unwrap();
+        storage.put(b"unflushed", b"2").await.unwrap();
+    }
+
+    assert!(wal_path.exists());
+    assert!(!data_dir.path().join("wal.bin").exists());
+    assert_eq!(std::fs::read_dir(data_dir.path().join("L0")).unwrap().count(), 1);
+    assert_eq!(std::fs::read_dir(wal_dir.path()).unwrap().count(), 1, "only the WAL directory");
+
+    // Recovery reads the configured WAL
+    let storage = LsmStorage::open(config).unwrap();
+    assert_eq!(storage.get(b"flushed").await.unwrap(), Some(b"1".to_vec()));
+    assert_eq!(storage.get(b"unflushed").await.unwrap(), Some(b"2".to_vec()));
+}
+
+#[tokio::test]
+async fn test_unknown_sstable_version_is_rejected() {
+    let dir = TempDir::new().unwrap();
+    {
+        let storage = LsmStorage::new(dir.path()).unwrap();
+        storage.put(b"key", b"value").await.unwrap();
+        storage.flush().await.unwrap();
+    }
+    let table = std::fs::read_dir(dir.path().join("L0")).unwrap().next().unwrap().unwrap().path();
+
+    // Rewrite the footer's format version (the 4 bytes before the magic) as 3
+    let mut bytes = std::fs::read(&table).unwrap();
+    let version_at = bytes.len() - 8;
+    assert_eq!(&bytes[version_at..version_at + 4], &2u32.to_le_bytes());
+    bytes[version_at..version_at + 4].copy_from_slice(&3u32.to_le_bytes());
+    std::fs::write(&table, bytes).unwrap();
+
+    match LsmStorage::new(dir.path()) {
+        Err(rust_db_core::DbError::Storage(message, _)) => {
+            assert!(message.contains("unsupported sstable version 3"), "{}", message)
+        }
+        Err(other) => panic!("expected a storage error, got {:?}", other),
+        Ok(_) => panic!("a v3 table opened with a v2 reader"),
+    }
+}
+
+#[tokio::test]
+async fn test_estimated_key_count_bounds_exact_count() {
+    let (_dir, storage) = temp_storage();
+    for i in 0..100 {
+        storage.put(format!("key{:03}", i).as_bytes(), b"v1").await.unwrap();
+    }
+    storage.flush().await.unwrap();
+    // Overwrite a quarter of the keys and delete a tenth, half of it after a flush
+    for i in 0..25 {
+        storage.put(format!("key{:03}", i).as_bytes(), b"v2").await.unwrap();
+    }
+    for i in 90..95 {
+        storage.delete(format!("key{:03}", i).as_bytes()).await.unwrap();
+    }
+    storage.flush().await.unwrap();
+    for i in 95..100 {
+        storage.delete(format!("key{:03}", i).as_bytes()).await.unwrap();
+    }
+
+    assert_eq!(storage.exact_key_count().await.unwrap(), 90);
+    let estimate = storage.estimated_key_count();
+    assert!((90..=135).contains(&estimate), "estimate {} out of range", estimate);
+
+    // The same counts through `Database`, and an engine without its own
+    // estimate falls back on the exact count
+    assert_eq!(Database::exact_key_count(&storage).await.unwrap(), 90);
+    assert_eq!(Database::estimated_key_count(&storage).await.unwrap(), estimate);
+    let db = rust_db_query::testing::InMemoryDb::new();
+    for i in 0..10u32 {
+        db.insert(format!("key{:03}", i).as_bytes(), &i).await.unwrap();
+    }
+    db.delete(b"key000").await.unwrap();
+    assert_eq!(db.exact_key_count().await.unwrap(), 9);
+    assert_eq!(db.estimated_key_count().await.unwrap(), 9);
+}
+
+#[tokio::test]
+async fn test_put_that_fills_memtable_flushes_in_background() {
+    let (dir, storage) = temp_storage();
+    let value = vec![7u8; 64 * 1024];
+
+    // The put that fills the memtable returns with its SSTable still unwritten;
+    // the flush runs on the blocking pool and finishes some time later
+    let mut written = 0;
+    while storage.pending_flushes() == 0 {
+        storage.put(format!("key{:03}", written).as_bytes(), &value).await.unwrap();
+        written += 1;
+        assert!(written < 100, "memtable never filled");
+    }
+
+    // Readable whether or not the flush has finished, next to writes that land
+    // after the swap
+    storage.put(b"after", b"swap").await.unwrap();
+    for i in 0..written {
+        assert_eq!(storage.get(format!("key{:03}", i).as_bytes()).await.unwrap(), Some(value.clone()));
+    }
+    assert_eq!(storage.scan(b"key").await.unwrap().len(), written);
+
+    for _ in 0..500 {
+        if storage.pending_flushes() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(storage.pending_flushes(), 0);
+    assert_eq!(storage.get_sstables_at_level(0).len(), 1);
+    for i in 0..written {
+        assert_eq!(storage.get(format!("key{:03}", i).as_bytes()).await.unwrap(), Some(value.clone()));
+    }
+    assert_eq!(storage.get(b"after").await.unwrap(), Some(b"swap".to_vec()));
+
+    // The flushed writes' WAL segment is gone; only the live WAL is left
+    let logs: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("wal.bin"))
+        .collect();
+    assert_eq!(logs, vec!["wal.bin".to_string()]);
+}
+
+#[tokio::test]
+async fn test_sstable_writer_keeps_newest_duplicate() {
+    use rust_db_storage::{HashFn, SSTable};
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("sst_1.bin");
+    let entries = vec![
+        (b"b".to_vec(), b"b-old".to_vec(), 10),
+        (b"a".to_vec(), b"a-only".to_vec(), 5),
+        (b"b".to_vec(), b"b-new".to_vec(), 30),
+        (b"c".to_vec(), b"c-new".to_vec(), 40),
+        (b"b".to_vec(), b"b-mid".to_vec(), 20),
+        (b"c".to_vec(), b"c-old".to_vec(), 1),
+    ];
+    let sstable = SSTable::from_entries(&path, entries, HashFn::default()).unwrap();
+    // The index is read back from the file, so this counts entries on disk
+    assert_eq!(sstable.len(), 3);
+
+    let contents: Vec<(Vec<u8>, Vec<u8>, u64)> = sstable
+        .iter()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(key, v)| (key, v.value, v.timestamp))
+        .collect();
+    assert_eq!(
+        contents,
+        vec![
+            (b"a".to_vec(), b"a-only".to_vec(), 5),
+            (b"b".to_vec(), b"b-new".to_vec(), 30),
+            (b"c".to_vec(), b"c-new".to_vec(), 40),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_subscribers_receive_writes_in_order() {
+    use rust_db_storage::{ChangeKind, WriteBatch};
+
+    let (_dir, storage) = temp_storage();
+    storage.put(b"before", b"unseen").await.unwrap();
+    let mut changes = storage.subscribe();
+
+    storage.put(b"a", b"1").await.unwrap();
+    storage.put(b"b", b"2").await.unwrap();
+    storage.delete(b"a").await.unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"c", b"3").delete(b"b");
+    storage.write_batch(&batch).await.unwrap();
+
+    let mut received = Vec::new();
+    let mut last_timestamp = 0;
+    while let Some(event) = changes.try_recv() {
+        assert!(event.timestamp > last_timestamp);
+        last_timestamp = event.timestamp;
+        received.push((event.key, event.kind));
+    }
+    assert_eq!(
+        received,
+        vec![
+            (b"a".to_vec(), ChangeKind::Put),
+            (b"b".to_vec(), ChangeKind::Put),
+            (b"a".to_vec(), ChangeKind::Delete),
+            (b"c".to_vec(), ChangeKind::Put),
+            (b"b".to_vec(), ChangeKind::Delete),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_lock_retries_end_in_timeout() {
+    use rust_db_storage::{LockConfig, StorageConfig};
+    use std::time::{Duration, Instant};
+
+    let config = LockConfig { max_retries: 4, base_backoff: Duration::from_millis(5) };
+
+    // Never free, so every retry backs off
+    let started = Instant::now();
+    match config.acquire(|| None::<()>).await {
+        Err(rust_db_core::DbError::Storage(message, _)) => assert_eq!(message, "lock timeout"),
+        other => panic!("expected a lock timeout, got {:?}", other),
+    }
+    // 5 + 10 + 20 + 40ms before jitter
+    assert!(started.elapsed() >= Duration::from_millis(75));
+    // Free on the last try
+    let mut tries = 0;
+    assert!(config.acquire(|| { tries += 1; (tries == 5).then_some(()) }).await.is_ok());
+
+    // Writes go through the configured retries, and succeed when uncontended
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::open(StorageConfig::new(dir.path()).with_lock_config(config)).unwrap();
+    storage.put(b"key", b"value").await.unwrap();
+    assert_eq!(storage.get(b"key").await.unwrap(), Some(b"value".to_vec()));
+}
+
+#[tokio::test]
+async fn test_read_repair_copies_shadowed_values_forward() {
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap().with_read_repair();
+    for version in ["v1", "v2"] {
+        storage.put(b"layered", version.as_bytes()).await.unwrap();
+        storage.put(b"scanned", version.as_bytes()).await.unwrap();
+        storage.flush().await.unwrap();
+    }
+    storage.put(b"single", b"only").await.unwrap();
+    storage.flush().await.unwrap();
+
+    // A key in one table has nothing to repair
+    assert_eq!(storage.get(b"single").await.unwrap(), Some(b"only".to_vec()));
+    assert!(storage.flush().await.unwrap().is_none());
+
+    // The memtable now holds the resolved values, so a flush writes them out
+    assert_eq!(storage.get(b"layered").await.unwrap(), Some(b"v2".to_vec()));
+    assert_eq!(storage.scan(b"scanned").await.unwrap(), vec![(b"scanned".to_vec(), b"v2".to_vec())]);
+    let repaired = storage.flush().await.unwrap().unwrap();
+    assert_eq!(repaired.entry_count, 2);
+    assert_eq!(storage.get(b"layered").await.unwrap(), Some(b"v2".to_vec()));
+
+    // A newer write isn't overwritten by a repair racing it
+    storage.put(b"layered", b"v3").await.unwrap();
+    assert_eq!(storage.scan(b"layered").await.unwrap(), vec![(b"layered".to_vec(), b"v3".to_vec())]);
+    assert_eq!(storage.get(b"layered").await.unwrap(), Some(b"v3".to_vec()));
+}
+
+#[tokio::test]
+async fn test_restore_from_full_and_incremental_backups() {
+    use rust_db_storage::BackupManifest;
+
+    let dir = TempDir::new().unwrap();
+    let backups = TempDir::new().unwrap();
+    let (full_dir, incremental_dir) = (backups.path().join("full"), backups.path().join("incr"));
+    let storage = LsmStorage::new(dir.path()).unwrap();
+
+    storage.put(b"a", b"1").await.unwrap();
+    storage.put(b"b", b"1").await.unwrap();
+    let full = storage.backup(&full_dir).await.unwrap();
+    assert_eq!(full.base, None);
+    assert_eq!(full.included, full.tables);
+
+    storage.put(b"b", b"2").await.unwrap();
+    storage.put(b"c", b"2").await.unwrap();
+    rust_db_core::Database::delete(&storage, b"a").await.unwrap();
+    storage.flush().await.unwrap();
+    storage.put(b"d", b"unflushed").await.unwrap();
+    let incremental = storage.backup_incremental(&incremental_dir, &full).await.unwrap();
+    assert_eq!(incremental.base, Some(full.id));
+    // Only the tables flushed since the full backup are copied
+    assert_eq!(incremental.included.len(), 2);
+    assert!(incremental.included.iter().all(|table| !full.tables.contains(table)));
+    assert_eq!(BackupManifest::read(&incremental_dir).unwrap(), incremental);
+
+    let restored_dir = TempDir::new().unwrap();
+    let target = restored_dir.path().join("db");
+    // Incrementals can't be restored without their base
+    assert!(LsmStorage::restore(&[&incremental_dir], &target).is_err());
+    LsmStorage::restore(&[&full_dir, &incremental_dir], &target).unwrap();
+
+    let restored = LsmStorage::new(&target).unwrap();
+    assert_eq!(restored.scan(b"").await.unwrap(), storage.scan(b"").await.unwrap());
+    assert_eq!(restored.get(b"a").await.unwrap(), None);
+    assert_eq!(restored.get(b"b").await.unwrap(), Some(b"2".to_vec()));
+    assert_eq!(restored.get(b"d").await.unwrap(), Some(b"unflushed".to_vec()));
+}
+
+#[tokio::test]
+async fn test_disabled_wal_is_never_written() {
+    use rust_db_storage::{StorageConfig, WriteBatch};
+
+    let dir = TempDir::new().unwrap();
+    let config = StorageConfig::new(dir.path()).without_wal();
+    let wal_path = config.resolved_wal_path();
+    let wal_len = || std::fs::metadata(&wal_path).map(|m| m.len()).unwrap_or(0);
+    {
+        let storage = LsmStorage::open(config.clone()).unwrap();
+        storage.put(b"a", b"1").await.unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2");
+        storage.write_batch(&batch).await.unwrap();
+        assert_eq!(wal_len(), 0);
+        assert_eq!(storage.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(storage.get(b"b").await.unwrap(), Some(b"2".to_vec()));
+
+        storage.flush().await.unwrap();
+        storage.put(b"c", b"3").await.unwrap();
+        assert_eq!(wal_len(), 0);
+        // Dropped without a flush, as in a crash
+    }
+
+    // Flushed writes survive; the rest had no log to replay
+    let storage = LsmStorage::open(config).unwrap();
+    assert_eq!(storage.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+    assert_eq!(storage.get(b"c").await.unwrap(), None);
+
+    // The WAL is on by default
+    let dir = TempDir::new().unwrap();
+    let logged = LsmStorage::new(dir.path()).unwrap();
+    logged.put(b"a", b"1").await.unwrap();
+    assert!(std::fs::metadata(dir.path().join("wal.bin")).unwrap().len() > 0);
+}
+
+#[tokio::test]
+async fn test_prefix_bloom_skips_tables_without_the_prefix() {
+    use rust_db_storage::StorageConfig;
+
+    let dir = TempDir::new().unwrap();
+    let config = StorageConfig::new(dir.path()).with_prefix_bloom(6);
+    let storage = LsmStorage::open(config.clone()).unwrap();
+    // One table per prefix, then one holding two of them
+    for table in ["users:", "order:", "items:"] {
+        for i in 0..50 {
+            storage.put(format!("{}{:03}", table, i).as_bytes(), b"row").await.unwrap();
+        }
+        storage.flush().await.unwrap();
+    }
+    storage.put(b"users:999", b"row").await.unwrap();
+    storage.put(b"items:999", b"row").await.unwrap();
+    storage.flush().await.unwrap();
+
+    let probes = |storage: &LsmStorage| storage.sstables_scanned();
+    let before = probes(&storage);
+    assert_eq!(storage.scan(b"order:").await.unwrap().len(), 50);
+    assert_eq!(probes(&storage) - before, 1);
+
+    let before = probes(&storage);
+    assert_eq!(storage.scan(b"users:").await.unwrap().len(), 51);
+    assert_eq!(probes(&storage) - before, 2);
+
+    // Longer prefixes are checked on their first six bytes
+    let before = probes(&storage);
+    assert_eq!(storage.scan(b"items:04").await.unwrap().len(), 10);
+    assert_eq!(probes(&storage) - before, 2);
+    let cursor = storage.scan_cursor(b"order:01").unwrap().collect().await.unwrap();
+    assert_eq!(cursor.len(), 10);
+
+    // Shorter prefixes, and prefixes no table has, behave as before
+    let before = probes(&storage);
+    assert_eq!(storage.scan(b"u").await.unwrap().len(), 51);
+    assert_eq!(probes(&storage) - before, 4);
+    let before = probes(&storage);
+    assert!(storage.scan(b"carts:").await.unwrap().is_empty());
+    assert_eq!(probes(&storage) - before, 0);
+
+    // Tables found on reopen get their filters too
+    drop(storage);
+    let reopened = LsmStorage::open(config).unwrap();
+    let before = probes(&reopened);
+    assert_eq!(reopened.scan(b"order:").await.unwrap().len(), 50);
+    assert_eq!(probes(&reopened) - before, 1);
+}
+
+#[tokio::test]
+async fn test_rebuild_from_archived_wal() {
+    use rust_db_storage::StorageConfig;
+
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::open(StorageConfig::new(dir.path()).with_wal_archive()).unwrap();
+    for round in 0..3u32 {
+        for i in 0..20u32 {
+            storage.put(format!("key{:02}", i).as_bytes(), format!("v{}", round).as_bytes()).await.unwrap();
+        }
+        storage.flush().await.unwrap();
+    }
+    storage.delete(b"key05").await.unwrap();
+    storage.put(b"key19", b"unflushed").await.unwrap();
+    storage.put(b"late", b"unflushed").await.unwrap();
+    drop(storage);
+
+    // Lose every SSTable; only the WAL and its archive are left
+    for entry in std::fs::read_dir(dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.file_name().unwrap().to_string_lossy().starts_with('L') {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    let rebuilt = TempDir::new().unwrap();
+    LsmStorage::rebuild_from_wal(&dir.path().join("wal.bin"), rebuilt.path()).unwrap();
+    let storage = LsmStorage::new(rebuilt.path()).unwrap();
+    assert_eq!(storage.get(b"key00").await.unwrap(), Some(b"v2".to_vec()));
+    assert_eq!(storage.get(b"key05").await.unwrap(), None);
+    assert_eq!(storage.get(b"key19").await.unwrap(), Some(b"unflushed".to_vec()));
+    assert_eq!(storage.get(b"late").await.unwrap(), Some(b"unflushed".to_vec()));
+    assert_eq!(storage.scan(b"key").await.unwrap().len(), 19);
+
+    // The target must start out empty
+    assert!(LsmStorage::rebuild_from_wal(&dir.path().join("wal.bin"), rebuilt.path()).is_err());
+}
+
+#[tokio::test]
+async fn test_verify_recovery_flags_keys_of_a_lost_sstable() {
+    use rust_db_storage::StorageConfig;
+
+    let dir = TempDir::new().unwrap();
+    let config = StorageConfig::new(dir.path()).with_wal_archive();
+    let storage = LsmStorage::open(config.clone()).unwrap();
+    storage.put(b"kept", b"v").await.unwrap();
+    storage.put(b"gone", b"v").await.unwrap();
+    storage.flush().await.unwrap();
+    storage.put(b"lost1", b"v").await.unwrap();
+    storage.put(b"lost2", b"v").await.unwrap();
+    let lost = storage.flush().await.unwrap().unwrap();
+    storage.delete(b"gone").await.unwrap();
+    storage.put(b"unflushed", b"v").await.unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(config.clone()).unwrap();
+    let report = storage.verify_recovery().unwrap();
+    assert!(report.is_consistent(), "{:?}", report);
+    assert_eq!(report.entries_checked, 6);
+    drop(storage);
+
+    // The second flush's SSTable disappears; its WAL segment is still archived
+    std::fs::remove_file(&lost.path).unwrap();
+    let storage = LsmStorage::open(config).unwrap();
+    let report = storage.verify_recovery().unwrap();
+    assert_eq!(report.missing, vec![b"lost1".to_vec(), b"lost2".to_vec()]);
+    assert_eq!(storage.get(b"kept").await.unwrap(), Some(b"v".to_vec()));
+    assert_eq!(storage.get(b"unflushed").await.unwrap(), Some(b"v".to_vec()));
+}
+
+#[tokio::test]
+async fn test_background_flush_of_old_memtable() {
+    use rust_db_storage::{BackgroundFlusher, StorageConfig};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let dir = TempDir::new().unwrap();
+    let config = StorageConfig::new(dir.path()).with_max_memtable_age(Duration::from_millis(100));
+    let storage = LsmStorage::open(config).unwrap();
+    let flusher = Arc::new(BackgroundFlusher::new(storage.clone()));
+    let task = tokio::spawn({
+        let flusher = Arc::clone(&flusher);
+        async move { flusher.start().await }
+    });
+
+    storage.put(b"small", b"write").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(storage.flush_count(), 0, "nowhere near the size threshold or the age yet");
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(storage.flush_count(), 1);
+    assert_eq!(storage.get_sstables_at_level(0).len(), 1);
+    assert_eq!(storage.get(b"small").await.unwrap(), Some(b"write".to_vec()));
+
+    flusher.stop().await;
+    task.await.unwrap().unwrap();
+}
+
+#[test]
+fn test_errors_keep_their_underlying_source() {
+    use rust_db_storage::BackupManifest;
+    use std::error::Error;
+
+    let dir = TempDir::new().unwrap();
+    let missing = BackupManifest::read(dir.path()).unwrap_err();
+    assert!(missing.to_string().starts_with("Storage error: No backup manifest in"));
+    let io = missing.source().and_then(|e| e.downcast_ref::<std::io::Error>()).unwrap();
+    assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+
+    std::fs::write(dir.path().join("manifest.bin"), [0xff]).unwrap();
+    let corrupt = BackupManifest::read(dir.path()).unwrap_err();
+    assert!(corrupt.to_string().starts_with("Serialization error: "));
+    assert!(corrupt.source().unwrap().downcast_ref::<bincode::Error>().is_some());
+}
+
+#[tokio::test]
+async fn test_encrypted_storage_round_trip() {
+    use rust_db_core::DbError;
+    use rust_db_storage::StorageConfig;
+
+    let dir = TempDir::new().unwrap();
+    let config = |key: u8| StorageConfig::new(dir.path()).with_encryption_key([key; 32]);
+    let storage = LsmStorage::open(config(7)).unwrap();
+    storage.put(b"flushed", b"secret in an sstable").await.unwrap();
+    storage.flush().await.unwrap();
+    storage.put(b"logged", b"secret in the wal").await.unwrap();
+    drop(storage);
+
+    // Neither value is on disk in the clear
+    let mut files = vec![dir.path().join("wal.bin")];
+    files.extend(std::fs::read_dir(dir.path().join("L0")).unwrap().map(|entry| entry.unwrap().path()));
+    for file in files {
+        let bytes = std::fs::read(&file).unwrap();
+        assert!(!bytes.windows(6).any(|window| window == b"secret"), "{:?} holds plaintext", file);
+    }
+
+    let reopened = LsmStorage::open(config(7)).unwrap();
+    assert_eq!(reopened.get(b"flushed").await.unwrap(), Some(b"secret in an sstable".to_vec()));
+    assert_eq!(reopened.get(b"logged").await.unwrap(), Some(b"secret in the wal".to_vec()));
+    drop(reopened);
+
+    assert!(matches!(LsmStorage::open(config(8)), Err(DbError::Encryption(_))));
+    assert!(matches!(LsmStorage::new(dir.path()), Err(DbError::Encryption(_))));
+}
+
+#[tokio::test]
+async fn test_encrypted_tables_read_block_by_block() {
+    use rust_db_core::DbError;
+    use rust_db_storage::StorageConfig;
+
+    let dir = TempDir::new().unwrap();
+    let config = || StorageConfig::new(dir.path()).with_encryption_key([3; 32]);
+    let storage = LsmStorage::open(config()).unwrap();
+    // Small entries share blocks and large ones span several
+    for i in 0..300u32 {
+        let value = if i % 50 == 0 { vec![i as u8; 10_000] } else { format!("value {}", i).into_bytes() };
+        storage.put(format!("k{:04}", i).as_bytes(), &value).await.unwrap();
+    }
+    storage.flush().await.unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(config()).unwrap();
+    assert_eq!(storage.get(b"k0100").await.unwrap(), Some(vec![100; 10_000]));
+    assert_eq!(storage.get(b"k0123").await.unwrap(), Some(b"value 123".to_vec()));
+    assert_eq!(storage.get(b"k9999").await.unwrap(), None);
+    let scanned = storage.scan(b"k01").await.unwrap();
+    assert_eq!(scanned.len(), 100);
+    assert_eq!(scanned[1], (b"k0101".to_vec(), b"value 101".to_vec()));
+    assert_eq!(storage.scan_cursor(b"k02").unwrap().collect().await.unwrap().len(), 100);
+    drop(storage);
+
+    // A flipped bit in one block fails the table rather than reading garbage
+    let table = std::fs::read_dir(dir.path().join("L0")).unwrap().next().unwrap().unwrap().path();
+    let mut bytes = std::fs::read(&table).unwrap();
+    bytes[5000] ^= 1;
+    std::fs::write(&table, bytes).unwrap();
+    assert!(matches!(LsmStorage::open(config()), Err(DbError::Encryption(_))));
+}
+
+// Files kept in a map, with directories implied by the paths under them
+#[derive(Debug, Default)]
+struct InMemoryFs {
+    files: std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<std::path::PathBuf, Vec<u8>>>>,
+}
+
+struct InMemoryAppend {
+    files: std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<std::path::PathBuf, Vec<u8>>>>,
+    path: std::path::PathBuf,
+}
+
+impl rust_db_storage::AppendFile for InMemoryAppend {
+    fn append(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.files.lock().unwrap().entry(self.path.clone()).or_default().extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl rust_db_storage::FileSystem for InMemoryFs {
+    fn open(&self, path: &std::path::Path) -> std::io::Result<rust_db_storage::FileData> {
+        let files = self.files.lock().unwrap();
+        let file = files.get(path).ok_or(std::io::ErrorKind::NotFound)?;
+        Ok(std::sync::Arc::new(file.clone()))
+    }
+
+    fn read_at(&self, path: &std::path::Path, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let files = self.files.lock().unwrap();
+        let file = files.get(path).ok_or(std::io::ErrorKind::NotFound)?;
+        let rest = file.get(offset as usize..).unwrap_or_default();
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        Ok(len)
+    }
+
+    fn append(&self, path: &std::path::Path) -> std::io::Result<Box<dyn rust_db_storage::AppendFile>> {
+        self.files.lock().unwrap().entry(path.to_path_buf()).or_default();
+        Ok(Box::new(InMemoryAppend { files: std::sync::Arc::clone(&self.files), path: path.to_path_buf() }))
+    }
+
+    fn write(&self, path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+        self.files.lock().unwrap().insert(path.to_path_buf(), data.to_vec());
+        Ok(())
+    }
+
+    fn rename(&self, from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let file = files.remove(from).ok_or(std::io::ErrorKind::NotFound)?;
+        files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove(&self, path: &std::path::Path) -> std::io::Result<()> {
+        self.files.lock().unwrap().remove(path).map(|_| ()).ok_or(std::io::ErrorKind::NotFound.into())
+    }
+
+    fn list(&self, dir: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+        let files = self.files.lock().unwrap();
+        if files.contains_key(dir) {
+            return Err(std::io::ErrorKind::NotADirectory.into());
+        }
+        let mut children: Vec<_> = files
+            .keys()
+            .filter_map(|path| path.strip_prefix(dir).ok()?.components().next())
+            .map(|child| dir.join(child))
+            .collect();
+        children.dedup();
+        Ok(children)
+    }
+
+    fn create_dir_all(&self, _dir: &std::path::Path) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_flush_and_read_through_an_in_memory_file_system() {
+    use rust_db_storage::StorageConfig;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    let fs = Arc::new(InMemoryFs::default());
+    let config = || StorageConfig::new(Path::new("/in-memory/db")).with_file_system(fs.clone());
+    let storage = LsmStorage::open(config()).unwrap();
+    for i in 0..50u32 {
+        storage.put(format!("key{:02}", i).as_bytes(), b"flushed").await.unwrap();
+    }
+    storage.flush().await.unwrap();
+    storage.put(b"key00", b"logged").await.unwrap();
+    assert_eq!(storage.get_sstables_at_level(0).len(), 1);
+    assert_eq!(storage.get(b"key01").await.unwrap(), Some(b"flushed".to_vec()));
+    drop(storage);
+
+    let tables: Vec<_> = fs.files.lock().unwrap().keys().filter(|path| path.starts_with("/in-memory/db/L0")).cloned().collect();
+    assert_eq!(tables.len(), 1);
+    assert!(!Path::new("/in-memory").exists());
+
+    let reopened = LsmStorage::open(config()).unwrap();
+    assert_eq!(reopened.get(b"key00").await.unwrap(), Some(b"logged".to_vec()));
+    assert_eq!(reopened.get(b"key49").await.unwrap(), Some(b"flushed".to_vec()));
+    assert_eq!(reopened.scan(b"key").await.unwrap().len(), 50);
+}
+
+// Leaves eight WAL segments beside `dir/wal.bin`, as flushes that never
+// finished would, plus the live log. Keys are overwritten and deleted across
+// logs, so replaying them out of order gives a different result.
+fn write_multi_segment_wal(dir: &std::path::Path) -> std::collections::BTreeMap<Vec<u8>, Vec<u8>> {
+    use rust_db_storage::{WalEntry, WriteAheadLog};
+
+    let mut expected = std::collections::BTreeMap::new();
+    for log in 0..9u64 {
+        let path = match log {
+            8 => dir.join("wal.bin"),
+            _ => dir.join(format!("wal.bin.{}", 1000 + log)),
+        };
+        let mut wal = WriteAheadLog::create(&path).unwrap();
+        for batch in 0..20u64 {
+            let entries: Vec<WalEntry> = (0..50u64)
+                .map(|i| {
+                    let key = format!("key{:04}", (log * 37 + batch * 11 + i) % 500).into_bytes();
+                    // Put entries hold the stored cell: a tag byte of 1, then the value
+                    let cell = match (log + batch + i) % 7 {
+                        0 => Vec::new(),
+                        _ => [&[1u8][..], format!("log{} batch{} entry{}", log, batch, i).as_bytes()].concat(),
+                    };
+                    expected.insert(key.clone(), cell[cell.len().min(1)..].to_vec());
+                    WalEntry::new(&key, &cell)
+                })
+                .collect();
+            wal.write_batch(&entries).unwrap();
+        }
+    }
+    expected
+}
+
+#[tokio::test]
+async fn test_parallel_wal_replay_matches_serial_replay() {
+    use rust_db_storage::StorageConfig;
+
+    let serial_dir = TempDir::new().unwrap();
+    let parallel_dir = TempDir::new().unwrap();
+    let expected = write_multi_segment_wal(serial_dir.path());
+    write_multi_segment_wal(parallel_dir.path());
+
+    let serial = LsmStorage::open(StorageConfig::new(serial_dir.path())).unwrap();
+    let parallel = LsmStorage::open(StorageConfig::new(parallel_dir.path()).with_recovery_parallelism(4)).unwrap();
+
+    let serial_state = serial.scan(b"key").await.unwrap();
+    assert_eq!(parallel.scan(b"key").await.unwrap(), serial_state);
+    let live: Vec<(Vec<u8>, Vec<u8>)> = expected.into_iter().filter(|(_, value)| !value.is_empty()).collect();
+    let serial_live: Vec<_> = serial_state.into_iter().filter(|(_, value)| !value.is_empty()).collect();
+    assert_eq!(serial_live, live);
+}
+
+#[tokio::test]
+async fn test_repair_removes_orphans_and_flags_missing_tables() {
+    use rust_db_storage::{RepairAction, WalEntry, WriteAheadLog};
+
+    let dir = TempDir::new().unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap();
+    storage.put(b"flushed", b"v").await.unwrap();
+    let flushed = storage.flush().await.unwrap().unwrap();
+    storage.put(b"pending", b"v").await.unwrap();
+    drop(storage);
+
+    let path = |name: &str| dir.path().join(name);
+    let flush_ts: u64 = flushed.path.file_stem().unwrap().to_str().unwrap()["sst_".len()..].parse().unwrap();
+    let write_segment = |timestamp: u64, key: &[u8], value: &[u8]| {
+        let segment = path(&format!("wal.bin.{}", timestamp));
+        let cell = [&[1u8][..], value].concat();
+        WriteAheadLog::create(&segment).unwrap().write_batch(&[WalEntry::new(key, &cell)]).unwrap();
+        segment
+    };
+    // The flushed table's segment outlived it, holding an older value
+    let flushed_segment = write_segment(flush_ts, b"flushed", b"stale");
+    // A flush that never wrote its table, and one that tore it
+    let unflushed_segment = write_segment(flush_ts + 10, b"unflushed", b"v");
+    let torn_segment = write_segment(flush_ts + 20, b"rewritten", b"v");
+    let torn = path(&format!("L0/sst_{}.bin", flush_ts + 20));
+    let table = std::fs::read(&flushed.path).unwrap();
+    std::fs::write(&torn, &table[..table.len() / 2]).unwrap();
+    // A flush that crashed before its first block, leaving an empty table
+    let emptied_segment = write_segment(flush_ts + 30, b"emptied", b"v");
+    let emptied = path(&format!("L0/sst_{}.bin", flush_ts + 30));
+    std::fs::write(&emptied, b"").unwrap();
+    // Debris, a checksum without its table, and a table nothing can rebuild
+    std::fs::create_dir_all(path("staging")).unwrap();
+    std::fs::write(path("staging/sst_9.bin"), b"partial").unwrap();
+    std::fs::write(path("compaction.tmp"), b"partial").unwrap();
+    std::fs::write(path("L0/sst_3.bin.tmp"), b"partial").unwrap();
+    std::fs::create_dir_all(path("L1")).unwrap();
+    std::fs::write(path("L1/sst_5.crc"), 7u32.to_le_bytes()).unwrap();
+    std::fs::create_dir_all(path("L2")).unwrap();
+    std::fs::write(path("L2/sst_7.bin"), b"garbage!").unwrap();
+
+    let report = LsmStorage::repair(dir.path()).unwrap();
+    let expected = [
+        RepairAction::RemovedTempFile(path("staging/sst_9.bin")),
+        RepairAction::RemovedTempFile(path("compaction.tmp")),
+        RepairAction::RemovedTempFile(path("L0/sst_3.bin.tmp")),
+        RepairAction::RemovedFlushedWalSegment(flushed_segment.clone()),
+        RepairAction::RemovedTornSSTable(torn.clone()),
+        RepairAction::RemovedTornSSTable(emptied.clone()),
+        RepairAction::RemovedOrphanChecksum(path("L1/sst_5.crc")),
+    ];
+    assert_eq!(report.actions.len(), expected.len(), "{:?}", report);
+    for action in &expected {
+        assert!(report.actions.contains(action), "{:?} not in {:?}", action, report);
+    }
+    assert_eq!(report.missing, vec![path("L1/sst_5.bin")]);
+    assert_eq!(report.unreadable, vec![path("L2/sst_7.bin")]);
+
+    // Live data stays put
+    assert!(flushed.path.exists() && path("wal.bin").exists());
+    assert!(unflushed_segment.exists() && torn_segment.exists() && emptied_segment.exists());
+    assert!(path("L2/sst_7.bin").exists());
+    assert!(!flushed_segment.exists() && !torn.exists() && !emptied.exists());
+
+    std::fs::remove_file(path("L2/sst_7.bin")).unwrap();
+    let storage = LsmStorage::new(dir.path()).unwrap();
+    for key in [&b"flushed"[..], b"pending", b"unflushed", b"rewritten", b"emptied"] {
+        assert_eq!(storage.get(key).await.unwrap(), Some(b"v".to_vec()), "{:?}", key);
+    }
+    drop(storage);
+    assert!(LsmStorage::repair(dir.path()).unwrap().is_clean());
+}
+
+#[tokio::test]
+async fn test_repair_archives_flushed_segments_when_archiving() {
+    use rust_db_storage::{RepairAction, StorageConfig, WalEntry, WriteAheadLog};
+
+    let dir = TempDir::new().unwrap();
+    let config = || StorageConfig::new(dir.path()).with_wal_archive();
+    let storage = LsmStorage::open(config()).unwrap();
+    storage.put(b"flushed", b"v").await.unwrap();
+    let flushed = storage.flush().await.unwrap().unwrap();
+    drop(storage);
+
+    let flush_ts: u64 = flushed.path.file_stem().unwrap().to_str().unwrap()["sst_".len()..].parse().unwrap();
+    let segment = dir.path().join(format!("wal.bin.{}", flush_ts));
+    WriteAheadLog::create(&segment).unwrap().write_batch(&[WalEntry::new(b"flushed", b"\x01v")]).unwrap();
+
+    let report = LsmStorage::repair_with(&config()).unwrap();
+    assert_eq!(report.actions, vec![RepairAction::RemovedFlushedWalSegment(segment.clone())]);
+    assert!(!segment.exists());
+    assert!(dir.path().join("wal.bin.archive").join(format!("wal.bin.{}", flush_ts)).exists());
+}
@@ -1,5 +1,5 @@
-use rust_db_core::{Database, MvccDatabase, TransactionContext};
-use rust_db_storage::MvccLsmStorage;
+use rust_db_core::{Database, DbError, GcConfig, MvccDatabase, Transaction, TransactionContext};
+use rust_db_storage::{LsmStorage, MvccLsmStorage, MvccStorage};
 use tempfile::TempDir;
 
 fn setup() -> (TempDir, MvccLsmStorage) {
@@ -24,7 +24,7 @@ async fn test_begin_and_commit_transaction() {
 
     // Write within transaction
     tx.transaction_mut()
-        .put(b"accounts:1".to_vec(), bincode::serialize(&200u64).unwrap());
+        .put(b"accounts:1".to_vec(), bincode::serialize(&200u64).unwrap()).unwrap();
     tx.commit().await.unwrap();
 
     // Verify committed value
@@ -40,7 +40,7 @@ async fn test_transaction_rollback() {
 
     let mut tx = TransactionContext::new(&storage).await.unwrap();
     tx.transaction_mut()
-        .put(b"key1".to_vec(), bincode::serialize(&"modified").unwrap());
+        .put(b"key1".to_vec(), bincode::serialize(&"modified").unwrap()).unwrap();
     tx.rollback().await.unwrap();
 
     // Value should remain original
@@ -56,7 +56,7 @@ async fn test_transaction_multiple_writes() {
     for i in 0..5u64 {
         let key = format!("item:{i}").into_bytes();
         tx.transaction_mut()
-            .put(key, bincode::serialize(&i).unwrap());
+            .put(key, bincode::serialize(&i).unwrap()).unwrap();
     }
     tx.commit().await.unwrap();
 
@@ -75,7 +75,7 @@ async fn test_transaction_delete() {
     storage.insert(b"delete_me", &42u32).await.unwrap();
 
     let mut tx = TransactionContext::new(&storage).await.unwrap();
-    tx.transaction_mut().delete(b"delete_me".to_vec());
+    tx.transaction_mut().delete(b"delete_me".to_vec()).unwrap();
     tx.commit().await.unwrap();
 
     // After commit, the key should be tombstoned (empty)
@@ -130,7 +130,7 @@ async fn test_concurrent_transactions() {
             .unwrap();
         let new_val = val.unwrap_or(0) + 10;
         tx.transaction_mut()
-            .put(b"counter".to_vec(), bincode::serialize(&new_val).unwrap());
+            .put(b"counter".to_vec(), bincode::serialize(&new_val).unwrap()).unwrap();
         tx.commit().await.unwrap();
     });
 
@@ -144,7 +144,7 @@ async fn test_concurrent_transactions() {
             .unwrap();
         let new_val = val.unwrap_or(0) + 5;
         tx.transaction_mut()
-            .put(b"counter".to_vec(), bincode::serialize(&new_val).unwrap());
+            .put(b"counter".to_vec(), bincode::serialize(&new_val).unwrap()).unwrap();
         tx.commit().await.unwrap();
     });
 
@@ -159,3 +159,654 @@ async fn test_concurrent_transactions() {
     let v = val.unwrap();
     assert!(v > 0, "Counter should have been incremented, got {v}");
 }
+
+#[tokio::test]
+async fn test_transaction_read_layers() {
+    let dir = TempDir::new().unwrap();
+    let base = LsmStorage::new(dir.path()).unwrap();
+    base.insert(b"acct:base", &1u64).await.unwrap();
+    base.insert(b"acct:shadowed", &10u64).await.unwrap();
+    let mvcc = MvccStorage::new(base.clone());
+
+    // A committed transaction leaves a version that takes precedence over base storage
+    let mut writer = Transaction::new();
+    writer.put(b"acct:shadowed".to_vec(), bincode::serialize(&20u64).unwrap()).unwrap();
+    mvcc.apply_transaction_writes(&writer).await.unwrap();
+
+    let mut tx = Transaction::new();
+    tx.put(b"acct:local".to_vec(), bincode::serialize(&30u64).unwrap()).unwrap();
+    tx.delete(b"acct:base".to_vec()).unwrap();
+
+    let local: Option<u64> = mvcc.get_for_transaction(b"acct:local", &tx).await.unwrap();
+    let shadowed: Option<u64> = mvcc.get_for_transaction(b"acct:shadowed", &tx).await.unwrap();
+    let deleted: Option<u64> = mvcc.get_for_transaction(b"acct:base", &tx).await.unwrap();
+    assert_eq!(local, Some(30));
+    assert_eq!(shadowed, Some(20));
+    assert_eq!(deleted, None);
+
+    // Another transaction without the local writes falls through to base storage
+    let other = Transaction::new();
+    let base_value: Option<u64> = mvcc.get_for_transaction(b"acct:base", &other).await.unwrap();
+    assert_eq!(base_value, Some(1));
+    let raw = mvcc.get_version(b"acct:shadowed", &other).await.unwrap().unwrap();
+    assert_eq!(bincode::deserialize::<u64>(&raw.value).unwrap(), 20);
+}
+
+#[tokio::test]
+async fn test_transaction_sees_own_uncommitted_write() {
+    let (_dir, storage) = setup();
+    storage.insert(b"key", &1u64).await.unwrap();
+
+    let mut tx = TransactionContext::new(&storage).await.unwrap();
+    tx.transaction_mut().put(b"key".to_vec(), bincode::serialize(&2u64).unwrap()).unwrap();
+    let val: Option<u64> = storage.get_for_transaction(b"key", tx.transaction()).await.unwrap();
+    assert_eq!(val, Some(2));
+    tx.rollback().await.unwrap();
+
+    let tx = TransactionContext::new(&storage).await.unwrap();
+    let val: Option<u64> = storage.get_for_transaction(b"key", tx.transaction()).await.unwrap();
+    assert_eq!(val, Some(1));
+    tx.rollback().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_empty_value_is_not_a_delete() {
+    let (_dir, storage) = setup();
+    let before = storage.begin_transaction().await.unwrap();
+
+    // `()` serializes to no bytes at all
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.put(b"unit".to_vec(), bincode::serialize(&()).unwrap()).unwrap();
+    storage.commit_transaction(tx).await.unwrap();
+
+    let reader = storage.begin_transaction().await.unwrap();
+    assert_eq!(storage.get_for_transaction::<()>(b"unit", &reader).await.unwrap(), Some(()));
+    let record = storage.mvcc_storage().get_version(b"unit", &reader).await.unwrap().unwrap();
+    assert!(record.value.is_empty());
+    assert_eq!(storage.scan_for_transaction(b"unit", &reader).await.unwrap(), vec![(b"unit".to_vec(), Vec::new())]);
+    assert_eq!(Database::get::<()>(&storage, b"unit").await.unwrap(), Some(()));
+    storage.rollback_transaction(reader).await.unwrap();
+
+    // Older snapshots still see the key as absent, and a delete removes it
+    assert_eq!(storage.get_for_transaction::<()>(b"unit", &before).await.unwrap(), None);
+    storage.rollback_transaction(before).await.unwrap();
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.delete(b"unit".to_vec()).unwrap();
+    storage.commit_transaction(tx).await.unwrap();
+    let reader = storage.begin_transaction().await.unwrap();
+    assert_eq!(storage.get_for_transaction::<()>(b"unit", &reader).await.unwrap(), None);
+    assert!(storage.scan_for_transaction(b"unit", &reader).await.unwrap().is_empty());
+    storage.rollback_transaction(reader).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_direct_writes_keep_snapshot_isolation() {
+    let (_dir, storage) = setup();
+    storage.insert(b"acct:1", &1u64).await.unwrap();
+    let mut tx = TransactionContext::new(&storage).await.unwrap();
+    tx.transaction_mut().put(b"acct:1".to_vec(), bincode::serialize(&10u64).unwrap()).unwrap();
+
+    let reader = storage.begin_transaction().await.unwrap();
+    storage.insert(b"acct:1", &2u64).await.unwrap();
+    storage.insert(b"acct:2", &3u64).await.unwrap();
+    storage.delete(b"acct:2").await.unwrap();
+
+    // The snapshot taken before the writes still reads what they replaced
+    assert_eq!(storage.get_for_transaction::<u64>(b"acct:1", &reader).await.unwrap(), Some(1));
+    assert_eq!(storage.get_for_transaction::<u64>(b"acct:2", &reader).await.unwrap(), None);
+    assert_eq!(storage.scan_for_transaction(b"acct:", &reader).await.unwrap().len(), 1);
+    storage.rollback_transaction(reader).await.unwrap();
+
+    // A direct write counts as a commit, so a transaction that wrote the same key conflicts
+    assert!(matches!(tx.commit().await, Err(DbError::TransactionConflict(_))));
+    assert_eq!(Database::get::<u64>(&storage, b"acct:1").await.unwrap(), Some(2));
+    assert_eq!(Database::get::<u64>(&storage, b"acct:2").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_disjoint_scopes_commit_concurrently() {
+    let (_dir, storage) = setup();
+
+    let mut accounts = storage.begin_transaction().await.unwrap();
+    let mut orders = storage.begin_transaction().await.unwrap();
+    accounts.scope(b"accounts:");
+    orders.scope(b"orders:");
+    accounts.put(b"accounts:1".to_vec(), bincode::serialize(&100u64).unwrap()).unwrap();
+    orders.put(b"orders:1".to_vec(), bincode::serialize(&7u64).unwrap()).unwrap();
+
+    let (a, b) = tokio::join!(
+        storage.commit_transaction(accounts),
+        storage.commit_transaction(orders),
+    );
+    a.unwrap();
+    b.unwrap();
+
+    let balance: Option<u64> = Database::get(&storage, b"accounts:1").await.unwrap();
+    let order: Option<u64> = Database::get(&storage, b"orders:1").await.unwrap();
+    assert_eq!(balance, Some(100));
+    assert_eq!(order, Some(7));
+}
+
+#[tokio::test]
+async fn test_scoped_transaction_conflicts_and_out_of_scope_writes() {
+    let (_dir, storage) = setup();
+
+    // Overlapping scopes still detect a write-write conflict
+    let mut first = storage.begin_transaction().await.unwrap();
+    let mut second = storage.begin_transaction().await.unwrap();
+    first.scope(b"accounts:");
+    second.scope(b"accounts:1");
+    first.put(b"accounts:1".to_vec(), bincode::serialize(&1u64).unwrap()).unwrap();
+    second.put(b"accounts:1".to_vec(), bincode::serialize(&2u64).unwrap()).unwrap();
+    storage.commit_transaction(first).await.unwrap();
+    assert!(matches!(
+        storage.commit_transaction(second).await,
+        Err(DbError::TransactionConflict(_))
+    ));
+
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.scope(b"accounts:");
+    tx.put(b"orders:1".to_vec(), bincode::serialize(&3u64).unwrap()).unwrap();
+    assert!(matches!(storage.commit_transaction(tx).await, Err(DbError::Transaction(_))));
+    let order: Option<u64> = Database::get(&storage, b"orders:1").await.unwrap();
+    assert_eq!(order, None);
+}
+
+#[tokio::test]
+async fn test_dropped_transaction_is_rolled_back() {
+    let (_dir, storage) = setup();
+
+    let tx_id = {
+        let mut tx = TransactionContext::new(&storage).await.unwrap();
+        tx.transaction_mut().put(b"key".to_vec(), bincode::serialize(&1u64).unwrap()).unwrap();
+        let id = tx.transaction().id;
+        assert!(storage.transaction_manager().is_transaction_active(id));
+        id
+    };
+
+    assert!(!storage.transaction_manager().is_transaction_active(tx_id));
+    assert!(!storage.transaction_manager().is_transaction_committed(tx_id));
+    let val: Option<u64> = Database::get(&storage, b"key").await.unwrap();
+    assert_eq!(val, None);
+}
+
+#[tokio::test]
+async fn test_transactional_scan_is_sorted() {
+    let (_dir, storage) = setup();
+    for id in [7u64, 3, 9] {
+        storage.insert(format!("table:{}", id).as_bytes(), &id).await.unwrap();
+    }
+    // Committed through a transaction, so these live in the version store too
+    let mut tx = storage.begin_transaction().await.unwrap();
+    for id in [8u64, 1, 5] {
+        tx.put(format!("table:{}", id).into_bytes(), bincode::serialize(&id).unwrap()).unwrap();
+    }
+    storage.commit_transaction(tx).await.unwrap();
+
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.put(b"table:4".to_vec(), bincode::serialize(&4u64).unwrap()).unwrap();
+    tx.put(b"table:0".to_vec(), bincode::serialize(&0u64).unwrap()).unwrap();
+    let results = storage.scan_for_transaction(b"table:", &tx).await.unwrap();
+
+    let keys: Vec<Vec<u8>> = results.into_iter().map(|(key, _)| key).collect();
+    let mut sorted = keys.clone();
+    sorted.sort();
+    assert_eq!(keys.len(), 8);
+    assert_eq!(keys, sorted);
+    storage.rollback_transaction(tx).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_estimate_gc_matches_real_gc() {
+    let dir = TempDir::new().unwrap();
+    let config = GcConfig {
+        version_retention_secs: 0,
+        ..GcConfig::default()
+    };
+    let storage = MvccLsmStorage::new(dir.path())
+        .unwrap()
+        .with_garbage_collection(config)
+        .unwrap();
+    for round in 0..3u64 {
+        let mut tx = storage.begin_transaction().await.unwrap();
+        for key in ["a", "b", "c"] {
+            tx.put(format!("gc:{}", key).into_bytes(), bincode::serialize(&round).unwrap()).unwrap();
+        }
+        storage.commit_transaction(tx).await.unwrap();
+    }
+
+    let estimate = storage.estimate_gc().await.unwrap();
+    assert_eq!(estimate.versions_removed, 3);
+    assert!(estimate.space_reclaimed > 0);
+    // The dry run leaves the estimate unchanged
+    assert_eq!(storage.estimate_gc().await.unwrap().versions_removed, 3);
+
+    let stats = storage.run_garbage_collection().await.unwrap();
+    assert_eq!(stats.versions_removed, estimate.versions_removed);
+    assert_eq!(stats.space_reclaimed, estimate.space_reclaimed);
+}
+
+#[tokio::test]
+async fn test_get_as_of_reads_history() {
+    use rust_db_core::VersionTimestamp;
+    use std::time::Duration;
+
+    let dir = TempDir::new().unwrap();
+    let config = GcConfig {
+        version_retention_secs: 0,
+        ..GcConfig::default()
+    };
+    let storage = MvccLsmStorage::new(dir.path())
+        .unwrap()
+        .with_garbage_collection(config)
+        .unwrap();
+    let before_any = VersionTimestamp::now();
+    tokio::time::sleep(Duration::from_millis(2)).await;
+
+    let mut checkpoints = Vec::new();
+    for balance in [100u64, 150, 75] {
+        let mut tx = storage.begin_transaction().await.unwrap();
+        tx.put(b"balance".to_vec(), bincode::serialize(&balance).unwrap()).unwrap();
+        storage.commit_transaction(tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        checkpoints.push((VersionTimestamp::now(), balance));
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.delete(b"balance".to_vec()).unwrap();
+    storage.commit_transaction(tx).await.unwrap();
+
+    let mvcc = storage.mvcc_storage();
+    assert_eq!(mvcc.get_as_of(b"balance", before_any).unwrap(), None);
+    for (ts, balance) in &checkpoints {
+        let value = mvcc.get_as_of(b"balance", *ts).unwrap().unwrap();
+        assert_eq!(bincode::deserialize::<u64>(&value).unwrap(), *balance);
+    }
+    assert_eq!(mvcc.get_as_of(b"balance", VersionTimestamp::now()).unwrap(), None);
+
+    // GC drops the oldest version, so the time it was current can't be answered
+    assert_eq!(storage.run_garbage_collection().await.unwrap().versions_removed, 1);
+    assert!(matches!(
+        mvcc.get_as_of(b"balance", checkpoints[0].0),
+        Err(DbError::GarbageCollection(_))
+    ));
+    let value = mvcc.get_as_of(b"balance", checkpoints[1].0).unwrap().unwrap();
+    assert_eq!(bincode::deserialize::<u64>(&value).unwrap(), 150);
+}
+
+#[tokio::test]
+async fn test_gc_spills_pruned_versions_for_time_travel() {
+    use rust_db_core::VersionTimestamp;
+    use rust_db_storage::VersionEncoding;
+    use std::time::Duration;
+
+    let dir = TempDir::new().unwrap();
+    let config = GcConfig {
+        version_retention_secs: 0,
+        ..GcConfig::default()
+    };
+    let storage = MvccLsmStorage::new(dir.path())
+        .unwrap()
+        .with_garbage_collection(config)
+        .unwrap()
+        .with_version_spill(VersionEncoding::Compact)
+        .unwrap();
+
+    let mut checkpoints = Vec::new();
+    for balance in [100u64, 150, 75] {
+        let mut tx = storage.begin_transaction().await.unwrap();
+        tx.put(b"balance".to_vec(), bincode::serialize(&balance).unwrap()).unwrap();
+        storage.commit_transaction(tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        checkpoints.push((VersionTimestamp::now(), balance));
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+
+    // The pruned version is still answered, now from the spill
+    assert_eq!(storage.run_garbage_collection().await.unwrap().versions_removed, 1);
+    let mvcc = storage.mvcc_storage();
+    for (ts, balance) in &checkpoints {
+        let value = mvcc.get_as_of(b"balance", *ts).unwrap().unwrap();
+        assert_eq!(bincode::deserialize::<u64>(&value).unwrap(), *balance);
+    }
+    assert_eq!(mvcc.get_as_of(b"other", checkpoints[0].0).unwrap(), None);
+
+    // One compact frame: tag, key and record lengths, key, and the record
+    // with its 8-byte value
+    let spilled = std::fs::read(dir.path().join("versions.spill")).unwrap();
+    assert!(spilled.len() < 8 + 40, "spill took {} bytes", spilled.len());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_readers_never_see_partial_commit() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    const KEYS: u64 = 200;
+    let dir = TempDir::new().unwrap();
+    let storage = Arc::new(MvccLsmStorage::new(dir.path()).unwrap());
+    let write_all = |value: u64| {
+        let mut tx = Transaction::new();
+        for i in 0..KEYS {
+            tx.put(format!("acct:{:03}", i).into_bytes(), bincode::serialize(&value).unwrap()).unwrap();
+        }
+        tx.writes
+    };
+
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.writes = write_all(0);
+    storage.commit_transaction(tx).await.unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut readers = Vec::new();
+    for _ in 0..3 {
+        let storage = Arc::clone(&storage);
+        let done = Arc::clone(&done);
+        readers.push(tokio::spawn(async move {
+            let mut scans = 0;
+            while !done.load(Ordering::SeqCst) || scans < 5 {
+                let tx = storage.begin_transaction().await.unwrap();
+                let rows = storage.scan_for_transaction(b"acct:", &tx).await.unwrap();
+                storage.rollback_transaction(tx).await.unwrap();
+
+                let values: Vec<u64> = rows.iter().map(|(_, v)| bincode::deserialize(v).unwrap()).collect();
+                assert_eq!(values.len(), KEYS as usize);
+                assert!(values.iter().all(|v| *v == values[0]), "partial commit: {:?}", values);
+                scans += 1;
+                tokio::task::yield_now().await;
+            }
+        }));
+    }
+
+    for round in 1..=20u64 {
+        let mut tx = storage.begin_transaction().await.unwrap();
+        tx.writes = write_all(round);
+        storage.commit_transaction(tx).await.unwrap();
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+        reader.await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_cross_table_commit_is_all_or_nothing() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    const TRANSFERS: u64 = 30;
+    let dir = TempDir::new().unwrap();
+    let storage = Arc::new(MvccLsmStorage::new(dir.path()).unwrap());
+    storage.insert(b"accounts:alice", &1000u64).await.unwrap();
+
+    // Began before the first transfer: must keep seeing the original balance
+    // and no transfers however many commit meanwhile
+    let before = storage.begin_transaction().await.unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut readers = Vec::new();
+    for _ in 0..3 {
+        let storage = Arc::clone(&storage);
+        let done = Arc::clone(&done);
+        readers.push(tokio::spawn(async move {
+            let mut reads = 0;
+            while !done.load(Ordering::SeqCst) || reads < 5 {
+                let tx = storage.begin_transaction().await.unwrap();
+                let balance: u64 = storage.get_for_transaction(b"accounts:alice", &tx).await.unwrap().unwrap();
+                let transfers = storage.scan_for_transaction(b"transfers:", &tx).await.unwrap();
+                storage.rollback_transaction(tx).await.unwrap();
+
+                // Every transfer debits 10, so a debit without its record (or
+                // the reverse) breaks the sum
+                assert_eq!(balance + 10 * transfers.len() as u64, 1000, "saw half a transfer");
+                reads += 1;
+                tokio::task::yield_now().await;
+            }
+        }));
+    }
+
+    for i in 0..TRANSFERS {
+        let mut tx = storage.begin_transaction().await.unwrap();
+        let balance: u64 = storage.get_for_transaction(b"accounts:alice", &tx).await.unwrap().unwrap();
+        tx.put(b"accounts:alice".to_vec(), bincode::serialize(&(balance - 10)).unwrap()).unwrap();
+        tx.put(format!("transfers:{:03}", i).into_bytes(), bincode::serialize(&10u64).unwrap()).unwrap();
+        storage.commit_transaction(tx).await.unwrap();
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+        reader.await.unwrap();
+    }
+
+    let balance: Option<u64> = storage.get_for_transaction(b"accounts:alice", &before).await.unwrap();
+    assert_eq!(balance, Some(1000));
+    assert!(storage.scan_for_transaction(b"transfers:", &before).await.unwrap().is_empty());
+    storage.rollback_transaction(before).await.unwrap();
+
+    let after = storage.begin_transaction().await.unwrap();
+    let balance: Option<u64> = storage.get_for_transaction(b"accounts:alice", &after).await.unwrap();
+    assert_eq!(balance, Some(1000 - 10 * TRANSFERS));
+    assert_eq!(storage.scan_for_transaction(b"transfers:", &after).await.unwrap().len() as u64, TRANSFERS);
+}
+
+#[tokio::test]
+async fn test_delete_existing_reports_whether_key_existed() {
+    let (_dir, storage) = setup();
+    storage.insert(b"acct:10", &10u64).await.unwrap();
+    storage.insert(b"acct:2", &2u64).await.unwrap();
+
+    let mut tx = TransactionContext::new(&storage).await.unwrap();
+    assert!(tx.delete_existing(b"acct:2").await.unwrap());
+    // Only a longer key shares this prefix
+    assert!(!tx.delete_existing(b"acct:1").await.unwrap());
+    // The first delete is visible to the transaction's own reads
+    assert!(!tx.delete_existing(b"acct:2").await.unwrap());
+    assert_eq!(tx.transaction().writes.len(), 1);
+    tx.commit().await.unwrap();
+
+    let tx = TransactionContext::new(&storage).await.unwrap();
+    let gone: Option<u64> = storage.get_for_transaction(b"acct:2", tx.transaction()).await.unwrap();
+    let kept: Option<u64> = storage.get_for_transaction(b"acct:10", tx.transaction()).await.unwrap();
+    assert_eq!((gone, kept), (None, Some(10)));
+    tx.rollback().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_transaction_stats_count_reads_writes_and_scans() {
+    let (_dir, storage) = setup();
+    for i in 1..=3u64 {
+        storage.insert(format!("s:{}", i).as_bytes(), &i).await.unwrap();
+    }
+
+    let mut tx = TransactionContext::new(&storage).await.unwrap();
+    let _: Option<u64> = storage.get_for_transaction(b"s:1", tx.transaction()).await.unwrap();
+    let _: Option<u64> = storage.get_for_transaction(b"s:missing", tx.transaction()).await.unwrap();
+    storage.scan_for_transaction(b"s:", tx.transaction()).await.unwrap();
+    tx.transaction_mut().put(b"s:4".to_vec(), bincode::serialize(&4u64).unwrap()).unwrap();
+    tx.transaction_mut().put(b"s:1".to_vec(), bincode::serialize(&10u64).unwrap()).unwrap();
+    tx.transaction_mut().delete(b"s:2".to_vec()).unwrap();
+
+    let stats = tx.stats();
+    assert_eq!((stats.keys_read, stats.keys_written, stats.rows_scanned), (2, 3, 3));
+
+    let before_commit = stats.duration;
+    let final_stats = tx.commit_with_stats().await.unwrap();
+    assert_eq!((final_stats.keys_read, final_stats.keys_written), (2, 3));
+    assert!(final_stats.duration >= before_commit);
+}
+
+#[tokio::test]
+async fn test_transactional_range_scan_sees_committed_and_pending_rows() {
+    let (_dir, storage) = setup();
+    for id in [1u64, 3, 9] {
+        storage.insert(format!("row:{}", id).as_bytes(), &id).await.unwrap();
+    }
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.put(b"row:5".to_vec(), bincode::serialize(&5u64).unwrap()).unwrap();
+    storage.commit_transaction(tx).await.unwrap();
+
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.put(b"row:4".to_vec(), bincode::serialize(&4u64).unwrap()).unwrap();
+    tx.put(b"row:8".to_vec(), bincode::serialize(&8u64).unwrap()).unwrap();
+    tx.delete(b"row:5".to_vec()).unwrap();
+    tx.delete(b"row:3".to_vec()).unwrap();
+    // Outside [row:2, row:9), so it's left out despite being the transaction's own write
+    tx.put(b"row:0".to_vec(), bincode::serialize(&0u64).unwrap()).unwrap();
+
+    let results = storage.scan_range_for_transaction(b"row:2", b"row:9", &tx).await.unwrap();
+    let keys: Vec<&[u8]> = results.iter().map(|(key, _)| key.as_slice()).collect();
+    assert_eq!(keys, vec![&b"row:4"[..], b"row:8"]);
+
+    // Uncommitted writes stay invisible to the plain range scan
+    tx.put(b"row:6".to_vec(), bincode::serialize(&6u64).unwrap()).unwrap();
+    let results = storage.scan_range_for_transaction(b"row:2", b"row:9", &tx).await.unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(storage.scan_range(b"row:2", b"row:9").await.unwrap().len(), 2);
+    storage.rollback_transaction(tx).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_list_active_reports_open_transactions() {
+    let (_dir, storage) = setup();
+    let manager = storage.transaction_manager();
+
+    let mut first = storage.begin_transaction().await.unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let second = storage.begin_transaction().await.unwrap();
+    let third = storage.begin_transaction().await.unwrap();
+    first.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+    first.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+    first.put(b"a".to_vec(), b"3".to_vec()).unwrap();
+
+    let active = manager.list_active();
+    assert_eq!(active.len(), 3);
+    // Oldest first
+    assert_eq!(active[0].id, first.id);
+    assert_eq!(active[0].snapshot_ts, first.snapshot_ts);
+    assert_eq!(active[0].write_set_size, 2);
+    assert!(active[0].age >= std::time::Duration::from_millis(20));
+    assert!(active[0].age > active[1].age);
+    let mut rest: Vec<u64> = active[1..].iter().map(|info| info.id.as_u64()).collect();
+    rest.sort();
+    assert_eq!(rest, vec![second.id.as_u64(), third.id.as_u64()]);
+    assert!(active[1..].iter().all(|info| info.write_set_size == 0));
+
+    storage.commit_transaction(first).await.unwrap();
+    storage.rollback_transaction(second).await.unwrap();
+    let active = manager.list_active();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].id, third.id);
+    storage.rollback_transaction(third).await.unwrap();
+    assert!(manager.list_active().is_empty());
+}
+
+#[test]
+fn test_compact_version_encoding_round_trips_and_is_smaller() {
+    use rust_db_core::{TransactionId, VersionTimestamp, VersionedRecord};
+    use rust_db_storage::VersionEncoding;
+
+    let live = VersionedRecord::new(b"small value".to_vec(), TransactionId::from_u64(42));
+    let mut expired = VersionedRecord::new(b"older".to_vec(), TransactionId::from_u64(7));
+    expired.mark_expired(TransactionId::from_u64(9));
+    let mut tombstone = VersionedRecord::new(Vec::new(), TransactionId::from_u64(u64::MAX));
+    // Expired "before" it was created, as after a clock step
+    tombstone.expired_tx = TransactionId::from_u64(1);
+    tombstone.expired_ts = VersionTimestamp::from_u64(tombstone.created_ts.as_u64() - 5);
+
+    for record in [&live, &expired, &tombstone] {
+        for encoding in [VersionEncoding::Bincode, VersionEncoding::Compact] {
+            let decoded = encoding.decode(&encoding.encode(record).unwrap()).unwrap();
+            assert_eq!(decoded.value, record.value);
+            assert_eq!(decoded.created_tx, record.created_tx);
+            assert_eq!(decoded.expired_tx, record.expired_tx);
+            assert_eq!(decoded.created_ts, record.created_ts);
+            assert_eq!(decoded.expired_ts, record.expired_ts);
+        }
+    }
+
+    // Typical versions carry 32 bytes of metadata under bincode
+    let bincode_len = VersionEncoding::Bincode.encode(&live).unwrap().len();
+    let compact_len = VersionEncoding::Compact.encode(&live).unwrap().len();
+    assert_eq!(bincode_len, live.value.len() + 40);
+    assert!(compact_len <= live.value.len() + 12, "compact form took {} bytes", compact_len);
+    let bincode_len = VersionEncoding::Bincode.encode(&expired).unwrap().len();
+    let compact_len = VersionEncoding::Compact.encode(&expired).unwrap().len();
+    assert!(compact_len * 2 < bincode_len, "{} vs {} bytes", compact_len, bincode_len);
+
+    let mut truncated = VersionEncoding::Compact.encode(&live).unwrap();
+    truncated.pop();
+    assert!(matches!(VersionEncoding::Compact.decode(&truncated), Err(DbError::Serialization(..))));
+}
+
+#[tokio::test]
+async fn test_write_set_limit() {
+    let dir = TempDir::new().unwrap();
+    let storage = MvccLsmStorage::new(dir.path()).unwrap().with_max_transaction_writes(3);
+
+    let mut tx = storage.begin_transaction().await.unwrap();
+    for i in 0..3u64 {
+        tx.put(format!("bulk:{}", i).into_bytes(), bincode::serialize(&i).unwrap()).unwrap();
+    }
+    // A fourth key is refused, but keys already in the set can still change
+    let err = tx.put(b"bulk:3".to_vec(), bincode::serialize(&3u64).unwrap()).unwrap_err();
+    assert!(matches!(err, DbError::Transaction(_)), "{:?}", err);
+    assert!(matches!(tx.delete(b"bulk:4".to_vec()), Err(DbError::Transaction(_))));
+    tx.delete(b"bulk:2".to_vec()).unwrap();
+    assert_eq!(tx.writes.len(), 3);
+    storage.rollback_transaction(tx).await.unwrap();
+
+    let mut tx = storage.begin_transaction().await.unwrap();
+    tx.put(b"bulk:0".to_vec(), bincode::serialize(&0u64).unwrap()).unwrap();
+    tx.put(b"bulk:1".to_vec(), bincode::serialize(&1u64).unwrap()).unwrap();
+    storage.commit_transaction(tx).await.unwrap();
+    let value: Option<u64> = Database::get(&storage, b"bulk:1").await.unwrap();
+    assert_eq!(value, Some(1));
+}
+
+#[tokio::test]
+async fn test_bounded_staleness_reads_may_lag_commits() {
+    use std::time::Duration;
+
+    let dir = TempDir::new().unwrap();
+    let storage = MvccLsmStorage::new(dir.path()).unwrap().with_read_staleness(Duration::from_secs(60));
+    let commit = |value: u64| {
+        let storage = &storage;
+        async move {
+            let mut tx = TransactionContext::new(storage).await.unwrap();
+            tx.transaction_mut().put(b"counter".to_vec(), bincode::serialize(&value).unwrap()).unwrap();
+            tx.commit().await.unwrap();
+        }
+    };
+
+    commit(1).await;
+    assert_eq!(storage.get::<u64>(b"counter").await.unwrap(), Some(1));
+    let scanned = storage.scan(b"counter").await.unwrap();
+    assert_eq!(scanned, vec![(b"counter".to_vec(), bincode::serialize(&1u64).unwrap())]);
+    assert_eq!(storage.get::<u64>(b"direct").await.unwrap(), None);
+
+    // Within the bound, reads are answered with what they read before
+    commit(2).await;
+    assert_eq!(storage.get::<u64>(b"counter").await.unwrap(), Some(1));
+    assert_eq!(storage.scan(b"counter").await.unwrap(), scanned);
+    // Writes made through the storage itself lag the same way
+    storage.insert(b"direct", &7u64).await.unwrap();
+    assert_eq!(storage.get::<u64>(b"direct").await.unwrap(), None);
+    // A read not made before is the latest
+    assert_eq!(storage.scan_range(b"counter", b"direct\xff").await.unwrap().len(), 2);
+
+    // Transactions and zero-bound reads always see the latest commit
+    let tx = storage.begin_transaction().await.unwrap();
+    assert_eq!(storage.get_for_transaction::<u64>(b"counter", &tx).await.unwrap(), Some(2));
+    storage.rollback_transaction(tx).await.unwrap();
+    storage.set_read_staleness(Duration::ZERO);
+    for value in 3..6 {
+        commit(value).await;
+        assert_eq!(storage.get::<u64>(b"counter").await.unwrap(), Some(value));
+    }
+
+    // Past the bound the view is dropped and reads go to storage again
+    storage.set_read_staleness(Duration::from_millis(20));
+    assert_eq!(storage.get::<u64>(b"counter").await.unwrap(), Some(5));
+    commit(6).await;
+    tokio::time::sleep(Duration::from_millis(40)).await;
+    assert_eq!(storage.get::<u64>(b"counter").await.unwrap(), Some(6));
+}
@@ -0,0 +1,26 @@
+use rust_db_core::Value;
+use rust_db_schema::Schema;
+use serde::Serialize;
+
+// Queryable, but deliberately not `Serialize`
+#[derive(Debug, Clone)]
+struct Region {
+    code: String,
+}
+
+impl From<&Region> for Value {
+    fn from(region: &Region) -> Self {
+        Value::String(region.code.clone())
+    }
+}
+
+#[derive(Serialize, Schema)]
+struct Shop {
+    #[key]
+    id: u64,
+    #[index]
+    #[serde(skip)]
+    region: Region,
+}
+
+fn main() {}
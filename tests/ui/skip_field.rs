@@ -0,0 +1,30 @@
+use rust_db_core::{FieldAccess, Value};
+use rust_db_schema::Schema;
+use serde::{Deserialize, Serialize};
+
+// Has no `From<&Thumbnail> for Value`, so it only compiles when skipped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Thumbnail {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Schema)]
+struct Photo {
+    #[key]
+    id: u64,
+    title: String,
+    #[skip]
+    thumbnail: Thumbnail,
+}
+
+fn main() {
+    let photo = Photo {
+        id: 1,
+        title: "harbour".to_string(),
+        thumbnail: Thumbnail { width: 2, height: 2, pixels: vec![0; 4] },
+    };
+    assert!(matches!(photo.get_field("title"), Some(Value::String(_))));
+    assert!(photo.get_field("thumbnail").is_none());
+}
@@ -0,0 +1,58 @@
+use rust_db_core::{DbError, Value};
+
+#[test]
+fn test_value_as_accessors() {
+    assert_eq!(Value::Float(2.5).as_f64(), Some(2.5));
+    assert_eq!(Value::Int(3).as_f64(), Some(3.0));
+    assert_eq!(Value::String("3".to_string()).as_f64(), None);
+
+    assert_eq!(Value::Int(-7).as_i64(), Some(-7));
+    assert_eq!(Value::Float(1.0).as_i64(), None);
+
+    assert_eq!(Value::String("alice".to_string()).as_str(), Some("alice"));
+    assert_eq!(Value::Null.as_str(), None);
+
+    assert_eq!(Value::Bool(true).as_bool(), Some(true));
+    assert_eq!(Value::Int(1).as_bool(), None);
+}
+
+#[test]
+fn test_value_try_from_success() {
+    assert_eq!(f64::try_from(Value::Float(1.5)).unwrap(), 1.5);
+    assert_eq!(f64::try_from(Value::Int(4)).unwrap(), 4.0);
+    assert_eq!(i64::try_from(Value::Int(42)).unwrap(), 42);
+    assert_eq!(String::try_from(Value::String("bob".to_string())).unwrap(), "bob");
+    assert!(bool::try_from(Value::Bool(true)).unwrap());
+}
+
+#[test]
+fn test_value_try_from_failure() {
+    assert!(matches!(f64::try_from(Value::Bool(true)), Err(DbError::Query(_))));
+    assert!(matches!(i64::try_from(Value::Float(1.0)), Err(DbError::Query(_))));
+    assert!(matches!(String::try_from(Value::Int(1)), Err(DbError::Query(_))));
+    assert!(matches!(bool::try_from(Value::Null), Err(DbError::Query(_))));
+
+    let err = i64::try_from(Value::String("7".to_string())).unwrap_err();
+    assert_eq!(err.to_string(), "Query error: Cannot convert String(\"7\") to i64");
+}
+
+#[test]
+fn test_values_key_a_hash_set() {
+    use std::collections::HashSet;
+
+    let values = [
+        Value::Float(f64::NAN),
+        Value::Float(-f64::NAN),
+        Value::Float(0.0),
+        Value::Float(-0.0),
+        Value::Int(0),
+        Value::Point { lat: f64::NAN, lon: 1.0 },
+        Value::Point { lat: f64::NAN, lon: 1.0 },
+        Value::List(vec![Value::Float(f64::NAN)]),
+        Value::List(vec![Value::Float(f64::NAN)]),
+    ];
+    assert_eq!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+    assert_ne!(Value::Float(0.0), Value::Float(-0.0));
+    let distinct: HashSet<Value> = values.into_iter().collect();
+    assert_eq!(distinct.len(), 6);
+}
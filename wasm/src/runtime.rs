@@ -149,7 +149,7 @@ impl WasmRuntime {
     fn encode_parameters(&self, context: &WasmExecutionContext) -> Result<Vec<u8>> {
         // Encode parameters as JSON for simplicity
         serde_json::to_vec(&context.parameters)
-            .map_err(|e| DbError::Serialization(format!("Failed to encode parameters: {}", e)))
+            .map_err(|e| DbError::Serialization(format!("Failed to encode parameters: {}", e), Some(Box::new(e))))
     }
 
     fn allocate_in_wasm(